@@ -1,12 +1,162 @@
-use std::sync::Arc;
+use std::{
+    num::NonZeroUsize,
+    sync::atomic::{AtomicU64, Ordering},
+    sync::{Arc, Mutex},
+};
 
 use hashbrown::HashMap;
+use lru::LruCache;
 use rayon::prelude::*;
 
-#[derive(Clone)]
+/// The storage of a `CachedFunction`.
+///
+/// The LRU cache is behind a `Mutex`, as even reading from it updates the
+/// recency of the entry, so it can be read from multiple threads at once.
+enum Cache<I, O> {
+    Unbounded(HashMap<I, O>),
+    Lru(Mutex<LruCache<I, O>>),
+}
+
+impl<I, O> Clone for Cache<I, O>
+where
+    I: Eq + std::hash::Hash + Clone,
+    O: Clone,
+{
+    fn clone(&self) -> Self {
+        match self {
+            Cache::Unbounded(cache) => Cache::Unbounded(cache.clone()),
+            Cache::Lru(cache) => {
+                let cache = cache.lock().unwrap();
+                let mut clone = LruCache::new(cache.cap());
+                // Insert from least to most recently used to keep the order
+                cache.iter().rev().for_each(|(input, output)| {
+                    clone.push(input.clone(), output.clone());
+                });
+                Cache::Lru(Mutex::new(clone))
+            }
+        }
+    }
+}
+
+impl<I, O> Cache<I, O>
+where
+    I: Eq + std::hash::Hash,
+    O: Clone,
+{
+    fn get(&self, input: &I) -> Option<O> {
+        match self {
+            Cache::Unbounded(cache) => cache.get(input).cloned(),
+            Cache::Lru(cache) => cache.lock().unwrap().get(input).cloned(),
+        }
+    }
+
+    /// Inserts an output and returns whether another entry was evicted for it.
+    fn insert(&mut self, input: I, output: O) -> bool {
+        match self {
+            Cache::Unbounded(cache) => {
+                cache.insert(input, output);
+                false
+            }
+            Cache::Lru(cache) => {
+                let cache = cache.get_mut().unwrap();
+                let is_new = !cache.contains(&input);
+                is_new && cache.push(input, output).is_some()
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Cache::Unbounded(cache) => cache.len(),
+            Cache::Lru(cache) => cache.lock().unwrap().len(),
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Cache::Unbounded(cache) => cache.clear(),
+            Cache::Lru(cache) => cache.get_mut().unwrap().clear(),
+        }
+    }
+
+    /// Returns clones of all entries.
+    fn entries(&self) -> Vec<(I, O)>
+    where
+        I: Clone,
+    {
+        match self {
+            Cache::Unbounded(cache) => cache
+                .iter()
+                .map(|(input, output)| (input.clone(), output.clone()))
+                .collect(),
+            Cache::Lru(cache) => cache
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(input, output)| (input.clone(), output.clone()))
+                .collect(),
+        }
+    }
+
+    fn contains(&self, input: &I) -> bool {
+        match self {
+            Cache::Unbounded(cache) => cache.contains_key(input),
+            Cache::Lru(cache) => cache.lock().unwrap().contains(input),
+        }
+    }
+
+    /// Removes all entries for which `keep` returns `false`.
+    fn retain(&mut self, mut keep: impl FnMut(&I, &O) -> bool)
+    where
+        I: Clone,
+    {
+        match self {
+            Cache::Unbounded(cache) => cache.retain(|input, output| keep(input, output)),
+            Cache::Lru(cache) => {
+                let cache = cache.get_mut().unwrap();
+                let removed = cache
+                    .iter()
+                    .filter(|(input, output)| !keep(input, output))
+                    .map(|(input, _)| input.clone())
+                    .collect::<Vec<I>>();
+                removed.iter().for_each(|input| {
+                    cache.pop(input);
+                });
+            }
+        }
+    }
+}
+
+/// The number of cache hits, misses and evictions of a `CachedFunction`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStatistics {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
 pub struct CachedFunction<I, O> {
-    cache: HashMap<I, O>,
+    cache: Cache<I, O>,
     function: Arc<dyn Fn(I) -> O + Send + Sync>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl<I, O> Clone for CachedFunction<I, O>
+where
+    I: Eq + std::hash::Hash + Clone,
+    O: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            cache: self.cache.clone(),
+            function: self.function.clone(),
+            hits: AtomicU64::new(self.hits.load(Ordering::Relaxed)),
+            misses: AtomicU64::new(self.misses.load(Ordering::Relaxed)),
+            evictions: AtomicU64::new(self.evictions.load(Ordering::Relaxed)),
+        }
+    }
 }
 
 impl<I, O> CachedFunction<I, O>
@@ -16,27 +166,79 @@ where
 {
     pub fn new(function: Arc<dyn Fn(I) -> O + Send + Sync>) -> Self {
         Self {
-            cache: HashMap::new(),
+            cache: Cache::Unbounded(HashMap::new()),
+            function,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Create a cached function which keeps at most `max_entries` outputs,
+    /// evicting the least recently used ones.
+    ///
+    /// # Panics
+    /// This method panics if `max_entries` is zero.
+    pub fn new_with_capacity(
+        function: Arc<dyn Fn(I) -> O + Send + Sync>,
+        max_entries: usize,
+    ) -> Self {
+        let max_entries =
+            NonZeroUsize::new(max_entries).expect("The cache capacity must not be zero");
+        Self {
+            cache: Cache::Lru(Mutex::new(LruCache::new(max_entries))),
             function,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
         }
     }
 
     #[allow(dead_code)]
     pub fn call(&mut self, input: I) -> O {
         if let Some(output) = self.cache.get(&input) {
-            output.clone()
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            output
         } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
             let output = self.bypass(input.clone());
-            self.cache.insert(input, output.clone());
+            self.insert(input, output.clone());
             output
         }
     }
 
-    #[allow(dead_code)]
+    fn insert(&mut self, input: I, output: O) {
+        if self.cache.insert(input, output) {
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
     pub fn clear(&mut self) {
         self.cache.clear();
     }
 
+    pub fn retain(&mut self, keep: impl FnMut(&I, &O) -> bool) {
+        self.cache.retain(keep);
+    }
+
+    /// Adds the cached entries of `other` which are not cached yet.
+    pub fn extend(&mut self, other: &Self) {
+        for (input, output) in other.cache.entries() {
+            if !self.cache.contains(&input) {
+                self.insert(input, output);
+            }
+        }
+    }
+
+    /// Returns whether both functions wrap the same function.
+    pub fn same_function(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(Arc::as_ptr(&self.function), Arc::as_ptr(&other.function))
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
     pub fn bypass(&self, input: I) -> O {
         (self.function)(input)
     }
@@ -46,19 +248,166 @@ where
         inputs.map(|input| self.call(input)).collect()
     }
 
+    #[allow(dead_code)]
     pub fn call_many_parallel(&mut self, inputs: impl IntoParallelIterator<Item = I>) -> Vec<O> {
-        let pairs = inputs
+        self.call_many_parallel_with_hits(inputs)
+            .into_iter()
+            .map(|(output, _)| output)
+            .collect()
+    }
+
+    /// Like `call_many_parallel`, but also returns for each output whether it
+    /// was taken from the cache.
+    pub fn call_many_parallel_with_hits(
+        &mut self,
+        inputs: impl IntoParallelIterator<Item = I>,
+    ) -> Vec<(O, bool)> {
+        let cache = &self.cache;
+        let results = inputs
             .into_par_iter()
-            .map(|input| (input.clone(), self.bypass(input)))
-            .collect::<Vec<(I, O)>>();
-        pairs.iter().for_each(|(input, output)| {
-            self.cache.insert(input.clone(), output.clone());
-        });
-        pairs.into_iter().map(|(_, output)| output).collect()
+            .map(|input| match cache.get(&input) {
+                Some(output) => {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    (input, output, true)
+                }
+                None => {
+                    self.misses.fetch_add(1, Ordering::Relaxed);
+                    let output = self.bypass(input.clone());
+                    (input, output, false)
+                }
+            })
+            .collect::<Vec<(I, O, bool)>>();
+        results
+            .iter()
+            .filter(|(_, _, cached)| !cached)
+            .for_each(|(input, output, _)| {
+                self.insert(input.clone(), output.clone());
+            });
+        results
+            .into_iter()
+            .map(|(_, output, cached)| (output, cached))
+            .collect()
+    }
+
+    pub fn statistics(&self) -> CacheStatistics {
+        CacheStatistics {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
     }
 
     #[allow(dead_code)]
+    pub fn reset_statistics(&mut self) {
+        *self.hits.get_mut() = 0;
+        *self.misses.get_mut() = 0;
+        *self.evictions.get_mut() = 0;
+    }
+
     pub fn function(&self) -> Arc<dyn Fn(I) -> O + Send + Sync> {
         self.function.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    #[test]
+    fn lru_eviction() {
+        let num_calls = Arc::new(AtomicUsize::new(0));
+        let counter = num_calls.clone();
+        let mut cached_function = CachedFunction::new_with_capacity(
+            Arc::new(move |input: i32| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                input * 2
+            }),
+            2,
+        );
+        assert_eq!(cached_function.call(1), 2);
+        assert_eq!(cached_function.call(2), 4);
+        assert_eq!(cached_function.call(1), 2);
+        assert_eq!(num_calls.load(Ordering::SeqCst), 2);
+
+        // 2 is the least recently used entry and gets evicted
+        assert_eq!(cached_function.call(3), 6);
+        assert_eq!(num_calls.load(Ordering::SeqCst), 3);
+        assert_eq!(cached_function.call(1), 2);
+        assert_eq!(num_calls.load(Ordering::SeqCst), 3);
+        assert_eq!(cached_function.call(2), 4);
+        assert_eq!(num_calls.load(Ordering::SeqCst), 4);
+
+        assert_eq!(
+            cached_function.call_many_parallel(vec![2, 3, 4]),
+            vec![4, 6, 8]
+        );
+        assert_eq!(num_calls.load(Ordering::SeqCst), 6);
+        let clone = cached_function.clone();
+        assert_eq!(clone.cache.get(&4), Some(8));
+        assert_eq!(clone.cache.get(&1), None);
+    }
+
+    #[test]
+    fn retain() {
+        for mut cached_function in [
+            CachedFunction::new(Arc::new(|input: i32| input * 2)),
+            CachedFunction::new_with_capacity(Arc::new(|input: i32| input * 2), 10),
+        ] {
+            cached_function.call_many(0..6);
+            cached_function.retain(|input, output| input % 2 == 0 && *output < 8);
+            assert_eq!(cached_function.len(), 2);
+            cached_function.call_many([0, 2].into_iter());
+            assert_eq!(cached_function.statistics().hits, 2);
+        }
+    }
+
+    #[test]
+    fn extend() {
+        let function: Arc<dyn Fn(i32) -> i32 + Send + Sync> = Arc::new(|input| input * 2);
+        let mut cached_function = CachedFunction::new(function.clone());
+        let mut other = CachedFunction::new_with_capacity(function, 10);
+        assert!(cached_function.same_function(&other));
+        assert!(!cached_function.same_function(&CachedFunction::new(Arc::new(|input| input * 2))));
+        cached_function.call_many(0..3);
+        other.call_many(2..5);
+        cached_function.extend(&other);
+        assert_eq!(cached_function.len(), 5);
+        cached_function.call_many(0..5);
+        assert_eq!(cached_function.statistics().hits, 5);
+    }
+
+    #[test]
+    fn statistics() {
+        let mut cached_function = CachedFunction::new(Arc::new(|input: i32| input * 2));
+        cached_function.call(1);
+        cached_function.call(1);
+        assert_eq!(
+            cached_function.statistics(),
+            CacheStatistics {
+                hits: 1,
+                misses: 1,
+                evictions: 0
+            }
+        );
+
+        cached_function.call_many_parallel(vec![1, 2, 3]);
+        assert_eq!(cached_function.statistics().hits, 2);
+        assert_eq!(cached_function.statistics().misses, 3);
+        cached_function.reset_statistics();
+        assert_eq!(cached_function.statistics(), CacheStatistics::default());
+
+        let mut cached_function =
+            CachedFunction::new_with_capacity(Arc::new(|input: i32| input), 1);
+        cached_function.call_many(vec![1, 1, 2, 1].into_iter());
+        assert_eq!(
+            cached_function.statistics(),
+            CacheStatistics {
+                hits: 1,
+                misses: 3,
+                evictions: 2
+            }
+        );
+    }
+}
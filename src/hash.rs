@@ -1,10 +1,56 @@
 use std::{
     collections::hash_map::DefaultHasher,
-    hash::{Hash, Hasher},
+    hash::{BuildHasher, BuildHasherDefault, Hash, Hasher},
+    sync::Arc,
 };
 
-pub(crate) fn hash(hashable: &impl Hash) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    hashable.hash(&mut hasher);
-    hasher.finish()
+/// Creates a hasher, passes it to the callback and returns the hash.
+type HashWith = dyn Fn(&mut dyn FnMut(&mut dyn Hasher)) -> u64 + Send + Sync;
+
+/// A type-erased `BuildHasher` used to hash states and transitions.
+///
+/// The hasher is created on the stack of the wrapped function and passed to a
+/// callback, so hashing does not allocate.
+#[derive(Clone)]
+pub(crate) struct HashFunction(Arc<HashWith>);
+
+impl HashFunction {
+    pub(crate) fn new<B>(build_hasher: B) -> Self
+    where
+        B: BuildHasher + Send + Sync + 'static,
+    {
+        Self(Arc::new(move |write| {
+            let mut hasher = build_hasher.build_hasher();
+            write(&mut hasher);
+            hasher.finish()
+        }))
+    }
+
+    pub(crate) fn hash(&self, hashable: &impl Hash) -> u64 {
+        (self.0)(&mut |mut hasher: &mut dyn Hasher| hashable.hash(&mut hasher))
+    }
+}
+
+impl Default for HashFunction {
+    fn default() -> Self {
+        Self::new(BuildHasherDefault::<DefaultHasher>::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::RandomState;
+
+    use super::*;
+
+    #[test]
+    fn hash_function() {
+        let mut hasher = DefaultHasher::new();
+        "state".hash(&mut hasher);
+        assert_eq!(HashFunction::default().hash(&"state"), hasher.finish());
+
+        let random_state = RandomState::new();
+        let hash_function = HashFunction::new(random_state.clone());
+        assert_eq!(hash_function.hash(&42), random_state.hash_one(42),);
+    }
 }
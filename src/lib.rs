@@ -24,7 +24,7 @@
 //! // The initial state. It has to be Hash + Clone + Send + Sync + PartialEq + Eq + Debug
 //! let initial_state: i32 = 0;
 //!
-//! // The state transition generator. The simulation panics if the probabilities don't sum to 1.0
+//! // The state transition generator. Stepping fails if the probabilities don't sum to 1.0
 //! let state_transition_generator =
 //! Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
 //!
@@ -32,12 +32,13 @@
 //!
 //! // The Shannon-entropy at the given time
 //! assert_eq!(simulation.entropy(0), 0.0);
-//! simulation.next_step();
+//! simulation.next_step().unwrap();
 //! assert_eq!(simulation.entropy(1), 1.0);
 //! ```
 
 mod cached_function;
 mod hash;
+mod linalg;
 pub mod models;
 pub mod prelude;
 pub mod simulation;
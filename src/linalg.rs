@@ -0,0 +1,397 @@
+use ndarray::{Array1, Array2, Axis};
+
+/// Pivots with an absolute value below this are treated as zero.
+const SINGULARITY_THRESHOLD: f64 = 1e-12;
+
+/// Solves the linear system `coefficients * x = constants` using gaussian
+/// elimination with partial pivoting.
+///
+/// Returns `None` if the coefficient matrix is singular.
+pub(crate) fn solve_linear_system(
+    coefficients: Array2<f64>,
+    constants: Array1<f64>,
+) -> Option<Array1<f64>> {
+    let num_equations = constants.len();
+    let constants = constants.into_shape((num_equations, 1)).unwrap();
+    solve_linear_systems(coefficients, constants)
+        .map(|solution| solution.index_axis_move(Axis(1), 0))
+}
+
+/// Solves the linear systems `coefficients * X = constants` for all columns of
+/// `constants` at once using gaussian elimination with partial pivoting.
+///
+/// Returns `None` if the coefficient matrix is singular.
+pub(crate) fn solve_linear_systems(
+    mut coefficients: Array2<f64>,
+    mut constants: Array2<f64>,
+) -> Option<Array2<f64>> {
+    let n = constants.nrows();
+    assert_eq!(
+        coefficients.dim(),
+        (n, n),
+        "Coefficient matrix must be square"
+    );
+    let num_systems = constants.ncols();
+
+    for column in 0..n {
+        let pivot_row = (column..n)
+            .max_by(|a, b| {
+                coefficients[(*a, column)]
+                    .abs()
+                    .total_cmp(&coefficients[(*b, column)].abs())
+            })
+            .unwrap();
+        if coefficients[(pivot_row, column)].abs() < SINGULARITY_THRESHOLD {
+            return None;
+        }
+        if pivot_row != column {
+            for k in 0..n {
+                coefficients.swap((pivot_row, k), (column, k));
+            }
+            for k in 0..num_systems {
+                constants.swap((pivot_row, k), (column, k));
+            }
+        }
+        for row in column + 1..n {
+            let factor = coefficients[(row, column)] / coefficients[(column, column)];
+            if factor == 0.0 {
+                continue;
+            }
+            for k in column..n {
+                coefficients[(row, k)] -= factor * coefficients[(column, k)];
+            }
+            for k in 0..num_systems {
+                constants[(row, k)] -= factor * constants[(column, k)];
+            }
+        }
+    }
+
+    let mut solution = Array2::zeros((n, num_systems));
+    for system in 0..num_systems {
+        for row in (0..n).rev() {
+            let sum = (row + 1..n)
+                .map(|k| coefficients[(row, k)] * solution[(k, system)])
+                .sum::<f64>();
+            solution[(row, system)] = (constants[(row, system)] - sum) / coefficients[(row, row)];
+        }
+    }
+    Some(solution)
+}
+
+/// Inverts a square matrix.
+///
+/// Returns `None` if the matrix is singular.
+pub(crate) fn invert_matrix(matrix: Array2<f64>) -> Option<Array2<f64>> {
+    let identity = Array2::eye(matrix.nrows());
+    solve_linear_systems(matrix, identity)
+}
+
+/// Raises a square matrix to the given power by repeated squaring, which
+/// needs `O(log exponent)` matrix multiplications.
+pub(crate) fn matrix_power(matrix: &Array2<f64>, mut exponent: u64) -> Array2<f64> {
+    let mut result = Array2::eye(matrix.nrows());
+    let mut base = matrix.clone();
+    while exponent > 0 {
+        if exponent % 2 == 1 {
+            result = result.dot(&base);
+        }
+        exponent /= 2;
+        if exponent > 0 {
+            base = base.dot(&base);
+        }
+    }
+    result
+}
+
+/// The maximum number of QR iterations to find a single eigenvalue.
+const EIGENVALUE_MAX_ITERATIONS: usize = 60;
+
+/// Reduces a square matrix to upper Hessenberg form using Householder
+/// reflections, which doesn't change its eigenvalues.
+fn hessenberg(mut matrix: Array2<f64>) -> Array2<f64> {
+    let n = matrix.nrows();
+    for column in 0..n.saturating_sub(2) {
+        let norm = (column + 1..n)
+            .map(|row| matrix[(row, column)].powi(2))
+            .sum::<f64>()
+            .sqrt();
+        if norm < SINGULARITY_THRESHOLD {
+            continue;
+        }
+        let alpha = if matrix[(column + 1, column)] >= 0.0 {
+            -norm
+        } else {
+            norm
+        };
+        let mut reflector = (column + 1..n)
+            .map(|row| matrix[(row, column)])
+            .collect::<Vec<_>>();
+        reflector[0] -= alpha;
+        let reflector_norm = reflector.iter().map(|value| value * value).sum::<f64>();
+        // H A H with H = I - 2 v v^T / (v^T v)
+        for k in 0..n {
+            let dot = (0..reflector.len())
+                .map(|i| reflector[i] * matrix[(column + 1 + i, k)])
+                .sum::<f64>();
+            let factor = 2.0 * dot / reflector_norm;
+            for (i, value) in reflector.iter().enumerate() {
+                matrix[(column + 1 + i, k)] -= factor * value;
+            }
+        }
+        for k in 0..n {
+            let dot = (0..reflector.len())
+                .map(|i| matrix[(k, column + 1 + i)] * reflector[i])
+                .sum::<f64>();
+            let factor = 2.0 * dot / reflector_norm;
+            for (i, value) in reflector.iter().enumerate() {
+                matrix[(k, column + 1 + i)] -= factor * value;
+            }
+        }
+    }
+    matrix
+}
+
+/// Computes the eigenvalues of a square matrix as pairs of their real and
+/// imaginary parts.
+///
+/// The matrix is reduced to Hessenberg form and then to quasi-triangular form
+/// using the shifted QR algorithm with Francis double shifts. The order of the
+/// eigenvalues is unspecified. Returns `None` if the QR iteration doesn't
+/// converge.
+pub(crate) fn eigenvalues(matrix: Array2<f64>) -> Option<Vec<(f64, f64)>> {
+    let n = matrix.nrows();
+    assert_eq!(matrix.dim(), (n, n), "Matrix must be square");
+    let mut a = hessenberg(matrix);
+    let norm = (0..n)
+        .flat_map(|row| (row.saturating_sub(1)..n).map(move |column| (row, column)))
+        .map(|index| a[index].abs())
+        .sum::<f64>();
+    let mut eigenvalues = vec![(0.0, 0.0); n];
+    // The sum of the exceptional shifts that were subtracted from the diagonal
+    let mut shift = 0.0;
+    let mut unsolved = n;
+    while unsolved > 0 {
+        let last = unsolved - 1;
+        let mut iterations = 0;
+        loop {
+            // Find the start of the unreduced block ending at `last`
+            let mut first = last;
+            while first > 0 {
+                let mut scale = a[(first - 1, first - 1)].abs() + a[(first, first)].abs();
+                if scale == 0.0 {
+                    scale = norm;
+                }
+                if a[(first, first - 1)].abs() <= f64::EPSILON * scale {
+                    a[(first, first - 1)] = 0.0;
+                    break;
+                }
+                first -= 1;
+            }
+            let mut x = a[(last, last)];
+            if first == last {
+                // A single eigenvalue split off
+                eigenvalues[last] = (x + shift, 0.0);
+                unsolved -= 1;
+                break;
+            }
+            let mut y = a[(last - 1, last - 1)];
+            let mut w = a[(last, last - 1)] * a[(last - 1, last)];
+            if first == last - 1 {
+                // The eigenvalues of a 2x2 block split off
+                let p = 0.5 * (y - x);
+                let q = p * p + w;
+                let z = q.abs().sqrt();
+                x += shift;
+                if q >= 0.0 {
+                    let z = if p >= 0.0 { p + z } else { p - z };
+                    eigenvalues[last - 1] = (x + z, 0.0);
+                    eigenvalues[last] = (if z != 0.0 { x - w / z } else { x + z }, 0.0);
+                } else {
+                    eigenvalues[last - 1] = (x + p, z);
+                    eigenvalues[last] = (x + p, -z);
+                }
+                unsolved -= 2;
+                break;
+            }
+            if iterations == EIGENVALUE_MAX_ITERATIONS {
+                return None;
+            }
+            if iterations == 10 || iterations == 20 {
+                // Exceptional shift to break cycles
+                shift += x;
+                for i in 0..=last {
+                    a[(i, i)] -= x;
+                }
+                let scale = a[(last, last - 1)].abs() + a[(last - 1, last - 2)].abs();
+                x = 0.75 * scale;
+                y = x;
+                w = -0.4375 * scale * scale;
+            }
+            iterations += 1;
+
+            // Find two consecutive small subdiagonal elements to start the
+            // double shift step at
+            let mut start = last - 2;
+            let (mut p, mut q, mut r);
+            loop {
+                let z = a[(start, start)];
+                let r_shifted = x - z;
+                let s_shifted = y - z;
+                p = (r_shifted * s_shifted - w) / a[(start + 1, start)] + a[(start, start + 1)];
+                q = a[(start + 1, start + 1)] - z - r_shifted - s_shifted;
+                r = a[(start + 2, start + 1)];
+                let scale = p.abs() + q.abs() + r.abs();
+                p /= scale;
+                q /= scale;
+                r /= scale;
+                if start == first {
+                    break;
+                }
+                let u = a[(start, start - 1)].abs() * (q.abs() + r.abs());
+                let v = p.abs()
+                    * (a[(start - 1, start - 1)].abs() + z.abs() + a[(start + 1, start + 1)].abs());
+                if u <= f64::EPSILON * v {
+                    break;
+                }
+                start -= 1;
+            }
+            for i in start..last - 1 {
+                a[(i + 2, i)] = 0.0;
+                if i != start {
+                    a[(i + 2, i - 1)] = 0.0;
+                }
+            }
+
+            // Double shift QR step on the block from `first` to `last`
+            for k in start..last {
+                if k != start {
+                    p = a[(k, k - 1)];
+                    q = a[(k + 1, k - 1)];
+                    r = if k + 1 != last {
+                        a[(k + 2, k - 1)]
+                    } else {
+                        0.0
+                    };
+                    x = p.abs() + q.abs() + r.abs();
+                    if x != 0.0 {
+                        p /= x;
+                        q /= x;
+                        r /= x;
+                    }
+                }
+                let s = (p * p + q * q + r * r).sqrt();
+                let s = if p >= 0.0 { s } else { -s };
+                if s == 0.0 {
+                    continue;
+                }
+                if k == start {
+                    if first != start {
+                        a[(k, k - 1)] = -a[(k, k - 1)];
+                    }
+                } else {
+                    a[(k, k - 1)] = -s * x;
+                }
+                p += s;
+                x = p / s;
+                y = q / s;
+                let z = r / s;
+                q /= p;
+                r /= p;
+                for j in k..=last {
+                    let mut p = a[(k, j)] + q * a[(k + 1, j)];
+                    if k + 1 != last {
+                        p += r * a[(k + 2, j)];
+                        a[(k + 2, j)] -= p * z;
+                    }
+                    a[(k + 1, j)] -= p * y;
+                    a[(k, j)] -= p * x;
+                }
+                for i in first..=last.min(k + 3) {
+                    let mut p = x * a[(i, k)] + y * a[(i, k + 1)];
+                    if k + 1 != last {
+                        p += z * a[(i, k + 2)];
+                        a[(i, k + 2)] -= p * r;
+                    }
+                    a[(i, k + 1)] -= p * q;
+                    a[(i, k)] -= p;
+                }
+            }
+        }
+    }
+    Some(eigenvalues)
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::arr2;
+
+    use super::*;
+
+    fn assert_eigenvalues(matrix: Array2<f64>, expected: &[(f64, f64)]) {
+        let mut eigenvalues = eigenvalues(matrix).unwrap();
+        eigenvalues.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(eigenvalues.len(), expected.len());
+        for (eigenvalue, expected) in eigenvalues.iter().zip(expected) {
+            assert!(
+                (eigenvalue.0 - expected.0).abs() < 1e-6
+                    && (eigenvalue.1 - expected.1).abs() < 1e-6,
+                "{eigenvalues:?} != {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn hessenberg() {
+        let matrix = arr2(&[
+            [4.0, 1.0, -2.0, 2.0],
+            [1.0, 2.0, 0.0, 1.0],
+            [-2.0, 0.0, 3.0, -2.0],
+            [2.0, 1.0, -2.0, -1.0],
+        ]);
+        let reduced = super::hessenberg(matrix.clone());
+        for row in 2..4 {
+            for column in 0..row - 1 {
+                assert!(reduced[(row, column)].abs() < 1e-12);
+            }
+        }
+        // The reduction is a similarity transformation with an orthogonal
+        // matrix, so the trace and the Frobenius norm don't change
+        assert!((reduced.diag().sum() - matrix.diag().sum()).abs() < 1e-12);
+        let frobenius_norm = |matrix: &Array2<f64>| matrix.iter().map(|x| x * x).sum::<f64>();
+        assert!((frobenius_norm(&reduced) - frobenius_norm(&matrix)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn real_eigenvalues() {
+        let matrix = arr2(&[[2.0, 0.0, 0.0], [0.0, 3.0, 4.0], [0.0, 4.0, 9.0]]);
+        assert_eigenvalues(matrix, &[(1.0, 0.0), (2.0, 0.0), (11.0, 0.0)]);
+        let matrix = arr2(&[
+            [0.5, 0.5, 0.0, 0.0],
+            [0.25, 0.25, 0.5, 0.0],
+            [0.0, 0.0, 0.5, 0.5],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        assert_eigenvalues(matrix, &[(0.0, 0.0), (0.5, 0.0), (0.75, 0.0), (1.0, 0.0)]);
+    }
+
+    #[test]
+    fn complex_eigenvalues() {
+        let rotation = arr2(&[[0.0, -1.0], [1.0, 0.0]]);
+        assert_eigenvalues(rotation, &[(0.0, -1.0), (0.0, 1.0)]);
+        let cycle = arr2(&[[0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0]]);
+        let root = 3.0_f64.sqrt() / 2.0;
+        assert_eigenvalues(cycle, &[(-0.5, -root), (-0.5, root), (1.0, 0.0)]);
+    }
+
+    #[test]
+    fn repeated_eigenvalues() {
+        let defective = arr2(&[[1.0, 1.0], [0.0, 1.0]]);
+        assert_eigenvalues(defective, &[(1.0, 0.0), (1.0, 0.0)]);
+        assert_eigenvalues(Array2::eye(3), &[(1.0, 0.0), (1.0, 0.0), (1.0, 0.0)]);
+    }
+
+    #[test]
+    fn single_eigenvalue() {
+        assert_eigenvalues(arr2(&[[5.0]]), &[(5.0, 0.0)]);
+    }
+}
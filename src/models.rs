@@ -1 +1,3 @@
+pub mod constraints;
+pub mod entities;
 pub mod rules;
@@ -0,0 +1,416 @@
+//! Constraints that all states of a markov chain have to satisfy.
+//!
+//! A [ConstraintSet](struct.ConstraintSet.html) holds named predicates on
+//! states. It can be checked by the simulation itself with
+//! [Simulation::with_constraints](../../simulation/struct.Simulation.html#method.with_constraints),
+//! in which case [next_step](../../simulation/struct.Simulation.html#method.next_step)
+//! returns an error for the first state violating a constraint, or by wrapping
+//! the state transition generator with
+//! [constrained_generator](fn.constrained_generator.html), which panics
+//! instead.
+//!
+//! ```rust
+//! use entromatica::prelude::*;
+//! use entromatica::models::constraints::*;
+//! use std::sync::Arc;
+//!
+//! let constraints = ConstraintSet::new().with_at_most("Capacity".to_string(), |state: &i32| *state as f64, 2.);
+//! let state_transition_generator = Arc::new(|state: i32| vec![(state + 1, "next", 1.0)]);
+//! let mut simulation = Simulation::new(0, state_transition_generator).with_constraints(constraints);
+//! simulation.run(2).unwrap();
+//! assert!(matches!(
+//!     simulation.next_step(),
+//!     Err(SimulationError::ConstraintViolated(_))
+//! ));
+//! ```
+
+use std::{fmt::Debug, sync::Arc};
+
+use thiserror::Error;
+
+use super::entities::{ParameterName, State};
+use crate::prelude::*;
+
+pub type ConstraintName = String;
+
+/// The reason why a state violates a constraint.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{reason}")]
+pub struct ConstraintViolation {
+    pub reason: String,
+}
+
+impl ConstraintViolation {
+    /// Create a new violation with the given reason.
+    pub fn new(reason: String) -> Self {
+        Self { reason }
+    }
+}
+
+/// The error returned if a state violates a constraint of a
+/// [ConstraintSet](struct.ConstraintSet.html).
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("Constraint {constraint} violated by {state:?}: {violation}")]
+pub struct ConstraintError<S> {
+    pub constraint: ConstraintName,
+    pub state: S,
+    pub violation: ConstraintViolation,
+}
+
+/// A predicate on states, which returns the reason if the state violates it.
+pub type Constraint<S> = Arc<dyn Fn(&S) -> Result<(), ConstraintViolation> + Send + Sync>;
+
+/// A set of named constraints.
+///
+/// The constraints are checked in the order they were added.
+pub struct ConstraintSet<S> {
+    constraints: Vec<(ConstraintName, Constraint<S>)>,
+}
+
+impl<S> Clone for ConstraintSet<S> {
+    fn clone(&self) -> Self {
+        Self {
+            constraints: self.constraints.clone(),
+        }
+    }
+}
+
+impl<S> Debug for ConstraintSet<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.constraints.iter().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+impl<S> Default for ConstraintSet<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> ConstraintSet<S> {
+    /// Create a new set without constraints.
+    pub fn new() -> Self {
+        Self {
+            constraints: Vec::new(),
+        }
+    }
+
+    /// Add a constraint with the given name.
+    pub fn with_constraint(mut self, name: ConstraintName, constraint: Constraint<S>) -> Self {
+        self.constraints.push((name, constraint));
+        self
+    }
+
+    /// Add a constraint that `value` of every state is at most `limit`.
+    pub fn with_at_most(
+        self,
+        name: ConstraintName,
+        value: impl Fn(&S) -> f64 + Send + Sync + 'static,
+        limit: f64,
+    ) -> Self {
+        self.with_in_range(name, value, f64::NEG_INFINITY, limit)
+    }
+
+    /// Add a constraint that `value` of every state is at least `limit`.
+    pub fn with_at_least(
+        self,
+        name: ConstraintName,
+        value: impl Fn(&S) -> f64 + Send + Sync + 'static,
+        limit: f64,
+    ) -> Self {
+        self.with_in_range(name, value, limit, f64::INFINITY)
+    }
+
+    /// Add a constraint that `value` of every state is in `[min, max]`.
+    pub fn with_in_range(
+        self,
+        name: ConstraintName,
+        value: impl Fn(&S) -> f64 + Send + Sync + 'static,
+        min: f64,
+        max: f64,
+    ) -> Self {
+        self.with_constraint(
+            name,
+            Arc::new(move |state| check_range(value(state), min, max)),
+        )
+    }
+
+    /// Returns the names of the constraints.
+    pub fn names(&self) -> impl Iterator<Item = &ConstraintName> {
+        self.constraints.iter().map(|(name, _)| name)
+    }
+
+    /// Returns the number of constraints.
+    pub fn len(&self) -> usize {
+        self.constraints.len()
+    }
+
+    /// Returns whether the set contains no constraints.
+    pub fn is_empty(&self) -> bool {
+        self.constraints.is_empty()
+    }
+
+    /// Check the state against all constraints and return the error of the
+    /// first one it violates.
+    pub fn check(&self, state: &S) -> Result<(), ConstraintError<S>>
+    where
+        S: Clone,
+    {
+        self.constraints.iter().try_for_each(|(name, constraint)| {
+            constraint(state).map_err(|violation| ConstraintError {
+                constraint: name.clone(),
+                state: state.clone(),
+                violation,
+            })
+        })
+    }
+}
+
+impl<T> ConstraintSet<State<T>>
+where
+    T: Copy + Into<f64> + 'static,
+{
+    /// Add a constraint that the sum of `parameter` over all entities is at
+    /// most `capacity`.
+    pub fn with_total_capacity(self, parameter: ParameterName, capacity: f64) -> Self {
+        self.with_at_most(
+            format!("Total capacity of {parameter}"),
+            move |state| total(state, &parameter),
+            capacity,
+        )
+    }
+
+    /// Add a constraint that `parameter` of every entity is at most
+    /// `capacity`.
+    pub fn with_capacity_per_entity(self, parameter: ParameterName, capacity: f64) -> Self {
+        let name = format!("Capacity per entity of {parameter}");
+        self.with_constraint(
+            name,
+            Arc::new(move |state| {
+                entity_values(state, &parameter).try_for_each(|(entity, value)| {
+                    check_range(value, f64::NEG_INFINITY, capacity).map_err(|violation| {
+                        ConstraintViolation::new(format!("{entity}: {}", violation.reason))
+                    })
+                })
+            }),
+        )
+    }
+
+    /// Add a constraint that `parameter` of every entity is not negative.
+    pub fn with_non_negative(self, parameter: ParameterName) -> Self {
+        let name = format!("Non-negative {parameter}");
+        self.with_constraint(
+            name,
+            Arc::new(move |state| {
+                entity_values(state, &parameter).try_for_each(|(entity, value)| {
+                    check_range(value, 0., f64::INFINITY).map_err(|violation| {
+                        ConstraintViolation::new(format!("{entity}: {}", violation.reason))
+                    })
+                })
+            }),
+        )
+    }
+}
+
+fn check_range(value: f64, min: f64, max: f64) -> Result<(), ConstraintViolation> {
+    if (min..=max).contains(&value) {
+        Ok(())
+    } else {
+        Err(ConstraintViolation::new(format!(
+            "{value} is not in [{min}, {max}]"
+        )))
+    }
+}
+
+/// The values of the given parameter of all entities having it.
+fn entity_values<'a, T>(
+    state: &'a State<T>,
+    parameter: &'a str,
+) -> impl Iterator<Item = (&'a String, f64)> + 'a
+where
+    T: Copy + Into<f64>,
+{
+    state.entities().filter_map(move |(name, entity)| {
+        entity
+            .parameter(parameter)
+            .map(|value| (name, (*value).into()))
+    })
+}
+
+fn total<T>(state: &State<T>, parameter: &str) -> f64
+where
+    T: Copy + Into<f64>,
+{
+    entity_values(state, parameter)
+        .map(|(_, value)| value)
+        .sum()
+}
+
+/// Wrap a state transition generator so that it checks every state it returns
+/// against the constraints.
+///
+/// # Panics
+/// The returned generator panics with the
+/// [ConstraintError](struct.ConstraintError.html), which names the constraint
+/// and the offending state, if a state violates a constraint. Use
+/// [Simulation::with_constraints](../../simulation/struct.Simulation.html#method.with_constraints)
+/// to get the error from
+/// [next_step](../../simulation/struct.Simulation.html#method.next_step)
+/// instead.
+pub fn constrained_generator<S, T>(
+    state_transition_generator: StateTransitionGenerator<S, T>,
+    constraints: ConstraintSet<S>,
+) -> StateTransitionGenerator<S, T>
+where
+    S: Clone + Debug + 'static,
+    T: 'static,
+{
+    Arc::new(move |state: S| {
+        let next_states = state_transition_generator(state);
+        for (new_state, _, _) in &next_states {
+            if let Err(error) = constraints.check(new_state) {
+                panic!("{error}");
+            }
+        }
+        next_states
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use hashbrown::HashMap;
+
+    use super::super::entities::Entity;
+    use super::*;
+
+    fn population(name: &str, count: i32) -> (String, Entity<i32>) {
+        (
+            name.to_string(),
+            Entity::from_iter([("count".to_string(), count)]),
+        )
+    }
+
+    fn capacity_constraints() -> ConstraintSet<State<i32>> {
+        ConstraintSet::new()
+            .with_total_capacity("count".to_string(), 5.)
+            .with_capacity_per_entity("count".to_string(), 3.)
+            .with_non_negative("count".to_string())
+    }
+
+    #[test]
+    fn capacity() {
+        let constraints = capacity_constraints();
+        assert_eq!(constraints.len(), 3);
+        let valid_state = State::from_iter([population("A", 2), population("B", 3)]);
+        assert_eq!(constraints.check(&valid_state), Ok(()));
+
+        let error = constraints
+            .check(&State::from_iter([population("A", 3), population("B", 3)]))
+            .unwrap_err();
+        assert_eq!(error.constraint, "Total capacity of count");
+        let error = constraints
+            .check(&State::from_iter([population("A", 4)]))
+            .unwrap_err();
+        assert_eq!(error.constraint, "Capacity per entity of count");
+        assert!(error.violation.reason.starts_with("A: 4"));
+        let error = constraints
+            .check(&State::from_iter([population("A", 1), population("B", -1)]))
+            .unwrap_err();
+        assert_eq!(error.constraint, "Non-negative count");
+        assert!(error.violation.reason.starts_with("B: -1"));
+
+        // Entities without the parameter are not constrained
+        let state = State::new().with_entity("C".to_string(), Entity::new());
+        assert_eq!(constraints.check(&state), Ok(()));
+    }
+
+    #[test]
+    fn simulation_with_constraints() {
+        let state_transition_generator = Arc::new(|state: State<i32>| {
+            let count = *state.parameter("A", "count").unwrap();
+            let mut new_state = state.clone();
+            new_state.set_parameter("A".to_string(), "count".to_string(), count + 1);
+            vec![(state, "Stay", 0.5), (new_state, "Grow", 0.5)]
+        });
+        let initial_state = State::from_iter([population("A", 2), population("B", 1)]);
+        let mut simulation = Simulation::new(initial_state, state_transition_generator)
+            .with_constraints(capacity_constraints());
+        simulation.next_step().unwrap();
+        let error = simulation.next_step().unwrap_err();
+        assert_eq!(
+            error,
+            SimulationError::ConstraintViolated(ConstraintError {
+                constraint: "Capacity per entity of count".to_string(),
+                state: State::from_iter([population("A", 4), population("B", 1)]),
+                violation: ConstraintViolation::new("A: 4 is not in [-inf, 3]".to_string()),
+            })
+        );
+        assert_eq!(simulation.time(), 1);
+    }
+
+    #[test]
+    fn simulation_checks_all_states() {
+        let constraints = ConstraintSet::new().with_at_most(
+            "Capacity".to_string(),
+            |state: &i32| *state as f64,
+            2.,
+        );
+        let state_transition_generator: StateTransitionGenerator<i32, &str> =
+            Arc::new(|state: i32| vec![(state.min(2), "stay", 1.0)]);
+        let is_violated_by = |error: Option<SimulationError<i32, &str>>, state| matches!(error, Some(SimulationError::ConstraintViolated(error)) if error.state == state);
+
+        // The initial distribution is checked with the first step
+        let mut simulation = Simulation::new(3, state_transition_generator.clone())
+            .with_constraints(constraints.clone());
+        assert!(is_violated_by(simulation.next_step().err(), 3));
+        assert_eq!(simulation.time(), 0);
+
+        let mut simulation = Simulation::new(0, state_transition_generator.clone())
+            .with_constraints(constraints.clone());
+        assert!(is_violated_by(
+            simulation
+                .set_distribution(HashMap::from([(1, 0.5), (4, 0.5)]))
+                .err(),
+            4
+        ));
+        assert!(is_violated_by(
+            simulation
+                .apply_intervention(|state| state + 5, "shock")
+                .err(),
+            5
+        ));
+        assert_eq!(simulation.time(), 0);
+        assert_eq!(simulation.known_states().len(), 1);
+
+        let mut other_simulation = Simulation::new(3, state_transition_generator);
+        other_simulation.next_step().unwrap();
+        assert!(matches!(
+            simulation.merge(&other_simulation),
+            Err(MergeError::ConstraintViolated(error)) if error.state == 3
+        ));
+        assert_eq!(simulation.known_states().len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Constraint Capacity violated by 3")]
+    fn constrained_generator_panics() {
+        let constraints = ConstraintSet::new().with_at_most(
+            "Capacity".to_string(),
+            |state: &i32| *state as f64,
+            2.,
+        );
+        let state_transition_generator = constrained_generator(
+            Arc::new(|state: i32| vec![(state + 1, "next", 1.0)]),
+            constraints,
+        );
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.run(2).unwrap();
+        assert_eq!(
+            simulation.probability_distribution(2),
+            HashMap::from([(2, 1.0)])
+        );
+        simulation.next_step().unwrap();
+    }
+}
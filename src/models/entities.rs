@@ -0,0 +1,900 @@
+//! States made up of named entities with named parameters.
+//!
+//! A [State](struct.State.html) maps entity names to
+//! [entities](struct.Entity.html), which in turn map parameter names to values.
+//! Both are ordered by name, so two states with the same entities and
+//! parameters are equal and have the same hash no matter in which order they
+//! were built. This makes them usable as states of a
+//! [Simulation](../../simulation/struct.Simulation.html), e.g. with a state
+//! transition generator built from [rules](../rules/index.html):
+//!
+//! ```rust
+//! use entromatica::prelude::*;
+//! use entromatica::models::{entities::*, rules::*};
+//!
+//! let initial_state = State::new().with_entity(
+//!     "Counter".to_string(),
+//!     Entity::new().with_parameter("count".to_string(), 0),
+//! );
+//! let rules = vec![Rule::new(
+//!     "Increment".to_string(),
+//!     Condition::Always,
+//!     0.5,
+//!     Action::update_parameter("Counter".to_string(), "count".to_string(), |count| count + 1),
+//! )];
+//! let mut simulation = Simulation::new(initial_state, get_state_transition_generator(rules));
+//! simulation.next_step().unwrap();
+//! assert_eq!(simulation.probability_distribution(1).len(), 2);
+//! ```
+
+use std::{any::Any, collections::BTreeMap, fmt::Debug, hash::Hash, ops::Add, sync::Arc};
+
+use hashbrown::HashMap;
+use itertools::Itertools;
+
+use super::rules::{Action, Condition, ProbabilityWeight, Rule};
+
+pub type EntityName = String;
+pub type ParameterName = String;
+
+/// An entity with named parameters.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Entity<T> {
+    parameters: BTreeMap<ParameterName, T>,
+}
+
+impl<T> Default for Entity<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Entity<T> {
+    /// Create a new entity without parameters.
+    pub fn new() -> Self {
+        Self {
+            parameters: BTreeMap::new(),
+        }
+    }
+
+    /// Set the given parameter of the entity.
+    pub fn with_parameter(mut self, parameter: ParameterName, value: T) -> Self {
+        self.set_parameter(parameter, value);
+        self
+    }
+
+    /// Returns the value of the given parameter, if the entity has it.
+    pub fn parameter(&self, parameter: &str) -> Option<&T> {
+        self.parameters.get(parameter)
+    }
+
+    /// Sets the given parameter and returns its previous value.
+    pub fn set_parameter(&mut self, parameter: ParameterName, value: T) -> Option<T> {
+        self.parameters.insert(parameter, value)
+    }
+
+    /// Returns the parameters of the entity ordered by name.
+    pub fn parameters(&self) -> impl Iterator<Item = (&ParameterName, &T)> {
+        self.parameters.iter()
+    }
+}
+
+impl<T> FromIterator<(ParameterName, T)> for Entity<T> {
+    fn from_iter<I: IntoIterator<Item = (ParameterName, T)>>(iter: I) -> Self {
+        Self {
+            parameters: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// A state made up of named entities.
+///
+/// Entities and their parameters are stored sorted by name, so equality and
+/// the hash only depend on the contents and not on the insertion order.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct State<T> {
+    entities: BTreeMap<EntityName, Entity<T>>,
+}
+
+impl<T> Default for State<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> State<T> {
+    /// Create a new state without entities.
+    pub fn new() -> Self {
+        Self {
+            entities: BTreeMap::new(),
+        }
+    }
+
+    /// Add the given entity to the state, replacing any entity with the same
+    /// name.
+    pub fn with_entity(mut self, name: EntityName, entity: Entity<T>) -> Self {
+        self.entities.insert(name, entity);
+        self
+    }
+
+    /// Returns the entity with the given name, if it exists.
+    pub fn entity(&self, name: &str) -> Option<&Entity<T>> {
+        self.entities.get(name)
+    }
+
+    /// Returns the entity with the given name mutably, if it exists.
+    pub fn entity_mut(&mut self, name: &str) -> Option<&mut Entity<T>> {
+        self.entities.get_mut(name)
+    }
+
+    /// Returns the entities of the state ordered by name.
+    pub fn entities(&self) -> impl Iterator<Item = (&EntityName, &Entity<T>)> {
+        self.entities.iter()
+    }
+
+    /// Returns the value of the given parameter of the given entity, if both
+    /// exist.
+    pub fn parameter(&self, entity: &str, parameter: &str) -> Option<&T> {
+        self.entity(entity)?.parameter(parameter)
+    }
+
+    /// Sets the given parameter of the given entity and returns its previous
+    /// value. The entity is created if it doesn't exist.
+    pub fn set_parameter(
+        &mut self,
+        entity: EntityName,
+        parameter: ParameterName,
+        value: T,
+    ) -> Option<T> {
+        self.entities
+            .entry(entity)
+            .or_default()
+            .set_parameter(parameter, value)
+    }
+}
+
+impl<T> FromIterator<(EntityName, Entity<T>)> for State<T> {
+    fn from_iter<I: IntoIterator<Item = (EntityName, Entity<T>)>>(iter: I) -> Self {
+        Self {
+            entities: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// The target and the value of a
+/// [SetParameter](../rules/enum.Action.html#variant.SetParameter) or
+/// [AdjustParameter](../rules/enum.Action.html#variant.AdjustParameter)
+/// action.
+///
+/// As [Action](../rules/enum.Action.html) is generic over the whole state, the
+/// value is stored type-erased and can be read back with
+/// [value](#method.value).
+pub struct ParameterAction<T> {
+    entity: EntityName,
+    parameter: ParameterName,
+    value: Arc<dyn Any + Send + Sync>,
+    value_description: String,
+    function: Arc<dyn Fn(T) -> T + Send + Sync>,
+}
+
+impl<T> Clone for ParameterAction<T> {
+    fn clone(&self) -> Self {
+        Self {
+            entity: self.entity.clone(),
+            parameter: self.parameter.clone(),
+            value: self.value.clone(),
+            value_description: self.value_description.clone(),
+            function: self.function.clone(),
+        }
+    }
+}
+
+impl<T> Debug for ParameterAction<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?}, {:?}, {}",
+            self.entity, self.parameter, self.value_description
+        )
+    }
+}
+
+impl<T> ParameterAction<T> {
+    /// Returns the name of the entity the action changes.
+    pub fn entity(&self) -> &EntityName {
+        &self.entity
+    }
+
+    /// Returns the name of the parameter the action changes.
+    pub fn parameter(&self) -> &ParameterName {
+        &self.parameter
+    }
+
+    /// Returns the value of the action, if it has the type `V`.
+    pub fn value<V: 'static>(&self) -> Option<&V> {
+        self.value.downcast_ref()
+    }
+
+    /// Applies the action to the given state and returns the new state.
+    pub fn apply(&self, state: T) -> T {
+        (self.function)(state)
+    }
+}
+
+impl<T> ParameterAction<State<T>>
+where
+    T: Clone + Debug + Send + Sync + 'static,
+{
+    fn new(
+        entity: EntityName,
+        parameter: ParameterName,
+        value: T,
+        function: impl Fn(State<T>, &EntityName, &ParameterName, &T) -> State<T> + Send + Sync + 'static,
+    ) -> Self {
+        let value_description = format!("{value:?}");
+        let value = Arc::new(value);
+        Self {
+            function: {
+                let (entity, parameter, value) = (entity.clone(), parameter.clone(), value.clone());
+                Arc::new(move |state| function(state, &entity, &parameter, &value))
+            },
+            entity,
+            parameter,
+            value,
+            value_description,
+        }
+    }
+}
+
+impl<T> Action<State<T>>
+where
+    T: Clone + Debug + Send + Sync + 'static,
+{
+    /// An action that sets the given parameter of the given entity to `value`.
+    ///
+    /// The entity is created if it doesn't exist.
+    pub fn set_parameter(entity: EntityName, parameter: ParameterName, value: T) -> Self {
+        Action::SetParameter(ParameterAction::new(
+            entity,
+            parameter,
+            value,
+            |mut state, entity, parameter, value| {
+                state.set_parameter(entity.clone(), parameter.clone(), value.clone());
+                state
+            },
+        ))
+    }
+
+    /// An action that adds `delta` to the given parameter of the given
+    /// entity.
+    ///
+    /// # Panics
+    /// The action panics if the entity or the parameter doesn't exist.
+    pub fn adjust_parameter(entity: EntityName, parameter: ParameterName, delta: T) -> Self
+    where
+        T: Add<Output = T>,
+    {
+        Action::AdjustParameter(ParameterAction::new(
+            entity,
+            parameter,
+            delta,
+            |mut state, entity, parameter, delta| {
+                let value = state
+                    .parameter(entity, parameter)
+                    .unwrap_or_else(|| panic!("Parameter {parameter} of entity {entity} not found"))
+                    .clone()
+                    + delta.clone();
+                state.set_parameter(entity.clone(), parameter.clone(), value);
+                state
+            },
+        ))
+    }
+
+    /// An action that replaces the given parameter of the given entity with
+    /// the result of `update`.
+    ///
+    /// # Panics
+    /// The action panics if the entity or the parameter doesn't exist.
+    pub fn update_parameter(
+        entity: EntityName,
+        parameter: ParameterName,
+        update: impl Fn(&T) -> T + Send + Sync + 'static,
+    ) -> Self {
+        Action::Custom(Arc::new(move |mut state: State<T>| {
+            let value =
+                update(state.parameter(&entity, &parameter).unwrap_or_else(|| {
+                    panic!("Parameter {parameter} of entity {entity} not found")
+                }));
+            state.set_parameter(entity.clone(), parameter.clone(), value);
+            state
+        }))
+    }
+}
+
+impl<T> Condition<State<T>>
+where
+    T: 'static,
+{
+    /// A condition that applies if the given parameter of the given entity
+    /// exists and `predicate` returns `true` for its value.
+    pub fn parameter(
+        entity: EntityName,
+        parameter: ParameterName,
+        predicate: impl Fn(&T) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Condition::Custom(Arc::new(move |state: State<T>| {
+            state.parameter(&entity, &parameter).is_some_and(&predicate)
+        }))
+    }
+}
+
+/// A rule acting on a single entity of a [State](struct.State.html).
+///
+/// Entity rules describe independent per-entity dynamics and are turned into
+/// mutually exclusive [rules](../rules/struct.Rule.html) for whole states with
+/// [combine_entity_rules](fn.combine_entity_rules.html).
+#[derive(Clone)]
+pub struct EntityRule<T> {
+    description: String,
+    condition: Condition<Entity<T>>,
+    weight: ProbabilityWeight,
+    action: Action<Entity<T>>,
+}
+
+impl<T> Debug for EntityRule<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "EntityRule:")?;
+        writeln!(f, "Description: {}", self.description)?;
+        writeln!(f, "Weight: {}", self.weight)?;
+        Ok(())
+    }
+}
+
+impl<T> EntityRule<T> {
+    /// Create a new entity rule.
+    ///
+    /// The arguments are the same as for
+    /// [Rule::new](../rules/struct.Rule.html#method.new), but the condition
+    /// and the action get the entity instead of the whole state.
+    pub fn new(
+        description: String,
+        condition: Condition<Entity<T>>,
+        probability_weight: ProbabilityWeight,
+        action: Action<Entity<T>>,
+    ) -> Self {
+        Self {
+            description,
+            condition,
+            weight: probability_weight,
+            action,
+        }
+    }
+
+    /// Returns the rule's probability weight.
+    pub fn weight(&self) -> ProbabilityWeight {
+        self.weight
+    }
+
+    /// Returns the rule's description.
+    pub fn description(&self) -> &String {
+        &self.description
+    }
+}
+
+/// Combine entity rules into one rule for every subset of the entities.
+///
+/// This works like
+/// [combine_rules_weighted](../rules/fn.combine_rules_weighted.html): For
+/// every subset of the entities there is a combined rule whose weight is the
+/// product of the weights of the rules of the included entities and of
+/// `1 - weight` of the excluded ones. Its condition requires the conditions of
+/// all included entities, which also have to exist, and its action applies
+/// their actions to their entities. The combined rules are named after the
+/// included entities and the descriptions of their rules, e.g.
+/// `"Sheep: Birth & Wolves: Death"`, or `"Nothing"` for the empty subset.
+/// Like there, a rule with a weight of 1 is excluded by requiring that its
+/// condition does not apply.
+///
+/// As the number of subsets grows exponentially with the number of entities,
+/// combinations with a weight below `cutoff` can be dropped. Their weight is
+/// added to the `"Nothing"` rule instead, or to the combination of only the
+/// rules with a weight of 1 that the dropped combination includes. The rules
+/// are returned in a deterministic order.
+///
+/// The combined rules are mutually exclusive, so their weights are the
+/// transition probabilities if they are used with
+/// [get_exclusive_state_transition_generator](../rules/fn.get_exclusive_state_transition_generator.html).
+/// [get_state_transition_generator](../rules/fn.get_state_transition_generator.html)
+/// would treat them as independent events instead.
+///
+/// # Example
+/// ```rust
+/// use entromatica::prelude::*;
+/// use entromatica::models::entities::*;
+/// use entromatica::models::rules::*;
+/// use std::sync::Arc;
+/// use hashbrown::HashMap;
+///
+/// let birth: EntityRule<i32> = EntityRule::new(
+///     "Birth".to_string(),
+///     Condition::Always,
+///     0.5,
+///     Action::Custom(Arc::new(|entity: Entity<i32>| {
+///         let count = entity.parameter("count").unwrap() + 1;
+///         entity.with_parameter("count".to_string(), count)
+///     })),
+/// );
+/// let rules = combine_entity_rules(HashMap::from([("Sheep".to_string(), birth)]), None);
+/// let state = State::new().with_entity("Sheep".to_string(), Entity::from_iter([("count".to_string(), 1)]));
+/// let mut simulation = Simulation::new(state.clone(), get_exclusive_state_transition_generator(rules));
+/// simulation.next_step().unwrap();
+/// assert_eq!(simulation.state_probability(state, 1), 0.5);
+/// ```
+pub fn combine_entity_rules<T>(
+    rules: HashMap<EntityName, EntityRule<T>>,
+    cutoff: Option<ProbabilityWeight>,
+) -> Vec<Rule<State<T>>>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    let rules = rules
+        .into_iter()
+        .sorted_by(|(a, _), (b, _)| a.cmp(b))
+        .collect_vec();
+    let cutoff = cutoff.unwrap_or(0.);
+    // Every weight is a product of factors of at most 1, so a combination
+    // below the cutoff stays below it when more entities are decided.
+    let certain_rules = rules
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, rule))| rule.weight == 1.)
+        .map(|(index, _)| index)
+        .collect_vec();
+    // The conditions of certain rules which are excluded must not apply
+    let excluded_condition = |subset: &[usize]| {
+        certain_rules
+            .iter()
+            .filter(|index| !subset.contains(index))
+            .map(|index| {
+                Condition::Not(Box::new(entity_condition(
+                    &rules[*index].0,
+                    &rules[*index].1.condition,
+                )))
+            })
+            .reduce(|a, b| Condition::And(Box::new(a), Box::new(b)))
+    };
+    let mut combinations = vec![(Vec::new(), 1.)];
+    for (index, (_, rule)) in rules.iter().enumerate() {
+        let excluded_weight = if rule.weight == 1. {
+            1.
+        } else {
+            1. - rule.weight
+        };
+        combinations = combinations
+            .into_iter()
+            .flat_map(|(subset, weight): (Vec<usize>, ProbabilityWeight)| {
+                let mut included = subset.clone();
+                included.push(index);
+                [
+                    (included, weight * rule.weight),
+                    (subset, weight * excluded_weight),
+                ]
+            })
+            .filter(|(_, weight)| *weight > 0. && *weight >= cutoff)
+            .collect();
+    }
+    // The weights of the combinations with the same certain rules sum up to
+    // 1, so the residual weight of the dropped ones is folded into the
+    // combination of only these certain rules
+    for certain_subset in certain_rules.iter().copied().powerset() {
+        let residual_weight = 1.
+            - combinations
+                .iter()
+                .filter(|(subset, _)| {
+                    subset
+                        .iter()
+                        .filter(|index| certain_rules.contains(index))
+                        .eq(certain_subset.iter())
+                })
+                .map(|(_, weight)| weight)
+                .sum::<ProbabilityWeight>();
+        match combinations
+            .iter_mut()
+            .find(|(subset, _)| *subset == certain_subset)
+        {
+            Some((_, weight)) => *weight += residual_weight,
+            None => combinations.push((certain_subset, residual_weight)),
+        }
+    }
+    combinations
+        .into_iter()
+        .filter(|(_, weight)| *weight > 0.)
+        .sorted_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(subset, weight)| {
+            if subset.is_empty() {
+                return Rule::new(
+                    "Nothing".to_string(),
+                    excluded_condition(&subset).unwrap_or(Condition::Always),
+                    weight,
+                    Action::Identity,
+                );
+            }
+            let name = subset
+                .iter()
+                .map(|index| format!("{}: {}", rules[*index].0, rules[*index].1.description))
+                .join(" & ");
+            let condition = subset
+                .iter()
+                .map(|index| entity_condition(&rules[*index].0, &rules[*index].1.condition))
+                .chain(excluded_condition(&subset))
+                .reduce(|a, b| Condition::And(Box::new(a), Box::new(b)))
+                .unwrap();
+            let action = Action::Sequential(
+                subset
+                    .iter()
+                    .map(|index| entity_action(&rules[*index].0, &rules[*index].1.action))
+                    .collect(),
+            );
+            Rule::new(name, condition, weight, action)
+        })
+        .collect()
+}
+
+/// Lifts a condition on an entity to a condition on the whole state, which
+/// doesn't apply if the entity doesn't exist.
+fn entity_condition<T>(entity: &EntityName, condition: &Condition<Entity<T>>) -> Condition<State<T>>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    let entity = entity.clone();
+    let condition = condition.clone();
+    Condition::Custom(Arc::new(move |state: State<T>| {
+        state
+            .entity(&entity)
+            .is_some_and(|entity| condition.evaluate(entity.clone()))
+    }))
+}
+
+/// Lifts an action on an entity to an action on the whole state, which leaves
+/// the state unchanged if the entity doesn't exist.
+fn entity_action<T>(entity: &EntityName, action: &Action<Entity<T>>) -> Action<State<T>>
+where
+    T: Send + Sync + 'static,
+{
+    let entity = entity.clone();
+    let action = action.clone();
+    Action::Custom(Arc::new(move |mut state: State<T>| {
+        if let Some(entity) = state.entity_mut(&entity) {
+            *entity = action.apply(std::mem::take(entity));
+        }
+        state
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::rules::*;
+    use crate::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn state() {
+        let state = State::new()
+            .with_entity(
+                "Sheep".to_string(),
+                Entity::new()
+                    .with_parameter("count".to_string(), 3)
+                    .with_parameter("age".to_string(), 1),
+            )
+            .with_entity("Wolves".to_string(), Entity::new());
+        let mut other_state = State::new();
+        other_state.set_parameter("Wolves".to_string(), "count".to_string(), 1);
+        other_state.set_parameter("Sheep".to_string(), "age".to_string(), 1);
+        other_state.set_parameter("Sheep".to_string(), "count".to_string(), 3);
+        assert_ne!(state, other_state);
+        assert_eq!(state.parameter("Wolves", "count"), None);
+        assert_eq!(
+            other_state.set_parameter("Wolves".to_string(), "count".to_string(), 2),
+            Some(1)
+        );
+        other_state.entity_mut("Wolves").unwrap().parameters.clear();
+        assert_eq!(state, other_state);
+        let hash_function = HashFunction::default();
+        assert_eq!(hash_function.hash(&state), hash_function.hash(&other_state));
+        assert_eq!(
+            state
+                .entities()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            ["Sheep", "Wolves"]
+        );
+    }
+
+    #[test]
+    fn insertion_order() {
+        let entries = (0..20)
+            .flat_map(|entity| (0..5).map(move |parameter| (entity, parameter)))
+            .collect::<Vec<_>>();
+        let state_from = |entries: &[(i32, i32)]| {
+            let mut state = State::new();
+            for (entity, parameter) in entries {
+                state.set_parameter(
+                    format!("Entity {entity}"),
+                    format!("Parameter {parameter}"),
+                    entity * parameter,
+                );
+            }
+            state
+        };
+        let state = state_from(&entries);
+        let reversed_entries = entries.iter().rev().copied().collect::<Vec<_>>();
+        let reversed_state = state_from(&reversed_entries);
+        assert_eq!(state, reversed_state);
+        let hash_function = HashFunction::default();
+        assert_eq!(
+            hash_function.hash(&state),
+            hash_function.hash(&reversed_state)
+        );
+
+        let mut other_state = reversed_state.clone();
+        other_state.set_parameter("Entity 3".to_string(), "Parameter 2".to_string(), 0);
+        assert_ne!(state, other_state);
+    }
+
+    #[test]
+    fn duplicate_states() {
+        // Both transitions lead to the same logical state, built in different
+        // orders, so they have to be merged into a single state
+        let state_transition_generator = Arc::new(|state: State<i32>| {
+            if state.entities().count() == 2 {
+                return vec![(state, "Stay".to_string(), 1.)];
+            }
+            let mut first = state.clone();
+            first.set_parameter("Sheep".to_string(), "count".to_string(), 1);
+            first.set_parameter("Wolves".to_string(), "count".to_string(), 1);
+            let mut second = state;
+            second.set_parameter("Wolves".to_string(), "count".to_string(), 1);
+            second.set_parameter("Sheep".to_string(), "count".to_string(), 1);
+            vec![
+                (first, "First".to_string(), 0.5),
+                (second, "Second".to_string(), 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(State::new(), state_transition_generator);
+        simulation.next_step().unwrap();
+        let distribution = simulation.probability_distribution(1);
+        assert_eq!(distribution.len(), 1);
+        assert_eq!(distribution.values().next(), Some(&1.));
+        assert_eq!(simulation.known_states().len(), 2);
+    }
+
+    #[test]
+    fn parameter_actions() {
+        let state = State::new().with_entity(
+            "Sheep".to_string(),
+            Entity::new().with_parameter("count".to_string(), 2),
+        );
+        let set = Action::set_parameter("Wolves".to_string(), "count".to_string(), 1);
+        let adjust = Action::adjust_parameter("Sheep".to_string(), "count".to_string(), -1);
+
+        let Action::SetParameter(action) = &set else {
+            panic!("{set:?} is not a SetParameter action");
+        };
+        assert_eq!(action.entity(), "Wolves");
+        assert_eq!(action.parameter(), "count");
+        assert_eq!(action.value::<i32>(), Some(&1));
+        assert_eq!(action.value::<u32>(), None);
+        let Action::AdjustParameter(action) = &adjust else {
+            panic!("{adjust:?} is not an AdjustParameter action");
+        };
+        assert_eq!(action.value::<i32>(), Some(&-1));
+        assert_eq!(
+            format!(
+                "{:?}",
+                Action::Sequential(vec![set.clone(), adjust.clone()])
+            ),
+            r#"Sequential([SetParameter("Wolves", "count", 1), AdjustParameter("Sheep", "count", -1)])"#
+        );
+
+        let new_state = Action::Sequential(vec![set, adjust.clone()]).apply(state);
+        assert_eq!(new_state.parameter("Sheep", "count"), Some(&1));
+        assert_eq!(new_state.parameter("Wolves", "count"), Some(&1));
+        assert_eq!(
+            adjust.apply(new_state).parameter("Sheep", "count"),
+            Some(&0)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Parameter count of entity Wolves not found")]
+    fn adjust_missing_parameter() {
+        Action::adjust_parameter("Wolves".to_string(), "count".to_string(), 1).apply(State::new());
+    }
+
+    #[test]
+    fn predator_prey() {
+        let population = |name: &str, count: i32| {
+            (
+                name.to_string(),
+                Entity::from_iter([("count".to_string(), count)]),
+            )
+        };
+        let initial_state = State::from_iter([population("Sheep", 2), population("Wolves", 1)]);
+        let count = |entity: &str| {
+            Condition::parameter(entity.to_string(), "count".to_string(), |count| *count > 0)
+        };
+        let change = |entity: &str, delta: i32| {
+            Action::adjust_parameter(entity.to_string(), "count".to_string(), delta)
+        };
+        let rules = vec![
+            Rule::new(
+                "Sheep birth".to_string(),
+                count("Sheep"),
+                0.2,
+                change("Sheep", 1),
+            ),
+            Rule::new(
+                "Wolf hunt".to_string(),
+                Condition::And(Box::new(count("Sheep")), Box::new(count("Wolves"))),
+                0.3,
+                Action::Sequential(vec![change("Sheep", -1), change("Wolves", 1)]),
+            ),
+            Rule::new(
+                "Wolf death".to_string(),
+                count("Wolves"),
+                0.1,
+                change("Wolves", -1),
+            ),
+            Rule::new(
+                "Extinction".to_string(),
+                Condition::Not(Box::new(count("Wolves"))),
+                1.,
+                Action::set_parameter("Sheep".to_string(), "count".to_string(), 0),
+            ),
+        ];
+        let mut simulation: Simulation<State<i32>, String> =
+            Simulation::new(initial_state, get_state_transition_generator(rules));
+        simulation.run(5).unwrap();
+        let distribution = simulation.probability_distribution(5);
+        assert!((distribution.values().sum::<Probability>() - 1.).abs() < 1e-10);
+        assert!(distribution.keys().all(|state| {
+            state.entities().count() == 2 && *state.parameter("Sheep", "count").unwrap() >= 0
+        }));
+        let extinct = State::from_iter([population("Sheep", 0), population("Wolves", 0)]);
+        assert!(distribution[&extinct] > 0.);
+    }
+
+    #[test]
+    fn combine_entity_rules() {
+        let birth = |weight| {
+            EntityRule::new(
+                "Birth".to_string(),
+                Condition::Custom(Arc::new(|entity: Entity<i32>| {
+                    entity.parameter("count").is_some_and(|count| *count > 0)
+                })),
+                weight,
+                Action::Custom(Arc::new(|entity: Entity<i32>| {
+                    let count = entity.parameter("count").unwrap() + 1;
+                    entity.with_parameter("count".to_string(), count)
+                })),
+            )
+        };
+        let populations = HashMap::from([
+            ("Sheep".to_string(), birth(0.5)),
+            ("Wolves".to_string(), birth(0.2)),
+            ("Rabbits".to_string(), birth(0.1)),
+        ]);
+        let combined_rules = super::combine_entity_rules(populations.clone(), None);
+        assert_eq!(combined_rules.len(), 8);
+        assert_eq!(combined_rules[0].description(), "Nothing");
+        assert!((combined_rules[0].weight() - 0.36).abs() < 1e-12);
+        let weight_sum = combined_rules
+            .iter()
+            .map(|rule| rule.weight())
+            .sum::<ProbabilityWeight>();
+        assert!((weight_sum - 1.).abs() < 1e-12);
+        let all = combined_rules
+            .iter()
+            .find(|rule| rule.description() == "Rabbits: Birth & Sheep: Birth & Wolves: Birth")
+            .unwrap();
+        assert!((all.weight() - 0.01).abs() < 1e-12);
+
+        let population = |name: &str, count: i32| {
+            (
+                name.to_string(),
+                Entity::from_iter([("count".to_string(), count)]),
+            )
+        };
+        let state = State::from_iter([population("Sheep", 1), population("Wolves", 1)]);
+        assert!(!all.applies(state.clone()));
+        let sheep_and_wolves = combined_rules
+            .iter()
+            .find(|rule| rule.description() == "Sheep: Birth & Wolves: Birth")
+            .unwrap();
+        assert!(sheep_and_wolves.applies(state.clone()));
+        assert_eq!(
+            sheep_and_wolves.apply(state.clone()),
+            State::from_iter([population("Sheep", 2), population("Wolves", 2)])
+        );
+
+        // Only "Nothing" (0.36), "Sheep" (0.36), "Sheep & Wolves" (0.09) and
+        // "Wolves" (0.09) reach the cutoff, the rest is folded into "Nothing"
+        let combined_rules = super::combine_entity_rules(populations, Some(0.05));
+        assert_eq!(
+            combined_rules
+                .iter()
+                .map(|rule| rule.description().as_str())
+                .collect_vec(),
+            [
+                "Nothing",
+                "Sheep: Birth",
+                "Sheep: Birth & Wolves: Birth",
+                "Wolves: Birth"
+            ]
+        );
+        assert!((combined_rules[0].weight() - 0.46).abs() < 1e-12);
+        let mut simulation = Simulation::new(
+            state,
+            get_exclusive_state_transition_generator(combined_rules),
+        );
+        simulation.run(3).unwrap();
+        let distribution = simulation.probability_distribution(3);
+        assert!((distribution.values().sum::<Probability>() - 1.).abs() < 1e-10);
+    }
+
+    #[test]
+    fn combined_entity_rules_transition_probabilities() {
+        let population = |name: &str, count: i32| {
+            (
+                name.to_string(),
+                Entity::from_iter([("count".to_string(), count)]),
+            )
+        };
+        let change = |delta: i32| {
+            Action::Custom(Arc::new(move |entity: Entity<i32>| {
+                let count = entity.parameter("count").unwrap() + delta;
+                entity.with_parameter("count".to_string(), count)
+            }))
+        };
+        let alive = || {
+            Condition::Custom(Arc::new(|entity: Entity<i32>| {
+                entity.parameter("count").is_some_and(|count| *count > 0)
+            }))
+        };
+        let rules = HashMap::from([
+            (
+                "Sheep".to_string(),
+                EntityRule::new("Birth".to_string(), alive(), 0.5, change(1)),
+            ),
+            (
+                "Wolves".to_string(),
+                EntityRule::new("Death".to_string(), alive(), 1., change(-1)),
+            ),
+        ]);
+        let state_transition_generator =
+            get_exclusive_state_transition_generator(super::combine_entity_rules(rules, None));
+        let probabilities = |sheep, wolves| {
+            state_transition_generator(State::from_iter([
+                population("Sheep", sheep),
+                population("Wolves", wolves),
+            ]))
+            .into_iter()
+            .map(|(state, _, probability)| {
+                (
+                    (
+                        *state.parameter("Sheep", "count").unwrap(),
+                        *state.parameter("Wolves", "count").unwrap(),
+                    ),
+                    probability,
+                )
+            })
+            .sorted_by(|a, b| a.partial_cmp(b).unwrap())
+            .collect_vec()
+        };
+        assert_eq!(probabilities(1, 1), vec![((1, 0), 0.5), ((2, 0), 0.5)]);
+        assert_eq!(probabilities(1, 0), vec![((1, 0), 0.5), ((2, 0), 0.5)]);
+        assert_eq!(probabilities(0, 1), vec![((0, 0), 1.)]);
+        assert_eq!(probabilities(0, 0), vec![((0, 0), 1.)]);
+    }
+}
@@ -7,15 +7,158 @@ use std::fmt::Debug;
 use std::hash::Hash;
 
 use crate::prelude::*;
+use thiserror::Error;
 
-pub type EntityName = String;
-pub type ParameterName = String;
-pub type Entity<T> = HashMap<ParameterName, T>;
+pub use super::entities::{Entity, EntityName, ParameterAction, ParameterName};
 
 pub type RuleName = String;
 pub type RuleApplies = bool;
 pub type ProbabilityWeight = f64;
 
+/// The condition of a rule, which determines whether the rule applies to a
+/// given state.
+///
+/// Conditions can be combined with `And`, `Or` and `Not`. Arbitrary conditions
+/// can be expressed with `Custom`, which wraps a function of the state.
+pub enum Condition<T> {
+    /// Applies to every state.
+    Always,
+    /// Applies to no state.
+    Never,
+    /// Applies if the function returns `true` for the state.
+    Custom(Arc<dyn Fn(T) -> RuleApplies + Send + Sync>),
+    /// Applies if both conditions apply.
+    And(Box<Condition<T>>, Box<Condition<T>>),
+    /// Applies if at least one of the conditions applies.
+    Or(Box<Condition<T>>, Box<Condition<T>>),
+    /// Applies if the condition does not apply.
+    Not(Box<Condition<T>>),
+}
+
+impl<T> Clone for Condition<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Condition::Always => Condition::Always,
+            Condition::Never => Condition::Never,
+            Condition::Custom(function) => Condition::Custom(function.clone()),
+            Condition::And(a, b) => Condition::And(a.clone(), b.clone()),
+            Condition::Or(a, b) => Condition::Or(a.clone(), b.clone()),
+            Condition::Not(condition) => Condition::Not(condition.clone()),
+        }
+    }
+}
+
+impl<T> Debug for Condition<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Condition::Always => write!(f, "Always"),
+            Condition::Never => write!(f, "Never"),
+            Condition::Custom(_) => write!(f, "Custom"),
+            Condition::And(a, b) => f.debug_tuple("And").field(a).field(b).finish(),
+            Condition::Or(a, b) => f.debug_tuple("Or").field(a).field(b).finish(),
+            Condition::Not(condition) => f.debug_tuple("Not").field(condition).finish(),
+        }
+    }
+}
+
+impl<T: Clone> Condition<T> {
+    /// Evaluates the condition for the given state.
+    pub fn evaluate(&self, state: T) -> RuleApplies {
+        match self {
+            Condition::Always => true,
+            Condition::Never => false,
+            Condition::Custom(function) => function(state),
+            Condition::And(a, b) => a.evaluate(state.clone()) && b.evaluate(state),
+            Condition::Or(a, b) => a.evaluate(state.clone()) || b.evaluate(state),
+            Condition::Not(condition) => !condition.evaluate(state),
+        }
+    }
+}
+
+/// The action of a rule, which determines the new state if the rule applies.
+///
+/// Arbitrary actions can be expressed with `Custom`, which wraps a function of
+/// the state. Note that the function of a `Custom` action is opaque: it can't
+/// be inspected, compared or serialized, so only the structure around it (e.g.
+/// its position inside a `Sequential` action) can be described.
+///
+/// Simple mutations of a [State](../entities/struct.State.html) don't need a
+/// closure: `SetParameter` and `AdjustParameter` are created with
+/// [Action::set_parameter](#method.set_parameter) and
+/// [Action::adjust_parameter](#method.adjust_parameter), and their entity,
+/// parameter and value can be inspected.
+pub enum Action<T> {
+    /// Leaves the state unchanged.
+    Identity,
+    /// Replaces the state with the result of the function.
+    Custom(Arc<dyn Fn(T) -> T + Send + Sync>),
+    /// Applies the actions one after another, in order.
+    Sequential(Vec<Action<T>>),
+    /// Sets a parameter of an entity to the value.
+    SetParameter(ParameterAction<T>),
+    /// Adds the value to a parameter of an entity.
+    AdjustParameter(ParameterAction<T>),
+}
+
+impl<T> Clone for Action<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Action::Identity => Action::Identity,
+            Action::Custom(function) => Action::Custom(function.clone()),
+            Action::Sequential(actions) => Action::Sequential(actions.clone()),
+            Action::SetParameter(action) => Action::SetParameter(action.clone()),
+            Action::AdjustParameter(action) => Action::AdjustParameter(action.clone()),
+        }
+    }
+}
+
+impl<T> Debug for Action<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::Identity => write!(f, "Identity"),
+            Action::Custom(_) => write!(f, "Custom"),
+            Action::Sequential(actions) => f.debug_tuple("Sequential").field(actions).finish(),
+            Action::SetParameter(action) => f.debug_tuple("SetParameter").field(action).finish(),
+            Action::AdjustParameter(action) => {
+                f.debug_tuple("AdjustParameter").field(action).finish()
+            }
+        }
+    }
+}
+
+impl<T> Action<T> {
+    /// Applies the action to the given state and returns the new state.
+    pub fn apply(&self, state: T) -> T {
+        match self {
+            Action::Identity => state,
+            Action::Custom(function) => function(state),
+            Action::Sequential(actions) => actions
+                .iter()
+                .fold(state, |state, action| action.apply(state)),
+            Action::SetParameter(action) | Action::AdjustParameter(action) => action.apply(state),
+        }
+    }
+}
+
+/// The errors that can occur when validating rules.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum RuleError {
+    /// The probability weight of a rule is not in `[0, 1]`.
+    #[error("The weight {weight} of rule {rule_name} is not in [0, 1]")]
+    WeightOutOfRange {
+        weight: ProbabilityWeight,
+        rule_name: RuleName,
+    },
+    /// The relative weights of the outcomes of a
+    /// [BranchingRule](struct.BranchingRule.html) are negative or don't have
+    /// a positive sum.
+    #[error("The outcome weights of rule {rule_name} with the sum {sum} can't be normalized")]
+    OutcomeWeightsNotNormalizable {
+        sum: ProbabilityWeight,
+        rule_name: RuleName,
+    },
+}
+
 /// The key part of the rule-mechanism.
 ///
 /// A rule consists of four parts:
@@ -38,6 +181,10 @@ pub type ProbabilityWeight = f64;
 /// 2. The remaining probability is distributed among the rules according to the
 ///    weights.
 ///
+/// If rules with different [priorities](#method.with_priority) apply to the
+/// same state, only the ones with the highest priority are considered. Rules
+/// with a weight of 0 never happen and are treated as if they didn't apply.
+///
 /// So if two rules apply for the same state, if both have a weight of 1 (which
 /// normally would mean that the rule applies with 100% probability), the
 /// probability for each transition is 0.5. If both rules have a weight of 0.5
@@ -56,22 +203,22 @@ pub type ProbabilityWeight = f64;
 /// let initial_state = 0;
 /// let return_rule: Rule<i32> = Rule::new(
 ///     "Return".to_string(),
-///     Arc::new(|state| state != 0),
+///     Condition::Custom(Arc::new(|state| state != 0)),
 ///     0.1,
-///     Arc::new(|_| 0),
+///     Action::Custom(Arc::new(|_| 0)),
 /// );
 /// let forward_rule: Rule<i32> = Rule::new(
 ///     "Forward".to_string(),
-///     Arc::new(|_| true),
+///     Condition::Always,
 ///     1.,
-///     Arc::new(|state| state + 1),
+///     Action::Custom(Arc::new(|state| state + 1)),
 /// );
 ///
 /// let backward_rule: Rule<i32> = Rule::new(
 ///     "Backward".to_string(),
-///     Arc::new(|_| true),
+///     Condition::Always,
 ///     1.,
-///     Arc::new(|state| state - 1),
+///     Action::Custom(Arc::new(|state| state - 1)),
 /// );
 ///
 /// let rules = vec![forward_rule, backward_rule, return_rule];
@@ -83,23 +230,24 @@ pub type ProbabilityWeight = f64;
 /// assert_eq!(simulation.probability_distribution(0).len(), 1);
 ///
 /// // now -1 and 1 are equally likely
-/// simulation.next_step();
+/// simulation.next_step().unwrap();
 /// assert_eq!(simulation.probability_distribution(1).len(), 2);
 ///
 /// // now are -2, 0 and 2 possible
-/// simulation.next_step();
+/// simulation.next_step().unwrap();
 /// assert_eq!(simulation.probability_distribution(2).len(), 3);
 ///
 /// // and last but not least -3, -1, 0, 1 and 3. 0 is only possible because of the return rule
-/// simulation.next_step();
+/// simulation.next_step().unwrap();
 /// assert_eq!(simulation.probability_distribution(3).len(), 5);
 /// ```
 #[derive(From, Into, Clone)]
 pub struct Rule<T> {
     description: String,
-    condition: Arc<dyn Fn(T) -> RuleApplies + Send + Sync>,
+    condition: Condition<T>,
     weight: ProbabilityWeight,
-    action: Arc<dyn Fn(T) -> T + Send + Sync>,
+    action: Action<T>,
+    priority: i32,
 }
 
 impl<T: Debug> Debug for Rule<T> {
@@ -107,6 +255,7 @@ impl<T: Debug> Debug for Rule<T> {
         writeln!(f, "Rule:")?;
         writeln!(f, "Description: {}", self.description)?;
         writeln!(f, "Weight: {}", self.weight)?;
+        writeln!(f, "Priority: {}", self.priority)?;
         Ok(())
     }
 }
@@ -116,6 +265,7 @@ impl<T> Display for Rule<T> {
         writeln!(f, "Rule:")?;
         writeln!(f, "Description: {}", self.description)?;
         writeln!(f, "Weight: {}", self.weight)?;
+        writeln!(f, "Priority: {}", self.priority)?;
         Ok(())
     }
 }
@@ -126,35 +276,248 @@ impl<T> Rule<T> {
     /// # Arguments
     /// - `description`: A description of the rule. This is used for the
     ///   description of the transition.
-    /// - `condition`: A function that determines whether the rule applies to a
+    /// - `condition`: A condition that determines whether the rule applies to a
     ///   given state.
     /// - `probability_weight`: The probability weight of the rule. This is used
     ///   to calculate the probability of the transition.
-    /// - `action`: A function that determines the new state if the rule
+    /// - `action`: An action that determines the new state if the rule
     ///   applies.
     pub fn new(
         description: String,
-        condition: Arc<dyn Fn(T) -> RuleApplies + Send + Sync>,
+        condition: Condition<T>,
         probability_weight: ProbabilityWeight,
-        action: Arc<dyn Fn(T) -> T + Send + Sync>,
+        action: Action<T>,
     ) -> Self {
         Self {
             description,
             condition,
             weight: probability_weight,
             action,
+            priority: 0,
         }
     }
 
-    /// Executes the rule's condition function on the given state and returns
-    /// the result.
-    pub fn applies(&self, state: T) -> RuleApplies {
-        (self.condition)(state)
+    /// Create a new rule, checking that its probability weight is valid.
+    ///
+    /// This is the same as [new](#method.new), but returns
+    /// `RuleError::WeightOutOfRange` if the weight is not in `[0, 1]`, see
+    /// [validate](#method.validate).
+    pub fn try_new(
+        description: String,
+        condition: Condition<T>,
+        probability_weight: ProbabilityWeight,
+        action: Action<T>,
+    ) -> Result<Self, RuleError> {
+        let rule = Self::new(description, condition, probability_weight, action);
+        rule.validate()?;
+        Ok(rule)
     }
 
-    /// Executes the rule's action function on the given state and returns the result.
+    /// Set the priority of the rule.
+    ///
+    /// Rules with a higher priority take precedence: If any rule applies to a
+    /// state, only the applying rules with the highest priority are used and
+    /// all rules with a lower priority are ignored. The default priority is 0.
+    pub fn with_priority(self, priority: i32) -> Self {
+        Self { priority, ..self }
+    }
+
+    /// Evaluates the rule's condition on the given state and returns the
+    /// result.
+    pub fn applies(&self, state: T) -> RuleApplies
+    where
+        T: Clone,
+    {
+        self.condition.evaluate(state)
+    }
+
+    /// Applies the rule's action to the given state and returns the result.
     pub fn apply(&self, state: T) -> T {
-        (self.action)(state)
+        self.action.apply(state)
+    }
+
+    /// Returns the rule's probability weight.
+    pub fn weight(&self) -> ProbabilityWeight {
+        self.weight
+    }
+
+    /// Returns the rule's description.
+    pub fn description(&self) -> &String {
+        &self.description
+    }
+
+    /// Compose this rule with another rule which is applied to the result of
+    /// this rule.
+    ///
+    /// The composed rule applies if this rule applies to the state and `other`
+    /// applies to the state this rule's action results in. Its action applies
+    /// both actions in sequence, its weight is the product of both weights and
+    /// its description is `"{self} -> {other}"`. The priority of this rule is
+    /// kept.
+    pub fn compose(self, other: Rule<T>) -> Rule<T>
+    where
+        T: Clone + 'static,
+    {
+        let action = self.action.clone();
+        let other_condition = other.condition;
+        let condition = Condition::And(
+            Box::new(self.condition),
+            Box::new(Condition::Custom(Arc::new(move |state| {
+                other_condition.evaluate(action.apply(state))
+            }))),
+        );
+        Rule {
+            description: format!("{} -> {}", self.description, other.description),
+            condition,
+            weight: self.weight * other.weight,
+            action: Action::Sequential(vec![self.action, other.action]),
+            priority: self.priority,
+        }
+    }
+
+    /// Returns the rule's priority.
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Returns a reference to the rule's condition.
+    pub fn condition(&self) -> &Condition<T> {
+        &self.condition
+    }
+
+    /// Returns a reference to the rule's action.
+    pub fn action(&self) -> &Action<T> {
+        &self.action
+    }
+
+    /// Checks that the rule's probability weight is in `[0, 1]`.
+    ///
+    /// A rule with a weight of 0 is valid, but never happens.
+    pub fn validate(&self) -> Result<(), RuleError> {
+        if (0.0..=1.0).contains(&self.weight) {
+            Ok(())
+        } else {
+            Err(RuleError::WeightOutOfRange {
+                weight: self.weight,
+                rule_name: self.description.clone(),
+            })
+        }
+    }
+}
+
+/// The action of a [BranchingRule](struct.BranchingRule.html), which maps a
+/// state to its possible new states with relative weights.
+pub type BranchingAction<T> = Arc<dyn Fn(T) -> Vec<(T, ProbabilityWeight)> + Send + Sync>;
+
+/// A rule whose outcome is random.
+///
+/// A branching rule works like a [Rule](struct.Rule.html), except that its
+/// action returns multiple new states with relative weights, e.g. minor or
+/// major damage if a machine breaks. The weights of the outcomes are
+/// normalized, so if the rule happens with the probability `p`, the outcome
+/// with the relative weight `w` happens with the probability `p * w / Σw`.
+/// For the probability of no rule happening it counts like a single rule with
+/// its weight.
+///
+/// Plain and branching rules can be mixed in
+/// [get_state_transition_generator](fn.get_state_transition_generator.html)
+/// as [AnyRule](enum.AnyRule.html)s.
+///
+/// # Example
+/// ```rust
+/// use entromatica::prelude::*;
+/// use entromatica::models::rules::*;
+/// use std::sync::Arc;
+///
+/// let break_rule: BranchingRule<i32> = BranchingRule::new(
+///     "Break".to_string(),
+///     Condition::Custom(Arc::new(|damage| damage == 0)),
+///     0.5,
+///     Arc::new(|damage| vec![(damage + 1, 3.), (damage + 10, 1.)]),
+/// );
+/// let state_transition_generator = get_state_transition_generator(vec![break_rule]);
+/// let mut simulation = Simulation::new(0, state_transition_generator);
+/// simulation.next_step().unwrap();
+/// assert_eq!(simulation.state_probability(0, 1), 0.5);
+/// assert_eq!(simulation.state_probability(1, 1), 0.375);
+/// assert_eq!(simulation.state_probability(10, 1), 0.125);
+/// ```
+#[derive(Clone)]
+pub struct BranchingRule<T> {
+    description: String,
+    condition: Condition<T>,
+    weight: ProbabilityWeight,
+    action: BranchingAction<T>,
+    priority: i32,
+}
+
+impl<T> Debug for BranchingRule<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "BranchingRule:")?;
+        writeln!(f, "Description: {}", self.description)?;
+        writeln!(f, "Weight: {}", self.weight)?;
+        writeln!(f, "Priority: {}", self.priority)?;
+        Ok(())
+    }
+}
+
+impl<T> BranchingRule<T> {
+    /// Create a new branching rule.
+    ///
+    /// The arguments are the same as for [Rule::new](struct.Rule.html#method.new),
+    /// except that the action returns the possible new states with relative
+    /// weights.
+    pub fn new(
+        description: String,
+        condition: Condition<T>,
+        probability_weight: ProbabilityWeight,
+        action: BranchingAction<T>,
+    ) -> Self {
+        Self {
+            description,
+            condition,
+            weight: probability_weight,
+            action,
+            priority: 0,
+        }
+    }
+
+    /// Set the priority of the rule, see
+    /// [Rule::with_priority](struct.Rule.html#method.with_priority).
+    pub fn with_priority(self, priority: i32) -> Self {
+        Self { priority, ..self }
+    }
+
+    /// Evaluates the rule's condition on the given state and returns the
+    /// result.
+    pub fn applies(&self, state: T) -> RuleApplies
+    where
+        T: Clone,
+    {
+        self.condition.evaluate(state)
+    }
+
+    /// Get the possible new states of the given state with their normalized
+    /// probabilities, given that the rule happens.
+    ///
+    /// Returns `RuleError::OutcomeWeightsNotNormalizable` if an outcome has a
+    /// negative weight or the weights don't have a positive sum.
+    pub fn outcomes(&self, state: T) -> Result<Vec<(T, ProbabilityWeight)>, RuleError> {
+        let outcomes = (self.action)(state);
+        let sum = outcomes
+            .iter()
+            .map(|(_, weight)| weight)
+            .sum::<ProbabilityWeight>();
+        if !(sum > 0. && sum.is_finite()) || outcomes.iter().any(|(_, weight)| *weight < 0.) {
+            return Err(RuleError::OutcomeWeightsNotNormalizable {
+                sum,
+                rule_name: self.description.clone(),
+            });
+        }
+        Ok(outcomes
+            .into_iter()
+            .map(|(new_state, weight)| (new_state, weight / sum))
+            .collect())
     }
 
     /// Returns the rule's probability weight.
@@ -167,14 +530,308 @@ impl<T> Rule<T> {
         &self.description
     }
 
-    /// Returns a reference to the rule's condition function.
-    pub fn condition(&self) -> &(dyn Fn(T) -> RuleApplies + Send + Sync) {
-        &*self.condition
+    /// Returns the rule's priority.
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Checks that the rule's probability weight is in `[0, 1]`.
+    ///
+    /// A rule with a weight of 0 is valid, but never happens.
+    pub fn validate(&self) -> Result<(), RuleError> {
+        if (0.0..=1.0).contains(&self.weight) {
+            Ok(())
+        } else {
+            Err(RuleError::WeightOutOfRange {
+                weight: self.weight,
+                rule_name: self.description.clone(),
+            })
+        }
+    }
+}
+
+/// Either a plain [Rule](struct.Rule.html) or a
+/// [BranchingRule](struct.BranchingRule.html), to mix both in a state
+/// transition generator.
+#[derive(Debug, Clone)]
+pub enum AnyRule<T> {
+    Plain(Rule<T>),
+    Branching(BranchingRule<T>),
+}
+
+impl<T> From<Rule<T>> for AnyRule<T> {
+    fn from(rule: Rule<T>) -> Self {
+        AnyRule::Plain(rule)
+    }
+}
+
+impl<T> From<BranchingRule<T>> for AnyRule<T> {
+    fn from(rule: BranchingRule<T>) -> Self {
+        AnyRule::Branching(rule)
+    }
+}
+
+impl<T> AnyRule<T> {
+    /// Evaluates the rule's condition on the given state and returns the
+    /// result.
+    pub fn applies(&self, state: T) -> RuleApplies
+    where
+        T: Clone,
+    {
+        match self {
+            AnyRule::Plain(rule) => rule.applies(state),
+            AnyRule::Branching(rule) => rule.applies(state),
+        }
     }
 
-    /// Returns a reference to the rule's action function.
-    pub fn action(&self) -> &(dyn Fn(T) -> T + Send + Sync) {
-        &*self.action
+    /// Returns the rule's probability weight.
+    pub fn weight(&self) -> ProbabilityWeight {
+        match self {
+            AnyRule::Plain(rule) => rule.weight(),
+            AnyRule::Branching(rule) => rule.weight(),
+        }
+    }
+
+    /// Returns the rule's priority.
+    pub fn priority(&self) -> i32 {
+        match self {
+            AnyRule::Plain(rule) => rule.priority(),
+            AnyRule::Branching(rule) => rule.priority(),
+        }
+    }
+
+    /// Checks that the rule's probability weight is in `[0, 1]`.
+    pub fn validate(&self) -> Result<(), RuleError> {
+        match self {
+            AnyRule::Plain(rule) => rule.validate(),
+            AnyRule::Branching(rule) => rule.validate(),
+        }
+    }
+}
+
+/// Validates all rules and returns the error of the first invalid one.
+///
+/// See [Rule::validate](struct.Rule.html#method.validate).
+pub fn validate_all<T>(rules: &[Rule<T>]) -> Result<(), RuleError> {
+    rules.iter().try_for_each(Rule::validate)
+}
+
+/// Combine rules into one rule for every subset of them.
+///
+/// For every subset of the rules, including the empty one, there is a combined
+/// rule named after its rules joined with `" & "`, or `"Nothing"` for the
+/// empty subset. Its weight is the probability that exactly the rules of the
+/// subset happen, i.e. the product of the weights of the included rules and of
+/// `1 - weight` of the excluded ones. Its condition requires all conditions of
+/// the included rules and its action applies their actions sequentially in
+/// the order of their names. The combined rules have the default priority.
+///
+/// A rule with a weight of 1 always happens if it applies, so the subsets
+/// excluding it require instead that its condition does not apply and its
+/// factor is 1. Subsets with a weight of 0 are left out.
+///
+/// The combined rules are mutually exclusive, so they have to be used with
+/// [get_exclusive_state_transition_generator](fn.get_exclusive_state_transition_generator.html),
+/// whose transition probabilities are the weights of the combined rules.
+///
+/// # Example
+/// ```rust
+/// use entromatica::prelude::*;
+/// use entromatica::models::rules::*;
+/// use std::sync::Arc;
+/// use hashbrown::HashMap;
+///
+/// let rule = |name: &str, weight| {
+///     let rule: Rule<i32> = Rule::new(
+///         name.to_string(),
+///         Condition::Always,
+///         weight,
+///         Action::Custom(Arc::new(|state| state + 1)),
+///     );
+///     (name.to_string(), rule)
+/// };
+/// let rules = HashMap::from([rule("a", 0.5), rule("b", 0.5)]);
+/// let combined_rules = combine_rules_weighted(rules).into_values().collect();
+/// let state_transition_generator = get_exclusive_state_transition_generator(combined_rules);
+/// let mut simulation = Simulation::new(0, state_transition_generator);
+/// simulation.next_step().unwrap();
+/// assert_eq!(simulation.probability_distribution(1), HashMap::from([(0, 0.25), (1, 0.5), (2, 0.25)]));
+/// ```
+pub fn combine_rules_weighted<T>(rules: HashMap<RuleName, Rule<T>>) -> HashMap<RuleName, Rule<T>>
+where
+    T: Clone,
+{
+    let rules = rules
+        .into_iter()
+        .sorted_by(|(a, _), (b, _)| a.cmp(b))
+        .collect_vec();
+    (0..rules.len())
+        .powerset()
+        .filter_map(|subset| {
+            let weight = rules
+                .iter()
+                .enumerate()
+                .map(|(index, (_, rule))| {
+                    if subset.contains(&index) {
+                        rule.weight
+                    } else if rule.weight == 1. {
+                        1.
+                    } else {
+                        1. - rule.weight
+                    }
+                })
+                .product::<ProbabilityWeight>();
+            if weight <= 0. {
+                return None;
+            }
+            let name = if subset.is_empty() {
+                "Nothing".to_string()
+            } else {
+                subset.iter().map(|index| &rules[*index].0).join(" & ")
+            };
+            // Certain rules which are excluded must not apply
+            let certain_conditions = rules
+                .iter()
+                .enumerate()
+                .filter(|(index, (_, rule))| !subset.contains(index) && rule.weight == 1.)
+                .map(|(_, (_, rule))| Condition::Not(Box::new(rule.condition.clone())));
+            let condition = subset
+                .iter()
+                .map(|index| rules[*index].1.condition.clone())
+                .chain(certain_conditions)
+                .reduce(|a, b| Condition::And(Box::new(a), Box::new(b)))
+                .unwrap_or(Condition::Always);
+            let action = match subset.len() {
+                0 => Action::Identity,
+                _ => Action::Sequential(
+                    subset
+                        .iter()
+                        .map(|index| rules[*index].1.action.clone())
+                        .collect(),
+                ),
+            };
+            Some((name.clone(), Rule::new(name, condition, weight, action)))
+        })
+        .collect()
+}
+
+/// A function merging the descriptions of multiple rules, see
+/// [DescriptionMerge](enum.DescriptionMerge.html).
+pub type DescriptionMergeFunction = Arc<dyn Fn(Vec<&str>) -> String + Send + Sync>;
+
+/// How the descriptions of multiple rules leading to the same state are
+/// merged into the description of the transition.
+///
+/// The descriptions are passed in sorted order, followed by `"Nothing"` if
+/// the state also stays the same because no rule happens, so the transitions
+/// don't depend on the order of the rules.
+#[derive(Clone)]
+pub enum DescriptionMerge {
+    /// Join all descriptions with the separator.
+    Join(String),
+    /// Use only the first description.
+    First,
+    /// Merge the descriptions with the function.
+    Custom(DescriptionMergeFunction),
+}
+
+impl Debug for DescriptionMerge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DescriptionMerge::Join(separator) => f.debug_tuple("Join").field(separator).finish(),
+            DescriptionMerge::First => write!(f, "First"),
+            DescriptionMerge::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+impl Default for DescriptionMerge {
+    /// Join the descriptions with `" | "`.
+    fn default() -> Self {
+        DescriptionMerge::Join(" | ".to_string())
+    }
+}
+
+impl DescriptionMerge {
+    /// Merges the given descriptions.
+    pub fn merge(&self, descriptions: Vec<&str>) -> String {
+        match self {
+            DescriptionMerge::Join(separator) => descriptions.join(separator),
+            DescriptionMerge::First => descriptions
+                .first()
+                .copied()
+                .unwrap_or_default()
+                .to_string(),
+            DescriptionMerge::Custom(function) => function(descriptions),
+        }
+    }
+}
+
+/// The rules whose application leads from a state to a new state, which is
+/// the transition type of
+/// [get_state_transition_generator_typed](fn.get_state_transition_generator_typed.html).
+///
+/// It consists of the sorted descriptions of the applied rules and whether the
+/// new state is also reached because no rule happens.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AppliedRules {
+    rules: Vec<RuleName>,
+    nothing: bool,
+}
+
+impl AppliedRules {
+    /// Create a new set of applied rules. The rule names are sorted.
+    pub fn new(rules: Vec<RuleName>, nothing: bool) -> Self {
+        let mut rules = rules;
+        rules.sort_unstable();
+        Self { rules, nothing }
+    }
+
+    /// Returns the sorted descriptions of the applied rules.
+    pub fn rules(&self) -> &[RuleName] {
+        &self.rules
+    }
+
+    /// Returns whether the new state is also reached because no rule happens.
+    pub fn nothing(&self) -> bool {
+        self.nothing
+    }
+
+    /// Merge the descriptions of the applied rules, followed by `"Nothing"` if
+    /// no rule happening leads to the new state as well, into a single
+    /// description.
+    pub fn describe(&self, description_merge: &DescriptionMerge) -> String {
+        let mut descriptions = self.rules.iter().map(String::as_str).collect_vec();
+        if self.nothing {
+            descriptions.push("Nothing");
+        }
+        description_merge.merge(descriptions)
+    }
+}
+
+impl Display for AppliedRules {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.describe(&DescriptionMerge::default()))
+    }
+}
+
+impl<S> Simulation<S, AppliedRules>
+where
+    S: Hash + Clone + Send + Sync + PartialEq + Eq + Debug,
+{
+    /// Remove the cached outgoing transitions of all states to which the rule
+    /// with the given name applied.
+    ///
+    /// This is enough to pick up a changed action or weight of the rule. If
+    /// its condition changed, the rule may apply to states it didn't apply to
+    /// before, so the whole cache has to be cleared with
+    /// [clear_cache](../../simulation/struct.Simulation.html#method.clear_cache).
+    pub fn clear_cache_for_rule(&mut self, rule_name: &RuleName) {
+        self.clear_cache_where(|_, outgoing_transitions| {
+            outgoing_transitions
+                .iter()
+                .any(|(_, applied_rules, _)| applied_rules.rules().contains(rule_name))
+        });
     }
 }
 
@@ -182,94 +839,798 @@ impl<T> Rule<T> {
 ///
 /// # Arguments
 /// - `rules`: A list of rules that are used to create the state transition
-///  generator.
+///   generator.
 ///
 /// # Returns
-/// A state transition generator that can be used to create a simulation.
-pub fn get_state_transition_generator<T>(rules: Vec<Rule<T>>) -> StateTransitionGenerator<T, String>
+/// A state transition generator that can be used to create a simulation. The
+/// descriptions of rules leading to the same state are joined with `" | "`,
+/// see [get_state_transition_generator_with_merge](fn.get_state_transition_generator_with_merge.html).
+///
+/// The rules can be plain [rules](struct.Rule.html), [branching
+/// rules](struct.BranchingRule.html) or a mix of both as
+/// [AnyRule](enum.AnyRule.html)s.
+///
+/// # Panics
+/// This function panics if one of the rules is invalid, see
+/// [validate_all](fn.validate_all.html).
+pub fn get_state_transition_generator<T, R>(rules: Vec<R>) -> StateTransitionGenerator<T, String>
 where
     T: Debug + Clone + Send + Sync + 'static + PartialEq + Eq + Hash,
+    R: Into<AnyRule<T>>,
 {
-    Arc::new(move |state: T| -> OutgoingTransitions<T, String> {
-        let new_states_by_weight = rules
-            .iter()
-            .filter(|rule| rule.applies(state.clone()))
-            .map(|rule| {
-                let new_state: T = rule.apply(state.clone());
-                let weight = rule.weight();
-                let description = rule.description().clone();
-                (hash(&new_state), (new_state, weight, description))
+    get_state_transition_generator_with_merge(rules, DescriptionMerge::default())
+}
+
+/// A function that creates a state transition generator from a set of rules,
+/// checking that all rules are valid.
+///
+/// This is the same as
+/// [get_state_transition_generator](fn.get_state_transition_generator.html),
+/// but returns the error of the first invalid rule instead of panicking, see
+/// [validate_all](fn.validate_all.html).
+pub fn try_get_state_transition_generator<T, R>(
+    rules: Vec<R>,
+) -> Result<StateTransitionGenerator<T, String>, RuleError>
+where
+    T: Debug + Clone + Send + Sync + 'static + PartialEq + Eq + Hash,
+    R: Into<AnyRule<T>>,
+{
+    try_get_state_transition_generator_with_merge(rules, DescriptionMerge::default())
+}
+
+/// A function that creates a state transition generator from a set of rules,
+/// merging the descriptions of rules leading to the same state with
+/// `description_merge`.
+///
+/// See [get_state_transition_generator](fn.get_state_transition_generator.html).
+///
+/// # Panics
+/// This function panics if one of the rules is invalid, see
+/// [validate_all](fn.validate_all.html).
+pub fn get_state_transition_generator_with_merge<T, R>(
+    rules: Vec<R>,
+    description_merge: DescriptionMerge,
+) -> StateTransitionGenerator<T, String>
+where
+    T: Debug + Clone + Send + Sync + 'static + PartialEq + Eq + Hash,
+    R: Into<AnyRule<T>>,
+{
+    try_get_state_transition_generator_with_merge(rules, description_merge)
+        .unwrap_or_else(|error| panic!("Invalid rule: {error}"))
+}
+
+/// A function that creates a state transition generator from a set of rules,
+/// merging the descriptions of rules leading to the same state with
+/// `description_merge` and checking that all rules are valid.
+///
+/// This is the same as
+/// [get_state_transition_generator_with_merge](fn.get_state_transition_generator_with_merge.html),
+/// but returns the error of the first invalid rule instead of panicking.
+pub fn try_get_state_transition_generator_with_merge<T, R>(
+    rules: Vec<R>,
+    description_merge: DescriptionMerge,
+) -> Result<StateTransitionGenerator<T, String>, RuleError>
+where
+    T: Debug + Clone + Send + Sync + 'static + PartialEq + Eq + Hash,
+    R: Into<AnyRule<T>>,
+{
+    let state_transition_generator = try_get_state_transition_generator_typed(rules)?;
+    Ok(Arc::new(move |state: T| -> OutgoingTransitions<T, String> {
+        state_transition_generator(state)
+            .into_iter()
+            .map(|(new_state, applied_rules, probability)| {
+                (
+                    new_state,
+                    applied_rules.describe(&description_merge),
+                    probability,
+                )
             })
-            .fold(
-                HashMap::new(),
-                |acc: HashMap<u64, (T, ProbabilityWeight, String)>,
-                 (_, (state, weight, description))| {
-                    let mut new_acc = acc;
-                    if let Some(e) = new_acc.get_mut(&hash(&state)) {
-                        e.1 += weight;
-                        e.2 = format!("{} | {}", e.2, description);
-                    } else {
-                        new_acc.insert(hash(&state), (state.clone(), weight, description));
+            .collect()
+    }) as StateTransitionGenerator<T, String>)
+}
+
+/// A function that creates a state transition generator from a set of rules,
+/// whose transitions are the [applied rules](struct.AppliedRules.html).
+///
+/// Unlike the descriptions of
+/// [get_state_transition_generator](fn.get_state_transition_generator.html),
+/// the transitions can be analyzed without parsing, e.g. to find out which
+/// combinations of rules ever happened with
+/// [known_transitions](../../simulation/struct.Simulation.html#method.known_transitions).
+///
+/// The outcome weights of a [BranchingRule](struct.BranchingRule.html)
+/// depend on the state, so they can only be checked when the generator is
+/// called. If they can't be normalized, see
+/// [BranchingRule::outcomes](struct.BranchingRule.html#method.outcomes), the
+/// generator doesn't panic, but returns a transition of the rule with a NaN
+/// probability, for which
+/// [next_step](../../simulation/struct.Simulation.html#method.next_step)
+/// returns an error.
+///
+/// # Panics
+/// This function panics if one of the rules is invalid, see
+/// [validate_all](fn.validate_all.html).
+pub fn get_state_transition_generator_typed<T, R>(
+    rules: Vec<R>,
+) -> StateTransitionGenerator<T, AppliedRules>
+where
+    T: Debug + Clone + Send + Sync + 'static + PartialEq + Eq + Hash,
+    R: Into<AnyRule<T>>,
+{
+    try_get_state_transition_generator_typed(rules)
+        .unwrap_or_else(|error| panic!("Invalid rule: {error}"))
+}
+
+/// A function that creates a state transition generator from a set of rules,
+/// whose transitions are the [applied rules](struct.AppliedRules.html),
+/// checking that all rules are valid.
+///
+/// This is the same as
+/// [get_state_transition_generator_typed](fn.get_state_transition_generator_typed.html),
+/// but returns the error of the first invalid rule instead of panicking.
+pub fn try_get_state_transition_generator_typed<T, R>(
+    rules: Vec<R>,
+) -> Result<StateTransitionGenerator<T, AppliedRules>, RuleError>
+where
+    T: Debug + Clone + Send + Sync + 'static + PartialEq + Eq + Hash,
+    R: Into<AnyRule<T>>,
+{
+    let mut rules = rules.into_iter().map(Into::into).collect_vec();
+    rules.iter().try_for_each(AnyRule::validate)?;
+    rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority()));
+    Ok(
+        Arc::new(move |state: T| -> OutgoingTransitions<T, AppliedRules> {
+            // Only the applying rules with the highest priority are used. As the
+            // rules are sorted by descending priority, these come first.
+            let applying_rules = rules
+                .iter()
+                .filter(|rule| rule.weight() > 0. && rule.applies(state.clone()))
+                .collect_vec();
+            let highest_priority = applying_rules.first().map(|rule| rule.priority());
+            let mut applied_rules = Vec::new();
+            let mut applied_branching_rules = Vec::new();
+            for rule in applying_rules
+                .into_iter()
+                .take_while(|rule| Some(rule.priority()) == highest_priority)
+            {
+                match rule {
+                    AnyRule::Plain(rule) => applied_rules.push((
+                        rule.apply(state.clone()),
+                        rule.weight(),
+                        rule.description(),
+                    )),
+                    AnyRule::Branching(rule) => {
+                        // The generator can't return an error, so outcomes which
+                        // can't be normalized get a NaN probability instead.
+                        let outcomes = rule
+                            .outcomes(state.clone())
+                            .unwrap_or_else(|_| vec![(state.clone(), ProbabilityWeight::NAN)]);
+                        applied_branching_rules.push((outcomes, rule.weight(), rule.description()))
                     }
-                    new_acc
-                },
-            );
-        let base_state_hash = hash(&state);
-        let nothing_probability = new_states_by_weight
-            .iter()
-            .map(|(_, (_, weight, _))| 1. - *weight)
-            .product::<ProbabilityWeight>();
-        let weight_sum = new_states_by_weight
+                }
+            }
+            outgoing_transitions(state, applied_rules, applied_branching_rules)
+        }) as StateTransitionGenerator<T, AppliedRules>,
+    )
+}
+
+/// A function that creates a state transition generator from a set of mutually
+/// exclusive rules.
+///
+/// Unlike [get_state_transition_generator](fn.get_state_transition_generator.html),
+/// the rules are not treated as independent events and there is no
+/// probability of no rule happening. Instead, the weights of the applying
+/// rules with the highest priority are the probabilities of their
+/// transitions. If they don't sum up to 1, e.g. because some
+/// [combined rules](fn.combine_rules_weighted.html) don't apply, they are
+/// normalized. Rules with a weight of 0 are ignored. If no rule applies, the
+/// state stays the same with the transition `"Nothing"`. The descriptions of rules leading to the same
+/// state are joined with `" | "`.
+///
+/// # Panics
+/// This function panics if one of the rules is invalid, see
+/// [validate_all](fn.validate_all.html).
+pub fn get_exclusive_state_transition_generator<T>(
+    rules: Vec<Rule<T>>,
+) -> StateTransitionGenerator<T, String>
+where
+    T: Debug + Clone + Send + Sync + 'static + PartialEq + Eq + Hash,
+{
+    if let Err(error) = validate_all(&rules) {
+        panic!("Invalid rule: {error}");
+    }
+    let mut rules = rules;
+    rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority()));
+    Arc::new(move |state: T| -> OutgoingTransitions<T, String> {
+        let applying_rules = rules
             .iter()
-            .map(|(_, (_, weight, _))| weight)
-            .sum::<ProbabilityWeight>()
-            + nothing_probability;
-        let mut new_states = new_states_by_weight
+            .filter(|rule| rule.weight() > 0. && rule.applies(state.clone()))
+            .collect_vec();
+        let highest_priority = applying_rules.first().map(|rule| rule.priority());
+        let applied_rules = applying_rules
             .into_iter()
-            .map(|(state_hash, (state, weight, description))| {
-                (state_hash, (state, weight / weight_sum, description))
-            })
-            .collect::<HashMap<u64, (T, f64, String)>>();
-        if nothing_probability > 0. {
-            new_states
-                .entry(base_state_hash)
-                .and_modify(|(_, probability, description)| {
-                    *probability += nothing_probability / weight_sum;
-                    description.push_str(" | Nothing");
-                })
-                .or_insert((
-                    state,
-                    nothing_probability / weight_sum,
-                    "Nothing".to_string(),
-                ));
+            .take_while(|rule| Some(rule.priority()) == highest_priority)
+            .collect_vec();
+        if applied_rules.is_empty() {
+            return vec![(state, "Nothing".to_string(), 1.)];
+        }
+        let weight_sum = applied_rules
+            .iter()
+            .map(|rule| rule.weight())
+            .sum::<ProbabilityWeight>();
+        // States are merged by equality instead of by their hash, so that a
+        // hash collision can't silently merge two distinct states.
+        let mut new_states: Vec<(T, ProbabilityWeight, Vec<RuleName>)> = Vec::new();
+        for rule in applied_rules {
+            let new_state = rule.apply(state.clone());
+            if let Some(e) = new_states.iter_mut().find(|e| e.0 == new_state) {
+                e.1 += rule.weight();
+                e.2.push(rule.description().clone());
+            } else {
+                new_states.push((new_state, rule.weight(), vec![rule.description().clone()]));
+            }
         }
         new_states
             .into_iter()
-            .map(|(_, (state, probability, description))| (state, description, probability))
-            .collect_vec()
+            .map(|(new_state, weight, rules)| {
+                (
+                    new_state,
+                    AppliedRules::new(rules, false).to_string(),
+                    weight / weight_sum,
+                )
+            })
+            .collect()
     }) as StateTransitionGenerator<T, String>
 }
 
+/// A condition of a [TimedRule](struct.TimedRule.html), which depends on the
+/// state and the time.
+pub type TimedCondition<T> = Arc<dyn Fn(T, Time) -> RuleApplies + Send + Sync>;
+
+/// A probability weight of a [TimedRule](struct.TimedRule.html), which depends
+/// on the state and the time.
+pub type TimedWeight<T> = Arc<dyn Fn(T, Time) -> ProbabilityWeight + Send + Sync>;
+
+/// A rule whose condition and weight depend on the time.
+///
+/// Timed rules describe time-inhomogeneous markov chains, e.g. with seasonal
+/// effects. They work like a [Rule](struct.Rule.html), except that the
+/// condition and the weight are functions of the state and the current time.
+/// The weight must be in `[0, 1]`, a weight of 0 means that the rule doesn't
+/// apply at that time.
+///
+/// The state transition generator of timed rules is created with
+/// [get_timed_state_transition_generator](fn.get_timed_state_transition_generator.html).
+///
+/// # Example
+/// ```rust
+/// use entromatica::prelude::*;
+/// use entromatica::models::rules::*;
+/// use std::sync::Arc;
+/// use hashbrown::HashMap;
+///
+/// // A counter which only counts up in even time steps
+/// let count_rule: TimedRule<i32> = TimedRule::new(
+///     "Count".to_string(),
+///     Arc::new(|_, time| time % 2 == 0),
+///     Arc::new(|_, _| 1.),
+///     Action::Custom(Arc::new(|state| state + 1)),
+/// );
+/// let state_transition_generator = get_timed_state_transition_generator(vec![count_rule]);
+/// let mut simulation = Simulation::new((0, 0), state_transition_generator);
+/// simulation.run(3).unwrap();
+/// assert_eq!(simulation.probability_distribution(3), HashMap::from([((2, 3), 1.)]));
+/// ```
+#[derive(Clone)]
+pub struct TimedRule<T> {
+    description: String,
+    condition: TimedCondition<T>,
+    weight: TimedWeight<T>,
+    action: Action<T>,
+    priority: i32,
+}
+
+impl<T> Debug for TimedRule<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "TimedRule:")?;
+        writeln!(f, "Description: {}", self.description)?;
+        writeln!(f, "Priority: {}", self.priority)?;
+        Ok(())
+    }
+}
+
+impl<T> TimedRule<T> {
+    /// Create a new timed rule.
+    ///
+    /// The arguments are the same as for [Rule::new](struct.Rule.html#method.new),
+    /// except that the condition and the probability weight are functions of
+    /// the state and the time.
+    pub fn new(
+        description: String,
+        condition: TimedCondition<T>,
+        probability_weight: TimedWeight<T>,
+        action: Action<T>,
+    ) -> Self {
+        Self {
+            description,
+            condition,
+            weight: probability_weight,
+            action,
+            priority: 0,
+        }
+    }
+
+    /// Set the priority of the rule, see
+    /// [Rule::with_priority](struct.Rule.html#method.with_priority).
+    pub fn with_priority(self, priority: i32) -> Self {
+        Self { priority, ..self }
+    }
+
+    /// Evaluates the rule's condition on the given state and time and returns
+    /// the result.
+    pub fn applies(&self, state: T, time: Time) -> RuleApplies {
+        (self.condition)(state, time)
+    }
+
+    /// Applies the rule's action to the given state and returns the result.
+    pub fn apply(&self, state: T) -> T {
+        self.action.apply(state)
+    }
+
+    /// Returns the rule's probability weight for the given state and time.
+    pub fn weight(&self, state: T, time: Time) -> ProbabilityWeight {
+        (self.weight)(state, time)
+    }
+
+    /// Returns the rule's description.
+    pub fn description(&self) -> &String {
+        &self.description
+    }
+
+    /// Returns the rule's priority.
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+}
+
+impl<T> From<Rule<T>> for TimedRule<T>
+where
+    T: Clone + 'static,
+{
+    /// Converts a rule into a timed rule which ignores the time.
+    fn from(rule: Rule<T>) -> Self {
+        let Rule {
+            description,
+            condition,
+            weight,
+            action,
+            priority,
+        } = rule;
+        Self {
+            description,
+            condition: Arc::new(move |state, _| condition.evaluate(state)),
+            weight: Arc::new(move |_, _| weight),
+            action,
+            priority,
+        }
+    }
+}
+
+/// A function that creates a state transition generator from a set of timed
+/// rules.
+///
+/// The states of the markov chain are pairs of a state and the current time,
+/// which increases by one in each transition, so the simulation has to be
+/// started with a state like `(initial_state, 0)`. Otherwise the transitions
+/// are determined like for
+/// [get_state_transition_generator](fn.get_state_transition_generator.html)
+/// with the weights and conditions at the current time.
+///
+/// Note that as the time is part of the state, the markov chain has infinitely
+/// many states, so e.g. a full traversal never returns.
+///
+/// # Panics
+/// The generator panics if an applying rule has a weight which is not in
+/// `[0, 1]`.
+pub fn get_timed_state_transition_generator<T>(
+    rules: Vec<TimedRule<T>>,
+) -> StateTransitionGenerator<(T, Time), String>
+where
+    T: Debug + Clone + Send + Sync + 'static + PartialEq + Eq + Hash,
+{
+    let mut rules = rules;
+    rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority()));
+    Arc::new(
+        move |(state, time): (T, Time)| -> OutgoingTransitions<(T, Time), String> {
+            let applying_rules = rules
+                .iter()
+                .filter(|rule| rule.applies(state.clone(), time))
+                .map(|rule| (rule, rule.weight(state.clone(), time)))
+                .filter(|(_, weight)| *weight != 0.)
+                .collect_vec();
+            let highest_priority = applying_rules.first().map(|(rule, _)| rule.priority());
+            let applied_rules = applying_rules
+                .into_iter()
+                .take_while(|(rule, _)| Some(rule.priority()) == highest_priority)
+                .map(|(rule, weight)| {
+                    if !(0.0..=1.0).contains(&weight) {
+                        let error = RuleError::WeightOutOfRange {
+                            weight,
+                            rule_name: rule.description().clone(),
+                        };
+                        panic!("Invalid rule at time {time}: {error}");
+                    }
+                    (rule.apply(state.clone()), weight, rule.description())
+                })
+                .collect_vec();
+            outgoing_transitions(state, applied_rules, Vec::new())
+                .into_iter()
+                .map(|(new_state, applied_rules, probability)| {
+                    (
+                        (new_state, time + 1),
+                        applied_rules.to_string(),
+                        probability,
+                    )
+                })
+                .collect_vec()
+        },
+    ) as StateTransitionGenerator<(T, Time), String>
+}
+
+/// The normalized outcomes, the total weight and the description of an applied
+/// branching rule.
+type AppliedBranchingRule<'a, T> = (Vec<(T, ProbabilityWeight)>, ProbabilityWeight, &'a String);
+
+/// Get the outgoing transitions of `state` given the new states, weights and
+/// descriptions of the applied rules and the normalized outcomes, total
+/// weights and descriptions of the applied branching rules, using the
+/// mechanism described in [Rule](struct.Rule.html).
+///
+/// A branching rule counts like a single rule with its total weight for the
+/// probability of no rule happening, its outcomes are only merged with the
+/// new states of other rules afterwards.
+fn outgoing_transitions<T>(
+    state: T,
+    applied_rules: Vec<(T, ProbabilityWeight, &String)>,
+    applied_branching_rules: Vec<AppliedBranchingRule<T>>,
+) -> OutgoingTransitions<T, AppliedRules>
+where
+    T: PartialEq,
+{
+    // States are merged by equality instead of by their hash, so that a
+    // hash collision can't silently merge two distinct states.
+    let merge =
+        |mut acc: Vec<(T, ProbabilityWeight, Vec<RuleName>)>,
+         (new_state, weight, description): (T, ProbabilityWeight, &String)| {
+            if let Some(e) = acc.iter_mut().find(|e| e.0 == new_state) {
+                e.1 += weight;
+                if !e.2.contains(description) {
+                    e.2.push(description.clone());
+                }
+            } else {
+                acc.push((new_state, weight, vec![description.clone()]));
+            }
+            acc
+        };
+    let new_states = applied_rules.into_iter().fold(Vec::new(), merge);
+    let nothing_probability = new_states
+        .iter()
+        .map(|(_, weight, _)| 1. - *weight)
+        .chain(
+            applied_branching_rules
+                .iter()
+                .map(|(_, weight, _)| 1. - *weight),
+        )
+        .product::<ProbabilityWeight>();
+    let weight_sum = new_states
+        .iter()
+        .map(|(_, weight, _)| weight)
+        .chain(applied_branching_rules.iter().map(|(_, weight, _)| weight))
+        .sum::<ProbabilityWeight>()
+        + nothing_probability;
+    let nothing_probability = if nothing_probability > 0. {
+        nothing_probability / weight_sum
+    } else {
+        0.
+    };
+    let new_states = applied_branching_rules
+        .into_iter()
+        .flat_map(|(outcomes, weight, description)| {
+            outcomes
+                .into_iter()
+                .map(move |(new_state, outcome_weight)| {
+                    (new_state, weight * outcome_weight, description)
+                })
+        })
+        .fold(new_states, merge);
+    let mut new_states = new_states
+        .into_iter()
+        .map(|(new_state, weight, rules)| {
+            let nothing = nothing_probability > 0. && new_state == state;
+            let probability = weight / weight_sum + if nothing { nothing_probability } else { 0. };
+            (new_state, AppliedRules::new(rules, nothing), probability)
+        })
+        .collect_vec();
+    if nothing_probability > 0. && !new_states.iter().any(|e| e.0 == state) {
+        new_states.push((
+            state,
+            AppliedRules::new(Vec::new(), true),
+            nothing_probability,
+        ));
+    }
+    new_states
+}
+
 #[cfg(test)]
 mod tests {
+    use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
     use super::*;
 
+    #[test]
+    fn clear_cache_for_rule() {
+        let rules = vec![
+            Rule::new(
+                "Increment".to_string(),
+                Condition::Custom(Arc::new(|state: i32| state < 2)),
+                0.5,
+                Action::Custom(Arc::new(|state| state + 1)),
+            ),
+            Rule::new(
+                "Reset".to_string(),
+                Condition::Custom(Arc::new(|state: i32| state > 0)),
+                0.5,
+                Action::Custom(Arc::new(|_| 0)),
+            ),
+        ];
+        let mut simulation = Simulation::new(0, get_state_transition_generator_typed(rules));
+        simulation.full_traversal(true, None).unwrap();
+        assert_eq!(simulation.cache_len(), 3);
+        simulation.clear_cache_for_rule(&"Reset".to_string());
+        assert_eq!(simulation.cache_len(), 1);
+        simulation.clear_cache_for_rule(&"Unknown".to_string());
+        assert_eq!(simulation.cache_len(), 1);
+        simulation.clear_cache_for_rule(&"Increment".to_string());
+        assert_eq!(simulation.cache_len(), 0);
+    }
+
+    #[test]
+    fn branching_rule() {
+        let break_rule: BranchingRule<i32> = BranchingRule::new(
+            "Break".to_string(),
+            Condition::Always,
+            0.5,
+            Arc::new(|state| vec![(state + 1, 1.), (state + 2, 2.), (state + 1, 1.)]),
+        );
+        let repair_rule: Rule<i32> = Rule::new(
+            "Repair".to_string(),
+            Condition::Always,
+            0.5,
+            Action::Custom(Arc::new(|state| state + 2)),
+        );
+        let state_transition_generator = get_state_transition_generator_typed(vec![
+            AnyRule::from(break_rule.clone()),
+            repair_rule.clone().into(),
+        ]);
+        let transitions = state_transition_generator(0)
+            .into_iter()
+            .map(|(state, applied_rules, probability)| (state, (applied_rules, probability)))
+            .collect::<HashMap<_, _>>();
+        // Both rules count with their weight of 0.5 for no rule happening,
+        // so each of the weights 0.5, 0.5 and 0.25 is divided by 1.25
+        assert_eq!(transitions.len(), 3);
+        assert_eq!(
+            transitions[&0],
+            (AppliedRules::new(vec![], true), 0.25 / 1.25)
+        );
+        assert_eq!(
+            transitions[&1],
+            (
+                AppliedRules::new(vec!["Break".to_string()], false),
+                0.25 / 1.25
+            )
+        );
+        assert_eq!(transitions[&2].0.rules(), ["Break", "Repair"]);
+        assert!((transitions[&2].1 - 0.75 / 1.25).abs() < 1e-12);
+
+        let mut simulation = Simulation::new(0, get_state_transition_generator(vec![break_rule]));
+        simulation.next_step().unwrap();
+        assert_eq!(
+            simulation.probability_distribution(1),
+            HashMap::from([(0, 0.5), (1, 0.25), (2, 0.25)])
+        );
+
+        let invalid_rule: BranchingRule<i32> = BranchingRule::new(
+            "Vanish".to_string(),
+            Condition::Always,
+            0.5,
+            Arc::new(|_| Vec::new()),
+        );
+        assert_eq!(
+            invalid_rule.outcomes(0),
+            Err(RuleError::OutcomeWeightsNotNormalizable {
+                sum: 0.,
+                rule_name: "Vanish".to_string()
+            })
+        );
+        let mut simulation = Simulation::new(0, get_state_transition_generator(vec![invalid_rule]));
+        assert!(matches!(
+            simulation.next_step(),
+            Err(SimulationError::ProbabilitiesDoNotSumToOne { state: 0, sum, .. }) if sum.is_nan()
+        ));
+    }
+
+    #[test]
+    fn applied_rules() {
+        let rule = |description: &str, step: i32, weight: ProbabilityWeight| -> Rule<i32> {
+            Rule::new(
+                description.to_string(),
+                Condition::Always,
+                weight,
+                Action::Custom(Arc::new(move |state| (state + step).clamp(-1, 1))),
+            )
+        };
+        let rules = vec![
+            rule("Forward", 1, 0.25),
+            rule("Advance", 1, 0.25),
+            rule("Backward", -1, 0.5),
+        ];
+        let mut simulation =
+            Simulation::new(0, get_state_transition_generator_typed(rules.clone()));
+        simulation.next_step().unwrap();
+        let transitions = simulation
+            .known_transitions()
+            .into_iter()
+            .sorted()
+            .collect_vec();
+        assert_eq!(
+            transitions,
+            vec![
+                AppliedRules::new(vec![], true),
+                AppliedRules::new(vec!["Forward".to_string(), "Advance".to_string()], false),
+                AppliedRules::new(vec!["Backward".to_string()], false),
+            ]
+        );
+        assert_eq!(transitions[1].rules(), ["Advance", "Forward"]);
+        assert!(transitions[0].nothing());
+        assert_eq!(transitions[0].to_string(), "Nothing");
+        assert_eq!(transitions[1].to_string(), "Advance | Forward");
+
+        let mut string_simulation = Simulation::new(0, get_state_transition_generator(rules));
+        string_simulation.next_step().unwrap();
+        assert_eq!(
+            string_simulation
+                .known_transitions()
+                .into_iter()
+                .sorted()
+                .collect_vec(),
+            transitions
+                .iter()
+                .map(ToString::to_string)
+                .sorted()
+                .collect_vec()
+        );
+        assert_eq!(
+            string_simulation.probability_distribution(1),
+            simulation.probability_distribution(1)
+        );
+    }
+
+    #[test]
+    fn description_merge() {
+        let rule = |description: &str, weight: ProbabilityWeight| -> Rule<i32> {
+            Rule::new(
+                description.to_string(),
+                Condition::Always,
+                weight,
+                Action::Custom(Arc::new(|state| (state + 1).min(1))),
+            )
+        };
+        let mut rules = vec![
+            rule("Forward", 0.2),
+            rule("Return", 0.2),
+            rule("Advance", 0.2),
+            Rule::new("Stay".to_string(), Condition::Always, 0.5, Action::Identity),
+        ];
+        let transitions = |rules: Vec<Rule<i32>>, description_merge: DescriptionMerge| {
+            get_state_transition_generator_with_merge(rules, description_merge)(0)
+                .into_iter()
+                .map(|(state, description, _)| (state, description))
+                .collect::<HashMap<_, _>>()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..10 {
+            rules.shuffle(&mut rng);
+            assert_eq!(
+                transitions(rules.clone(), DescriptionMerge::default()),
+                HashMap::from([
+                    (1, "Advance | Forward | Return".to_string()),
+                    (0, "Stay | Nothing".to_string())
+                ])
+            );
+            assert_eq!(
+                transitions(rules.clone(), DescriptionMerge::First),
+                HashMap::from([(1, "Advance".to_string()), (0, "Stay".to_string())])
+            );
+            assert_eq!(
+                transitions(
+                    rules.clone(),
+                    DescriptionMerge::Custom(Arc::new(|descriptions| descriptions
+                        .len()
+                        .to_string()))
+                ),
+                HashMap::from([(1, "3".to_string()), (0, "2".to_string())])
+            );
+        }
+    }
+
+    #[test]
+    fn timed_rules() {
+        // Births only happen in the first two of every four time steps
+        let birth_rule: TimedRule<i32> = TimedRule::new(
+            "Birth".to_string(),
+            Arc::new(|_, time| time % 4 < 2),
+            Arc::new(|_, time| if time % 4 == 0 { 0.5 } else { 0.25 }),
+            Action::Custom(Arc::new(|state| state + 1)),
+        );
+        let cap_rule: TimedRule<i32> = Rule::new(
+            "Cap".to_string(),
+            Condition::Custom(Arc::new(|state| state >= 2)),
+            1.,
+            Action::Custom(Arc::new(|_| 0)),
+        )
+        .with_priority(1)
+        .into();
+        let state_transition_generator =
+            get_timed_state_transition_generator(vec![birth_rule, cap_rule]);
+        let mut simulation = Simulation::new((0, 0), state_transition_generator);
+        simulation.run(4).unwrap();
+        assert_eq!(
+            simulation.probability_distribution(1),
+            HashMap::from([((0, 1), 0.5), ((1, 1), 0.5)])
+        );
+        assert_eq!(
+            simulation.probability_distribution(2),
+            HashMap::from([((0, 2), 0.375), ((1, 2), 0.5), ((2, 2), 0.125)])
+        );
+        // Nothing is born at time 2 and 3, only the cap applies
+        assert_eq!(
+            simulation.probability_distribution(4),
+            HashMap::from([((0, 4), 0.5), ((1, 4), 0.5)])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid rule at time 2")]
+    fn timed_rules_invalid_weight() {
+        let rule: TimedRule<i32> = TimedRule::new(
+            "Grow".to_string(),
+            Arc::new(|_, _| true),
+            Arc::new(|_, time| time as f64),
+            Action::Custom(Arc::new(|state| state + 1)),
+        );
+        let state_transition_generator = get_timed_state_transition_generator(vec![rule]);
+        Simulation::new((0, 0), state_transition_generator)
+            .run(3)
+            .unwrap();
+    }
+
     #[test]
     fn random_walk() {
         let initial_state = 0;
 
         let forward_rule: Rule<i32> = Rule::new(
             "Forward".to_string(),
-            Arc::new(|_| true),
+            Condition::Always,
             1.,
-            Arc::new(|state| state + 1),
+            Action::Custom(Arc::new(|state| state + 1)),
         );
 
         let backward_rule: Rule<i32> = Rule::new(
             "Backward".to_string(),
-            Arc::new(|_| true),
+            Condition::Always,
             1.,
-            Arc::new(|state| state - 1),
+            Action::Custom(Arc::new(|state| state - 1)),
         );
 
         let rules = vec![forward_rule, backward_rule];
@@ -284,7 +1645,7 @@ mod tests {
         assert_eq!(simulation.state_transition_graph().edge_count(), 0);
         assert_eq!(simulation.entropy(0), 0.0);
 
-        simulation.next_step();
+        simulation.next_step().unwrap();
         dbg!(&simulation);
         assert_eq!(simulation.known_states().len(), 3);
         assert_eq!(simulation.known_transitions().len(), 2);
@@ -312,22 +1673,22 @@ mod tests {
         let initial_state = 0;
         let return_rule: Rule<i32> = Rule::new(
             "Return".to_string(),
-            Arc::new(|_| true),
+            Condition::Always,
             0.1,
-            Arc::new(|_| 0),
+            Action::Custom(Arc::new(|_| 0)),
         );
         let forward_rule: Rule<i32> = Rule::new(
             "Forward".to_string(),
-            Arc::new(|_| true),
+            Condition::Always,
             1.,
-            Arc::new(|state| state + 1),
+            Action::Custom(Arc::new(|state| state + 1)),
         );
 
         let backward_rule: Rule<i32> = Rule::new(
             "Backward".to_string(),
-            Arc::new(|_| true),
+            Condition::Always,
             1.,
-            Arc::new(|state| state - 1),
+            Action::Custom(Arc::new(|state| state - 1)),
         );
 
         let rules = vec![forward_rule, backward_rule, return_rule];
@@ -342,7 +1703,7 @@ mod tests {
         assert_eq!(simulation.state_transition_graph().edge_count(), 0);
         assert_eq!(simulation.entropy(0), 0.0);
 
-        simulation.next_step();
+        simulation.next_step().unwrap();
         dbg!(&simulation);
         assert_eq!(simulation.known_states().len(), 3);
         assert_eq!(dbg!(simulation.known_transitions()).len(), 3);
@@ -351,4 +1712,384 @@ mod tests {
         assert_eq!(simulation.state_transition_graph().edge_count(), 3);
         dbg!(simulation.entropy(1));
     }
+
+    #[test]
+    fn condition() {
+        let positive: Condition<i32> = Condition::Custom(Arc::new(|state| state > 0));
+        let even: Condition<i32> = Condition::Custom(Arc::new(|state| state % 2 == 0));
+        let positive_and_even = Condition::And(Box::new(positive.clone()), Box::new(even.clone()));
+        let positive_or_even = Condition::Or(Box::new(positive.clone()), Box::new(even));
+        let not_positive = Condition::Not(Box::new(positive));
+        assert!(Condition::Always.evaluate(1));
+        assert!(!Condition::Never.evaluate(1));
+        assert!(positive_and_even.evaluate(2));
+        assert!(!positive_and_even.evaluate(1));
+        assert!(positive_or_even.evaluate(1));
+        assert!(positive_or_even.evaluate(-2));
+        assert!(!positive_or_even.evaluate(-1));
+        assert!(not_positive.evaluate(-1));
+        assert_eq!(format!("{not_positive:?}"), "Not(Custom)");
+
+        let rule = Rule::new(
+            "Forward".to_string(),
+            positive_and_even.clone(),
+            1.,
+            Action::Custom(Arc::new(|state| state + 1)),
+        );
+        let state_transition_generator = get_state_transition_generator(vec![rule]);
+        assert_eq!(
+            state_transition_generator(1),
+            vec![(1, "Nothing".to_string(), 1.0)]
+        );
+        assert_eq!(
+            state_transition_generator(2),
+            vec![(3, "Forward".to_string(), 1.0)]
+        );
+    }
+
+    #[test]
+    fn action() {
+        let increment: Action<i32> = Action::Custom(Arc::new(|state| state + 1));
+        let double: Action<i32> = Action::Custom(Arc::new(|state| state * 2));
+        assert_eq!(Action::Identity.apply(3), 3);
+        assert_eq!(increment.apply(3), 4);
+        assert_eq!(
+            Action::Sequential(vec![increment.clone(), double.clone()]).apply(3),
+            8
+        );
+        assert_eq!(Action::Sequential(vec![double, increment]).apply(3), 7);
+        assert_eq!(Action::<i32>::Sequential(vec![]).apply(3), 3);
+        assert_eq!(
+            format!("{:?}", Action::<i32>::Sequential(vec![Action::Identity])),
+            "Sequential([Identity])"
+        );
+    }
+
+    #[test]
+    fn hash_collision() {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        struct CollidingState(i32);
+
+        impl Hash for CollidingState {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                0.hash(state);
+            }
+        }
+
+        let forward_rule = Rule::new(
+            "Forward".to_string(),
+            Condition::Always,
+            0.5,
+            Action::Custom(Arc::new(|state: CollidingState| {
+                CollidingState(state.0 + 1)
+            })),
+        );
+        let state_transition_generator = get_state_transition_generator(vec![forward_rule]);
+        let next_states = state_transition_generator(CollidingState(0));
+        assert_eq!(next_states.len(), 2);
+        assert!(next_states.contains(&(CollidingState(1), "Forward".to_string(), 0.5)));
+        assert!(next_states.contains(&(CollidingState(0), "Nothing".to_string(), 0.5)));
+    }
+
+    #[test]
+    fn priority() {
+        let low_priority_rule: Rule<i32> = Rule::new(
+            "Low".to_string(),
+            Condition::Always,
+            1.,
+            Action::Custom(Arc::new(|state| state - 1)),
+        );
+        let high_priority_rule = Rule::new(
+            "High".to_string(),
+            Condition::Custom(Arc::new(|state| state >= 0)),
+            0.1,
+            Action::Custom(Arc::new(|state| state + 1)),
+        )
+        .with_priority(1);
+        assert_eq!(low_priority_rule.priority(), 0);
+        assert_eq!(high_priority_rule.priority(), 1);
+
+        let state_transition_generator =
+            get_state_transition_generator(vec![low_priority_rule, high_priority_rule]);
+        let next_states = state_transition_generator(0);
+        assert_eq!(next_states.len(), 2);
+        assert!(next_states.contains(&(1, "High".to_string(), 0.1 / (0.1 + 0.9))));
+        assert!(next_states.contains(&(0, "Nothing".to_string(), 0.9 / (0.1 + 0.9))));
+
+        // The high priority rule doesn't apply, so the low priority rule is used
+        assert_eq!(
+            state_transition_generator(-1),
+            vec![(-2, "Low".to_string(), 1.0)]
+        );
+    }
+
+    #[test]
+    fn compose() {
+        let increment: Rule<i32> = Rule::new(
+            "Increment".to_string(),
+            Condition::Custom(Arc::new(|state| state >= 0)),
+            0.5,
+            Action::Custom(Arc::new(|state| state + 1)),
+        );
+        let double: Rule<i32> = Rule::new(
+            "Double".to_string(),
+            Condition::Custom(Arc::new(|state| state % 2 == 0)),
+            0.4,
+            Action::Custom(Arc::new(|state| state * 2)),
+        );
+        let composed = increment.clone().compose(double.clone());
+        assert_eq!(composed.description(), "Increment -> Double");
+        assert_eq!(composed.weight(), 0.5 * 0.4);
+
+        for state in [-3, 0, 1, 2, 3] {
+            let applies_in_sequence =
+                increment.applies(state) && double.applies(increment.apply(state));
+            assert_eq!(composed.applies(state), applies_in_sequence);
+            assert_eq!(composed.apply(state), double.apply(increment.apply(state)));
+        }
+        assert!(composed.applies(1));
+        assert!(!composed.applies(0));
+        assert!(!composed.applies(-3));
+        assert_eq!(composed.apply(3), 8);
+    }
+
+    #[test]
+    fn validate() {
+        let rule = |weight| {
+            Rule::new(
+                "Rule".to_string(),
+                Condition::<i32>::Always,
+                weight,
+                Action::Identity,
+            )
+        };
+        assert_eq!(rule(1.).validate(), Ok(()));
+        assert_eq!(rule(0.1).validate(), Ok(()));
+        assert_eq!(rule(0.).validate(), Ok(()));
+        for weight in [-0.5, 1.5, f64::NAN] {
+            assert!(matches!(
+                rule(weight).validate(),
+                Err(RuleError::WeightOutOfRange { rule_name, .. }) if rule_name == "Rule"
+            ));
+        }
+        assert_eq!(validate_all(&[rule(0.5), rule(1.)]), Ok(()));
+        assert_eq!(
+            validate_all(&[rule(0.5), rule(1.5)]),
+            Err(RuleError::WeightOutOfRange {
+                weight: 1.5,
+                rule_name: "Rule".to_string()
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid rule: The weight 1.5 of rule Rule is not in [0, 1]")]
+    fn invalid_rule_generator() {
+        get_state_transition_generator(vec![Rule::new(
+            "Rule".to_string(),
+            Condition::<i32>::Always,
+            1.5,
+            Action::Identity,
+        )]);
+    }
+
+    #[test]
+    fn try_new() {
+        let rule = |weight| {
+            Rule::try_new(
+                "Jump".to_string(),
+                Condition::<i32>::Always,
+                weight,
+                Action::Custom(Arc::new(|state| state + 1)),
+            )
+        };
+        assert_eq!(
+            rule(1.5).unwrap_err(),
+            RuleError::WeightOutOfRange {
+                weight: 1.5,
+                rule_name: "Jump".to_string()
+            }
+        );
+        assert!(rule(f64::NAN).is_err());
+        assert_eq!(rule(0.5).unwrap().weight(), 0.5);
+
+        let invalid_rule = Rule::new(
+            "Jump".to_string(),
+            Condition::<i32>::Always,
+            1.5,
+            Action::Custom(Arc::new(|state| state + 1)),
+        );
+        assert!(matches!(
+            try_get_state_transition_generator(vec![rule(0.5).unwrap(), invalid_rule.clone()]),
+            Err(RuleError::WeightOutOfRange { weight, .. }) if weight == 1.5
+        ));
+        let state_transition_generator =
+            try_get_state_transition_generator(vec![rule(0.5).unwrap()]).unwrap();
+        assert_eq!(state_transition_generator(0).len(), 2);
+        assert!(matches!(
+            try_get_state_transition_generator_typed(vec![invalid_rule.clone()]),
+            Err(RuleError::WeightOutOfRange { weight, .. }) if weight == 1.5
+        ));
+        assert!(try_get_state_transition_generator_with_merge(
+            vec![invalid_rule],
+            DescriptionMerge::First
+        )
+        .is_err());
+
+        // A rule with a weight of 0 never happens, even with a higher priority
+        let never = rule(0.).unwrap().with_priority(1);
+        let state_transition_generator =
+            try_get_state_transition_generator_typed(vec![rule(0.5).unwrap(), never.clone()])
+                .unwrap();
+        assert_eq!(
+            state_transition_generator(0)
+                .into_iter()
+                .sorted_by_key(|(state, _, _)| *state)
+                .collect_vec(),
+            vec![
+                (0, AppliedRules::new(Vec::new(), true), 0.5),
+                (1, AppliedRules::new(vec!["Jump".to_string()], false), 0.5)
+            ]
+        );
+        let combined_rules = super::combine_rules_weighted(HashMap::from([
+            ("Jump".to_string(), rule(0.5).unwrap()),
+            ("Never".to_string(), never.clone()),
+        ]));
+        assert_eq!(
+            combined_rules.keys().sorted().collect_vec(),
+            vec!["Jump", "Nothing"]
+        );
+        let state_transition_generator = get_exclusive_state_transition_generator(vec![never]);
+        assert_eq!(
+            state_transition_generator(0),
+            vec![(0, "Nothing".to_string(), 1.)]
+        );
+    }
+
+    #[test]
+    fn combine_rules_weighted() {
+        let rules = HashMap::from([
+            (
+                "Increment".to_string(),
+                Rule::new(
+                    "Increment".to_string(),
+                    Condition::Always,
+                    0.5,
+                    Action::Custom(Arc::new(|state: i32| state + 1)),
+                ),
+            ),
+            (
+                "Double".to_string(),
+                Rule::new(
+                    "Double".to_string(),
+                    Condition::Custom(Arc::new(|state: i32| state > 0)),
+                    0.5,
+                    Action::Custom(Arc::new(|state: i32| state * 2)),
+                ),
+            ),
+        ]);
+        let combined_rules = super::combine_rules_weighted(rules);
+        assert_eq!(combined_rules.len(), 4);
+        assert_eq!(
+            combined_rules.keys().sorted().collect_vec(),
+            vec!["Double", "Double & Increment", "Increment", "Nothing"]
+        );
+        for rule in combined_rules.values() {
+            assert_eq!(rule.weight(), 0.25);
+        }
+
+        let nothing = &combined_rules["Nothing"];
+        assert!(nothing.applies(-1));
+        assert_eq!(nothing.apply(3), 3);
+        let both = &combined_rules["Double & Increment"];
+        assert!(both.applies(1));
+        assert!(!both.applies(0));
+        assert_eq!(both.apply(3), 7);
+
+        let rules = HashMap::from([
+            (
+                "a".to_string(),
+                Rule::new(
+                    "a".to_string(),
+                    Condition::<i32>::Always,
+                    0.2,
+                    Action::Identity,
+                ),
+            ),
+            (
+                "b".to_string(),
+                Rule::new("b".to_string(), Condition::Always, 0.6, Action::Identity),
+            ),
+        ]);
+        let combined_rules = super::combine_rules_weighted(rules);
+        let weight = |name: &str| combined_rules[name].weight();
+        assert!((weight("Nothing") - 0.32).abs() < 1e-12);
+        assert!((weight("a") - 0.08).abs() < 1e-12);
+        assert!((weight("b") - 0.48).abs() < 1e-12);
+        assert!((weight("a & b") - 0.12).abs() < 1e-12);
+    }
+
+    #[test]
+    fn combined_rules_transition_probabilities() {
+        let rule = |name: &str, condition: Condition<i32>, weight, increment| {
+            let rule = Rule::new(
+                name.to_string(),
+                condition,
+                weight,
+                Action::Custom(Arc::new(move |state: i32| state + increment)),
+            );
+            (name.to_string(), rule)
+        };
+        let probabilities = |rules: Vec<(String, Rule<i32>)>, state: i32| {
+            let combined_rules = super::combine_rules_weighted(HashMap::from_iter(rules));
+            let state_transition_generator =
+                get_exclusive_state_transition_generator(combined_rules.into_values().collect());
+            state_transition_generator(state)
+                .into_iter()
+                .map(|(new_state, description, probability)| {
+                    (new_state, description, (probability * 1e9).round() / 1e9)
+                })
+                .sorted_by_key(|(new_state, _, _)| *new_state)
+                .collect_vec()
+        };
+
+        let rules = vec![
+            rule("a", Condition::Always, 0.5, 1),
+            rule("b", Condition::Always, 0.5, 10),
+        ];
+        assert_eq!(
+            probabilities(rules, 0),
+            vec![
+                (0, "Nothing".to_string(), 0.25),
+                (1, "a".to_string(), 0.25),
+                (10, "b".to_string(), 0.25),
+                (11, "a & b".to_string(), 0.25),
+            ]
+        );
+
+        // A certain rule doesn't produce combinations with a weight of 0
+        let positive = || Condition::Custom(Arc::new(|state: i32| state > 0));
+        let rules = vec![
+            rule("a", Condition::Always, 0.2, 1),
+            rule("b", positive(), 1.0, 10),
+        ];
+        assert_eq!(
+            probabilities(rules.clone(), 1),
+            vec![(11, "b".to_string(), 0.8), (12, "a & b".to_string(), 0.2)]
+        );
+        assert_eq!(
+            probabilities(rules, 0),
+            vec![(0, "Nothing".to_string(), 0.8), (1, "a".to_string(), 0.2)]
+        );
+
+        // Rules which don't apply don't change the probabilities of the others
+        let rules = vec![
+            rule("a", Condition::Always, 0.4, 1),
+            rule("b", positive(), 0.5, 10),
+        ];
+        assert_eq!(
+            probabilities(rules, 0),
+            vec![(0, "Nothing".to_string(), 0.6), (1, "a".to_string(), 0.4)]
+        );
+    }
 }
@@ -1,4 +1,5 @@
 pub(crate) use crate::cached_function::*;
 pub(crate) use crate::hash::*;
+pub(crate) use crate::linalg::*;
 pub use crate::models::*;
 pub use crate::simulation::*;
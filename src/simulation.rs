@@ -1,24 +1,58 @@
-use std::{
-    fmt::Debug,
-    hash::Hash,
-    sync::{Arc, Mutex},
-};
+use std::{fmt::Debug, hash::Hash, iter::Sum, ops::Mul, sync::Arc};
 
+use crate::models::constraints::{ConstraintError, ConstraintSet};
 use crate::prelude::*;
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use itertools::Itertools;
-use ndarray::Array2;
-use petgraph::{graph::Graph, visit::EdgeRef};
+use ndarray::{Array1, Array2, Axis};
+use petgraph::{
+    algo::{condensation, kosaraju_scc, tarjan_scc},
+    graph::{Graph, NodeIndex},
+    visit::EdgeRef,
+};
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    Rng, RngCore,
+};
 use rayon::prelude::*;
+use thiserror::Error;
+
+mod builder;
+mod dot;
+mod export;
+pub mod generator;
+mod lumping;
+#[cfg(feature = "serde")]
+mod serialization;
+mod steps;
+mod sweep;
+mod transport;
+pub use builder::*;
+pub use lumping::*;
+#[cfg(feature = "serde")]
+pub use serialization::*;
+pub use steps::*;
+pub use sweep::*;
+pub use transport::*;
 
-type StateHash = u64;
+/// The hash identifying a state inside a simulation.
+///
+/// The value is opaque, but stable within a single simulation instance, see
+/// [resolve_state](struct.Simulation.html#method.resolve_state).
+pub type StateHash = u64;
 type KnownStates<S> = HashMap<StateHash, S>;
 
-type TransitionHash = u64;
+/// The hash identifying a transition inside a simulation.
+///
+/// The value is opaque, but stable within a single simulation instance, see
+/// [resolve_transition](struct.Simulation.html#method.resolve_transition).
+pub type TransitionHash = u64;
 type KnownTransitions<T> = HashMap<TransitionHash, T>;
 
 type StateTransitionGraph = Graph<StateHash, (TransitionHash, Probability)>;
 
+type HashedTransitionFlows = Vec<(StateHash, StateHash, TransitionHash, Probability)>;
+
 pub type StateTransitionGenerator<S, T> =
     Arc<dyn Fn(S) -> OutgoingTransitions<S, T> + Send + Sync + 'static>;
 
@@ -31,13 +65,267 @@ type HashedStateProbabilityDistribution = HashMap<StateHash, Probability>;
 pub type Probability = f64;
 pub type Time = u64;
 
+/// A callback of a [watch](struct.Simulation.html#method.watch), which is
+/// called with the time and the new probability of the watched state.
+pub type WatchCallback = Arc<dyn Fn(Time, Probability) + Send + Sync>;
+
+/// The derivatives of the steady state probabilities of all states with
+/// respect to each transition probability, see
+/// [sensitivity_analysis](struct.Simulation.html#method.sensitivity_analysis).
+pub type Sensitivities<S> = HashMap<(S, S), Vec<(S, f64)>>;
+
+const NO_PROBABILITY_DISTRIBUTION_FOUND: &str = "No probability distribution found for given time";
+const INITIAL_DISTRIBUTION_TOLERANCE: Probability = 1e-10;
+const TRANSITION_MATRIX_TOLERANCE: Probability = 1e-10;
+const ABSORBING_PROBABILITY_TOLERANCE: Probability = 1e-10;
+/// The default tolerance for the sum of the probabilities of the state
+/// transition generator, see
+/// [set_probability_tolerance](struct.Simulation.html#method.set_probability_tolerance).
+pub const DEFAULT_PROBABILITY_TOLERANCE: Probability = 1e-9;
+const DETAILED_BALANCE_TOLERANCE: Probability = 1e-9;
+const STATIONARY_DISTRIBUTION_TOLERANCE: Probability = 1e-12;
+const STATIONARY_DISTRIBUTION_MAX_ITERATIONS: u64 = 100_000;
+const SPECTRAL_GAP_TOLERANCE: f64 = 1e-10;
+
+/// The errors that can occur while running a simulation.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum SimulationError<S, T> {
+    /// The simulation did not finish within the given maximum number of steps.
+    #[error("Maximum number of {max_steps} steps reached")]
+    MaxStepsReached { max_steps: u64 },
+    /// The probabilities of the outgoing transitions the state transition
+    /// generator returned for a state do not sum up to 1.0.
+    #[error("Sum of probabilities of next states of {state:?} is {sum}, not 1.0: {next_states:?}")]
+    ProbabilitiesDoNotSumToOne {
+        state: S,
+        next_states: OutgoingTransitions<S, T>,
+        sum: Probability,
+    },
+    /// The probabilities of the outgoing transitions the state transition
+    /// generator returned for a state can't be normalized, as their sum is not
+    /// a positive number.
+    #[error("Sum of probabilities of next states of {state:?} is {sum}, which can't be normalized: {next_states:?}")]
+    ProbabilitiesNotNormalizable {
+        state: S,
+        next_states: OutgoingTransitions<S, T>,
+        sum: Probability,
+    },
+    /// The markov chain is not absorbing, i.e. it has no absorbing states or
+    /// not every state can reach one.
+    #[error("The markov chain is not absorbing")]
+    NotAbsorbing,
+    /// There is no probability distribution for the given time, e.g. because
+    /// the markov chain has not been simulated that far yet.
+    #[error("No probability distribution found for time {time}")]
+    NoProbabilityDistribution { time: Time },
+    /// The initial distribution contains no states.
+    #[error("The initial distribution is empty")]
+    EmptyDistribution,
+    /// A state of the initial distribution has a negative or NaN probability
+    /// or weight.
+    #[error("State {state:?} has the negative probability {probability}")]
+    NegativeProbability { state: S, probability: Probability },
+    /// The probabilities of the initial distribution do not sum up to 1.0.
+    #[error("Sum of probabilities of the initial distribution is {sum}, not 1.0")]
+    InitialProbabilitiesDoNotSumToOne { sum: Probability },
+    /// The weights of the initial distribution can't be normalized, as their
+    /// sum is not a positive number.
+    #[error("Sum of weights of the initial distribution is {sum}, which can't be normalized")]
+    WeightsNotNormalizable { sum: Probability },
+    /// The full traversal did not finish within the given maximum number of
+    /// iterations.
+    #[error(transparent)]
+    IterationLimitReached(#[from] IterationLimitReached),
+    /// The full traversal was cancelled by the progress callback.
+    #[error("Traversal cancelled after {iterations} iterations with {known_states} known states")]
+    TraversalCancelled {
+        iterations: u64,
+        known_states: usize,
+    },
+    /// Two distinct states have the same hash.
+    #[error(transparent)]
+    HashCollision(#[from] HashCollisionError<S>),
+    /// The state transition generator returned different outgoing
+    /// transitions for a state than before.
+    #[error(transparent)]
+    NonDeterministicGenerator(#[from] NonDeterministicGeneratorError<S, T>),
+    /// The transition matrix a simulation was created from is not a valid
+    /// stochastic matrix for the given states.
+    #[error("Invalid transition matrix: {reason}")]
+    InvalidTransitionMatrix { reason: String },
+    /// A state violates a constraint of the simulation, see
+    /// [with_constraints](struct.Simulation.html#method.with_constraints).
+    #[error(transparent)]
+    ConstraintViolated(#[from] ConstraintError<S>),
+    /// A step would make more states known than allowed, see
+    /// [with_max_known_states](struct.Simulation.html#method.with_max_known_states).
+    #[error("A step would increase the number of known states to {known_states}, but at most {max_known_states} are allowed")]
+    MaxKnownStatesExceeded {
+        max_known_states: usize,
+        known_states: usize,
+    },
+    /// The simulation already reached the target time.
+    #[error("The simulation is already at time {time}, which is not before the target time {target_time}")]
+    AlreadyAtTime { time: Time, target_time: Time },
+    /// The probability distribution did not converge within the given
+    /// maximum number of steps, see
+    /// [steady_state](struct.Simulation.html#method.steady_state).
+    #[error(transparent)]
+    NotConverged(#[from] ConvergenceError),
+}
+
+/// The error returned if a full traversal does not finish within the given
+/// maximum number of iterations.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Iteration limit of {iterations} reached with {known_states} known states")]
+pub struct IterationLimitReached {
+    /// The number of iterations that were made.
+    pub iterations: u64,
+    /// The number of states discovered before the limit was reached.
+    pub known_states: usize,
+}
+
+/// The error returned if two distinct states have the same hash.
+///
+/// All states are identified by their hash internally, so two colliding states
+/// would otherwise be merged silently.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("Hash collision between {state:?} and {colliding_state:?}")]
+pub struct HashCollisionError<S> {
+    /// The state which was about to be added.
+    pub state: S,
+    /// The already known state with the same hash.
+    pub colliding_state: S,
+}
+
+/// The error returned if the state transition generator returns different
+/// outgoing transitions for a state than before.
+///
+/// The state transition graph keeps one set of outgoing transitions per
+/// state, so a non-deterministic generator would otherwise silently overwrite
+/// them.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error(
+    "The state transition generator returned {current:?} for {state:?}, but {previous:?} before"
+)]
+pub struct NonDeterministicGeneratorError<S, T> {
+    /// The state the generator was called for.
+    pub state: S,
+    /// The outgoing transitions in the state transition graph.
+    pub previous: OutgoingTransitions<S, T>,
+    /// The outgoing transitions the generator returned now.
+    pub current: OutgoingTransitions<S, T>,
+}
+
+/// The error returned if a simulation does not converge within the given
+/// number of steps.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("No convergence after {steps} steps, the last distance was {distance}")]
+pub struct ConvergenceError {
+    /// The number of steps that were made.
+    pub steps: u64,
+    /// The L1 distance between the last two probability distributions.
+    pub distance: f64,
+}
+
+/// The error returned if two simulations can't be
+/// [merged](struct.Simulation.html#method.merge).
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum MergeError<S, T> {
+    /// The simulations don't share the same state transition generator.
+    #[error("The simulations use different state transition generators")]
+    DifferentGenerators,
+    /// The states of the simulations are hashed with different hashers.
+    #[error("The simulations use different hashers")]
+    DifferentHashers,
+    /// A state of the other simulation has the same hash as a different state.
+    #[error(transparent)]
+    HashCollision(#[from] HashCollisionError<S>),
+    /// A state has different outgoing transitions in the two simulations.
+    #[error(transparent)]
+    NonDeterministicGenerator(#[from] NonDeterministicGeneratorError<S, T>),
+    /// A state of the other simulation violates a constraint of this one.
+    #[error(transparent)]
+    ConstraintViolated(#[from] ConstraintError<S>),
+}
+
+/// The error returned if the mixing time of a markov chain can't be computed.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum MixingError<S, T> {
+    #[error(transparent)]
+    Simulation(#[from] SimulationError<S, T>),
+    /// The markov chain is reducible, i.e. it consists of multiple
+    /// communication classes.
+    #[error("The markov chain is not irreducible")]
+    NotIrreducible,
+    /// The markov chain is periodic, so its probability distribution
+    /// oscillates instead of converging.
+    #[error("The markov chain is periodic with period {period}")]
+    Periodic { period: u64 },
+    /// The chain started in `state` is not mixed after `max_steps` steps.
+    #[error("Starting in {state:?} the markov chain is not mixed after {max_steps} steps, the last distance was {distance}")]
+    MaxStepsReached {
+        state: S,
+        max_steps: u64,
+        /// The total variation distance to the steady state after the last
+        /// step.
+        distance: f64,
+    },
+}
+
+/// The error returned if the state transition graph is needed in full, but the
+/// outgoing transitions of some known states have not been generated yet.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("The markov chain is not fully traversed, untraversed states: {untraversed_states:?}")]
+pub struct NotFullyTraversedError<S> {
+    /// The known states whose outgoing transitions are unknown.
+    pub untraversed_states: Vec<S>,
+}
+
+/// The classification of a state of a markov chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StateClass {
+    /// The markov chain returns to the state with a probability below 1.0.
+    Transient,
+    /// The markov chain returns to the state with certainty and the expected
+    /// return time is finite.
+    PositiveRecurrent,
+    /// The markov chain returns to the state with certainty, but the expected
+    /// return time is infinite. This is only possible for infinite markov
+    /// chains.
+    NullRecurrent,
+}
+
+/// The probability flowing along a single transition in one step.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransitionFlow<S, T> {
+    /// The state the transition starts at.
+    pub source: S,
+    /// The state the transition leads to.
+    pub target: S,
+    /// The transition itself.
+    pub transition: T,
+    /// The probability of the source state multiplied with the probability of
+    /// the transition.
+    pub flow: Probability,
+}
+
+/// The transitions that carried probability in a single step.
+#[derive(Debug, Clone)]
+pub struct StepHistory<S, T> {
+    /// The transitions that were traversed with a nonzero probability.
+    pub transitions: HashSet<T>,
+    /// The probability flowing along each traversed transition.
+    pub flows: Vec<TransitionFlow<S, T>>,
+}
+
 /// `Simulation` is the a struct for a cached markov chain simulation.
 ///
 /// `Simulation` has two generic parameters:
 /// - `S`: The type of the states in the markov chain.
 /// - `T`: The type of the transitions in the markov chain, usually a
-/// description. To do anything useful both have to be `Hash + Clone + Send +
-/// Sync + PartialEq + Eq + Debug`.
+///   description. To do anything useful both have to be `Hash + Clone + Send +
+///   Sync + PartialEq + Eq + Debug`.
 ///
 /// It primarily consists of an initial state `S` and a
 /// [StateTransitionGenerator](type.StateTransitionGenerator.html). This
@@ -57,7 +345,7 @@ pub type Time = u64;
 /// // The initial state. It has to be Hash + Clone + Send + Sync + PartialEq + Eq + Debug
 /// let initial_state: i32 = 0;
 ///
-/// // The state transition generator. The simulation panics if the probabilities don't sum to 1.0
+/// // The state transition generator. Stepping fails if the probabilities don't sum to 1.0
 /// let state_transition_generator =
 /// Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
 ///
@@ -65,16 +353,51 @@ pub type Time = u64;
 ///
 /// // The Shannon-entropy at the given time
 /// assert_eq!(simulation.entropy(0), 0.0);
-/// simulation.next_step();
+/// simulation.next_step().unwrap();
 /// assert_eq!(simulation.entropy(1), 1.0);
 /// ```
-#[derive(Clone)]
 pub struct Simulation<S, T> {
     state_transition_graph: StateTransitionGraph,
+    node_indices: HashMap<StateHash, NodeIndex>,
     probability_distributions: HashMap<Time, HashedStateProbabilityDistribution>,
     known_states: KnownStates<S>,
     known_transitions: KnownTransitions<T>,
     state_transition_generator: CachedFunction<S, OutgoingTransitions<S, T>>,
+    normalize_probabilities: bool,
+    history: Option<HashMap<Time, HashedTransitionFlows>>,
+    hash_function: HashFunction,
+    watches: Vec<(S, Probability, WatchCallback)>,
+    constraints: ConstraintSet<S>,
+    pruning_threshold: Probability,
+    pruned_masses: HashMap<Time, Probability>,
+    probability_tolerance: Probability,
+    max_known_states: Option<usize>,
+}
+
+impl<S, T> Clone for Simulation<S, T>
+where
+    S: Hash + Clone + Eq,
+    T: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            state_transition_graph: self.state_transition_graph.clone(),
+            node_indices: self.node_indices.clone(),
+            probability_distributions: self.probability_distributions.clone(),
+            known_states: self.known_states.clone(),
+            known_transitions: self.known_transitions.clone(),
+            state_transition_generator: self.state_transition_generator.clone(),
+            normalize_probabilities: self.normalize_probabilities,
+            history: self.history.clone(),
+            hash_function: self.hash_function.clone(),
+            watches: self.watches.clone(),
+            constraints: self.constraints.clone(),
+            pruning_threshold: self.pruning_threshold,
+            pruned_masses: self.pruned_masses.clone(),
+            probability_tolerance: self.probability_tolerance,
+            max_known_states: self.max_known_states,
+        }
+    }
 }
 
 impl<S, T> Debug for Simulation<S, T>
@@ -104,39 +427,221 @@ where
         initial_state: S,
         state_transition_generator: StateTransitionGenerator<S, T>,
     ) -> Self {
-        let initial_state_hash = hash(&initial_state);
+        SimulationBuilder::new(state_transition_generator)
+            .initial_state(initial_state)
+            .build()
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
 
-        let mut state_transition_graph = Graph::new();
-        state_transition_graph.add_node(initial_state_hash);
+    /// Create a new `Simulation` with the given initial state and state
+    /// transition generator that normalizes the probabilities of the
+    /// generator.
+    ///
+    /// Instead of requiring the probabilities of the outgoing transitions to
+    /// sum up to 1.0, they are treated as relative weights and divided by
+    /// their sum in each step. The state transition graph contains the
+    /// normalized probabilities.
+    pub fn new_normalizing(
+        initial_state: S,
+        state_transition_generator: StateTransitionGenerator<S, T>,
+    ) -> Self {
+        SimulationBuilder::new(state_transition_generator)
+            .initial_state(initial_state)
+            .normalize_probabilities(true)
+            .build()
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
 
-        let probabilities = HashMap::from([(0, HashMap::from([(initial_state_hash, 1.0)]))]);
+    /// Enable the tracking of the transitions that carried probability in each
+    /// step.
+    ///
+    /// The history is recorded for all steps made after calling this method
+    /// and can be read with [history](#method.history). As it stores every
+    /// traversed edge for every step, it is disabled by default.
+    pub fn with_history_tracking(self) -> Self {
+        Self {
+            history: Some(self.history.unwrap_or_default()),
+            ..self
+        }
+    }
 
-        let known_states = HashMap::from([(initial_state_hash, initial_state)]);
+    /// Check every state of the markov chain against the constraints.
+    ///
+    /// [next_step](#method.next_step) checks the states it is made from and
+    /// every state it reaches, so the initial distribution is checked with the
+    /// first step. If a state violates a constraint, it returns
+    /// `SimulationError::ConstraintViolated` with the first constraint it
+    /// violates and leaves the simulation unchanged. The same applies to
+    /// [set_distribution](#method.set_distribution),
+    /// [apply_intervention](#method.apply_intervention) and
+    /// [merge](#method.merge), which check the states they add.
+    ///
+    /// The sampling methods, e.g. [sample_path](#method.sample_path), check
+    /// the states they visit in the same way.
+    pub fn with_constraints(self, constraints: ConstraintSet<S>) -> Self {
+        Self {
+            constraints,
+            ..self
+        }
+    }
 
-        let known_transitions = HashMap::new();
+    /// Limit the number of known states to `max_known_states`.
+    ///
+    /// If a step would make more states known,
+    /// [next_step](#method.next_step) returns
+    /// `SimulationError::MaxKnownStatesExceeded` and leaves the simulation
+    /// unchanged. This guards against markov chains with a state space
+    /// growing faster than expected. The states of the initial distribution
+    /// are not limited.
+    pub fn with_max_known_states(self, max_known_states: usize) -> Self {
+        Self {
+            max_known_states: Some(max_known_states),
+            ..self
+        }
+    }
 
+    /// Limit the cache of the state transition generator to `max_entries`
+    /// states.
+    ///
+    /// By default the outputs of the state transition generator are cached for
+    /// every state, which for markov chains with an infinite number of states
+    /// grows without bound. With a capacity the least recently used entries
+    /// are evicted and recomputed when they are needed again. The current
+    /// cache is discarded.
+    ///
+    /// # Panics
+    /// This method panics if `max_entries` is zero.
+    pub fn with_cache_capacity(self, max_entries: usize) -> Self {
         Self {
-            state_transition_graph,
-            probability_distributions: probabilities,
-            known_states,
-            known_transitions,
-            state_transition_generator: CachedFunction::new(state_transition_generator),
+            state_transition_generator: CachedFunction::new_with_capacity(
+                self.state_transition_generator.function(),
+                max_entries,
+            ),
+            ..self
+        }
+    }
+
+    /// Get the number of states whose outgoing transitions are currently
+    /// cached.
+    pub fn cache_len(&self) -> usize {
+        self.state_transition_generator.len()
+    }
+
+    /// Get the share of calls to the state transition generator that were
+    /// answered by the cache.
+    ///
+    /// Returns 0.0 if the state transition generator was not called yet.
+    pub fn cache_hit_rate(&self) -> f64 {
+        let statistics = self.state_transition_generator.statistics();
+        let calls = statistics.hits + statistics.misses;
+        if calls == 0 {
+            0.0
+        } else {
+            statistics.hits as f64 / calls as f64
         }
     }
 
+    /// Clear the cache of the state transition generator.
+    ///
+    /// The outputs of the generator are recomputed when they are needed
+    /// again. The known states, the state transition graph and the hit rate
+    /// are not affected. As the generator has to be deterministic,
+    /// [next_step](#method.next_step) returns
+    /// `SimulationError::NonDeterministicGenerator` if a recomputed output
+    /// differs from the state transition graph. Use
+    /// [clear_cache_where](#method.clear_cache_where) if the generator
+    /// changed.
+    pub fn clear_cache(&mut self) {
+        self.state_transition_generator.clear();
+    }
+
+    /// Remove the cached outgoing transitions of the states for which
+    /// `predicate` returns `true`.
+    ///
+    /// This allows invalidating only the part of the cache that depends on a
+    /// changed part of the state transition generator. The outgoing
+    /// transitions of these states are removed from the state transition
+    /// graph as well, so that the new outputs of the generator replace them.
+    /// Like [clear_cache](#method.clear_cache), it doesn't affect the known
+    /// states or the hit rate.
+    pub fn clear_cache_where(
+        &mut self,
+        predicate: impl Fn(&S, &OutgoingTransitions<S, T>) -> bool,
+    ) {
+        let mut removed_state_hashes = HashSet::new();
+        self.state_transition_generator
+            .retain(|state, outgoing_transitions| {
+                let remove = predicate(state, outgoing_transitions);
+                if remove {
+                    removed_state_hashes.insert(self.hash_function.hash(state));
+                }
+                !remove
+            });
+        self.state_transition_graph.retain_edges(|graph, edge| {
+            let (source, _) = graph.edge_endpoints(edge).unwrap();
+            !removed_state_hashes.contains(&graph[source])
+        });
+    }
+
     /// Create a new `Simulation` with the given initial state distribution and
     /// state transition generator.
     ///
     /// The initial state distribution is a `HashMap` from states to their
     /// respective probabilities.
+    ///
+    /// # Panics
+    /// This method panics if the initial distribution is invalid or if two of
+    /// the states have the same hash. See
+    /// [try_new_with_distribution](#method.try_new_with_distribution) for a
+    /// non-panicking variant.
     pub fn new_with_distribution(
         probabilities: StateProbabilityDistribution<S>,
         state_transition_generator: StateTransitionGenerator<S, T>,
     ) -> Self {
+        Self::try_new_with_distribution(probabilities, state_transition_generator)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Create a new Simulation with a given initial probability distribution.
+    ///
+    /// Returns an error if the distribution is empty, contains a negative
+    /// probability, its probabilities do not sum up to 1.0 within a tolerance
+    /// of 1e-10 or if two of the states have the same hash.
+    pub fn try_new_with_distribution(
+        probabilities: StateProbabilityDistribution<S>,
+        state_transition_generator: StateTransitionGenerator<S, T>,
+    ) -> Result<Self, SimulationError<S, T>> {
+        SimulationBuilder::new(state_transition_generator)
+            .initial_distribution(probabilities)
+            .build_complete()
+    }
+
+    /// Create a new Simulation with a given initial probability distribution
+    /// which hashes states and transitions with `hash_function`.
+    ///
+    /// This is the constructor behind
+    /// [SimulationBuilder::build](struct.SimulationBuilder.html#method.build),
+    /// which all other constructors go through. The remaining settings of the
+    /// builder are applied to the returned simulation.
+    fn from_distribution(
+        probabilities: StateProbabilityDistribution<S>,
+        state_transition_generator: StateTransitionGenerator<S, T>,
+        hash_function: HashFunction,
+    ) -> Result<Self, SimulationError<S, T>> {
+        validate_weights(&probabilities)?;
+        let sum = probabilities.values().sum::<Probability>();
+        if (sum - 1.0).abs() > INITIAL_DISTRIBUTION_TOLERANCE {
+            return Err(SimulationError::InitialProbabilitiesDoNotSumToOne { sum });
+        }
+        if let Some(error) =
+            find_hash_collision(&hash_function, &HashMap::new(), probabilities.keys())
+        {
+            return Err(error.into());
+        }
         let known_states = probabilities
             .iter()
             .map(|(state, _)| {
-                let state_hash = hash(state);
+                let state_hash = hash_function.hash(state);
                 (state_hash, state.clone())
             })
             .collect::<HashMap<_, _>>();
@@ -146,24 +651,53 @@ where
         let hashed_probabilities = probabilities
             .iter()
             .map(|(state, probability)| {
-                let state_hash = hash(state);
+                let state_hash = hash_function.hash(state);
                 (state_hash, *probability)
             })
             .collect::<HashMap<_, _>>();
 
         let mut graph: StateTransitionGraph = Graph::new();
-        probabilities.iter().for_each(|(state, _)| {
-            let state_hash = hash(state);
-            graph.add_node(state_hash);
-        });
+        let node_indices = probabilities
+            .keys()
+            .map(|state| {
+                let state_hash = hash_function.hash(state);
+                (state_hash, graph.add_node(state_hash))
+            })
+            .collect();
 
-        Self {
+        Ok(Self {
             state_transition_graph: graph,
+            node_indices,
             probability_distributions: HashMap::from([(0, hashed_probabilities)]),
             known_states,
             known_transitions,
             state_transition_generator: CachedFunction::new(state_transition_generator),
-        }
+            normalize_probabilities: false,
+            history: None,
+            hash_function,
+            watches: Vec::new(),
+            constraints: ConstraintSet::new(),
+            pruning_threshold: 0.0,
+            pruned_masses: HashMap::new(),
+            probability_tolerance: DEFAULT_PROBABILITY_TOLERANCE,
+            max_known_states: None,
+        })
+    }
+
+    /// Create a new Simulation with an initial distribution given by relative
+    /// weights.
+    ///
+    /// The weights have to be non-negative and are divided by their sum to get
+    /// the initial probability distribution. Returns an error if there are no
+    /// weights, a weight is negative, the sum of the weights is not positive
+    /// or if two of the states have the same hash.
+    pub fn new_with_weights(
+        weights: HashMap<S, f64>,
+        state_transition_generator: StateTransitionGenerator<S, T>,
+    ) -> Result<Self, SimulationError<S, T>> {
+        SimulationBuilder::new(state_transition_generator)
+            .initial_weights(weights)
+            .build_complete()
     }
 
     fn state(&self, state_hash: StateHash) -> Option<&S> {
@@ -174,6 +708,129 @@ where
         self.known_transitions.get(&transition_hash)
     }
 
+    /// Get the node of a state in the state transition graph.
+    fn node_index(&self, state_hash: StateHash) -> Option<NodeIndex> {
+        self.node_indices.get(&state_hash).copied()
+    }
+
+    /// Get the node of a state in the state transition graph, adding it if it
+    /// doesn't exist yet.
+    fn node_index_or_insert(&mut self, state_hash: StateHash) -> NodeIndex {
+        *self
+            .node_indices
+            .entry(state_hash)
+            .or_insert_with(|| self.state_transition_graph.add_node(state_hash))
+    }
+
+    /// Add the outgoing transitions of a known state to the known states, the
+    /// known transitions and the state transition graph.
+    fn record_transitions(&mut self, source: &S, next_states: &OutgoingTransitions<S, T>) {
+        let source = self.node_index(self.hash_function.hash(source)).unwrap();
+        for (new_state, transition, probability) in next_states {
+            let new_state_hash = self.hash_function.hash(new_state);
+            let transition_hash = self.hash_function.hash(transition);
+            self.known_states.insert(new_state_hash, new_state.clone());
+            self.known_transitions
+                .insert(transition_hash, transition.clone());
+            let target = self.node_index_or_insert(new_state_hash);
+            self.update_transition_edge(source, target, transition_hash, *probability);
+        }
+    }
+
+    /// Add an edge for a transition to the state transition graph or update
+    /// its probability if it already exists.
+    ///
+    /// Distinct transitions between the same states are kept as parallel
+    /// edges.
+    fn update_transition_edge(
+        &mut self,
+        source: NodeIndex,
+        target: NodeIndex,
+        transition_hash: TransitionHash,
+        probability: Probability,
+    ) {
+        let existing_edge = self
+            .state_transition_graph
+            .edges_connecting(source, target)
+            .find(|edge| edge.weight().0 == transition_hash)
+            .map(|edge| edge.id());
+        match existing_edge {
+            Some(edge) => self.state_transition_graph[edge].1 = probability,
+            None => {
+                self.state_transition_graph.add_edge(
+                    source,
+                    target,
+                    (transition_hash, probability),
+                );
+            }
+        }
+    }
+
+    /// Add the outgoing transitions of many known states to the known
+    /// states, the known transitions and the state transition graph.
+    ///
+    /// The new states and transitions and the hashes of the edges are
+    /// collected in parallel, only the state transition graph is updated
+    /// sequentially.
+    fn record_many_transitions(&mut self, transitions: &[(&S, &OutgoingTransitions<S, T>)]) {
+        let hash_function = &self.hash_function;
+        let known_states = &self.known_states;
+        let known_transitions = &self.known_transitions;
+        let (new_states, new_transitions) = transitions
+            .par_iter()
+            .flat_map_iter(|(_, next_states)| next_states.iter())
+            .fold(
+                || (KnownStates::new(), KnownTransitions::new()),
+                |(mut new_states, mut new_transitions), (new_state, transition, _)| {
+                    let new_state_hash = hash_function.hash(new_state);
+                    if !known_states.contains_key(&new_state_hash) {
+                        new_states
+                            .entry(new_state_hash)
+                            .or_insert_with(|| new_state.clone());
+                    }
+                    let transition_hash = hash_function.hash(transition);
+                    if !known_transitions.contains_key(&transition_hash) {
+                        new_transitions
+                            .entry(transition_hash)
+                            .or_insert_with(|| transition.clone());
+                    }
+                    (new_states, new_transitions)
+                },
+            )
+            .reduce(
+                || (KnownStates::new(), KnownTransitions::new()),
+                |(mut states, mut transitions), (other_states, other_transitions)| {
+                    states.extend(other_states);
+                    transitions.extend(other_transitions);
+                    (states, transitions)
+                },
+            );
+        self.known_states.extend(new_states);
+        self.known_transitions.extend(new_transitions);
+
+        let edges = transitions
+            .par_iter()
+            .flat_map_iter(|(source, next_states)| {
+                let source_hash = hash_function.hash(source);
+                next_states
+                    .iter()
+                    .map(move |(new_state, transition, probability)| {
+                        (
+                            source_hash,
+                            hash_function.hash(new_state),
+                            hash_function.hash(transition),
+                            *probability,
+                        )
+                    })
+            })
+            .collect::<HashedTransitionFlows>();
+        for (source, target, transition_hash, probability) in edges {
+            let source = self.node_index(source).unwrap();
+            let target = self.node_index_or_insert(target);
+            self.update_transition_edge(source, target, transition_hash, probability);
+        }
+    }
+
     /// The state transitioning graph of the markov chain.
     ///
     /// The nodes of the graph are the states of the markov chain and the edges
@@ -187,70 +844,69 @@ where
     /// include nodes and transitions generated by e.g. the
     /// (full_traversal)[#method.full_traversal] method.
     pub fn state_transition_graph(&self) -> Graph<S, (T, Probability)> {
-        let mut graph = Graph::new();
-        self.state_transition_graph
-            .node_indices()
-            .map(|node| {
-                let state_hash = *self.state_transition_graph.node_weight(node).unwrap();
-                self.state(state_hash).unwrap().clone()
-            })
-            .for_each(|state| {
-                graph.add_node(state);
-            });
-        let edges = self
-            .state_transition_graph
-            .edge_references()
-            .map(|edge| {
-                let source_hash = *self
-                    .state_transition_graph
-                    .node_weight(edge.source())
-                    .unwrap();
-                let target_hash = *self
-                    .state_transition_graph
-                    .node_weight(edge.target())
-                    .unwrap();
-                let (transition_hash, probability) = edge.weight();
-                let source = self.state(source_hash).unwrap().clone();
-                let target = self.state(target_hash).unwrap().clone();
-                let transition = self.transition(*transition_hash).unwrap().clone();
-                (source, target, transition, *probability)
-            })
-            .collect::<Vec<(S, S, T, Probability)>>();
-        for (source_state, target_state, transition, probability) in edges {
-            // check if nodes already exist
-            let source = graph
-                .node_indices()
-                .find(|node| graph.node_weight(*node).unwrap() == &source_state)
-                .unwrap_or_else(|| graph.add_node(source_state.clone()));
-            let target = graph
-                .node_indices()
-                .find(|node| graph.node_weight(*node).unwrap() == &target_state)
-                .unwrap_or_else(|| graph.add_node(target_state.clone()));
-            graph.update_edge(source, target, (transition, probability));
-        }
-        graph
+        // The mapped graph keeps the node and edge indices of the internal one
+        self.state_transition_graph.map(
+            |_, state_hash| self.state(*state_hash).unwrap().clone(),
+            |_, (transition_hash, probability)| {
+                (
+                    self.transition(*transition_hash).unwrap().clone(),
+                    *probability,
+                )
+            },
+        )
+    }
+
+    /// Get the probability distribution for the given time without cloning
+    /// the states.
+    ///
+    /// The distribution maps the hashes of the states to their probabilities.
+    /// The states can be looked up with [resolve_state](#method.resolve_state).
+    /// Returns `None` if there is no probability distribution for the given
+    /// time.
+    pub fn hashed_distribution(&self, time: Time) -> Option<&HashMap<StateHash, Probability>> {
+        self.probability_distributions.get(&time)
+    }
+
+    /// Get the state transition graph without cloning the states and
+    /// transitions.
+    ///
+    /// The nodes are the hashes of the states and the edges the hashes of the
+    /// transitions with their probabilities. The node and edge indices are
+    /// the same as in [state_transition_graph](#method.state_transition_graph).
+    pub fn hashed_graph(&self) -> &Graph<StateHash, (TransitionHash, Probability)> {
+        &self.state_transition_graph
+    }
+
+    /// Get the known state with the given hash.
+    ///
+    /// Returns `None` if no known state has this hash.
+    pub fn resolve_state(&self, state_hash: StateHash) -> Option<&S> {
+        self.state(state_hash)
+    }
+
+    /// Get the known transition with the given hash.
+    ///
+    /// Returns `None` if no known transition has this hash.
+    pub fn resolve_transition(&self, transition_hash: TransitionHash) -> Option<&T> {
+        self.transition(transition_hash)
+    }
+
+    /// Get the hash the simulation identifies the given state with.
+    pub fn state_hash(&self, state: &S) -> StateHash {
+        self.hash_function.hash(state)
     }
 
     /// Get a HashMap of the probability distributions indexed by time.
     ///
     /// Each probability distribution is a HashMap from states to their
     /// probabilities. The time starts at zero and increases by one for each
-    /// step.
+    /// step. As this clones every state for every time, consider
+    /// [hashed_distribution](#method.hashed_distribution) for large states.
     pub fn probability_distributions(&self) -> HashMap<Time, StateProbabilityDistribution<S>> {
         self.probability_distributions
-            .iter()
-            .map(|(time, state_probability_distribution)| {
-                let state_probability_distribution = state_probability_distribution
-                    .iter()
-                    .map(|(state_hash, probability)| {
-                        let state = self.state(*state_hash).unwrap().clone();
-                        (state, *probability)
-                    })
-                    .collect::<HashMap<_, _>>();
-
-                (*time, state_probability_distribution)
-            })
-            .collect::<HashMap<_, _>>()
+            .keys()
+            .map(|time| (*time, self.try_probability_distribution(*time).unwrap()))
+            .collect()
     }
 
     /// Get the probability of a specific state for the given time.
@@ -260,33 +916,78 @@ where
         self.probability_distributions
             .get(&time)
             .and_then(|state_probability_distribution| {
-                state_probability_distribution.get(&hash(&state))
+                state_probability_distribution.get(&self.hash_function.hash(&state))
             })
             .copied()
             .unwrap_or(0.0)
     }
 
     /// Get the probability distribution for the initial distribution.
+    ///
+    /// # Panics
+    /// This method panics if the initial distribution is no longer recorded.
     pub fn initial_distribution(&self) -> StateProbabilityDistribution<S> {
-        self.probability_distribution(0)
+        self.try_probability_distribution(0)
+            .expect(NO_PROBABILITY_DISTRIBUTION_FOUND)
     }
 
     /// Get the probability distribution for the given time.
     ///
-    /// If the time is not known, the method panics.
+    /// # Panics
+    /// This method panics if there is no probability distribution for the
+    /// given time. See
+    /// [try_probability_distribution](#method.try_probability_distribution)
+    /// for a non-panicking variant.
     pub fn probability_distribution(&self, time: Time) -> StateProbabilityDistribution<S> {
-        self.probability_distributions
-            .get(&time)
+        self.try_probability_distribution(time)
+            .expect(NO_PROBABILITY_DISTRIBUTION_FOUND)
+    }
+
+    /// Get the probability distribution for the given time.
+    ///
+    /// Returns `None` if there is no probability distribution for the given
+    /// time, e.g. because the markov chain has not been simulated that far yet.
+    pub fn try_probability_distribution(
+        &self,
+        time: Time,
+    ) -> Option<StateProbabilityDistribution<S>> {
+        self.hashed_distribution(time)
             .map(|state_probability_distribution| {
                 state_probability_distribution
                     .iter()
                     .map(|(state_hash, probability)| {
-                        let state = self.state(*state_hash).unwrap().clone();
+                        let state = self.resolve_state(*state_hash).unwrap().clone();
                         (state, *probability)
                     })
                     .collect::<HashMap<_, _>>()
             })
-            .expect("No probability distribution found for given time")
+    }
+
+    /// Get the transitions that carried probability in each step.
+    ///
+    /// The history is indexed by the time the step started at, so the entry
+    /// for `time` describes the step from `time` to `time + 1`. Returns `None`
+    /// if history tracking was not enabled with
+    /// [with_history_tracking](#method.with_history_tracking).
+    pub fn history(&self) -> Option<HashMap<Time, StepHistory<S, T>>> {
+        self.history.as_ref().map(|history| {
+            history
+                .iter()
+                .map(|(time, flows)| {
+                    let flows = flows
+                        .iter()
+                        .map(|(source, target, transition, flow)| TransitionFlow {
+                            source: self.state(*source).unwrap().clone(),
+                            target: self.state(*target).unwrap().clone(),
+                            transition: self.transition(*transition).unwrap().clone(),
+                            flow: *flow,
+                        })
+                        .collect_vec();
+                    let transitions = flows.iter().map(|flow| flow.transition.clone()).collect();
+                    (*time, StepHistory { transitions, flows })
+                })
+                .collect()
+        })
     }
 
     /// Gets a list of all known states.
@@ -307,573 +1008,5878 @@ where
         self.known_transitions.values().cloned().collect()
     }
 
-    /// Get the shannon entropy of the markov chain at the given time.
-    pub fn entropy(&self, time: Time) -> f64 {
-        let state_probability_distribution = self.probability_distribution(time);
-        let entropy = state_probability_distribution
-            .values()
-            .map(|probability| probability * probability.log2())
-            .sum::<f64>()
-            .abs();
-        entropy
-    }
-
-    /// Get the current time of the markov chain.
+    /// Get the expected value of a function of the state at the given time.
     ///
-    /// The time starts at zero and increases by one for each step. This method
-    /// returns the time of the newest probability distribution.
-    pub fn time(&self) -> Time {
+    /// This is the sum of `f(state) * probability` over all states of the
+    /// probability distribution at `time`.
+    ///
+    /// # Panics
+    /// This method panics if there is no probability distribution for the
+    /// given time.
+    pub fn expected_value<R>(&self, time: Time, f: impl Fn(&S) -> R) -> R
+    where
+        R: Sum + Mul<f64, Output = R>,
+    {
         self.probability_distributions
-            .keys()
-            .max()
-            .copied()
-            .unwrap_or(0)
+            .get(&time)
+            .expect(NO_PROBABILITY_DISTRIBUTION_FOUND)
+            .iter()
+            .map(|(state_hash, probability)| f(self.state(*state_hash).unwrap()) * *probability)
+            .sum()
     }
 
-    /// Update the markov chain by one step.
+    /// Get the probability distribution at the given time conditioned on the
+    /// state satisfying `predicate`.
     ///
-    /// This method returns the new probability distribution. This method calls
-    /// the state transition generator for each state in the current probability
-    /// distribution. These probabilities are then multiplied with the
-    /// probability of the state they are called on. The new probability
-    /// distribution is the combination of all those distributions.
+    /// Only the states for which `predicate` returns `true` are included and
+    /// their probabilities are renormalized to sum up to 1.0. If the filtered
+    /// states have a total probability of zero, the conditional distribution
+    /// is undefined and an empty distribution is returned.
     ///
     /// # Panics
-    /// This method panics if the probabilities of the state transition
-    /// generator do not sum up to 1.0.
-    pub fn next_step(&mut self) -> StateProbabilityDistribution<S> {
-        let initial_time = self.time();
-        let state_probability_distribution: Vec<(S, Probability)> = self
-            .probability_distribution(initial_time)
-            .into_par_iter()
-            .collect();
-
-        let state_transition_probabilities = self.state_transition_generator.call_many_parallel(
-            state_probability_distribution
-                .par_iter()
-                .map(|(state, _)| state.clone()),
-        );
-
-        // Check if probabilities sum up to 1.0
-        state_transition_probabilities
-            .par_iter()
-            .for_each(|next_states| {
-                assert_eq!(
-                    (next_states
-                        .iter()
-                        .map(|(_, _, probability)| probability)
-                        .sum::<Probability>()
-                        * 10_i64.pow(10) as f64)
-                        .round()
-                        / 10_i64.pow(10) as f64,
-                    1.0,
-                    "Sum of probabilities of next states is not 1.0"
-                );
-            });
-
-        // Calculate new state probability distribution
-        let new_hashed_state_probability_distribution_mutex = Mutex::new(HashMap::new());
-        state_transition_probabilities
-            .par_iter()
-            .zip_eq(state_probability_distribution.par_iter())
-            .for_each(|(next_states, (_, current_state_probability))| {
-                next_states.iter().for_each(|(new_state, _, probability)| {
-                    new_hashed_state_probability_distribution_mutex
-                        .lock()
-                        .unwrap()
-                        .entry(hash(new_state))
-                        .and_modify(|state_probability| {
-                            *state_probability += current_state_probability * probability;
-                        })
-                        .or_insert(current_state_probability * probability);
-                });
-            });
-        // Add new state probability distribution to list of all state probability distributions
-        self.probability_distributions.insert(
-            initial_time + 1,
-            new_hashed_state_probability_distribution_mutex
-                .into_inner()
-                .unwrap(),
-        );
-
-        // Add new states and transitions to known states and transitions
-        state_transition_probabilities
+    /// This method panics if there is no probability distribution for the
+    /// given time.
+    pub fn filter_states(
+        &self,
+        time: Time,
+        predicate: impl Fn(&S) -> bool,
+    ) -> StateProbabilityDistribution<S> {
+        let filtered_distribution = self
+            .probability_distributions
+            .get(&time)
+            .expect(NO_PROBABILITY_DISTRIBUTION_FOUND)
             .iter()
-            .for_each(|next_states| {
-                next_states.iter().for_each(|(new_state, transition, _)| {
-                    self.known_states.insert(hash(new_state), new_state.clone());
-                    self.known_transitions
-                        .insert(hash(transition), transition.clone());
-                });
-            });
-
-        // Add new state transitions to state transition graph
-        state_transition_probabilities
+            .map(|(state_hash, probability)| (self.state(*state_hash).unwrap(), *probability))
+            .filter(|(state, probability)| *probability > 0.0 && predicate(state))
+            .collect_vec();
+        let total_probability = filtered_distribution
             .iter()
-            .zip(state_probability_distribution.iter())
-            .for_each(|(next_states, (old_state, _))| {
-                next_states
-                    .iter()
-                    .for_each(|(new_state, transition, probability)| {
-                        let source = self
-                            .state_transition_graph
-                            .node_indices()
-                            .find(|node_index| {
-                                self.state_transition_graph
-                                    .node_weight(*node_index)
-                                    .unwrap()
-                                    == &hash(old_state)
-                            })
-                            .unwrap();
-                        let target = self
-                            .state_transition_graph
-                            .node_indices()
-                            .find(|node_index| {
-                                self.state_transition_graph
-                                    .node_weight(*node_index)
-                                    .unwrap()
-                                    == &hash(new_state)
-                            })
-                            .unwrap_or_else(|| {
-                                self.state_transition_graph.add_node(hash(new_state))
-                            });
-                        self.state_transition_graph.update_edge(
-                            source,
-                            target,
-                            (hash(transition), *probability),
-                        );
-                    });
-            });
+            .map(|(_, probability)| probability)
+            .sum::<Probability>();
+        filtered_distribution
+            .into_iter()
+            .map(|(state, probability)| (state.clone(), probability / total_probability))
+            .collect()
+    }
 
-        // Return the new state probability distribution
-        self.probability_distribution(initial_time + 1)
+    /// Get all states whose probability at the given time is greater than
+    /// `threshold`.
+    ///
+    /// The ordering is arbitrary.
+    ///
+    /// # Panics
+    /// This method panics if there is no probability distribution for the
+    /// given time.
+    pub fn states_above_threshold(&self, time: Time, threshold: Probability) -> Vec<S> {
+        self.probability_distributions
+            .get(&time)
+            .expect(NO_PROBABILITY_DISTRIBUTION_FOUND)
+            .iter()
+            .filter(|(_, probability)| **probability > threshold)
+            .map(|(state_hash, _)| self.state(*state_hash).unwrap().clone())
+            .collect()
     }
 
-    /// Update the markov chain until all states are known.
+    /// Get the `k` most probable states at the given time.
     ///
-    /// This method calls the (next_step)[#method.next_step] method until the
-    /// list of known states does not change anymore. This means that all states
-    /// reachable by the markov chain are then traversed. If the
-    /// modify_cache_only property is set to `true` the list of probability
-    /// distributions is not updated. Things like
-    /// [state_transition_graph](#method.state_transition_graph) or
-    /// [known_states](#method.known_states) will still be affected by this
-    /// traversal.
+    /// The states are sorted by descending probability, ties are broken by
+    /// the hash of the state. Only the returned states are looked up, so this
+    /// is cheap even for large distributions.
     ///
-    /// If the number of states is infite this method will never return.
-    pub fn full_traversal(&mut self, modify_cache_only: bool) {
-        if modify_cache_only {
-            let mut simulation_clone = self.clone();
-            let mut num_current_known_states = 0;
-            while num_current_known_states != simulation_clone.known_states.len() {
-                num_current_known_states = simulation_clone.known_states.len();
-                simulation_clone.next_step();
-                self.known_states = simulation_clone.known_states.clone();
-                self.known_transitions = simulation_clone.known_transitions.clone();
-                self.state_transition_graph = simulation_clone.state_transition_graph.clone();
-                self.state_transition_generator =
-                    simulation_clone.state_transition_generator.clone();
-            }
-        } else {
-            let mut num_current_known_states = 0;
-            while num_current_known_states != self.known_states.len() {
-                num_current_known_states = self.known_states.len();
-                self.next_step();
-            }
-        }
+    /// # Panics
+    /// This method panics if there is no probability distribution for the
+    /// given time.
+    pub fn most_probable_states(&self, time: Time, k: usize) -> Vec<(S, Probability)> {
+        self.probability_distributions
+            .get(&time)
+            .expect(NO_PROBABILITY_DISTRIBUTION_FOUND)
+            .iter()
+            .sorted_by(|(hash_a, probability_a), (hash_b, probability_b)| {
+                probability_b
+                    .total_cmp(probability_a)
+                    .then(hash_a.cmp(hash_b))
+            })
+            .take(k)
+            .map(|(state_hash, probability)| {
+                (self.state(*state_hash).unwrap().clone(), *probability)
+            })
+            .collect()
     }
 
-    /// Check if the uniform distribution is steady.
+    /// Get all states whose probability at the given time is greater than
+    /// `threshold` together with the remaining probability of all other
+    /// states.
     ///
-    /// This method checks if the uniform distribution is stable i.e. if it
-    /// doesn't change anymore if this distribution is set.
+    /// The states are sorted like in
+    /// [most_probable_states](#method.most_probable_states).
     ///
-    /// If the number of states is infinte this method will never return. It
-    /// will modify the cache of the markov chain, so e.g.
-    /// [state_transition_graph](#method.state_transition_graph) will afterwards
-    /// show the full markov chain.
-    pub fn uniform_distribution_is_steady(&mut self) -> bool {
-        self.full_traversal(true);
-        let mut simulation_clone = self.clone();
-        let uniform_probability = 1.0 / self.known_states.len() as Probability;
-        let uniform_state_probability_distribution = self
-            .known_states
+    /// # Panics
+    /// This method panics if there is no probability distribution for the
+    /// given time.
+    pub fn probability_mass_above(
+        &self,
+        time: Time,
+        threshold: Probability,
+    ) -> (Vec<(S, Probability)>, Probability) {
+        let distribution = self
+            .probability_distributions
+            .get(&time)
+            .expect(NO_PROBABILITY_DISTRIBUTION_FOUND);
+        let (above, below): (Vec<_>, Vec<_>) = distribution
             .iter()
-            .map(|(state_hash, _)| (*state_hash, uniform_probability))
-            .collect::<HashMap<_, _>>();
-        let next_time = simulation_clone.time() + 1;
-        simulation_clone
+            .partition(|(_, probability)| **probability > threshold);
+        let states = above
+            .into_iter()
+            .sorted_by(|(hash_a, probability_a), (hash_b, probability_b)| {
+                probability_b
+                    .total_cmp(probability_a)
+                    .then(hash_a.cmp(hash_b))
+            })
+            .map(|(state_hash, probability)| {
+                (self.state(*state_hash).unwrap().clone(), *probability)
+            })
+            .collect();
+        let remaining_mass = below.into_iter().map(|(_, probability)| probability).sum();
+        (states, remaining_mass)
+    }
+
+    /// Get the shannon entropy of the markov chain at the given time.
+    ///
+    /// # Panics
+    /// This method panics if there is no probability distribution for the
+    /// given time. See [try_entropy](#method.try_entropy) for a non-panicking
+    /// variant.
+    pub fn entropy(&self, time: Time) -> f64 {
+        self.try_entropy(time)
+            .expect(NO_PROBABILITY_DISTRIBUTION_FOUND)
+    }
+
+    /// Get the shannon entropy of the markov chain at the given time.
+    ///
+    /// Returns `None` if there is no probability distribution for the given
+    /// time.
+    pub fn try_entropy(&self, time: Time) -> Option<f64> {
+        self.probability_distributions
+            .get(&time)
+            .map(|state_probability_distribution| {
+                state_probability_distribution
+                    .values()
+                    .map(|probability| probability * probability.log2())
+                    .sum::<f64>()
+                    .abs()
+            })
+    }
+
+    /// Get the Rényi entropy of order `alpha` of the markov chain at the
+    /// given time in bits.
+    ///
+    /// The Rényi entropy is `H_α = log₂(Σ p_i^α) / (1 - α)`. For `alpha = 1`
+    /// it is the shannon [entropy](#method.entropy) and for
+    /// `alpha = f64::INFINITY` the [min-entropy](#method.min_entropy). States
+    /// with a probability of zero are skipped.
+    ///
+    /// # Panics
+    /// This method panics if there is no probability distribution for the
+    /// given time.
+    pub fn renyi_entropy(&self, time: Time, alpha: f64) -> f64 {
+        if alpha == 1.0 {
+            return self.entropy(time);
+        }
+        if alpha == f64::INFINITY {
+            return self.min_entropy(time);
+        }
+        let sum = self
             .probability_distributions
-            .insert(next_time, uniform_state_probability_distribution);
-        let uniform_entropy = simulation_clone.entropy(next_time);
-        simulation_clone.next_step();
-        let uniform_entropy_after_step = simulation_clone.entropy(next_time + 1);
-        uniform_entropy_after_step == uniform_entropy
+            .get(&time)
+            .expect(NO_PROBABILITY_DISTRIBUTION_FOUND)
+            .values()
+            .filter(|probability| **probability > 0.0)
+            .map(|probability| probability.powf(alpha))
+            .sum::<f64>();
+        sum.log2() / (1.0 - alpha)
     }
 
-    /// Get the transition rate matrix of the markov chain.
+    /// Get the min-entropy `-log₂(max p_i)` of the markov chain at the given
+    /// time in bits.
     ///
-    /// This method returns the transition rate matrix of the state transition
-    /// Graph. This is a ndarray where the value at index (i, j) is the
-    /// probability that the markov chain transitions from state i to state j.
-    /// To do that it makes a cache-only full traversal.
+    /// # Panics
+    /// This method panics if there is no probability distribution for the
+    /// given time.
+    pub fn min_entropy(&self, time: Time) -> f64 {
+        let max_probability = self
+            .probability_distributions
+            .get(&time)
+            .expect(NO_PROBABILITY_DISTRIBUTION_FOUND)
+            .values()
+            .copied()
+            .fold(0.0, f64::max);
+        // Avoid returning -0.0 for deterministic distributions
+        0.0 - max_probability.log2()
+    }
+
+    /// Get the total variation distance between the probability distributions
+    /// at the given times.
     ///
-    /// The second part of the return type is the ordering. If a state is at the
-    /// nth position in the vector, it means that it corresponds to the nth row
-    /// and the nth column. The ordering is arbitrary, not necessarily
-    /// consistent over multiple calls and can change at any time in the future.
+    /// See [total_variation_distance](fn.total_variation_distance.html) for
+    /// details.
+    ///
+    /// # Panics
+    /// This method panics if there is no probability distribution for one of
+    /// the given times.
+    pub fn total_variation_distance(&self, time_a: Time, time_b: Time) -> f64 {
+        let distribution_a = self
+            .probability_distributions
+            .get(&time_a)
+            .expect(NO_PROBABILITY_DISTRIBUTION_FOUND);
+        let distribution_b = self
+            .probability_distributions
+            .get(&time_b)
+            .expect(NO_PROBABILITY_DISTRIBUTION_FOUND);
+        total_variation_distance(distribution_a, distribution_b)
+    }
+
+    /// Get the entropy rate of the markov chain in bits.
+    ///
+    /// The entropy rate `H = -Σ π(i) P(i, j) log₂ P(i, j)` is the conditional
+    /// entropy of the next state given the current state, when the markov
+    /// chain is in its [steady state](#method.steady_state_distribution).
+    /// Returns `f64::NAN` if the markov chain is reducible, as the steady state
+    /// is not unique then.
     ///
     /// If the number of states is infinte this method will never return.
     ///
-    /// # Example
-    /// ```rust
-    ///  use entromatica::prelude::*;
-    ///  use std::sync::Arc;
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn entropy_rate(&mut self) -> f64 {
+        let Some(steady_state_distribution) = self.steady_state_distribution() else {
+            return f64::NAN;
+        };
+        let (transition_rate_matrix, ordering) = self.transition_rate_matrix_unchecked();
+        transition_rate_matrix
+            .indexed_iter()
+            .filter(|(_, probability)| **probability > 0.0)
+            .map(|((source, _), probability)| {
+                -steady_state_distribution[&ordering[source]] * probability * probability.log2()
+            })
+            .sum()
+    }
+
+    /// Get the Kullback-Leibler divergence `D_KL(P(time_a) || P(time_b))` in
+    /// bits.
     ///
-    /// // A simple random walk in a circle with NUM_STATES positions
-    /// let initial_state = 0;
-    /// const NUM_STATES: i32 = 5;
-    /// let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
-    ///     vec![
-    ///         (
-    ///             {
-    ///                 if state + 1 == NUM_STATES {
-    ///                     0
-    ///                 } else {
-    ///                     state + 1
-    ///                 }
-    ///             },
-    ///             "forward",
-    ///             0.5,
-    ///         ),
-    ///         (
-    ///             {
-    ///                 if state - 1 == -1 {
-    ///                     NUM_STATES - 1
-    ///                 } else {
-    ///                     state - 1
-    ///                 }
-    ///             },
-    ///             "backward",
-    ///             0.5,
-    ///         ),
-    ///     ]
-    /// });
-    /// let mut simulation = Simulation::new(initial_state, state_transition_generator);
-    /// let (transition_rate_matrix, ordering) = simulation.transition_rate_matrix();
-    /// // The transition rate matrix is a square matrix with a size equal to the number of states
-    /// assert_eq!(transition_rate_matrix.nrows(), NUM_STATES as usize);
-    /// assert_eq!(transition_rate_matrix.ncols(), NUM_STATES as usize);
+    /// See [kl_divergence](fn.kl_divergence.html) for details.
     ///
-    /// // The probability of transitioning from state 0 to 1 is 0.5
-    /// let index = (ordering.iter().position(|state| *state == 0).unwrap(), ordering.iter().position(|state| *state == 1).unwrap());
-    /// assert_eq!(transition_rate_matrix.get(index), Some(&0.5));
-    /// ```
-    pub fn transition_rate_matrix(&mut self) -> (Array2<Probability>, Vec<S>) {
-        self.full_traversal(true);
-        let ordering_hash_map: HashMap<StateHash, usize> = self
-            .known_states
-            .iter()
-            .enumerate()
-            .map(|(index, (hash, _))| (*hash, index))
-            .collect();
-        let mut transition_rate_matrix =
-            Array2::zeros((ordering_hash_map.len(), ordering_hash_map.len()));
-        self.state_transition_graph
-            .edge_references()
-            .for_each(|edge_reference| {
-                let source_index = ordering_hash_map
-                    .get(
-                        self.state_transition_graph
-                            .node_weight(edge_reference.source())
-                            .unwrap(),
-                    )
-                    .unwrap();
-                let target_index = ordering_hash_map
-                    .get(
-                        self.state_transition_graph
-                            .node_weight(edge_reference.target())
-                            .unwrap(),
-                    )
-                    .unwrap();
-                *transition_rate_matrix
-                    .get_mut((*source_index, *target_index))
-                    .unwrap() = edge_reference.weight().1;
-            });
-        (
-            transition_rate_matrix,
-            ordering_hash_map
-                .iter()
-                .map(|(hash, order)| (order, self.known_states.get(hash).unwrap()))
-                .sorted_by(|(index_a, _), (index_b, _)| index_a.cmp(index_b))
-                .map(|(_, state)| state.clone())
-                .collect(),
-        )
+    /// # Panics
+    /// This method panics if there is no probability distribution for one of
+    /// the given times.
+    pub fn kl_divergence(&self, time_a: Time, time_b: Time) -> f64 {
+        let distribution_a = self
+            .probability_distributions
+            .get(&time_a)
+            .expect(NO_PROBABILITY_DISTRIBUTION_FOUND);
+        let distribution_b = self
+            .probability_distributions
+            .get(&time_b)
+            .expect(NO_PROBABILITY_DISTRIBUTION_FOUND);
+        kl_divergence(distribution_a, distribution_b)
     }
 
-    /// Get the stationary distribution of the markov chain.
+    /// Get the mutual information `I(X_s; X_t)` between the states at two times
+    /// in bits.
     ///
-    /// This method returns the stationary distribution of the markov chain by
-    /// exponentiating the transition rate matrix. `rounds` is the number of
-    /// times the transition rate matrix is multiplied with itself and thus
-    /// determines the accuracy and time usage of this method.
-    pub fn stationary_distribution(&mut self, rounds: u64) -> StateProbabilityDistribution<S> {
-        let (transition_rate_matrix, ordering) = self.transition_rate_matrix();
-        let mut new_matrix = transition_rate_matrix.clone();
-        for _ in 0..rounds {
-            new_matrix = new_matrix.dot(&transition_rate_matrix);
-        }
-        dbg!(&new_matrix);
-        let stationary_distribution = new_matrix.row(0);
-
-        ordering
+    /// The joint distribution is `P(X_s = i, X_t = j) = P(X_s = i) P^(t-s)(i, j)`,
+    /// where the `t - s` step transition probabilities are computed by
+    /// propagating each state of the distribution at the earlier time along
+    /// the transitions of the state transition graph. As the graph only
+    /// contains the transitions of states that were stepped, the distributions
+    /// between the two times should be the result of
+    /// [next_step](#method.next_step). The mutual information is symmetric in
+    /// the two times and `I(X_t; X_t)` is the [entropy](#method.entropy) at `t`.
+    ///
+    /// # Panics
+    /// This method panics if there is no probability distribution for one of
+    /// the given times.
+    pub fn mutual_information(&self, time_s: Time, time_t: Time) -> f64 {
+        let (time_s, time_t) = (time_s.min(time_t), time_s.max(time_t));
+        if !self.probability_distributions.contains_key(&time_t) {
+            panic!("{NO_PROBABILITY_DISTRIBUTION_FOUND}");
+        }
+        let graph = &self.state_transition_graph;
+        let joint_distribution = self
+            .probability_distributions
+            .get(&time_s)
+            .expect(NO_PROBABILITY_DISTRIBUTION_FOUND)
+            .par_iter()
+            .filter(|(_, probability)| **probability > 0.0)
+            .map(|(state_hash, probability)| {
+                let mut distribution = HashMap::from([(*state_hash, *probability)]);
+                for _ in time_s..time_t {
+                    let mut next_distribution = HashMap::new();
+                    for (state_hash, probability) in distribution {
+                        for edge in graph.edges(self.node_indices[&state_hash]) {
+                            *next_distribution.entry(graph[edge.target()]).or_insert(0.0) +=
+                                probability * edge.weight().1;
+                        }
+                    }
+                    distribution = next_distribution;
+                }
+                distribution
+            })
+            .collect::<Vec<_>>();
+
+        // The marginals of the joint distribution
+        let mut distribution_t = HashMap::new();
+        for distribution in &joint_distribution {
+            for (state_hash, probability) in distribution {
+                *distribution_t.entry(*state_hash).or_insert(0.0) += probability;
+            }
+        }
+        joint_distribution
             .iter()
-            .zip(stationary_distribution.iter())
-            .map(|(state, probability)| (state.clone(), *probability))
-            .collect()
+            .map(|distribution| {
+                let probability_s = distribution.values().sum::<Probability>();
+                distribution
+                    .iter()
+                    .filter(|(_, probability)| **probability > 0.0)
+                    .map(|(state_hash, probability)| {
+                        probability
+                            * (probability / (probability_s * distribution_t[state_hash])).log2()
+                    })
+                    .sum::<f64>()
+            })
+            .sum()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use ndarray::{Array1, Axis};
+    /// Get the current time of the markov chain.
+    ///
+    /// The time starts at zero and increases by one for each step. This method
+    /// returns the time of the newest probability distribution.
+    pub fn time(&self) -> Time {
+        self.probability_distributions
+            .keys()
+            .max()
+            .copied()
+            .unwrap_or(0)
+    }
 
-    use super::*;
+    /// Get the earliest time which still has a recorded probability
+    /// distribution.
+    ///
+    /// This is 0 unless the timeline was trimmed with
+    /// [trim_before](#method.trim_before).
+    fn earliest_time(&self) -> Time {
+        self.probability_distributions
+            .keys()
+            .min()
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Revert the markov chain to a previous time.
+    ///
+    /// All probability distributions after `to_time` are removed, so
+    /// afterwards [time](#method.time) returns `to_time`. The state transition
+    /// graph, the known states and transitions and the state transition
+    /// generator cache are left intact, as they don't depend on the time.
+    /// Note that this means that the cache is not cleared, so if the state
+    /// transition generator behaves differently than before, e.g. because it
+    /// depends on external state that was changed, the following steps still
+    /// use the old outputs for already known states.
+    ///
+    /// Returns `SimulationError::NoProbabilityDistribution` if there is no
+    /// probability distribution for `to_time`.
+    pub fn rollback(&mut self, to_time: Time) -> Result<(), SimulationError<S, T>> {
+        if !self.probability_distributions.contains_key(&to_time) {
+            return Err(SimulationError::NoProbabilityDistribution { time: to_time });
+        }
+        self.probability_distributions
+            .retain(|time, _| *time <= to_time);
+        if let Some(history) = &mut self.history {
+            history.retain(|time, _| *time < to_time);
+        }
+        self.pruned_masses.retain(|time, _| *time <= to_time);
+        Ok(())
+    }
+
+    /// Drop all probability distributions after `time`, so that
+    /// [next_step](#method.next_step) continues from there.
+    ///
+    /// This is the same as [rollback](#method.rollback).
+    pub fn truncate(&mut self, time: Time) -> Result<(), SimulationError<S, T>> {
+        self.rollback(time)
+    }
+
+    /// Drop all probability distributions before `time` to free memory.
+    ///
+    /// The history and the pruned masses before `time` are dropped as well.
+    /// The distribution of the current time is always kept, so that
+    /// [next_step](#method.next_step) can continue. The known states, the
+    /// state transition graph and the cache of the state transition generator
+    /// are not affected.
+    ///
+    /// Afterwards the dropped times are missing from the recorded timeline:
+    /// [probability_distribution](#method.probability_distribution) and
+    /// [initial_distribution](#method.initial_distribution) panic for them,
+    /// [rollback](#method.rollback),
+    /// [occupation_time](#method.occupation_time) and
+    /// [discounted_occupation](#method.discounted_occupation) return
+    /// `SimulationError::NoProbabilityDistribution` if they need them, and
+    /// [export_csv](#method.export_csv) skips them.
+    /// [sample_path](#method.sample_path),
+    /// [sample_paths](#method.sample_paths),
+    /// [time_reversed](#method.time_reversed), [product](#method.product),
+    /// [lump](#method.lump) and [lumped_chain](#method.lumped_chain) start
+    /// from the earliest recorded distribution instead of the initial one.
+    pub fn trim_before(&mut self, time: Time) {
+        let time = time.min(self.time());
+        self.probability_distributions.retain(|t, _| *t >= time);
+        if let Some(history) = &mut self.history {
+            history.retain(|t, _| *t >= time);
+        }
+        self.pruned_masses.retain(|t, _| *t >= time);
+    }
+
+    /// Keep only the probability distributions of the last `keep_last` times
+    /// to free memory.
+    ///
+    /// This is the same as [trim_before](#method.trim_before) with the first
+    /// time to keep. The distribution of the current time is always kept,
+    /// even if `keep_last` is 0.
+    pub fn trim_history(&mut self, keep_last: u64) {
+        self.trim_before((self.time() + 1).saturating_sub(keep_last));
+    }
+
+    /// Get an independent copy of the simulation as it was at the given time.
+    ///
+    /// This is the same as [branch](#method.branch), but it panics if there is
+    /// no probability distribution for `at_time`.
+    ///
+    /// The copy only contains the probability distributions up to and
+    /// including `at_time`, so its [time](#method.time) is `at_time`. The state
+    /// transition graph, the known states and transitions and the generator
+    /// cache are cloned, while the state transition generator itself is shared,
+    /// which makes this cheaper than cloning and rolling back the simulation.
+    ///
+    /// # Panics
+    /// This method panics if there is no probability distribution for
+    /// `at_time`.
+    pub fn fork(&self, at_time: Time) -> Simulation<S, T> {
+        self.branch(at_time)
+            .unwrap_or_else(|_| panic!("{NO_PROBABILITY_DISTRIBUTION_FOUND}"))
+    }
+
+    /// Get an independent copy of the simulation as it was at the given time.
+    ///
+    /// The copy only contains the probability distributions up to and
+    /// including `at_time`, so its latest distribution is the one at
+    /// `at_time`. The state transition graph, the known states and transitions
+    /// and the generator cache are cloned, while the state transition
+    /// generator itself is shared.
+    ///
+    /// Returns `SimulationError::NoProbabilityDistribution` if there is no
+    /// probability distribution for `at_time`.
+    pub fn branch(&self, at_time: Time) -> Result<Simulation<S, T>, SimulationError<S, T>> {
+        if !self.probability_distributions.contains_key(&at_time) {
+            return Err(SimulationError::NoProbabilityDistribution { time: at_time });
+        }
+        Ok(Self {
+            state_transition_graph: self.state_transition_graph.clone(),
+            node_indices: self.node_indices.clone(),
+            probability_distributions: self
+                .probability_distributions
+                .iter()
+                .filter(|(time, _)| **time <= at_time)
+                .map(|(time, distribution)| (*time, distribution.clone()))
+                .collect(),
+            known_states: self.known_states.clone(),
+            known_transitions: self.known_transitions.clone(),
+            state_transition_generator: self.state_transition_generator.clone(),
+            normalize_probabilities: self.normalize_probabilities,
+            history: self.history.as_ref().map(|history| {
+                history
+                    .iter()
+                    .filter(|(time, _)| **time < at_time)
+                    .map(|(time, flows)| (*time, flows.clone()))
+                    .collect()
+            }),
+            hash_function: self.hash_function.clone(),
+            watches: self.watches.clone(),
+            constraints: self.constraints.clone(),
+            pruning_threshold: self.pruning_threshold,
+            pruned_masses: self
+                .pruned_masses
+                .iter()
+                .filter(|(time, _)| **time <= at_time)
+                .map(|(time, mass)| (*time, *mass))
+                .collect(),
+            probability_tolerance: self.probability_tolerance,
+            max_known_states: self.max_known_states,
+        })
+    }
+
+    /// Merge the explored part of another simulation of the same markov chain
+    /// into this one.
+    ///
+    /// The known states and transitions, the state transition graphs and the
+    /// generator caches are united, so e.g. simulations started from
+    /// different initial distributions on different threads can be combined
+    /// into a single view of the markov chain. Transitions between the same
+    /// states are deduplicated by their hash. The probability distributions of
+    /// both simulations are left untouched.
+    ///
+    /// Both simulations have to share the same state transition generator,
+    /// i.e. `other` was created with a clone of the `Arc` of this simulation's
+    /// generator, otherwise `MergeError::DifferentGenerators` is returned.
+    /// Returns `MergeError::DifferentHashers` if the states of `other` are
+    /// hashed differently and `MergeError::HashCollision` if one of its states
+    /// has the same hash as a different known state. If a state was traversed
+    /// by both simulations but has different outgoing transitions in them,
+    /// e.g. because the generator depends on external state,
+    /// `MergeError::NonDeterministicGenerator` is returned and
+    /// `MergeError::ConstraintViolated` if a state of `other` violates a
+    /// [constraint](#method.with_constraints) of this simulation. Nothing is
+    /// merged in case of an error.
+    pub fn merge(&mut self, other: &Simulation<S, T>) -> Result<(), MergeError<S, T>> {
+        if !self
+            .state_transition_generator
+            .same_function(&other.state_transition_generator)
+        {
+            return Err(MergeError::DifferentGenerators);
+        }
+        if other
+            .known_states
+            .iter()
+            .any(|(state_hash, state)| self.hash_function.hash(state) != *state_hash)
+        {
+            return Err(MergeError::DifferentHashers);
+        }
+        if let Some(error) = find_hash_collision(
+            &self.hash_function,
+            &self.known_states,
+            other.known_states.values(),
+        ) {
+            return Err(error.into());
+        }
+        let graph = &other.state_transition_graph;
+        for node in graph.node_indices() {
+            let next_states = graph
+                .edges(node)
+                .map(|edge| {
+                    let (transition_hash, probability) = edge.weight();
+                    (
+                        other.state(graph[edge.target()]).unwrap().clone(),
+                        other.transition(*transition_hash).unwrap().clone(),
+                        *probability,
+                    )
+                })
+                .collect_vec();
+            if next_states.is_empty() {
+                continue;
+            }
+            let state = other.state(graph[node]).unwrap();
+            if let Some(error) = self.find_nondeterminism(state, &next_states) {
+                return Err(error.into());
+            }
+        }
+        other
+            .known_states
+            .values()
+            .try_for_each(|state| self.constraints.check(state))?;
+
+        for (state_hash, state) in &other.known_states {
+            self.known_states
+                .entry(*state_hash)
+                .or_insert_with(|| state.clone());
+            self.node_index_or_insert(*state_hash);
+        }
+        for (transition_hash, transition) in &other.known_transitions {
+            self.known_transitions
+                .entry(*transition_hash)
+                .or_insert_with(|| transition.clone());
+        }
+        for edge in graph.edge_references() {
+            let source = self.node_index(graph[edge.source()]).unwrap();
+            let target = self.node_index(graph[edge.target()]).unwrap();
+            let (transition_hash, probability) = *edge.weight();
+            self.update_transition_edge(source, target, transition_hash, probability);
+        }
+        self.state_transition_generator
+            .extend(&other.state_transition_generator);
+        Ok(())
+    }
+
+    /// Apply an external intervention to the current probability distribution.
+    ///
+    /// Every state of the current distribution is mapped through `f` and the
+    /// probabilities of states that are mapped onto the same state are added
+    /// up. The result is appended as the distribution of the next time step
+    /// without calling the state transition generator and returned.
+    ///
+    /// The intervention is recorded in the [history](#method.history) with
+    /// `label` as the transition, if history tracking is enabled. It is not
+    /// added to the state transition graph, which only contains the
+    /// transitions of the markov chain itself.
+    ///
+    /// Returns `SimulationError::HashCollision` if a new state has the same
+    /// hash as a different known state and
+    /// `SimulationError::ConstraintViolated` if a new state violates a
+    /// [constraint](#method.with_constraints).
+    pub fn apply_intervention(
+        &mut self,
+        f: impl Fn(&S) -> S,
+        label: T,
+    ) -> Result<StateProbabilityDistribution<S>, SimulationError<S, T>> {
+        let time = self.time();
+        let mapped_states = self.probability_distributions[&time]
+            .iter()
+            .map(|(state_hash, probability)| {
+                (
+                    *state_hash,
+                    f(self.state(*state_hash).unwrap()),
+                    *probability,
+                )
+            })
+            .collect_vec();
+        if let Some(error) = find_hash_collision(
+            &self.hash_function,
+            &self.known_states,
+            mapped_states.iter().map(|(_, state, _)| state),
+        ) {
+            return Err(error.into());
+        }
+        mapped_states
+            .iter()
+            .try_for_each(|(_, state, _)| self.constraints.check(state))?;
+
+        let label_hash = self.hash_function.hash(&label);
+        let mut distribution = HashMap::new();
+        let mut flows = HashMap::new();
+        for (source, state, probability) in mapped_states {
+            let state_hash = self.hash_function.hash(&state);
+            self.known_states.entry(state_hash).or_insert(state);
+            self.node_index_or_insert(state_hash);
+            *distribution.entry(state_hash).or_insert(0.0) += probability;
+            if probability > 0.0 {
+                *flows.entry((source, state_hash)).or_insert(0.0) += probability;
+            }
+        }
+        if let Some(history) = &mut self.history {
+            self.known_transitions.insert(label_hash, label);
+            history.insert(
+                time,
+                flows
+                    .into_iter()
+                    .map(|((source, target), flow)| (source, target, label_hash, flow))
+                    .collect(),
+            );
+        }
+        self.probability_distributions
+            .insert(time + 1, distribution);
+        Ok(self.probability_distribution(time + 1))
+    }
+
+    /// Append the given probability distribution as the next time step.
+    ///
+    /// This replaces the distribution the markov chain would have evolved to,
+    /// e.g. to model an external shock. Returns an error if the distribution is
+    /// empty, contains a negative probability, its probabilities do not sum up
+    /// to 1.0 within the
+    /// [probability tolerance](#method.set_probability_tolerance), if one of its states has the same
+    /// hash as a different known state or if one of its states violates a
+    /// [constraint](#method.with_constraints).
+    pub fn set_distribution(
+        &mut self,
+        probabilities: StateProbabilityDistribution<S>,
+    ) -> Result<(), SimulationError<S, T>> {
+        validate_weights(&probabilities)?;
+        let sum = probabilities.values().sum::<Probability>();
+        if (sum - 1.0).abs() > self.probability_tolerance {
+            return Err(SimulationError::InitialProbabilitiesDoNotSumToOne { sum });
+        }
+        if let Some(error) = find_hash_collision(
+            &self.hash_function,
+            &self.known_states,
+            probabilities.keys(),
+        ) {
+            return Err(error.into());
+        }
+        probabilities
+            .keys()
+            .try_for_each(|state| self.constraints.check(state))?;
+        let time = self.time();
+        let distribution = probabilities
+            .into_iter()
+            .map(|(state, probability)| {
+                let state_hash = self.hash_function.hash(&state);
+                self.known_states.entry(state_hash).or_insert(state);
+                self.node_index_or_insert(state_hash);
+                (state_hash, probability)
+            })
+            .collect();
+        self.probability_distributions
+            .insert(time + 1, distribution);
+        Ok(())
+    }
+
+    /// Update the markov chain by one step.
+    ///
+    /// This method returns the new probability distribution. This method calls
+    /// the state transition generator for each state in the current probability
+    /// distribution. These probabilities are then multiplied with the
+    /// probability of the state they are called on. The new probability
+    /// distribution is the combination of all those distributions.
+    ///
+    /// If the probabilities the state transition generator returned for a
+    /// state do not sum up to 1.0 within the
+    /// [probability tolerance](#method.set_probability_tolerance), the markov
+    /// chain is not updated and a `SimulationError::ProbabilitiesDoNotSumToOne`
+    /// naming that state is returned.
+    ///
+    /// If the simulation was created with
+    /// [new_normalizing](#method.new_normalizing), the probabilities are
+    /// normalized instead and `SimulationError::ProbabilitiesNotNormalizable`
+    /// is returned if their sum is not positive.
+    ///
+    /// If a new state has the same hash as a different state,
+    /// `SimulationError::HashCollision` is returned.
+    pub fn next_step(&mut self) -> Result<StateProbabilityDistribution<S>, SimulationError<S, T>> {
+        let initial_time = self.time();
+        let state_probability_distribution: Vec<(S, Probability)> = self
+            .probability_distribution(initial_time)
+            .into_par_iter()
+            .collect();
+
+        let state_transition_probabilities = self.checked_next_states(
+            &state_probability_distribution
+                .iter()
+                .map(|(state, _)| state)
+                .collect::<Vec<_>>(),
+        )?;
+
+        // Calculate new state probability distribution, each worker sums up
+        // the probabilities of its chunk and the partial sums are merged
+        let new_hashed_state_probability_distribution = state_transition_probabilities
+            .par_iter()
+            .zip_eq(state_probability_distribution.par_iter())
+            .fold(
+                HashMap::new,
+                |mut distribution: HashedStateProbabilityDistribution,
+                 (next_states, (_, current_state_probability))| {
+                    next_states.iter().for_each(|(new_state, _, probability)| {
+                        *distribution
+                            .entry(self.hash_function.hash(new_state))
+                            .or_insert(0.0) += current_state_probability * probability;
+                    });
+                    distribution
+                },
+            )
+            .reduce(HashMap::new, merge_distributions);
+        // Record the probability flowing along each transition
+        if let Some(history) = &mut self.history {
+            let mut flows = HashMap::new();
+            state_transition_probabilities
+                .iter()
+                .zip(state_probability_distribution.iter())
+                .for_each(|(next_states, (old_state, old_state_probability))| {
+                    let old_state_hash = self.hash_function.hash(old_state);
+                    next_states
+                        .iter()
+                        .for_each(|(new_state, transition, probability)| {
+                            let flow = old_state_probability * probability;
+                            if flow > 0.0 {
+                                *flows
+                                    .entry((
+                                        old_state_hash,
+                                        self.hash_function.hash(new_state),
+                                        self.hash_function.hash(transition),
+                                    ))
+                                    .or_insert(0.0) += flow;
+                            }
+                        });
+                });
+            history.insert(
+                initial_time,
+                flows
+                    .into_iter()
+                    .map(|((source, target, transition), flow)| (source, target, transition, flow))
+                    .collect(),
+            );
+        }
+
+        let new_hashed_state_probability_distribution =
+            self.prune(initial_time + 1, new_hashed_state_probability_distribution);
+
+        // Add new state probability distribution to list of all state probability distributions
+        self.probability_distributions
+            .insert(initial_time + 1, new_hashed_state_probability_distribution);
+
+        // Add new states, transitions and edges to the known states, known
+        // transitions and state transition graph
+        self.record_many_transitions(
+            &state_probability_distribution
+                .iter()
+                .map(|(old_state, _)| old_state)
+                .zip(state_transition_probabilities.iter())
+                .collect::<Vec<_>>(),
+        );
+
+        self.notify_watches(initial_time, initial_time + 1);
+
+        // Return the new state probability distribution
+        Ok(self.probability_distribution(initial_time + 1))
+    }
+
+    /// Call the state transition generator for the given states and check
+    /// the returned transitions.
+    ///
+    /// The probabilities are normalized if the simulation is normalizing. An
+    /// error is returned if the probabilities of a state do not sum up to 1.0,
+    /// if a new state has the same hash as a different state or if a new state
+    /// violates a constraint.
+    fn checked_next_states(
+        &mut self,
+        states: &[&S],
+    ) -> Result<Vec<OutgoingTransitions<S, T>>, SimulationError<S, T>> {
+        let (mut state_transition_probabilities, cached): (Vec<_>, Vec<_>) = self
+            .state_transition_generator
+            .call_many_parallel_with_hits(states.par_iter().map(|state| (*state).clone()))
+            .into_iter()
+            .unzip();
+
+        if self.normalize_probabilities {
+            let invalid_next_states = states
+                .par_iter()
+                .zip_eq(state_transition_probabilities.par_iter_mut())
+                .find_map_any(|(state, next_states)| {
+                    let sum = next_states
+                        .iter()
+                        .map(|(_, _, probability)| probability)
+                        .sum::<Probability>();
+                    if !(sum.is_finite() && sum > 0.0) {
+                        return Some((state, next_states.clone(), sum));
+                    }
+                    next_states
+                        .iter_mut()
+                        .for_each(|(_, _, probability)| *probability /= sum);
+                    None
+                });
+            if let Some((state, next_states, sum)) = invalid_next_states {
+                return Err(SimulationError::ProbabilitiesNotNormalizable {
+                    state: (*state).clone(),
+                    next_states,
+                    sum,
+                });
+            }
+        }
+
+        // Check if probabilities sum up to 1.0
+        let invalid_next_states = states
+            .par_iter()
+            .zip_eq(state_transition_probabilities.par_iter())
+            .map(|(state, next_states)| {
+                let sum = next_states
+                    .iter()
+                    .map(|(_, _, probability)| probability)
+                    .sum::<Probability>();
+                (state, next_states, sum)
+            })
+            .find_any(|(_, _, sum)| sum.is_nan() || (sum - 1.0).abs() > self.probability_tolerance);
+        if let Some((state, next_states, sum)) = invalid_next_states {
+            return Err(SimulationError::ProbabilitiesDoNotSumToOne {
+                state: (*state).clone(),
+                next_states: next_states.clone(),
+                sum,
+            });
+        }
+
+        // Check that the generator returned the same outgoing transitions as
+        // before for already traversed states. Cached outputs are skipped, as
+        // they are the ones the edges were recorded from.
+        if let Some(error) = states
+            .par_iter()
+            .zip_eq(state_transition_probabilities.par_iter())
+            .zip_eq(cached.par_iter())
+            .filter(|(_, cached)| !**cached)
+            .find_map_first(|((state, next_states), _)| {
+                self.find_nondeterminism(state, next_states)
+            })
+        {
+            return Err(error.into());
+        }
+
+        // Check that no new state collides with a different state
+        if let Some(error) = find_hash_collision(
+            &self.hash_function,
+            &self.known_states,
+            state_transition_probabilities
+                .iter()
+                .flatten()
+                .map(|(new_state, _, _)| new_state),
+        ) {
+            return Err(error.into());
+        }
+
+        // Check that the new states don't exceed the maximum number of known
+        // states
+        if let Some(max_known_states) = self.max_known_states {
+            let known_states = self.known_states.len()
+                + state_transition_probabilities
+                    .iter()
+                    .flatten()
+                    .map(|(new_state, _, _)| self.hash_function.hash(new_state))
+                    .filter(|new_state_hash| !self.known_states.contains_key(new_state_hash))
+                    .collect::<HashSet<_>>()
+                    .len();
+            if known_states > max_known_states {
+                return Err(SimulationError::MaxKnownStatesExceeded {
+                    max_known_states,
+                    known_states,
+                });
+            }
+        }
+
+        // Check that no state violates a constraint
+        if !self.constraints.is_empty() {
+            if let Some(error) = states
+                .par_iter()
+                .copied()
+                .chain(
+                    state_transition_probabilities
+                        .par_iter()
+                        .flatten()
+                        .map(|(new_state, _, _)| new_state),
+                )
+                .find_map_first(|state| self.constraints.check(state).err())
+            {
+                return Err(error.into());
+            }
+        }
+        Ok(state_transition_probabilities)
+    }
+
+    /// Compare the outgoing transitions the generator returned for a state
+    /// with those in the state transition graph.
+    ///
+    /// Returns `None` if the state was not traversed yet.
+    fn find_nondeterminism(
+        &self,
+        state: &S,
+        next_states: &OutgoingTransitions<S, T>,
+    ) -> Option<NonDeterministicGeneratorError<S, T>> {
+        let node = self.node_index(self.hash_function.hash(state))?;
+        let graph = &self.state_transition_graph;
+        let previous = graph
+            .edges(node)
+            .map(|edge| ((graph[edge.target()], edge.weight().0), edge.weight().1))
+            .collect::<HashMap<_, _>>();
+        if previous.is_empty() {
+            return None;
+        }
+        let current = next_states
+            .iter()
+            .map(|(new_state, transition, probability)| {
+                (
+                    (
+                        self.hash_function.hash(new_state),
+                        self.hash_function.hash(transition),
+                    ),
+                    *probability,
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        if previous == current {
+            return None;
+        }
+        Some(NonDeterministicGeneratorError {
+            state: state.clone(),
+            previous: graph
+                .edges(node)
+                .map(|edge| {
+                    let (transition_hash, probability) = edge.weight();
+                    (
+                        self.state(graph[edge.target()]).unwrap().clone(),
+                        self.transition(*transition_hash).unwrap().clone(),
+                        *probability,
+                    )
+                })
+                .collect(),
+            current: next_states.clone(),
+        })
+    }
+
+    /// Drop the states below the pruning threshold from the distribution of
+    /// the given time, renormalize it and record the pruned mass.
+    fn prune(
+        &mut self,
+        time: Time,
+        mut distribution: HashedStateProbabilityDistribution,
+    ) -> HashedStateProbabilityDistribution {
+        if self.pruning_threshold <= 0.0
+            || distribution
+                .values()
+                .all(|probability| *probability < self.pruning_threshold)
+        {
+            return distribution;
+        }
+        let total_mass = distribution.values().sum::<Probability>();
+        distribution.retain(|_, probability| *probability >= self.pruning_threshold);
+        let remaining_mass = distribution.values().sum::<Probability>();
+        distribution
+            .values_mut()
+            .for_each(|probability| *probability /= remaining_mass);
+        self.pruned_masses.insert(time, total_mass - remaining_mass);
+        distribution
+    }
+
+    /// Drop states with a probability below `epsilon` from every new
+    /// probability distribution.
+    ///
+    /// On diffusive markov chains the number of states with tiny
+    /// probabilities grows without bound, which makes every
+    /// [next_step](#method.next_step) slower. With a positive threshold, the
+    /// states below it are removed from each new distribution and the
+    /// remainder is renormalized. The removed probability of each step can be
+    /// read with [pruned_mass](#method.pruned_mass). If all states are below
+    /// the threshold, nothing is pruned. Pruned states stay in the known
+    /// states and the state transition graph. A threshold of 0 disables
+    /// pruning, which is the default.
+    ///
+    /// # Panics
+    /// This method panics if `epsilon` is not in `[0, 1)`, e.g. if it is NaN.
+    pub fn set_pruning_threshold(&mut self, epsilon: Probability) {
+        assert!(
+            (0.0..1.0).contains(&epsilon),
+            "The pruning threshold {epsilon} is not in [0, 1)"
+        );
+        self.pruning_threshold = epsilon;
+    }
+
+    /// Set the tolerance for the sum of the probabilities the state transition
+    /// generator returns for a state.
+    ///
+    /// [next_step](#method.next_step) returns
+    /// `SimulationError::ProbabilitiesDoNotSumToOne` if the sum differs from
+    /// 1.0 by more than `tolerance`. The default is
+    /// [DEFAULT_PROBABILITY_TOLERANCE](constant.DEFAULT_PROBABILITY_TOLERANCE.html).
+    /// The same tolerance applies to the distributions given to
+    /// [set_distribution](#method.set_distribution).
+    ///
+    /// # Panics
+    /// This method panics if `tolerance` is negative or NaN.
+    pub fn set_probability_tolerance(&mut self, tolerance: Probability) {
+        assert!(
+            tolerance >= 0.0,
+            "The probability tolerance {tolerance} is negative or NaN"
+        );
+        self.probability_tolerance = tolerance;
+    }
+
+    /// Get the tolerance for the sum of the probabilities the state transition
+    /// generator returns for a state, see
+    /// [set_probability_tolerance](#method.set_probability_tolerance).
+    pub fn probability_tolerance(&self) -> Probability {
+        self.probability_tolerance
+    }
+
+    /// Get the probability that was pruned from the distribution of the given
+    /// time before renormalizing, see
+    /// [set_pruning_threshold](#method.set_pruning_threshold).
+    ///
+    /// Returns 0.0 if nothing was pruned at that time.
+    pub fn pruned_mass(&self, time: Time) -> Probability {
+        self.pruned_masses.get(&time).copied().unwrap_or(0.0)
+    }
+
+    /// Call the callbacks of all watches whose threshold was crossed in the
+    /// step to `time`.
+    fn notify_watches(&self, from_time: Time, to_time: Time) {
+        let probability = |time: Time, state: &S| {
+            self.probability_distributions[&time]
+                .get(&self.hash_function.hash(state))
+                .copied()
+                .unwrap_or(0.0)
+        };
+        for (state, threshold, callback) in &self.watches {
+            let old_probability = probability(from_time, state);
+            let new_probability = probability(to_time, state);
+            if (old_probability < *threshold) != (new_probability < *threshold) {
+                callback(to_time, new_probability);
+            }
+        }
+    }
+
+    /// Watch the probability of a state.
+    ///
+    /// After each [next_step](#method.next_step) the callback is called with
+    /// the new time and the new probability of `state` if the probability
+    /// crossed `threshold` in that step, either upwards or downwards. A
+    /// probability equal to the threshold counts as above it.
+    ///
+    /// Watches are kept by clones and [forks](#method.fork) of the simulation,
+    /// but they are not called for steps made internally by analysis methods,
+    /// e.g. in a [full_traversal](#method.full_traversal) with
+    /// `modify_cache_only`.
+    pub fn watch(&mut self, state: S, threshold: Probability, callback: WatchCallback) {
+        self.watches.push((state, threshold, callback));
+    }
+
+    /// Remove all [watches](#method.watch).
+    pub fn clear_watches(&mut self) {
+        self.watches.clear();
+    }
+
+    /// Update the markov chain by one step.
+    ///
+    /// This is the same as [next_step](#method.next_step), but panics instead
+    /// of returning an error.
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn next_step_unchecked(&mut self) -> StateProbabilityDistribution<S> {
+        self.next_step().unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Update the markov chain by the given number of steps.
+    ///
+    /// This method calls [next_step](#method.next_step) `steps` times and
+    /// returns the probability distribution after the last step. The first
+    /// error returned by [next_step](#method.next_step) is propagated.
+    pub fn run(
+        &mut self,
+        steps: u64,
+    ) -> Result<StateProbabilityDistribution<S>, SimulationError<S, T>> {
+        for _ in 0..steps {
+            self.next_step()?;
+        }
+        Ok(self.probability_distribution(self.time()))
+    }
+
+    /// Update the markov chain by the given number of steps.
+    ///
+    /// This is the same as [run](#method.run).
+    pub fn run_n_steps(
+        &mut self,
+        n: u64,
+    ) -> Result<StateProbabilityDistribution<S>, SimulationError<S, T>> {
+        self.run(n)
+    }
+
+    /// Update the markov chain until it reaches the given time.
+    ///
+    /// This method returns the probability distribution at `target_time`. If
+    /// the simulation is already at or after `target_time`,
+    /// `SimulationError::AlreadyAtTime` is returned. The first error returned
+    /// by [next_step](#method.next_step) is propagated.
+    pub fn run_to_time(
+        &mut self,
+        target_time: Time,
+    ) -> Result<StateProbabilityDistribution<S>, SimulationError<S, T>> {
+        let time = self.time();
+        if target_time <= time {
+            return Err(SimulationError::AlreadyAtTime { time, target_time });
+        }
+        self.run(target_time - time)
+    }
+
+    /// Update the markov chain by `n` steps at once using the `n`-th power of
+    /// the transition rate matrix.
+    ///
+    /// The new distribution `π(t + n) = π(t) · P^n` is calculated with
+    /// `O(log n)` multiplications of the
+    /// [transition rate matrix](#method.transition_rate_matrix) by repeated
+    /// squaring, which is faster than [run](#method.run) for large `n` and
+    /// few states. It is stored as the distribution at `self.time() + n` and
+    /// returned. Pruning is applied to the new distribution and
+    /// [watches](#method.watch) are called if the probability crossed the
+    /// threshold between `self.time()` and `self.time() + n`.
+    ///
+    /// The distributions of the skipped times and their history are not
+    /// recorded, so the timeline has a gap afterwards:
+    /// [probability_distribution](#method.probability_distribution) panics for
+    /// the skipped times, [rollback](#method.rollback),
+    /// [occupation_time](#method.occupation_time) and
+    /// [discounted_occupation](#method.discounted_occupation) return
+    /// `SimulationError::NoProbabilityDistribution` if they need them, and
+    /// [export_csv](#method.export_csv) skips them.
+    ///
+    /// If the number of states is infinte this method will never return. It
+    /// will modify the cache of the markov chain, as it makes a cache-only
+    /// [full_traversal](#method.full_traversal).
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn multi_step_matrix(&mut self, n: u64) -> StateProbabilityDistribution<S> {
+        let time = self.time();
+        if n == 0 {
+            return self.probability_distribution(time);
+        }
+        let (transition_rate_matrix, ordering) = self.transition_rate_matrix_unchecked();
+        let state_hashes = ordering
+            .iter()
+            .map(|state| self.hash_function.hash(state))
+            .collect_vec();
+        let distribution = self.hashed_distribution(time).unwrap();
+        let probabilities = state_hashes
+            .iter()
+            .map(|state_hash| distribution.get(state_hash).copied().unwrap_or(0.0))
+            .collect::<Array1<_>>();
+        let new_probabilities = probabilities.dot(&matrix_power(&transition_rate_matrix, n));
+        let new_distribution = state_hashes
+            .into_iter()
+            .zip(new_probabilities)
+            .filter(|(_, probability)| *probability > 0.0)
+            .collect::<HashedStateProbabilityDistribution>();
+        let new_distribution = self.prune(time + n, new_distribution);
+        self.probability_distributions
+            .insert(time + n, new_distribution);
+        self.notify_watches(time, time + n);
+        self.probability_distribution(time + n)
+    }
+
+    /// Get the expected number of times each state is visited up to
+    /// `horizon`.
+    ///
+    /// The occupation time of a state is the sum of its probabilities at the
+    /// times 0 to `horizon`, so the occupation times of all states sum up to
+    /// `horizon + 1`. This is the same as
+    /// [discounted_occupation](#method.discounted_occupation) with a discount
+    /// of 1.0.
+    pub fn occupation_time(
+        &mut self,
+        horizon: Time,
+    ) -> Result<HashMap<S, f64>, SimulationError<S, T>> {
+        self.discounted_occupation(horizon, 1.0)
+    }
+
+    /// Get the expected discounted number of times each state is visited up
+    /// to `horizon`.
+    ///
+    /// The probabilities at time `t` are weighted with `discount^t`. If the
+    /// markov chain has not been simulated up to `horizon` yet, it is updated
+    /// until then, otherwise the recorded probability distributions are used.
+    /// The first error returned by [next_step](#method.next_step) is
+    /// propagated and `SimulationError::NoProbabilityDistribution` is
+    /// returned if a distribution before `horizon` was
+    /// [trimmed](#method.trim_before).
+    pub fn discounted_occupation(
+        &mut self,
+        horizon: Time,
+        discount: f64,
+    ) -> Result<HashMap<S, f64>, SimulationError<S, T>> {
+        if self.time() < horizon {
+            self.run_to_time(horizon)?;
+        }
+        let mut occupation: HashMap<StateHash, f64> = HashMap::new();
+        let mut weight = 1.0;
+        for time in 0..=horizon {
+            let distribution = self
+                .hashed_distribution(time)
+                .ok_or(SimulationError::NoProbabilityDistribution { time })?;
+            for (state_hash, probability) in distribution {
+                *occupation.entry(*state_hash).or_insert(0.0) += weight * probability;
+            }
+            weight *= discount;
+        }
+        Ok(occupation
+            .into_iter()
+            .map(|(state_hash, occupation)| {
+                (self.resolve_state(state_hash).unwrap().clone(), occupation)
+            })
+            .collect())
+    }
+
+    /// Get the probability of being in state `to` after `n` steps when
+    /// starting in state `from`.
+    ///
+    /// The steps are made from a point distribution on `from` besides the
+    /// main timeline, so the time and the probability distributions of the
+    /// simulation stay unchanged. The state transition generator cache, the
+    /// known states and the state transition graph are shared with the main
+    /// timeline, so repeated queries are cheap.
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0 or if `from` has the same hash as a
+    /// different known state.
+    pub fn n_step_probability(&mut self, from: S, to: S, n: u64) -> Probability {
+        if let Some(error) = find_hash_collision(&self.hash_function, &self.known_states, [&from]) {
+            panic!("{error}");
+        }
+        let from_hash = self.hash_function.hash(&from);
+        if !self.known_states.contains_key(&from_hash) {
+            self.known_states.insert(from_hash, from);
+            self.node_index_or_insert(from_hash);
+        }
+        let probability_distributions = std::mem::replace(
+            &mut self.probability_distributions,
+            HashMap::from([(0, HashMap::from([(from_hash, 1.0)]))]),
+        );
+        let history = self.history.take();
+        let watches = std::mem::take(&mut self.watches);
+        let pruning_threshold = std::mem::take(&mut self.pruning_threshold);
+        let pruned_masses = std::mem::take(&mut self.pruned_masses);
+        let result = (0..n).try_for_each(|_| self.next_step().map(|_| ()));
+        let final_distribution = self.probability_distributions.remove(&n);
+        self.probability_distributions = probability_distributions;
+        self.history = history;
+        self.watches = watches;
+        self.pruning_threshold = pruning_threshold;
+        self.pruned_masses = pruned_masses;
+        result.unwrap_or_else(|error| panic!("{error}"));
+        final_distribution
+            .and_then(|distribution| distribution.get(&self.hash_function.hash(&to)).copied())
+            .unwrap_or(0.0)
+    }
+
+    /// Update the markov chain until the predicate is satisfied.
+    ///
+    /// The predicate is checked for the current probability distribution and
+    /// after each step. If `max_steps` is given, at most that many steps are
+    /// made, so this method also returns for infinite markov chains. Returns
+    /// the number of steps that were actually made. The first error returned
+    /// by [next_step](#method.next_step) is propagated.
+    pub fn run_until(
+        &mut self,
+        predicate: impl Fn(&StateProbabilityDistribution<S>) -> bool,
+        max_steps: Option<u64>,
+    ) -> Result<u64, SimulationError<S, T>> {
+        let mut steps = 0;
+        let mut state_probability_distribution = self.probability_distribution(self.time());
+        while !predicate(&state_probability_distribution)
+            && max_steps.is_none_or(|max_steps| steps < max_steps)
+        {
+            state_probability_distribution = self.next_step()?;
+            steps += 1;
+        }
+        Ok(steps)
+    }
+
+    /// Update the markov chain until the probability distribution converges.
+    ///
+    /// This method calls [next_step](#method.next_step) until the L1 distance
+    /// between two successive probability distributions is below `epsilon`
+    /// and returns the last probability distribution.
+    ///
+    /// If `max_steps` is given and the probability distribution has not
+    /// converged after that many steps,
+    /// `SimulationError::NotConverged` with the number of steps and the last
+    /// distance is returned instead. Any other error returned by
+    /// [next_step](#method.next_step) is propagated.
+    pub fn steady_state(
+        &mut self,
+        epsilon: f64,
+        max_steps: Option<u64>,
+    ) -> Result<StateProbabilityDistribution<S>, SimulationError<S, T>> {
+        match self.run_until_convergence(epsilon, max_steps) {
+            Ok(time) => Ok(self.probability_distribution(time)),
+            Err(SimulationError::MaxStepsReached { max_steps }) => Err(ConvergenceError {
+                steps: max_steps,
+                distance: match max_steps {
+                    0 => f64::INFINITY,
+                    _ => 2.0 * self.total_variation_distance(self.time() - 1, self.time()),
+                },
+            }
+            .into()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Update the markov chain until the probability distribution converges.
+    ///
+    /// The stopping criterion is the same as for
+    /// [steady_state](#method.steady_state): the L1 distance between two
+    /// successive probability distributions has to be below `tolerance`.
+    /// Unlike [full_traversal](#method.full_traversal) this also terminates
+    /// for infinite markov chains, as long as the probability mass converges.
+    ///
+    /// Returns the time at which convergence was detected, or
+    /// `SimulationError::MaxStepsReached` if `max_steps` is given and exceeded.
+    /// The first error returned by [next_step](#method.next_step) is
+    /// propagated.
+    pub fn run_until_convergence(
+        &mut self,
+        tolerance: f64,
+        max_steps: Option<u64>,
+    ) -> Result<Time, SimulationError<S, T>> {
+        let mut steps = 0;
+        let mut distance = f64::INFINITY;
+        while distance >= tolerance {
+            if max_steps.is_some_and(|max_steps| steps >= max_steps) {
+                return Err(SimulationError::MaxStepsReached { max_steps: steps });
+            }
+            self.next_step()?;
+            steps += 1;
+            distance = 2.0 * self.total_variation_distance(self.time() - 1, self.time());
+        }
+        Ok(self.time())
+    }
+
+    /// Update the markov chain until all states are known.
+    ///
+    /// This method calls the (next_step)[#method.next_step] method until the
+    /// list of known states does not change anymore. This means that all states
+    /// reachable by the markov chain are then traversed. If the
+    /// modify_cache_only property is set to `true` the list of probability
+    /// distributions is not updated. Things like
+    /// [state_transition_graph](#method.state_transition_graph) or
+    /// [known_states](#method.known_states) will still be affected by this
+    /// traversal.
+    ///
+    /// With modify_cache_only the traversal also starts from the known states
+    /// the state transition generator was not called for yet, e.g. those
+    /// added by [n_step_probability](#method.n_step_probability), so that
+    /// afterwards every known state has outgoing transitions.
+    ///
+    /// If `max_iterations` is given, at most that many steps are made and
+    /// `SimulationError::IterationLimitReached` is returned if the list of
+    /// known states still changes. Otherwise this method never returns if the
+    /// number of states is infinite. The first error returned by
+    /// [next_step](#method.next_step) is propagated.
+    pub fn full_traversal(
+        &mut self,
+        modify_cache_only: bool,
+        max_iterations: Option<u64>,
+    ) -> Result<(), SimulationError<S, T>> {
+        self.full_traversal_with_progress(modify_cache_only, max_iterations, |_, _| true)
+    }
+
+    /// Update the markov chain until all states are known, reporting the
+    /// progress.
+    ///
+    /// This is the same as [full_traversal](#method.full_traversal), but before
+    /// each step `should_continue` is called with the number of steps made so
+    /// far and the current number of known states. If it returns `false`, the
+    /// traversal is stopped and `SimulationError::TraversalCancelled` is
+    /// returned. The states discovered until then stay known.
+    pub fn full_traversal_with_progress(
+        &mut self,
+        modify_cache_only: bool,
+        max_iterations: Option<u64>,
+        should_continue: impl Fn(u64, usize) -> bool,
+    ) -> Result<(), SimulationError<S, T>> {
+        if modify_cache_only {
+            let mut simulation_clone = self.clone();
+            simulation_clone.history = None;
+            simulation_clone.watches.clear();
+            // Pruned states would not be explored any further
+            simulation_clone.pruning_threshold = 0.0;
+            // States added besides the main timeline, e.g. by
+            // n_step_probability, are not reachable from the current
+            // distribution, so the traversal starts from them as well
+            let untraversed_state_hashes = simulation_clone
+                .untraversed_nodes()
+                .map(|node| simulation_clone.state_transition_graph[node])
+                .collect_vec();
+            if !untraversed_state_hashes.is_empty() {
+                let time = simulation_clone.time();
+                let start_state_hashes = simulation_clone.probability_distributions[&time]
+                    .keys()
+                    .copied()
+                    .chain(untraversed_state_hashes)
+                    .collect::<HashSet<_>>();
+                let probability = 1.0 / start_state_hashes.len() as Probability;
+                simulation_clone.probability_distributions.insert(
+                    time,
+                    start_state_hashes
+                        .into_iter()
+                        .map(|state_hash| (state_hash, probability))
+                        .collect(),
+                );
+            }
+            let result = simulation_clone.full_traversal_with_progress(
+                false,
+                max_iterations,
+                should_continue,
+            );
+            self.known_states = simulation_clone.known_states;
+            self.known_transitions = simulation_clone.known_transitions;
+            self.state_transition_graph = simulation_clone.state_transition_graph;
+            self.node_indices = simulation_clone.node_indices;
+            self.state_transition_generator = simulation_clone.state_transition_generator;
+            return result;
+        }
+        let mut iterations = 0;
+        let mut num_current_known_states = 0;
+        while num_current_known_states != self.known_states.len() {
+            num_current_known_states = self.known_states.len();
+            if max_iterations.is_some_and(|max_iterations| iterations >= max_iterations) {
+                return Err(IterationLimitReached {
+                    iterations,
+                    known_states: num_current_known_states,
+                }
+                .into());
+            }
+            if !should_continue(iterations, num_current_known_states) {
+                return Err(SimulationError::TraversalCancelled {
+                    iterations,
+                    known_states: num_current_known_states,
+                });
+            }
+            self.next_step()?;
+            iterations += 1;
+        }
+        Ok(())
+    }
+
+    /// Update the markov chain until all states are known with a breadth-first
+    /// search.
+    ///
+    /// The search starts at the known states the state transition generator
+    /// was not called for yet. The generator is called for all states of the
+    /// current level and the states discovered by it form the next level. In
+    /// contrast to [full_traversal](#method.full_traversal) no probability
+    /// distributions are calculated during the search, so each state is
+    /// visited exactly once. The transitions are checked like in
+    /// [next_step](#method.next_step) and the first error is returned.
+    ///
+    /// If modify_cache_only is set to `false`, the markov chain is afterwards
+    /// updated by one step per level of the search. If the number of states is
+    /// infinite this method never returns.
+    pub fn bfs_traversal(&mut self, modify_cache_only: bool) -> Result<(), SimulationError<S, T>> {
+        let mut levels = 0;
+        let mut frontier = self
+            .untraversed_nodes()
+            .map(|node| {
+                self.state(self.state_transition_graph[node])
+                    .unwrap()
+                    .clone()
+            })
+            .collect::<Vec<_>>();
+        while !frontier.is_empty() {
+            let next_states = self.checked_next_states(&frontier.iter().collect::<Vec<_>>())?;
+            let mut new_states = KnownStates::new();
+            for (new_state, _, _) in next_states.iter().flatten() {
+                let new_state_hash = self.hash_function.hash(new_state);
+                if !self.known_states.contains_key(&new_state_hash) {
+                    new_states
+                        .entry(new_state_hash)
+                        .or_insert_with(|| new_state.clone());
+                }
+            }
+            self.record_many_transitions(
+                &frontier.iter().zip(next_states.iter()).collect::<Vec<_>>(),
+            );
+            frontier = new_states.into_values().collect();
+            levels += 1;
+        }
+        if !modify_cache_only {
+            self.run(levels)?;
+        }
+        Ok(())
+    }
+
+    /// Cache-only full traversal for analysis methods that panic on errors.
+    ///
+    /// The traversal is skipped if every known state already has outgoing
+    /// transitions, as then no new states can be found.
+    fn full_traversal_unchecked(&mut self) {
+        if self.untraversed_nodes().next().is_none() {
+            return;
+        }
+        self.full_traversal(true, None)
+            .unwrap_or_else(|error| panic!("{error}"));
+    }
+
+    /// The nodes of the known states the state transition generator was not
+    /// called for yet, i.e. which have no outgoing transitions.
+    fn untraversed_nodes(&self) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.state_transition_graph
+            .node_indices()
+            .filter(|node| self.state_transition_graph.edges(*node).next().is_none())
+    }
+
+    /// Check if the uniform distribution is steady.
+    ///
+    /// This method checks if the uniform distribution is stable i.e. if it
+    /// doesn't change anymore if this distribution is set.
+    ///
+    /// If the number of states is infinte this method will never return. It
+    /// will modify the cache of the markov chain, so e.g.
+    /// [state_transition_graph](#method.state_transition_graph) will afterwards
+    /// show the full markov chain. `max_iterations` is passed to
+    /// [full_traversal](#method.full_traversal). The first error returned by
+    /// [next_step](#method.next_step) is propagated.
+    pub fn uniform_distribution_is_steady(
+        &mut self,
+        max_iterations: Option<u64>,
+    ) -> Result<bool, SimulationError<S, T>> {
+        self.full_traversal(true, max_iterations)?;
+        let mut simulation_clone = self.clone();
+        simulation_clone.watches.clear();
+        simulation_clone.pruning_threshold = 0.0;
+        let uniform_probability = 1.0 / self.known_states.len() as Probability;
+        let uniform_state_probability_distribution = self
+            .known_states
+            .iter()
+            .map(|(state_hash, _)| (*state_hash, uniform_probability))
+            .collect::<HashMap<_, _>>();
+        let next_time = simulation_clone.time() + 1;
+        simulation_clone
+            .probability_distributions
+            .insert(next_time, uniform_state_probability_distribution);
+        let uniform_entropy = simulation_clone.entropy(next_time);
+        simulation_clone.next_step()?;
+        let uniform_entropy_after_step = simulation_clone.entropy(next_time + 1);
+        Ok(uniform_entropy_after_step == uniform_entropy)
+    }
+
+    /// Get the probabilities of ending up in each absorbing state.
+    ///
+    /// For each transient state, i.e. each state that is not
+    /// [absorbing](#method.absorbing_states), this returns the probability of
+    /// being absorbed in each of the absorbing states when starting in that
+    /// state. This uses the fundamental matrix `N = (I - Q)^-1`, where `Q` is
+    /// the part of the transition rate matrix between transient states.
+    ///
+    /// Returns `SimulationError::NotAbsorbing` if the markov chain has no
+    /// absorbing states or not every state can reach one. If the number of
+    /// states is infinte this method will never return.
+    pub fn absorption_probabilities(
+        &mut self,
+    ) -> Result<HashMap<S, HashMap<S, Probability>>, SimulationError<S, T>> {
+        let absorbing_chain = self.absorbing_chain()?;
+        let absorption_probabilities = absorbing_chain
+            .fundamental_matrix
+            .dot(&absorbing_chain.transient_to_absorbing);
+        Ok(absorbing_chain
+            .transient_states
+            .into_iter()
+            .zip(absorption_probabilities.rows())
+            .map(|(transient_state, probabilities)| {
+                let probabilities = absorbing_chain
+                    .absorbing_states
+                    .iter()
+                    .cloned()
+                    .zip(probabilities.iter().copied())
+                    .collect();
+                (transient_state, probabilities)
+            })
+            .collect())
+    }
+
+    /// Get the expected number of steps until absorption.
+    ///
+    /// For each transient state this returns the expected number of steps
+    /// until the markov chain reaches an absorbing state when starting in that
+    /// state. See
+    /// [absorption_probabilities](#method.absorption_probabilities) for
+    /// details.
+    pub fn expected_steps_to_absorption(
+        &mut self,
+    ) -> Result<HashMap<S, f64>, SimulationError<S, T>> {
+        let absorbing_chain = self.absorbing_chain()?;
+        let expected_steps = absorbing_chain.fundamental_matrix.sum_axis(Axis(1));
+        Ok(absorbing_chain
+            .transient_states
+            .into_iter()
+            .zip(expected_steps)
+            .collect())
+    }
+
+    /// Get the fundamental matrix of an absorbing markov chain.
+    ///
+    /// The fundamental matrix is `N = (I - Q)^-1`, where `Q` is the part of the
+    /// transition rate matrix between transient states, i.e. states that are
+    /// not [absorbing](#method.absorbing_states). The value at index (i, j) is
+    /// the expected number of visits of transient state j when starting in
+    /// transient state i before the markov chain gets absorbed.
+    ///
+    /// The second part of the return type is the ordering of the transient
+    /// states, see [transition_rate_matrix](#method.transition_rate_matrix).
+    /// Returns `None` if the markov chain has no absorbing states or not every
+    /// state can reach one. If the number of states is infinte this method will
+    /// never return.
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn fundamental_matrix(&mut self) -> Option<(Array2<f64>, Vec<S>)> {
+        match self.absorbing_chain() {
+            Ok(absorbing_chain) => Some((
+                absorbing_chain.fundamental_matrix,
+                absorbing_chain.transient_states,
+            )),
+            Err(SimulationError::NotAbsorbing) => None,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    fn absorbing_chain(&mut self) -> Result<AbsorbingChain<S>, SimulationError<S, T>> {
+        let (transition_rate_matrix, ordering) = self.transition_rate_matrix_complete(None)?;
+        let (absorbing, transient): (Vec<usize>, Vec<usize>) =
+            (0..ordering.len()).partition(|index| {
+                (transition_rate_matrix[(*index, *index)] - 1.0).abs()
+                    < ABSORBING_PROBABILITY_TOLERANCE
+            });
+        if absorbing.is_empty() {
+            return Err(SimulationError::NotAbsorbing);
+        }
+        let from_transient = transition_rate_matrix.select(Axis(0), &transient);
+        let transient_to_transient = from_transient.select(Axis(1), &transient);
+        let fundamental_matrix =
+            invert_matrix(Array2::eye(transient.len()) - transient_to_transient)
+                .ok_or(SimulationError::NotAbsorbing)?;
+        Ok(AbsorbingChain {
+            fundamental_matrix,
+            transient_to_absorbing: from_transient.select(Axis(1), &absorbing),
+            transient_states: transient
+                .iter()
+                .map(|index| ordering[*index].clone())
+                .collect(),
+            absorbing_states: absorbing
+                .iter()
+                .map(|index| ordering[*index].clone())
+                .collect(),
+        })
+    }
+
+    /// Check if the markov chain is irreducible.
+    ///
+    /// A markov chain is irreducible if every state can be reached from every
+    /// other state, i.e. if the state transition graph consists of a single
+    /// strongly connected component.
+    ///
+    /// If the number of states is infinte this method will never return. It
+    /// will modify the cache of the markov chain, as it makes a cache-only
+    /// [full_traversal](#method.full_traversal).
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn is_irreducible(&mut self) -> bool {
+        self.full_traversal_unchecked();
+        kosaraju_scc(&self.state_transition_graph).len() == 1
+    }
+
+    /// Get the communication classes of the markov chain.
+    ///
+    /// Two states communicate if each can be reached from the other. Each
+    /// inner vector is one class of states that communicate with each other,
+    /// i.e. one strongly connected component of the state transition graph.
+    /// The ordering is arbitrary, not necessarily consistent over multiple
+    /// calls and can change at any time in the future.
+    ///
+    /// If the number of states is infinte this method will never return. It
+    /// will modify the cache of the markov chain, as it makes a cache-only
+    /// [full_traversal](#method.full_traversal).
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn communication_classes(&mut self) -> Vec<Vec<S>> {
+        self.full_traversal_unchecked();
+        tarjan_scc(&self.state_transition_graph)
+            .into_iter()
+            .map(|class| {
+                class
+                    .into_iter()
+                    .map(|node| {
+                        self.state(self.state_transition_graph[node])
+                            .unwrap()
+                            .clone()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Get the strongly connected components of the state transition graph.
+    ///
+    /// This is the same as
+    /// [communication_classes](#method.communication_classes), named after
+    /// the graph theoretic term.
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn strongly_connected_components(&mut self) -> Vec<Vec<S>> {
+        self.communication_classes()
+    }
+
+    /// Get the condensation graph of the markov chain.
+    ///
+    /// Each node of the condensation graph is one
+    /// [communication class](#method.communication_classes), i.e. one strongly
+    /// connected component of the state transition graph, with its member
+    /// states as the node weight. There is an edge from one class to another if
+    /// any state of the first class has a transition into the second one. The
+    /// resulting graph is acyclic, self loops and duplicate edges are omitted.
+    ///
+    /// If the number of states is infinte this method will never return. It
+    /// will modify the cache of the markov chain, as it makes a cache-only
+    /// [full_traversal](#method.full_traversal) if the state transition graph
+    /// is not complete yet.
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn condensation_graph(&mut self) -> Graph<Vec<S>, ()> {
+        self.full_traversal_unchecked();
+        let graph = self.state_transition_graph.map(
+            |_, state_hash| self.state(*state_hash).unwrap().clone(),
+            |_, _| (),
+        );
+        condensation(graph, true)
+    }
+
+    /// Classify the given state as transient or recurrent.
+    ///
+    /// A state is recurrent if its communication class is closed, i.e. if no
+    /// transition leaves the class, and transient otherwise. As the number of
+    /// states has to be finite for this method to return, recurrent states are
+    /// always positive recurrent.
+    ///
+    /// If the number of states is infinte this method will never return. It
+    /// will modify the cache of the markov chain, as it makes a cache-only
+    /// [full_traversal](#method.full_traversal).
+    ///
+    /// # Panics
+    /// This method panics if the state is not reachable by the markov chain or
+    /// if the probabilities of the state transition generator do not sum up to
+    /// 1.0.
+    pub fn classify_state(&mut self, state: S) -> StateClass {
+        self.full_traversal_unchecked();
+        let state_hash = self.hash_function.hash(&state);
+        let class = tarjan_scc(&self.state_transition_graph)
+            .into_iter()
+            .find(|class| {
+                class
+                    .iter()
+                    .any(|node| self.state_transition_graph[*node] == state_hash)
+            })
+            .expect("State is not reachable by the markov chain");
+        let is_closed = class.iter().all(|node| {
+            self.state_transition_graph
+                .edges(*node)
+                .all(|edge| class.contains(&edge.target()))
+        });
+        if is_closed {
+            StateClass::PositiveRecurrent
+        } else {
+            StateClass::Transient
+        }
+    }
+
+    /// Get the period of the given state.
+    ///
+    /// The period is the greatest common divisor of the lengths of all cycles
+    /// through the state, i.e. of all numbers of steps after which the markov
+    /// chain can return to the state. A state with period 1 is aperiodic. All
+    /// states of a [communication class](#method.communication_classes) have
+    /// the same period. If the markov chain can't return to the state at all,
+    /// the period is 0.
+    ///
+    /// If the number of states is infinte this method will never return. It
+    /// will modify the cache of the markov chain, as it makes a cache-only
+    /// [full_traversal](#method.full_traversal).
+    ///
+    /// # Panics
+    /// This method panics if the state is not reachable by the markov chain or
+    /// if the probabilities of the state transition generator do not sum up to
+    /// 1.0.
+    pub fn period(&mut self, state: S) -> u64 {
+        self.full_traversal_unchecked();
+        let state_hash = self.hash_function.hash(&state);
+        let graph = &self.state_transition_graph;
+        let class = tarjan_scc(graph)
+            .into_iter()
+            .find(|class| class.iter().any(|node| graph[*node] == state_hash))
+            .expect("State is not reachable by the markov chain");
+        let start = *class
+            .iter()
+            .find(|node| graph[**node] == state_hash)
+            .unwrap();
+
+        // Breadth-first search inside the class. Every edge (u, v) inside the
+        // class closes cycles whose lengths differ by level(u) + 1 - level(v),
+        // so the period is the gcd of these differences.
+        let mut levels: HashMap<NodeIndex, u64> = HashMap::from([(start, 0)]);
+        let mut queue = std::collections::VecDeque::from([start]);
+        let mut period = 0;
+        while let Some(node) = queue.pop_front() {
+            let level = levels[&node];
+            for edge in graph.edges(node) {
+                let target = edge.target();
+                if edge.weight().1 <= 0.0 || !class.contains(&target) {
+                    continue;
+                }
+                match levels.get(&target) {
+                    Some(target_level) => {
+                        period = gcd(period, (level + 1).abs_diff(*target_level));
+                    }
+                    None => {
+                        levels.insert(target, level + 1);
+                        queue.push_back(target);
+                    }
+                }
+            }
+        }
+        period
+    }
+
+    /// Get all absorbing states of the markov chain.
+    ///
+    /// A state is absorbing if its only outgoing transition is a transition to
+    /// itself with a probability of 1.0. The ordering is arbitrary, not
+    /// necessarily consistent over multiple calls and can change at any time
+    /// in the future.
+    ///
+    /// If the number of states is infinte this method will never return. It
+    /// will modify the cache of the markov chain, as it makes a cache-only
+    /// [full_traversal](#method.full_traversal).
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn absorbing_states(&mut self) -> Vec<S> {
+        self.full_traversal_unchecked();
+        self.state_transition_graph
+            .node_indices()
+            .filter(|node| self.is_absorbing_node(*node))
+            .map(|node| {
+                let state_hash = self.state_transition_graph.node_weight(node).unwrap();
+                self.state(*state_hash).unwrap().clone()
+            })
+            .collect()
+    }
+
+    /// Check if the given state is absorbing.
+    ///
+    /// See [absorbing_states](#method.absorbing_states) for details. States
+    /// that are not reachable by the markov chain are not absorbing.
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn is_absorbing(&mut self, state: S) -> bool {
+        self.full_traversal_unchecked();
+        self.node_index(self.hash_function.hash(&state))
+            .is_some_and(|node| self.is_absorbing_node(node))
+    }
+
+    fn is_absorbing_node(&self, node: NodeIndex) -> bool {
+        let edges = self.state_transition_graph.edges(node);
+        let mut self_loop_probability = 0.0;
+        for edge in edges {
+            if edge.target() != node {
+                return false;
+            }
+            self_loop_probability += edge.weight().1;
+        }
+        (self_loop_probability - 1.0).abs() < ABSORBING_PROBABILITY_TOLERANCE
+    }
+
+    /// Check if the markov chain is reversible.
+    ///
+    /// A markov chain is reversible if its stationary distribution `π`
+    /// satisfies the detailed balance condition `π(i) P(i, j) = π(j) P(j, i)`
+    /// for all states `i` and `j`. This method uses the [steady state
+    /// distribution](#method.steady_state_distribution), so markov chains that
+    /// are not irreducible are never reversible.
+    ///
+    /// If the number of states is infinte this method will never return.
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn is_reversible(&mut self) -> bool {
+        let Some(steady_state_distribution) = self.steady_state_distribution() else {
+            return false;
+        };
+        let (transition_rate_matrix, ordering) = self.transition_rate_matrix_unchecked();
+        let steady_state_distribution = ordering
+            .iter()
+            .map(|state| steady_state_distribution[state])
+            .collect::<Array1<Probability>>();
+        transition_rate_matrix
+            .indexed_iter()
+            .all(|((source, target), probability)| {
+                let flow = steady_state_distribution[source] * probability;
+                let reverse_flow =
+                    steady_state_distribution[target] * transition_rate_matrix[(target, source)];
+                (flow - reverse_flow).abs() < DETAILED_BALANCE_TOLERANCE
+            })
+    }
+
+    /// Get the time-reversed markov chain.
+    ///
+    /// The time-reversed chain has the transition probabilities
+    /// `P*(i, j) = π(j) P(j, i) / π(i)`, where `π` is the [steady state
+    /// distribution](#method.steady_state_distribution). Each transition from
+    /// `j` to `i` becomes a transition from `i` to `j` with the same label. If
+    /// the markov chain is [reversible](#method.is_reversible), the reversed
+    /// chain has the same transition probabilities.
+    ///
+    /// The returned simulation starts with the same initial distribution, has
+    /// the same probability tolerance and its state transition generator looks
+    /// the states up in a table. If the
+    /// timeline was [trimmed](#method.trim_before), it starts with the
+    /// earliest recorded distribution instead.
+    ///
+    /// Returns `None` if there is no steady state distribution.
+    ///
+    /// If the number of states is infinte this method will never return. It
+    /// will modify the cache of the markov chain, as it makes a cache-only
+    /// [full_traversal](#method.full_traversal).
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn time_reversed(&mut self) -> Option<Simulation<S, T>>
+    where
+        S: 'static,
+        T: 'static,
+    {
+        let steady_state_distribution = self
+            .steady_state_distribution()?
+            .into_iter()
+            .map(|(state, probability)| (self.hash_function.hash(&state), probability))
+            .collect::<HashedStateProbabilityDistribution>();
+        let graph = &self.state_transition_graph;
+        let mut reversed_transitions: HashMap<
+            S,
+            HashMap<(StateHash, TransitionHash), Probability>,
+        > = self
+            .known_states
+            .values()
+            .map(|state| (state.clone(), HashMap::new()))
+            .collect();
+        for edge in graph.edge_references() {
+            let (source, target) = (graph[edge.source()], graph[edge.target()]);
+            let (transition_hash, probability) = edge.weight();
+            *reversed_transitions
+                .get_mut(&self.known_states[&target])
+                .unwrap()
+                .entry((source, *transition_hash))
+                .or_insert(0.0) += steady_state_distribution[&source] * probability
+                / steady_state_distribution[&target];
+        }
+        let reversed_transitions = reversed_transitions
+            .into_iter()
+            .map(|(state, transitions)| {
+                let next_states = transitions
+                    .into_iter()
+                    .map(|((target, transition_hash), probability)| {
+                        (
+                            self.known_states[&target].clone(),
+                            self.known_transitions[&transition_hash].clone(),
+                            probability,
+                        )
+                    })
+                    .collect::<OutgoingTransitions<S, T>>();
+                (state, next_states)
+            })
+            .collect::<HashMap<S, OutgoingTransitions<S, T>>>();
+        let state_transition_generator: StateTransitionGenerator<S, T> =
+            Arc::new(move |state: S| reversed_transitions[&state].clone());
+        let simulation = SimulationBuilder::new(state_transition_generator)
+            .initial_distribution(self.probability_distribution(self.earliest_time()))
+            .hash_function(self.hash_function.clone())
+            .normalize_probabilities(self.normalize_probabilities)
+            .probability_tolerance(self.probability_tolerance)
+            .build()
+            .unwrap_or_else(|error| panic!("{error}"));
+        Some(simulation)
+    }
+
+    /// Get the product of two markov chains.
+    ///
+    /// The states of the product chain are pairs of the states of both chains,
+    /// which transition independently of each other: the probability of a
+    /// transition is the product of the probabilities of the transitions of
+    /// both components. The initial distribution is the product of the two
+    /// initial distributions, or of the earliest recorded distributions if a
+    /// timeline was [trimmed](#method.trim_before).
+    ///
+    /// The product chain uses the hasher of this simulation and normalizes the
+    /// probabilities of the state transition generator if one of the
+    /// simulations does. Its probability tolerance is the sum of both
+    /// tolerances, as the errors of the sums add up in the product. The caches of the state transition generators are
+    /// not shared.
+    ///
+    /// # Panics
+    /// This method panics if two distinct states of the product chain have the
+    /// same hash.
+    pub fn product<S2, T2>(self, other: Simulation<S2, T2>) -> Simulation<(S, S2), (T, T2)>
+    where
+        S: 'static,
+        T: 'static,
+        S2: Hash + Clone + Send + Sync + PartialEq + Eq + Debug + 'static,
+        T2: Hash + Clone + Send + Sync + PartialEq + Eq + Debug + 'static,
+    {
+        let initial_distribution = self
+            .probability_distribution(self.earliest_time())
+            .into_iter()
+            .cartesian_product(
+                other
+                    .probability_distribution(other.earliest_time())
+                    .into_iter()
+                    .collect_vec(),
+            )
+            .map(|((state, probability), (other_state, other_probability))| {
+                ((state, other_state), probability * other_probability)
+            })
+            .collect();
+        let state_transition_generator = self.state_transition_generator.function();
+        let other_state_transition_generator = other.state_transition_generator.function();
+        SimulationBuilder::new(Arc::new(move |(state, other_state): (S, S2)| {
+            state_transition_generator(state)
+                .into_iter()
+                .cartesian_product(other_state_transition_generator(other_state))
+                .map(
+                    |(
+                        (next_state, transition, probability),
+                        (other_next_state, other_transition, other_probability),
+                    )| {
+                        (
+                            (next_state, other_next_state),
+                            (transition, other_transition),
+                            probability * other_probability,
+                        )
+                    },
+                )
+                .collect()
+        }) as StateTransitionGenerator<(S, S2), (T, T2)>)
+        .initial_distribution(initial_distribution)
+        .hash_function(self.hash_function.clone())
+        .normalize_probabilities(self.normalize_probabilities || other.normalize_probabilities)
+        .probability_tolerance(self.probability_tolerance + other.probability_tolerance)
+        .build()
+        .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Get the net probability flow between all pairs of states in the steady
+    /// state as a matrix.
+    ///
+    /// The value at index (i, j) is `F(i→j) = π(i) P(i, j) - π(j) P(j, i)`,
+    /// where `π` is the [steady state
+    /// distribution](#method.steady_state_distribution) and `P` is the
+    /// [transition rate matrix](#method.transition_rate_matrix). The matrix is
+    /// antisymmetric and is zero if the markov chain is
+    /// [reversible](#method.is_reversible). The second part of the return type
+    /// is the ordering of the states, as for the transition rate matrix.
+    ///
+    /// Returns `None` if there is no steady state distribution.
+    ///
+    /// If the number of states is infinte this method will never return.
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn net_flux_matrix(&mut self) -> Option<(Array2<f64>, Vec<S>)> {
+        let steady_state_distribution = self.steady_state_distribution()?;
+        let (transition_rate_matrix, ordering) = self.transition_rate_matrix_unchecked();
+        let steady_state_distribution = ordering
+            .iter()
+            .map(|state| steady_state_distribution[state])
+            .collect::<Array1<Probability>>();
+        let flows = transition_rate_matrix * steady_state_distribution.insert_axis(Axis(1));
+        let net_flux_matrix = &flows - &flows.t();
+        Some((net_flux_matrix, ordering))
+    }
+
+    /// Get the net probability flow along each edge of the state transition
+    /// graph in the steady state.
+    ///
+    /// For every pair of states (i, j) with a transition from i to j the net
+    /// flow `F(i→j) = π(i) P(i, j) - π(j) P(j, i)` is returned, see
+    /// [net_flux_matrix](#method.net_flux_matrix). A positive flow means that
+    /// more probability moves from i to j than back. All flows are zero if the
+    /// markov chain is in detailed balance.
+    ///
+    /// Returns `None` if there is no steady state distribution.
+    ///
+    /// If the number of states is infinte this method will never return.
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn probability_flow(&mut self) -> Option<HashMap<(S, S), f64>> {
+        let (net_flux_matrix, ordering) = self.net_flux_matrix()?;
+        let index = ordering
+            .iter()
+            .enumerate()
+            .map(|(index, state)| (self.hash_function.hash(state), index))
+            .collect::<HashMap<StateHash, usize>>();
+        let graph = &self.state_transition_graph;
+        Some(
+            graph
+                .edge_references()
+                .map(|edge| {
+                    let source = index[&graph[edge.source()]];
+                    let target = index[&graph[edge.target()]];
+                    (
+                        (ordering[source].clone(), ordering[target].clone()),
+                        net_flux_matrix[(source, target)],
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// Get the transition rate matrix of the markov chain.
+    ///
+    /// This method returns the transition rate matrix of the state transition
+    /// Graph. This is a ndarray where the value at index (i, j) is the
+    /// probability that the markov chain transitions from state i to state j.
+    /// To do that it makes a cache-only full traversal.
+    ///
+    /// The second part of the return type is the ordering. If a state is at the
+    /// nth position in the vector, it means that it corresponds to the nth row
+    /// and the nth column. The ordering is arbitrary, not necessarily
+    /// consistent over multiple calls and can change at any time in the future.
+    ///
+    /// `max_iterations` is passed to [full_traversal](#method.full_traversal),
+    /// without it this method never returns if the number of states is
+    /// infinite. The first error returned by [next_step](#method.next_step) is
+    /// propagated.
+    ///
+    /// # Example
+    /// ```rust
+    ///  use entromatica::prelude::*;
+    ///  use std::sync::Arc;
+    ///
+    /// // A simple random walk in a circle with NUM_STATES positions
+    /// let initial_state = 0;
+    /// const NUM_STATES: i32 = 5;
+    /// let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+    ///     vec![
+    ///         (
+    ///             {
+    ///                 if state + 1 == NUM_STATES {
+    ///                     0
+    ///                 } else {
+    ///                     state + 1
+    ///                 }
+    ///             },
+    ///             "forward",
+    ///             0.5,
+    ///         ),
+    ///         (
+    ///             {
+    ///                 if state - 1 == -1 {
+    ///                     NUM_STATES - 1
+    ///                 } else {
+    ///                     state - 1
+    ///                 }
+    ///             },
+    ///             "backward",
+    ///             0.5,
+    ///         ),
+    ///     ]
+    /// });
+    /// let mut simulation = Simulation::new(initial_state, state_transition_generator);
+    /// let (transition_rate_matrix, ordering) = simulation.transition_rate_matrix_complete(None).unwrap();
+    /// // The transition rate matrix is a square matrix with a size equal to the number of states
+    /// assert_eq!(transition_rate_matrix.nrows(), NUM_STATES as usize);
+    /// assert_eq!(transition_rate_matrix.ncols(), NUM_STATES as usize);
+    ///
+    /// // The probability of transitioning from state 0 to 1 is 0.5
+    /// let index = (ordering.iter().position(|state| *state == 0).unwrap(), ordering.iter().position(|state| *state == 1).unwrap());
+    /// assert_eq!(transition_rate_matrix.get(index), Some(&0.5));
+    /// ```
+    pub fn transition_rate_matrix_complete(
+        &mut self,
+        max_iterations: Option<u64>,
+    ) -> Result<(Array2<Probability>, Vec<S>), SimulationError<S, T>> {
+        self.full_traversal(true, max_iterations)?;
+        Ok(self
+            .transition_rate_matrix()
+            .expect("The markov chain is fully traversed"))
+    }
+
+    /// Get the transition rate matrix of the already traversed markov chain.
+    ///
+    /// This is the same as
+    /// [transition_rate_matrix_complete](#method.transition_rate_matrix_complete),
+    /// but it doesn't traverse the markov chain. Instead, an error listing the
+    /// untraversed states is returned if some known state has no outgoing
+    /// transitions yet, i.e. the state transition generator was never called
+    /// for it.
+    pub fn transition_rate_matrix(
+        &self,
+    ) -> Result<(Array2<Probability>, Vec<S>), NotFullyTraversedError<S>> {
+        let untraversed_states = self
+            .untraversed_nodes()
+            .map(|node| {
+                self.state(self.state_transition_graph[node])
+                    .unwrap()
+                    .clone()
+            })
+            .collect_vec();
+        if !untraversed_states.is_empty() {
+            return Err(NotFullyTraversedError { untraversed_states });
+        }
+        let ordering_hash_map: HashMap<StateHash, usize> = self
+            .known_states
+            .iter()
+            .enumerate()
+            .map(|(index, (hash, _))| (*hash, index))
+            .collect();
+        let mut transition_rate_matrix =
+            Array2::zeros((ordering_hash_map.len(), ordering_hash_map.len()));
+        self.state_transition_graph
+            .edge_references()
+            .for_each(|edge_reference| {
+                let source_index = ordering_hash_map
+                    .get(
+                        self.state_transition_graph
+                            .node_weight(edge_reference.source())
+                            .unwrap(),
+                    )
+                    .unwrap();
+                let target_index = ordering_hash_map
+                    .get(
+                        self.state_transition_graph
+                            .node_weight(edge_reference.target())
+                            .unwrap(),
+                    )
+                    .unwrap();
+                *transition_rate_matrix
+                    .get_mut((*source_index, *target_index))
+                    .unwrap() += edge_reference.weight().1;
+            });
+        Ok((
+            transition_rate_matrix,
+            ordering_hash_map
+                .iter()
+                .map(|(hash, order)| (order, self.known_states.get(hash).unwrap()))
+                .sorted_by(|(index_a, _), (index_b, _)| index_a.cmp(index_b))
+                .map(|(_, state)| state.clone())
+                .collect(),
+        ))
+    }
+
+    /// Transition rate matrix for analysis methods that panic on errors.
+    fn transition_rate_matrix_unchecked(&mut self) -> (Array2<Probability>, Vec<S>) {
+        self.transition_rate_matrix_complete(None)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Get the expected number of steps to reach `target` for every state.
+    ///
+    /// The expected hitting times `h` solve the system `h(target) = 0` and
+    /// `h(i) = 1 + Σ_k P(i, k) h(k)` for all other states `i`, where `P` is
+    /// the [transition rate matrix](#method.transition_rate_matrix). For states
+    /// from which `target` is not reached with certainty, including all states
+    /// if `target` is not reachable at all, the value is `f64::INFINITY`.
+    ///
+    /// If the number of states is infinte this method will never return. It
+    /// will modify the cache of the markov chain, as it makes a cache-only
+    /// [full_traversal](#method.full_traversal).
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn expected_hitting_time(&mut self, target: S) -> HashMap<S, f64> {
+        let (transition_rate_matrix, ordering) = self.transition_rate_matrix_unchecked();
+        let Some(target) = ordering.iter().position(|state| *state == target) else {
+            return ordering
+                .into_iter()
+                .map(|state| (state, f64::INFINITY))
+                .collect();
+        };
+        let hitting_times = expected_hitting_times(&transition_rate_matrix, target);
+        ordering.into_iter().zip(hitting_times).collect()
+    }
+
+    /// Get the mean first passage time matrix of the markov chain.
+    ///
+    /// The value at index (i, j) is the expected number of steps to reach
+    /// state j for the first time when starting in state i. On the diagonal
+    /// this is the expected return time. If state j is not reached with
+    /// certainty from state i, the value is `f64::INFINITY`.
+    ///
+    /// The second part of the return type is the ordering, the same as for
+    /// the [transition rate matrix](#method.transition_rate_matrix), which
+    /// this method uses and thus makes a cache-only full traversal.
+    ///
+    /// If the number of states is infinte this method will never return.
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn mfpt_matrix(&mut self) -> (Array2<f64>, Vec<S>) {
+        let (transition_rate_matrix, ordering) = self.transition_rate_matrix_unchecked();
+        let num_states = ordering.len();
+        let mut mfpt_matrix = Array2::zeros((num_states, num_states));
+        for target in 0..num_states {
+            let hitting_times = expected_hitting_times(&transition_rate_matrix, target);
+            mfpt_matrix.column_mut(target).assign(&hitting_times);
+            mfpt_matrix[(target, target)] = 1.0
+                + transition_rate_matrix
+                    .row(target)
+                    .iter()
+                    .zip(hitting_times.iter())
+                    .filter(|(probability, _)| **probability > 0.0)
+                    .map(|(probability, hitting_time)| probability * hitting_time)
+                    .sum::<f64>();
+        }
+        (mfpt_matrix, ordering)
+    }
+
+    /// Get the stationary distribution of the markov chain.
+    ///
+    /// This method returns the stationary distribution of the markov chain by
+    /// power iteration over the [transition rate
+    /// matrix](#method.transition_rate_matrix), starting from a single state.
+    /// To do that it makes a cache-only full traversal.
+    ///
+    /// Returns `None` if there is no unique stationary distribution the chain
+    /// converges to, i.e. if the markov chain is reducible or periodic.
+    ///
+    /// If the number of states is infinte this method will never return.
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn stationary_distribution(&mut self) -> Option<StateProbabilityDistribution<S>> {
+        self.stationary_distribution_power_iteration(
+            STATIONARY_DISTRIBUTION_MAX_ITERATIONS,
+            STATIONARY_DISTRIBUTION_TOLERANCE,
+        )
+    }
+
+    /// Get the stationary distribution of the markov chain by power iteration
+    /// with the given limits.
+    ///
+    /// Starting from a single state, `π_{n+1} = π_n P` is iterated until the
+    /// L1 distance `||π_{n+1} - π_n||₁` is below `tolerance`. Unlike
+    /// [steady_state_distribution](#method.steady_state_distribution) this
+    /// doesn't solve a linear system, so it also works if that system is
+    /// close to singular. To do that it makes a cache-only full traversal.
+    ///
+    /// Returns `None` if the markov chain is reducible or if it didn't
+    /// converge within `max_iterations` iterations, e.g. because it is
+    /// periodic.
+    ///
+    /// If the number of states is infinte this method will never return.
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn stationary_distribution_power_iteration(
+        &mut self,
+        max_iterations: u64,
+        tolerance: f64,
+    ) -> Option<StateProbabilityDistribution<S>> {
+        if !self.is_irreducible() {
+            return None;
+        }
+        let (transition_rate_matrix, ordering) = self.transition_rate_matrix_unchecked();
+        let mut stationary_distribution = Array1::zeros(ordering.len());
+        stationary_distribution[0] = 1.0;
+        for _ in 0..max_iterations {
+            let next_distribution = stationary_distribution.dot(&transition_rate_matrix);
+            let difference = (&next_distribution - &stationary_distribution)
+                .mapv(Probability::abs)
+                .sum();
+            stationary_distribution = next_distribution;
+            if difference < tolerance {
+                return Some(ordering.into_iter().zip(stationary_distribution).collect());
+            }
+        }
+        // Periodic chains oscillate instead of converging
+        None
+    }
+
+    /// Get the steady state distribution of the markov chain.
+    ///
+    /// This method solves the linear system `π P = π` with `Σ π = 1`, where
+    /// `P` is the [transition rate matrix](#method.transition_rate_matrix). To
+    /// do that it makes a cache-only full traversal.
+    ///
+    /// Returns `None` if the markov chain is reducible, i.e. if it consists of
+    /// multiple communicating classes.
+    ///
+    /// If the number of states is infinte this method will never return.
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn steady_state_distribution(&mut self) -> Option<StateProbabilityDistribution<S>> {
+        if !self.is_irreducible() {
+            return None;
+        }
+        let (transition_rate_matrix, ordering) = self.transition_rate_matrix_unchecked();
+        let num_states = ordering.len();
+        // π (P - I) = 0 transposed, with the last equation replaced by Σ π = 1
+        let mut coefficients =
+            (transition_rate_matrix - Array2::<Probability>::eye(num_states)).reversed_axes();
+        coefficients.row_mut(num_states - 1).fill(1.0);
+        let mut constants = Array1::zeros(num_states);
+        constants[num_states - 1] = 1.0;
+        let steady_state = solve_linear_system(coefficients, constants)?;
+
+        Some(ordering.into_iter().zip(steady_state).collect())
+    }
+
+    /// Get the sensitivity of the steady state distribution to each transition
+    /// probability.
+    ///
+    /// For every transition `(i, j)` with `P(i, j) > 0` this returns the
+    /// derivatives `dπ(k)/dP(i, j) = π(i) A#(j, k)` for all states `k`, where
+    /// `π` is the [steady state distribution](#method.steady_state_distribution)
+    /// and `A# = (I - P + 1π)^-1 - 1π` is the group inverse of `I - P`, derived
+    /// from the fundamental matrix of the ergodic chain. As `π` stays
+    /// normalized, the derivatives of each transition sum up to 0. The
+    /// transitions with the largest derivatives for a state are the ones that
+    /// influence its steady state probability the most.
+    ///
+    /// This is only valid for irreducible markov chains, otherwise `None` is
+    /// returned. It makes a cache-only full traversal, so if the number of
+    /// states is infinte this method will never return.
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn sensitivity_analysis(&mut self) -> Option<Sensitivities<S>> {
+        let steady_state_distribution = self.steady_state_distribution()?;
+        let (transition_rate_matrix, ordering) = self.transition_rate_matrix_unchecked();
+        let num_states = ordering.len();
+        let steady_state_distribution = ordering
+            .iter()
+            .map(|state| steady_state_distribution[state])
+            .collect::<Array1<Probability>>();
+        let limit_matrix = Array2::from_shape_fn((num_states, num_states), |(_, column)| {
+            steady_state_distribution[column]
+        });
+        let fundamental_matrix =
+            invert_matrix(Array2::eye(num_states) - &transition_rate_matrix + &limit_matrix)?;
+        let group_inverse = fundamental_matrix - limit_matrix;
+        Some(
+            transition_rate_matrix
+                .indexed_iter()
+                .filter(|(_, probability)| **probability > 0.0)
+                .map(|((source, target), _)| {
+                    let derivatives = ordering
+                        .iter()
+                        .zip(group_inverse.row(target))
+                        .map(|(state, value)| {
+                            (state.clone(), steady_state_distribution[source] * value)
+                        })
+                        .collect();
+                    (
+                        (ordering[source].clone(), ordering[target].clone()),
+                        derivatives,
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// Get the mixing time of the markov chain.
+    ///
+    /// The mixing time `τ(ε) = min{t : max_x d_TV(P^t(x, ·), π) ≤ ε}` is the
+    /// first time at which the distribution of the markov chain is within a
+    /// total variation distance of `epsilon` of the steady state `π`, no
+    /// matter which state it started in. This is the maximum of the
+    /// [mixing times](#method.mixing_times) of all starting states.
+    ///
+    /// Returns the errors of [mixing_times](#method.mixing_times).
+    ///
+    /// If the number of states is infinte this method will never return.
+    pub fn mixing_time(&mut self, epsilon: f64, max_steps: u64) -> Result<Time, MixingError<S, T>> {
+        Ok(self
+            .mixing_times(epsilon, max_steps)?
+            .into_values()
+            .max()
+            .unwrap_or(0))
+    }
+
+    /// Get the mixing time of the markov chain for each starting state.
+    ///
+    /// For each state `x` this is the first time `t` at which the distribution
+    /// `P^t(x, ·)` of the markov chain started in `x` is within a total
+    /// variation distance of `epsilon` of the steady state `π`. States with a
+    /// large mixing time are bottlenecks of the markov chain. `π` is the
+    /// uniform distribution if it is [steady](#method.uniform_distribution_is_steady)
+    /// and the [steady state distribution](#method.steady_state_distribution)
+    /// otherwise.
+    ///
+    /// The chains of all starting states are propagated in parallel over the
+    /// [transition rate matrix](#method.transition_rate_matrix), which is only
+    /// generated once in a full traversal. The time and the probability
+    /// distributions of the simulation stay unchanged.
+    ///
+    /// Returns `MixingError::NotIrreducible` if the markov chain is reducible
+    /// and `MixingError::Periodic` if it is periodic, as it never mixes then,
+    /// and `MixingError::MaxStepsReached` if a chain is not mixed after
+    /// `max_steps` steps. Errors of the full traversal are propagated.
+    ///
+    /// If the number of states is infinte this method will never return.
+    pub fn mixing_times(
+        &mut self,
+        epsilon: f64,
+        max_steps: u64,
+    ) -> Result<HashMap<S, Time>, MixingError<S, T>> {
+        self.full_traversal(true, None)?;
+        if !self.is_irreducible() {
+            return Err(MixingError::NotIrreducible);
+        }
+        let state = self.known_states.values().next().unwrap().clone();
+        let period = self.period(state);
+        if period != 1 {
+            return Err(MixingError::Periodic { period });
+        }
+        let steady_state_distribution = if self.uniform_distribution_is_steady(None)? {
+            let uniform_probability = 1.0 / self.known_states.len() as Probability;
+            self.known_states
+                .values()
+                .map(|state| (state.clone(), uniform_probability))
+                .collect()
+        } else {
+            self.steady_state_distribution()
+                .ok_or(MixingError::NotIrreducible)?
+        };
+
+        let (transition_rate_matrix, ordering) = self.transition_rate_matrix_unchecked();
+        let steady_state_distribution = ordering
+            .iter()
+            .map(|state| steady_state_distribution[state])
+            .collect::<Array1<Probability>>();
+        // The transitions of each state as (target, probability) pairs
+        let transitions = transition_rate_matrix
+            .rows()
+            .into_iter()
+            .map(|row| {
+                row.indexed_iter()
+                    .filter(|(_, probability)| **probability > 0.0)
+                    .map(|(target, probability)| (target, *probability))
+                    .collect_vec()
+            })
+            .collect_vec();
+        (0..ordering.len())
+            .into_par_iter()
+            .map(|start| {
+                let mut distribution = Array1::<Probability>::zeros(ordering.len());
+                distribution[start] = 1.0;
+                let mut time = 0;
+                loop {
+                    let distance = (&distribution - &steady_state_distribution)
+                        .mapv(f64::abs)
+                        .sum()
+                        / 2.0;
+                    if distance <= epsilon {
+                        return Ok((ordering[start].clone(), time));
+                    }
+                    if time == max_steps {
+                        return Err(MixingError::MaxStepsReached {
+                            state: ordering[start].clone(),
+                            max_steps,
+                            distance,
+                        });
+                    }
+                    let mut next_distribution = Array1::zeros(ordering.len());
+                    for (source, probability) in distribution.indexed_iter() {
+                        for (target, transition_probability) in &transitions[source] {
+                            next_distribution[*target] += probability * transition_probability;
+                        }
+                    }
+                    distribution = next_distribution;
+                    time += 1;
+                }
+            })
+            .collect()
+    }
+
+    /// Get the rate at which the probability distribution of the markov chain
+    /// converges.
+    ///
+    /// This is the second largest absolute value `|λ₂|` of the eigenvalues of
+    /// the [transition rate matrix](#method.transition_rate_matrix), the
+    /// distance to the steady state shrinks roughly by this factor in each
+    /// step. It is 1.0 for chains which don't converge to a unique steady
+    /// state, i.e. reducible or periodic ones, and 0.0 for a chain with a
+    /// single state. Returns `f64::NAN` if the eigenvalues can't be computed.
+    ///
+    /// If the number of states is infinte this method will never return. It
+    /// will modify the cache of the markov chain, as it makes a cache-only
+    /// [full_traversal](#method.full_traversal).
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn convergence_rate(&mut self) -> f64 {
+        let (transition_rate_matrix, _) = self.transition_rate_matrix_unchecked();
+        let Some(eigenvalues) = eigenvalues(transition_rate_matrix) else {
+            return f64::NAN;
+        };
+        let magnitudes = eigenvalues
+            .into_iter()
+            .map(|(real, imaginary)| real.hypot(imaginary))
+            .sorted_by(|a, b| b.total_cmp(a))
+            .collect_vec();
+        match magnitudes.get(1) {
+            None => 0.0,
+            Some(magnitude) if 1.0 - magnitude < SPECTRAL_GAP_TOLERANCE => 1.0,
+            Some(magnitude) => *magnitude,
+        }
+    }
+
+    /// Get the absolute spectral gap `1 - |λ₂|` of the markov chain.
+    ///
+    /// See [convergence_rate](#method.convergence_rate) for details. The
+    /// spectral gap is 0.0 for chains which don't converge to a unique steady
+    /// state.
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0.
+    pub fn spectral_gap(&mut self) -> f64 {
+        1.0 - self.convergence_rate()
+    }
+
+    /// Sample a single trajectory of the markov chain.
+    ///
+    /// Instead of propagating the full probability distribution, this method
+    /// draws a starting state from the initial distribution, or from the
+    /// earliest recorded distribution if the timeline was
+    /// [trimmed](#method.trim_before), and then samples
+    /// one outgoing transition per step according to the probabilities of the
+    /// state transition generator. Each entry of the returned path is the
+    /// state reached in that step together with the transition taken.
+    ///
+    /// The probability distributions are not modified, but the known states,
+    /// known transitions and the state transition graph are updated with every
+    /// state visited. The outgoing transitions of each visited state are
+    /// checked like in [next_step](#method.next_step), so the first error,
+    /// e.g. `SimulationError::ProbabilitiesDoNotSumToOne` or
+    /// `SimulationError::ConstraintViolated`, is returned and the state is not
+    /// recorded.
+    pub fn sample_path(
+        &mut self,
+        steps: u64,
+        rng: &mut impl Rng,
+    ) -> Result<Vec<(S, T)>, SimulationError<S, T>> {
+        let mut state = self.sample_initial_state(rng);
+        let mut path = Vec::new();
+        for _ in 0..steps {
+            let next_states = self.checked_next_states(&[&state])?.remove(0);
+            self.record_transitions(&state, &next_states);
+            let Some((next_state, transition, _)) = sample_transition(&next_states, rng) else {
+                break;
+            };
+            path.push((next_state.clone(), transition.clone()));
+            state = next_state.clone();
+        }
+        Ok(path)
+    }
+
+    /// Sample a single trajectory of the markov chain starting at the current
+    /// time.
+    ///
+    /// The starting state is drawn from the probability distribution at
+    /// [time](#method.time), then each step samples the next state according
+    /// to the probabilities of the state transition generator. The returned
+    /// path contains the starting state followed by the state reached in each
+    /// step.
+    ///
+    /// Like [sample_path](#method.sample_path), this method does not modify the
+    /// probability distributions and returns the first error of the checks of
+    /// [next_step](#method.next_step) for a visited state.
+    pub fn sample(
+        &mut self,
+        steps: u64,
+        rng: &mut impl RngCore,
+    ) -> Result<Vec<S>, SimulationError<S, T>> {
+        let mut state = self.sample_state(self.time(), rng);
+        let mut path = vec![state.clone()];
+        for _ in 0..steps {
+            let next_states = self.checked_next_states(&[&state])?.remove(0);
+            self.record_transitions(&state, &next_states);
+            let Some((next_state, _, _)) = sample_transition(&next_states, rng) else {
+                break;
+            };
+            state = next_state.clone();
+            path.push(state.clone());
+        }
+        Ok(path)
+    }
+
+    /// Sample `num_paths` trajectories of the markov chain in parallel.
+    ///
+    /// Returns the empirical probability distribution of the states the paths
+    /// ended up in after `steps` steps. See [sample_path](#method.sample_path)
+    /// for how a single trajectory is sampled and which errors are returned.
+    pub fn sample_paths(
+        &mut self,
+        num_paths: usize,
+        steps: u64,
+    ) -> Result<StateProbabilityDistribution<S>, SimulationError<S, T>> {
+        let mut rng = rand::thread_rng();
+        let mut states = (0..num_paths)
+            .map(|_| self.sample_initial_state(&mut rng))
+            .collect::<Vec<S>>();
+        for _ in 0..steps {
+            let unique_states = states.iter().unique().cloned().collect::<Vec<S>>();
+            let outgoing_transitions =
+                self.checked_next_states(&unique_states.iter().collect::<Vec<_>>())?;
+            self.record_many_transitions(
+                &unique_states
+                    .iter()
+                    .zip(outgoing_transitions.iter())
+                    .collect::<Vec<_>>(),
+            );
+            let outgoing_transitions = unique_states
+                .iter()
+                .map(|state| self.hash_function.hash(state))
+                .zip(outgoing_transitions)
+                .collect::<HashMap<StateHash, OutgoingTransitions<S, T>>>();
+            states = states
+                .into_par_iter()
+                .map_init(rand::thread_rng, |rng, state| {
+                    let next_states = &outgoing_transitions[&self.hash_function.hash(&state)];
+                    match sample_transition(next_states, rng) {
+                        Some((next_state, _, _)) => next_state.clone(),
+                        None => state,
+                    }
+                })
+                .collect();
+        }
+        let state_counts = states.into_iter().counts();
+        Ok(state_counts
+            .into_iter()
+            .map(|(state, count)| (state, count as Probability / num_paths as Probability))
+            .collect())
+    }
+
+    fn sample_initial_state(&self, rng: &mut impl Rng) -> S {
+        self.sample_state(self.earliest_time(), rng)
+    }
+
+    /// Draw a state from the probability distribution at the given time.
+    fn sample_state(&self, time: Time, rng: &mut impl Rng) -> S {
+        let distribution = self
+            .probability_distributions
+            .get(&time)
+            .expect(NO_PROBABILITY_DISTRIBUTION_FOUND)
+            .iter()
+            .collect::<Vec<_>>();
+        let index = WeightedIndex::new(distribution.iter().map(|(_, probability)| **probability))
+            .expect("Distribution is not a valid probability distribution");
+        let (state_hash, _) = distribution[index.sample(rng)];
+        self.state(*state_hash).unwrap().clone()
+    }
+}
+
+impl<S> Simulation<S, ()>
+where
+    S: Hash + Clone + Send + Sync + PartialEq + Eq + Debug + 'static,
+{
+    /// Create a new `Simulation` from a transition matrix.
+    ///
+    /// The entry `(i, j)` of the matrix is the probability of transitioning
+    /// from `states[i]` to `states[j]`. The state transition graph contains
+    /// all transitions with a positive probability, so the markov chain is
+    /// fully traversed from the start. The transitions are not labeled and
+    /// the simulation starts in the first state.
+    ///
+    /// Returns `SimulationError::InvalidTransitionMatrix` if the matrix is not
+    /// square, the number of states doesn't match its number of rows, there
+    /// are no states, a state is given twice, a probability is negative or a
+    /// row does not sum up to 1.0. Returns `SimulationError::HashCollision` if
+    /// two of the states have the same hash.
+    pub fn from_transition_matrix(
+        matrix: Array2<Probability>,
+        states: Vec<S>,
+    ) -> Result<Self, SimulationError<S, ()>> {
+        let invalid = |reason: String| Err(SimulationError::InvalidTransitionMatrix { reason });
+        if matrix.nrows() != matrix.ncols() {
+            return invalid(format!(
+                "the matrix is not square, it has {} rows and {} columns",
+                matrix.nrows(),
+                matrix.ncols()
+            ));
+        }
+        if states.len() != matrix.nrows() {
+            return invalid(format!(
+                "there are {} states, but the matrix has {} rows",
+                states.len(),
+                matrix.nrows()
+            ));
+        }
+        if states.is_empty() {
+            return invalid("there are no states".to_string());
+        }
+        if let Some(error) = find_hash_collision(&HashFunction::default(), &HashMap::new(), &states)
+        {
+            return Err(error.into());
+        }
+        if let Some(state) = states.iter().duplicates().next() {
+            return invalid(format!("the state {state:?} is given twice"));
+        }
+        for (row, state) in matrix.rows().into_iter().zip(&states) {
+            if let Some(probability) = row
+                .iter()
+                .find(|probability| probability.is_nan() || **probability < 0.0)
+            {
+                return invalid(format!(
+                    "the row of {state:?} contains the negative probability {probability}"
+                ));
+            }
+            let sum = row.sum();
+            if (sum - 1.0).abs() > TRANSITION_MATRIX_TOLERANCE {
+                return invalid(format!("the row of {state:?} sums up to {sum}, not 1.0"));
+            }
+        }
+
+        let transitions = states
+            .iter()
+            .zip(matrix.rows())
+            .map(|(state, row)| {
+                let next_states = states
+                    .iter()
+                    .zip(row)
+                    .filter(|(_, probability)| **probability > 0.0)
+                    .map(|(next_state, probability)| (next_state.clone(), (), *probability))
+                    .collect::<OutgoingTransitions<S, ()>>();
+                (state.clone(), next_states)
+            })
+            .collect::<HashMap<S, OutgoingTransitions<S, ()>>>();
+        let state_transition_generator: StateTransitionGenerator<S, ()> = {
+            let transitions = transitions.clone();
+            Arc::new(move |state: S| transitions[&state].clone())
+        };
+
+        let mut simulation = Self::new(states[0].clone(), state_transition_generator);
+        for state in &states {
+            let state_hash = simulation.hash_function.hash(state);
+            simulation.known_states.insert(state_hash, state.clone());
+            simulation.node_index_or_insert(state_hash);
+        }
+        for state in &states {
+            simulation.record_transitions(state, &transitions[state]);
+        }
+        Ok(simulation)
+    }
+}
+
+/// The canonical form of an absorbing markov chain.
+struct AbsorbingChain<S> {
+    /// `N = (I - Q)^-1` with `Q` being the transitions between transient states
+    fundamental_matrix: Array2<f64>,
+    /// `R`, the transitions from transient to absorbing states
+    transient_to_absorbing: Array2<Probability>,
+    transient_states: Vec<S>,
+    absorbing_states: Vec<S>,
+}
+
+/// Get the expected number of steps to reach the state with the index `target`
+/// for every state of a transition rate matrix.
+///
+/// The expected number of steps is zero for the target itself and
+/// `f64::INFINITY` for every state from which the target is not reached with
+/// certainty.
+fn expected_hitting_times(
+    transition_rate_matrix: &Array2<Probability>,
+    target: usize,
+) -> Array1<f64> {
+    let num_states = transition_rate_matrix.nrows();
+    // The target is made absorbing, as everything after reaching it is irrelevant
+    let mut predecessors = vec![Vec::new(); num_states];
+    for ((source, destination), probability) in transition_rate_matrix.indexed_iter() {
+        if source != target && *probability > 0.0 {
+            predecessors[destination].push(source);
+        }
+    }
+    let reachers = |destinations: &[bool]| -> Vec<bool> {
+        let mut reaches = destinations.to_vec();
+        let mut queue = (0..num_states)
+            .filter(|state| destinations[*state])
+            .collect::<Vec<_>>();
+        while let Some(state) = queue.pop() {
+            for predecessor in &predecessors[state] {
+                if !reaches[*predecessor] {
+                    reaches[*predecessor] = true;
+                    queue.push(*predecessor);
+                }
+            }
+        }
+        reaches
+    };
+    let reaches_target = reachers(&(0..num_states).map(|state| state == target).collect_vec());
+    let misses_target = reachers(&reaches_target.iter().map(|reaches| !reaches).collect_vec());
+    let unknowns = (0..num_states)
+        .filter(|state| *state != target && !misses_target[*state])
+        .collect_vec();
+
+    let mut coefficients = Array2::<f64>::eye(unknowns.len());
+    for (row, source) in unknowns.iter().enumerate() {
+        for (column, destination) in unknowns.iter().enumerate() {
+            coefficients[(row, column)] -= transition_rate_matrix[(*source, *destination)];
+        }
+    }
+    let solution = solve_linear_system(coefficients, Array1::ones(unknowns.len()))
+        .expect("Hitting times of states that reach the target with certainty are finite");
+
+    let mut hitting_times = Array1::from_elem(num_states, f64::INFINITY);
+    hitting_times[target] = 0.0;
+    for (state, hitting_time) in unknowns.into_iter().zip(solution) {
+        hitting_times[state] = hitting_time;
+    }
+    hitting_times
+}
+
+/// Merge two partial probability distributions by adding up the
+/// probabilities of the states they have in common.
+fn merge_distributions(
+    mut distribution: HashedStateProbabilityDistribution,
+    mut other: HashedStateProbabilityDistribution,
+) -> HashedStateProbabilityDistribution {
+    if distribution.len() < other.len() {
+        std::mem::swap(&mut distribution, &mut other);
+    }
+    other.into_iter().for_each(|(state_hash, probability)| {
+        *distribution.entry(state_hash).or_insert(0.0) += probability;
+    });
+    distribution
+}
+
+/// Divides non-negative weights by their sum to get a probability
+/// distribution.
+fn normalize_weights<S, T>(
+    weights: HashMap<S, f64>,
+) -> Result<StateProbabilityDistribution<S>, SimulationError<S, T>>
+where
+    S: Hash + Clone + Eq,
+{
+    validate_weights(&weights)?;
+    let sum = weights.values().sum::<f64>();
+    if !(sum.is_finite() && sum > 0.0) {
+        return Err(SimulationError::WeightsNotNormalizable { sum });
+    }
+    Ok(weights
+        .into_iter()
+        .map(|(state, weight)| (state, weight / sum))
+        .collect())
+}
+
+/// Checks that the initial distribution is not empty and contains no negative
+/// probabilities or weights.
+fn validate_weights<S, T>(weights: &HashMap<S, f64>) -> Result<(), SimulationError<S, T>>
+where
+    S: Clone,
+{
+    if weights.is_empty() {
+        return Err(SimulationError::EmptyDistribution);
+    }
+    match weights
+        .iter()
+        .find(|(_, weight)| weight.is_nan() || **weight < 0.0)
+    {
+        Some((state, weight)) => Err(SimulationError::NegativeProbability {
+            state: state.clone(),
+            probability: *weight,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// The greatest common divisor of two numbers, where `gcd(0, n) = n`.
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Finds the first state which has the same hash as a different known state or
+/// a different state earlier in `states`.
+fn find_hash_collision<'a, S>(
+    hash_function: &HashFunction,
+    known_states: &KnownStates<S>,
+    states: impl IntoIterator<Item = &'a S>,
+) -> Option<HashCollisionError<S>>
+where
+    S: Hash + Clone + PartialEq + 'a,
+{
+    let mut new_states: HashMap<StateHash, &S> = HashMap::new();
+    for state in states {
+        let state_hash = hash_function.hash(state);
+        let colliding_state = known_states
+            .get(&state_hash)
+            .or_else(|| new_states.get(&state_hash).copied());
+        match colliding_state {
+            Some(colliding_state) if colliding_state != state => {
+                return Some(HashCollisionError {
+                    state: state.clone(),
+                    colliding_state: colliding_state.clone(),
+                });
+            }
+            Some(_) => {}
+            None => {
+                new_states.insert(state_hash, state);
+            }
+        }
+    }
+    None
+}
+
+/// Sample one of the outgoing transitions proportionally to its probability.
+///
+/// Returns `None` if there is no transition with a positive probability.
+fn sample_transition<'a, S, T>(
+    next_states: &'a OutgoingTransitions<S, T>,
+    rng: &mut impl Rng,
+) -> Option<&'a (S, T, Probability)> {
+    let index =
+        WeightedIndex::new(next_states.iter().map(|(_, _, probability)| *probability)).ok()?;
+    Some(&next_states[index.sample(rng)])
+}
+
+/// Get the total variation distance `½ Σ |μ(x) - ν(x)|` of two probability
+/// distributions.
+///
+/// States that are missing in a distribution have a probability of zero. The
+/// distance is zero for identical distributions and one for distributions with
+/// disjoint support.
+///
+/// # Example
+/// ```rust
+/// use entromatica::prelude::*;
+/// use hashbrown::HashMap;
+/// use std::sync::Arc;
+///
+/// let state_transition_generator =
+///     Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+/// let mut simulation = Simulation::new(0, state_transition_generator);
+/// simulation.next_step().unwrap();
+///
+/// // Compare against an externally constructed reference distribution
+/// let reference = HashMap::from([(-1, 0.5), (0, 0.5)]);
+/// let distance = total_variation_distance(&simulation.probability_distribution(1), &reference);
+/// assert_eq!(distance, 0.5);
+/// ```
+pub fn total_variation_distance<S>(
+    mu: &StateProbabilityDistribution<S>,
+    nu: &StateProbabilityDistribution<S>,
+) -> f64
+where
+    S: Hash + Eq,
+{
+    let over_mu_support = mu
+        .iter()
+        .map(|(state, probability)| (probability - nu.get(state).copied().unwrap_or(0.0)).abs())
+        .sum::<f64>();
+    let over_nu_only_support = nu
+        .iter()
+        .filter(|(state, _)| !mu.contains_key(*state))
+        .map(|(_, probability)| probability.abs())
+        .sum::<f64>();
+    (over_mu_support + over_nu_only_support) / 2.0
+}
+
+/// Get the Kullback-Leibler divergence `D_KL(P || Q)` of two probability
+/// distributions in bits.
+///
+/// States that are missing in a distribution have a probability of zero. If a
+/// state has a nonzero probability in `p` but a probability of zero in `q`,
+/// the divergence is `f64::INFINITY`.
+///
+/// # Example
+/// ```rust
+/// use entromatica::prelude::*;
+/// use hashbrown::HashMap;
+/// use std::sync::Arc;
+///
+/// let state_transition_generator =
+///     Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+/// let mut simulation = Simulation::new(0, state_transition_generator);
+/// simulation.next_step().unwrap();
+///
+/// let reference = HashMap::from([(-1, 0.25), (0, 0.5), (1, 0.25)]);
+/// let distribution = simulation.probability_distribution(1);
+/// assert_eq!(kl_divergence(&distribution, &reference), 1.0);
+/// // State 0 is impossible at time 1
+/// assert_eq!(kl_divergence(&reference, &distribution), f64::INFINITY);
+/// ```
+pub fn kl_divergence<S>(
+    p: &StateProbabilityDistribution<S>,
+    q: &StateProbabilityDistribution<S>,
+) -> f64
+where
+    S: Hash + Eq,
+{
+    p.iter()
+        .filter(|(_, probability)| **probability > 0.0)
+        .map(
+            |(state, probability)| match q.get(state).copied().unwrap_or(0.0) {
+                other_probability if other_probability > 0.0 => {
+                    probability * (probability / other_probability).log2()
+                }
+                _ => f64::INFINITY,
+            },
+        )
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use ndarray::Array1;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn random_walk() {
+        let initial_state = 0;
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+
+        let mut simulation = Simulation::new(initial_state, state_transition_generator);
+        dbg!(&simulation);
+        assert_eq!(simulation.known_states().len(), 1);
+        assert_eq!(simulation.known_transitions().len(), 0);
+        assert_eq!(simulation.probability_distributions().len(), 1);
+        assert_eq!(simulation.state_transition_graph().node_count(), 1);
+        assert_eq!(simulation.state_transition_graph().edge_count(), 0);
+        assert_eq!(simulation.entropy(0), 0.0);
+
+        simulation.next_step().unwrap();
+        dbg!(&simulation);
+        assert_eq!(simulation.known_states().len(), 3);
+        assert_eq!(simulation.known_transitions().len(), 2);
+        assert_eq!(simulation.probability_distributions().len(), 2);
+        assert_eq!(simulation.state_transition_graph().node_count(), 3);
+        assert_eq!(simulation.state_transition_graph().edge_count(), 2);
+        assert_eq!(simulation.entropy(1), 1.0);
+
+        let graph = simulation.state_transition_graph();
+        dbg!(&graph);
+        assert_eq!(graph.node_count(), 3);
+        assert_eq!(graph.edge_count(), 2);
+
+        assert_eq!(simulation.state_probability(0, 1), 0.);
+        assert_eq!(simulation.state_probability(1, 1), 0.5);
+        assert_eq!(simulation.state_probability(-1, 1), 0.5);
+
+        assert_eq!(simulation.initial_distribution(), HashMap::from([(0, 1.0)]));
+
+        assert_eq!(simulation.time(), 1);
+
+        assert_eq!(simulation.try_probability_distribution(2), None);
+        assert_eq!(simulation.try_entropy(2), None);
+        assert_eq!(simulation.try_entropy(1), Some(1.0));
+    }
+
+    #[test]
+    fn next_step_matches_sequential() {
+        // Propagates the distribution sequentially like next_step did before
+        // it merged partial distributions of the workers
+        fn sequential_steps<S: Hash + Clone + Eq, T>(
+            initial_state: S,
+            state_transition_generator: impl Fn(S) -> OutgoingTransitions<S, T>,
+            steps: u64,
+        ) -> HashMap<S, Probability> {
+            let mut distribution = HashMap::from([(initial_state, 1.0)]);
+            for _ in 0..steps {
+                let mut new_distribution = HashMap::new();
+                for (state, probability) in distribution {
+                    for (new_state, _, transition_probability) in state_transition_generator(state)
+                    {
+                        *new_distribution.entry(new_state).or_insert(0.0) +=
+                            probability * transition_probability;
+                    }
+                }
+                distribution = new_distribution;
+            }
+            distribution
+        }
+
+        const STEPS: u64 = 50;
+        const NUM_STATES: i32 = 7;
+        let random_walk = |state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)];
+        let circular_walk = |state: i32| {
+            vec![
+                ((state + 1).rem_euclid(NUM_STATES), "next", 0.3),
+                ((state - 1).rem_euclid(NUM_STATES), "previous", 0.7),
+            ]
+        };
+        // The random walk only expands the states it reached before the last step
+        for (generator, num_states, num_edges) in [
+            (
+                Arc::new(random_walk) as StateTransitionGenerator<i32, &str>,
+                2 * STEPS as usize + 1,
+                4 * STEPS as usize - 2,
+            ),
+            (
+                Arc::new(circular_walk),
+                NUM_STATES as usize,
+                2 * NUM_STATES as usize,
+            ),
+        ] {
+            let expected = sequential_steps(0, generator.as_ref(), STEPS);
+            let mut simulation = Simulation::new(0, generator);
+            let distribution = simulation.run(STEPS).unwrap();
+            assert_eq!(distribution.len(), expected.len());
+            for (state, probability) in expected {
+                assert!((distribution[&state] - probability).abs() < 1e-12);
+            }
+            assert_eq!(simulation.known_states().len(), num_states);
+            assert_eq!(simulation.known_transitions().len(), 2);
+            assert_eq!(simulation.state_transition_graph().edge_count(), num_edges);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "No probability distribution found for given time")]
+    fn probability_distribution_unknown_time() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let simulation = Simulation::new(0, state_transition_generator);
+        simulation.entropy(1);
+    }
+
+    #[test]
+    fn random_walk_with_initial_distribution() {
+        let initial_distribution = HashMap::from([(0, 0.5), (1, 0.5)]);
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation =
+            Simulation::new_with_distribution(initial_distribution, state_transition_generator);
+        assert_eq!(simulation.known_states().len(), 2);
+        assert_eq!(simulation.known_transitions().len(), 0);
+        assert_eq!(simulation.probability_distributions().len(), 1);
+        assert_eq!(simulation.state_transition_graph().node_count(), 2);
+        assert_eq!(simulation.state_transition_graph().edge_count(), 0);
+        assert_eq!(simulation.entropy(0), 1.0);
+        dbg!(&simulation);
+
+        simulation.next_step().unwrap();
+
+        assert_eq!(simulation.known_states().len(), 4);
+        assert_eq!(simulation.known_transitions().len(), 2);
+        assert_eq!(simulation.probability_distributions().len(), 2);
+        assert_eq!(simulation.state_transition_graph().node_count(), 4);
+        assert_eq!(simulation.state_transition_graph().edge_count(), 4);
+        assert_eq!(simulation.entropy(1), 2.0);
+        assert_eq!(
+            simulation.probability_distributions(),
+            HashMap::from([
+                (0, HashMap::from([(0, 0.5), (1, 0.5)]),),
+                (
+                    1,
+                    HashMap::from([(-1, 0.25), (0, 0.25), (1, 0.25), (2, 0.25)]),
+                ),
+            ])
+        );
+
+        dbg!(&simulation);
+    }
+
+    #[test]
+    fn full_traversal() {
+        let initial_state = 0;
+        const NUM_STATES: i32 = 5;
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![
+                (
+                    {
+                        if state + 1 == NUM_STATES {
+                            0
+                        } else {
+                            state + 1
+                        }
+                    },
+                    "forward",
+                    0.5,
+                ),
+                (
+                    {
+                        if state - 1 == -1 {
+                            NUM_STATES - 1
+                        } else {
+                            state - 1
+                        }
+                    },
+                    "backward",
+                    0.5,
+                ),
+            ]
+        });
+        let mut simulation = Simulation::new(initial_state, state_transition_generator);
+        simulation.full_traversal(false, None).unwrap();
+        dbg!(&simulation);
+        let graph = simulation.state_transition_graph();
+        let dot = petgraph::dot::Dot::with_config(&graph, &[]);
+        println!("{dot:#?}");
+        assert_eq!(simulation.known_states().len(), NUM_STATES as usize);
+        assert_eq!(simulation.known_transitions().len(), 2);
+        assert_eq!(
+            simulation.probability_distribution(simulation.time()).len(),
+            4
+        );
+        assert_eq!(
+            simulation.state_transition_graph().node_count(),
+            NUM_STATES as usize
+        );
+        assert_eq!(
+            simulation.state_transition_graph().edge_count(),
+            2 * NUM_STATES as usize
+        );
+
+        let (transition_rate_matrix, ordering) = simulation.transition_rate_matrix().unwrap();
+        dbg!(&transition_rate_matrix);
+        dbg!(&ordering);
+        assert_eq!(transition_rate_matrix.nrows(), NUM_STATES as usize);
+        assert_eq!(transition_rate_matrix.ncols(), NUM_STATES as usize);
+        assert_eq!(
+            transition_rate_matrix.sum_axis(Axis(0)),
+            Array1::from_elem(NUM_STATES as usize, 1.0)
+        );
+        assert_eq!(
+            transition_rate_matrix.sum_axis(Axis(1)),
+            Array1::from_elem(NUM_STATES as usize, 1.0)
+        );
+        let index = |state: i32| ordering.iter().position(|s| *s == state).unwrap();
+        assert_eq!(transition_rate_matrix.get((index(0), index(1))), Some(&0.5));
+        assert_eq!(transition_rate_matrix.get((index(1), index(0))), Some(&0.5));
+    }
+
+    #[test]
+    fn uniform_distribution_is_steady() {
+        {
+            let initial_state = 0;
+            const NUM_STATES: i32 = 5;
+            let state_transition_generator =
+                Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+                    vec![
+                        (
+                            {
+                                if state + 1 == NUM_STATES {
+                                    0
+                                } else {
+                                    state + 1
+                                }
+                            },
+                            "forward",
+                            0.5,
+                        ),
+                        (
+                            {
+                                if state - 1 == -1 {
+                                    NUM_STATES - 1
+                                } else {
+                                    state - 1
+                                }
+                            },
+                            "backward",
+                            0.5,
+                        ),
+                    ]
+                });
+            let mut simulation = Simulation::new(initial_state, state_transition_generator);
+            assert!(simulation.uniform_distribution_is_steady(None).unwrap());
+        }
+        {
+            let initial_state = 0;
+            const NUM_STATES: i32 = 5;
+            let state_transition_generator =
+                Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+                    vec![
+                        (
+                            {
+                                if state + 1 == NUM_STATES {
+                                    1
+                                } else {
+                                    state + 1
+                                }
+                            },
+                            "forward",
+                            0.5,
+                        ),
+                        (0, "stay", 0.5),
+                    ]
+                });
+            let mut simulation = Simulation::new(initial_state, state_transition_generator);
+            assert!(!simulation.uniform_distribution_is_steady(None).unwrap());
+        }
+    }
+
+    #[test]
+    fn stationary_distribution() {
+        let initial_state = 0;
+        const NUM_STATES: i32 = 5;
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            let next = if state + 1 == NUM_STATES {
+                0
+            } else {
+                state + 1
+            };
+            if next == 0 {
+                vec![(0, "forward|stay", 1.0)]
+            } else {
+                vec![(next, "forward", 0.1), (0, "stay", 0.9)]
+            }
+        });
+        let mut simulation = Simulation::new(initial_state, state_transition_generator);
+        simulation.full_traversal(true, None).unwrap();
+        for _ in 0..100 {
+            simulation.next_step().unwrap();
+        }
+        let stationary_distribution = simulation.stationary_distribution().unwrap();
+        assert!(
+            simulation
+                .probability_distribution(simulation.time())
+                .iter()
+                .zip(&stationary_distribution)
+                .map(|(a, b)| a.1 - b.1)
+                .sum::<f64>()
+                .abs()
+                < 1e-6
+        );
+    }
+
+    #[test]
+    fn stationary_distribution_circular_walk() {
+        let initial_state = 0;
+        const NUM_STATES: i32 = 5;
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![
+                ((state + 1).rem_euclid(NUM_STATES), "forward", 0.5),
+                ((state - 1).rem_euclid(NUM_STATES), "backward", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(initial_state, state_transition_generator);
+        let stationary_distribution = simulation.stationary_distribution().unwrap();
+        assert_eq!(stationary_distribution.len(), NUM_STATES as usize);
+        for probability in stationary_distribution.values() {
+            assert!((probability - 1. / NUM_STATES as f64).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn stationary_distribution_two_states() {
+        let initial_state = 0;
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            if state == 0 {
+                vec![(1, "switch", 0.2), (0, "stay", 0.8)]
+            } else {
+                vec![(0, "switch", 0.6), (1, "stay", 0.4)]
+            }
+        });
+        let mut simulation = Simulation::new(initial_state, state_transition_generator);
+        let stationary_distribution = simulation.stationary_distribution().unwrap();
+        assert!((stationary_distribution[&0] - 0.75).abs() < 1e-9);
+        assert!((stationary_distribution[&1] - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn stationary_distribution_periodic() {
+        let initial_state = 0;
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![((state + 1) % 2, "switch", 1.0)]);
+        let mut simulation = Simulation::new(initial_state, state_transition_generator);
+        assert_eq!(simulation.stationary_distribution(), None);
+    }
+
+    #[test]
+    fn steady_state_distribution() {
+        let initial_state = 0;
+        const NUM_STATES: i32 = 5;
+        // Doubly stochastic, so the steady state is the uniform distribution
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![
+                ((state + 1).rem_euclid(NUM_STATES), "forward", 0.7),
+                ((state - 1).rem_euclid(NUM_STATES), "backward", 0.3),
+            ]
+        });
+        let mut simulation = Simulation::new(initial_state, state_transition_generator);
+        let steady_state_distribution = simulation.steady_state_distribution().unwrap();
+        assert_eq!(steady_state_distribution.len(), NUM_STATES as usize);
+        for _ in 0..200 {
+            simulation.next_step().unwrap();
+        }
+        let distribution = simulation.probability_distribution(simulation.time());
+        for (state, probability) in steady_state_distribution {
+            assert!((probability - 1. / NUM_STATES as f64).abs() < 1e-9);
+            assert!((probability - distribution[&state]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn steady_state_distribution_reducible() {
+        let initial_state = 0;
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            if state == 0 {
+                vec![(1, "left", 0.5), (2, "right", 0.5)]
+            } else {
+                vec![(state, "stay", 1.0)]
+            }
+        });
+        let mut simulation = Simulation::new(initial_state, state_transition_generator);
+        assert_eq!(simulation.steady_state_distribution(), None);
+    }
+
+    #[test]
+    fn kl_divergence() {
+        let initial_distribution = HashMap::from([(0, 0.5), (1, 0.5)]);
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![
+                ((state + 1).rem_euclid(3), "forward", 0.9),
+                (state, "stay", 0.1),
+            ]
+        });
+        let mut simulation =
+            Simulation::new_with_distribution(initial_distribution, state_transition_generator);
+        for _ in 0..5 {
+            simulation.next_step().unwrap();
+        }
+        for time_a in 0..=simulation.time() {
+            assert_eq!(simulation.kl_divergence(time_a, time_a), 0.0);
+            for time_b in 1..=simulation.time() {
+                assert!(simulation.kl_divergence(time_a, time_b) >= 0.0);
+            }
+        }
+        // State 2 is not part of the initial distribution
+        assert_eq!(simulation.kl_divergence(1, 0), f64::INFINITY);
+
+        let p = HashMap::from([("a", 0.5), ("b", 0.5)]);
+        let q = HashMap::from([("a", 0.25), ("b", 0.75)]);
+        let expected = 0.5 * (0.5f64 / 0.25).log2() + 0.5 * (0.5f64 / 0.75).log2();
+        assert!((super::kl_divergence(&p, &q) - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn total_variation_distance() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.next_step().unwrap();
+        simulation.next_step().unwrap();
+        assert_eq!(simulation.total_variation_distance(1, 1), 0.0);
+        assert_eq!(simulation.total_variation_distance(0, 1), 1.0);
+        assert_eq!(simulation.total_variation_distance(0, 2), 0.5);
+
+        let a = HashMap::from([(0, 0.5), (1, 0.5)]);
+        let b = HashMap::from([(1, 0.25), (2, 0.75)]);
+        let c = HashMap::from([(0, 0.1), (2, 0.2), (3, 0.7)]);
+        let distance = super::total_variation_distance;
+        assert_eq!(distance(&a, &a), 0.0);
+        assert_eq!(distance(&a, &HashMap::from([(3, 1.0)])), 1.0);
+        assert_eq!(distance(&a, &b), distance(&b, &a));
+        assert!(distance(&a, &c) <= distance(&a, &b) + distance(&b, &c));
+        assert!(distance(&a, &b) <= distance(&a, &c) + distance(&c, &b));
+        assert!(distance(&b, &c) <= distance(&b, &a) + distance(&a, &c));
+    }
+
+    #[test]
+    fn sample_path() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 2.0), (state - 1, "previous", 2.0)]);
+        let mut simulation = Simulation::new_normalizing(0, state_transition_generator);
+        let mut rng = StdRng::seed_from_u64(0);
+        let path = simulation.sample_path(10, &mut rng).unwrap();
+        assert_eq!(path.len(), 10);
+        let mut state = 0;
+        for (next_state, transition) in path {
+            match transition {
+                "next" => assert_eq!(next_state, state + 1),
+                "previous" => assert_eq!(next_state, state - 1),
+                _ => unreachable!(),
+            }
+            state = next_state;
+        }
+        assert_eq!(simulation.time(), 0);
+        assert_eq!(simulation.known_transitions().len(), 2);
+        assert_eq!(simulation.state_transition_graph().edge_count() % 2, 0);
+        assert!(simulation
+            .state_transition_graph()
+            .edge_weights()
+            .all(|(_, probability)| *probability == 0.5));
+
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator).with_constraints(
+            ConstraintSet::new().with_at_most(
+                "Bound".to_string(),
+                |state: &i32| state.abs() as f64,
+                1.,
+            ),
+        );
+        let error = (0..100)
+            .find_map(|_| simulation.sample_path(10, &mut rng).err())
+            .unwrap();
+        assert!(matches!(error, SimulationError::ConstraintViolated(_)));
+        assert!(simulation
+            .known_states()
+            .iter()
+            .all(|state| state.abs() < 2));
+
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.4)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert!(matches!(
+            simulation.sample(1, &mut rng),
+            Err(SimulationError::ProbabilitiesDoNotSumToOne { .. })
+        ));
+        assert!(matches!(
+            simulation.sample_paths(10, 1),
+            Err(SimulationError::ProbabilitiesDoNotSumToOne { .. })
+        ));
+        assert_eq!(simulation.known_states().len(), 1);
+    }
+
+    #[test]
+    fn sample_paths() {
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![
+                ((state + 1).rem_euclid(3), "forward", 0.5),
+                ((state - 1).rem_euclid(3), "backward", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let empirical_distribution = simulation.sample_paths(10_000, 2).unwrap();
+        simulation.next_step().unwrap();
+        simulation.next_step().unwrap();
+        assert!(
+            super::total_variation_distance(
+                &empirical_distribution,
+                &simulation.probability_distribution(2)
+            ) < 0.05
+        );
+        assert_eq!(simulation.known_states().len(), 3);
+    }
+
+    #[test]
+    fn probabilities_do_not_sum_to_one() {
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            if state == 2 {
+                vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.4)]
+            } else {
+                vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]
+            }
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.run(2).unwrap();
+        let known_states = simulation.known_states().len();
+        let error = simulation.next_step().unwrap_err();
+        match error {
+            SimulationError::ProbabilitiesDoNotSumToOne {
+                state,
+                next_states,
+                sum,
+            } => {
+                assert_eq!(state, 2);
+                assert_eq!(next_states, vec![(3, "next", 0.5), (1, "previous", 0.4)]);
+                assert!((sum - 0.9).abs() < 1e-12);
+            }
+            _ => panic!("Unexpected error {error:?}"),
+        }
+        assert_eq!(simulation.time(), 2);
+        assert_eq!(simulation.known_states().len(), known_states);
+        assert!(simulation.full_traversal(true, None).is_err());
+        assert!(simulation.transition_rate_matrix_complete(None).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Sum of probabilities of next states of 0 is 0.9")]
+    fn next_step_unchecked() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.4)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.next_step_unchecked();
+    }
+
+    #[test]
+    fn run() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator.clone());
+        let distribution = simulation.run(4).unwrap();
+        assert_eq!(simulation.time(), 4);
+        assert_eq!(distribution.len(), 5);
+        assert_eq!(distribution, simulation.probability_distribution(4));
+        assert_eq!(simulation.run(0).unwrap(), distribution);
+
+        let mut other_simulation = Simulation::new(0, state_transition_generator.clone());
+        for _ in 0..4 {
+            other_simulation.next_step().unwrap();
+        }
+        assert_eq!(
+            other_simulation.probability_distributions(),
+            simulation.probability_distributions()
+        );
+        let mut other_simulation = Simulation::new(0, state_transition_generator.clone());
+        assert_eq!(other_simulation.run_n_steps(4).unwrap(), distribution);
+        assert_eq!(
+            other_simulation.probability_distributions(),
+            simulation.probability_distributions()
+        );
+
+        let distribution = simulation.run_to_time(6).unwrap();
+        assert_eq!(simulation.time(), 6);
+        assert_eq!(distribution, simulation.probability_distribution(6));
+        assert_eq!(
+            simulation.run_to_time(6),
+            Err(SimulationError::AlreadyAtTime {
+                time: 6,
+                target_time: 6
+            })
+        );
+        assert!(simulation.run_to_time(2).is_err());
+        assert_eq!(simulation.time(), 6);
+
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let steps = simulation
+            .run_until(|distribution| distribution.len() >= 3, None)
+            .unwrap();
+        assert_eq!(steps, 2);
+        assert_eq!(simulation.time(), 2);
+        let steps = simulation
+            .run_until(|distribution| distribution.is_empty(), Some(5))
+            .unwrap();
+        assert_eq!(steps, 5);
+        assert_eq!(simulation.time(), 7);
+    }
+
+    #[test]
+    fn is_irreducible() {
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![
+                ((state + 1).rem_euclid(4), "forward", 0.5),
+                ((state - 1).rem_euclid(4), "backward", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert!(simulation.is_irreducible());
+        assert_eq!(simulation.known_states().len(), 4);
+        assert_eq!(simulation.time(), 0);
+
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![((state + 1).min(3), "forward", 1.0)]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert!(!simulation.is_irreducible());
+    }
+
+    #[test]
+    fn absorbing_states() {
+        const NUM_STATES: i32 = 5;
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            if state == 0 || state == NUM_STATES - 1 {
+                vec![(state, "absorbed", 1.0)]
+            } else {
+                vec![(state + 1, "forward", 0.5), (state - 1, "backward", 0.5)]
+            }
+        });
+        let mut simulation = Simulation::new(2, state_transition_generator);
+        let absorbing_states = simulation.absorbing_states();
+        assert_eq!(absorbing_states.len(), 2);
+        assert!(absorbing_states.contains(&0));
+        assert!(absorbing_states.contains(&(NUM_STATES - 1)));
+        assert!(simulation.is_absorbing(0));
+        assert!(!simulation.is_absorbing(2));
+        assert!(!simulation.is_absorbing(NUM_STATES));
+    }
+
+    #[test]
+    fn steady_state() {
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            if state == 0 {
+                vec![(1, "switch", 0.2), (0, "stay", 0.8)]
+            } else {
+                vec![(0, "switch", 0.6), (1, "stay", 0.4)]
+            }
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let steady_state = simulation.steady_state(1e-10, None).unwrap();
+        assert!((steady_state[&0] - 0.75).abs() < 1e-9);
+        assert!((steady_state[&1] - 0.25).abs() < 1e-9);
+
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![((state + 1) % 2, "switch", 1.0)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let error = simulation.steady_state(1e-10, Some(10)).unwrap_err();
+        assert_eq!(
+            error,
+            SimulationError::NotConverged(ConvergenceError {
+                steps: 10,
+                distance: 2.0
+            })
+        );
+        assert_eq!(simulation.time(), 10);
+
+        let state_transition_generator = Arc::new(|state: i32| vec![(state, "stay", 0.9)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert!(matches!(
+            simulation.steady_state(1e-10, None),
+            Err(SimulationError::ProbabilitiesDoNotSumToOne { sum, .. }) if sum == 0.9
+        ));
+    }
+
+    #[test]
+    fn mfpt_matrix() {
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            if state == 0 {
+                vec![(1, "switch", 0.2), (0, "stay", 0.8)]
+            } else {
+                vec![(0, "switch", 0.6), (1, "stay", 0.4)]
+            }
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let (mfpt_matrix, ordering) = simulation.mfpt_matrix();
+        let index = |state: i32| ordering.iter().position(|s| *s == state).unwrap();
+        assert!((mfpt_matrix[(index(0), index(1))] - 1. / 0.2).abs() < 1e-9);
+        assert!((mfpt_matrix[(index(1), index(0))] - 1. / 0.6).abs() < 1e-9);
+        assert!((mfpt_matrix[(index(0), index(0))] - 0.8 / 0.6).abs() < 1e-9);
+        assert!((mfpt_matrix[(index(1), index(1))] - 0.8 / 0.2).abs() < 1e-9);
+
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            match state {
+                0 => vec![(1, "left", 0.5), (2, "right", 0.5)],
+                _ => vec![(state, "stay", 1.0)],
+            }
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let (mfpt_matrix, ordering) = simulation.mfpt_matrix();
+        let index = |state: i32| ordering.iter().position(|s| *s == state).unwrap();
+        assert_eq!(mfpt_matrix[(index(0), index(1))], f64::INFINITY);
+        assert_eq!(mfpt_matrix[(index(1), index(0))], f64::INFINITY);
+        assert_eq!(mfpt_matrix[(index(1), index(1))], 1.0);
+        assert_eq!(mfpt_matrix[(index(0), index(0))], f64::INFINITY);
+    }
+
+    #[test]
+    fn run_until_convergence() {
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            if state == 0 {
+                vec![(1, "switch", 0.2), (0, "stay", 0.8)]
+            } else {
+                vec![(0, "switch", 0.6), (1, "stay", 0.4)]
+            }
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let time = simulation.run_until_convergence(1e-10, Some(1000)).unwrap();
+        assert_eq!(time, simulation.time());
+        assert!(2.0 * simulation.total_variation_distance(time - 1, time) < 1e-10);
+        assert!(2.0 * simulation.total_variation_distance(time - 2, time - 1) >= 1e-10);
+
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert_eq!(
+            simulation.run_until_convergence(1e-10, Some(20)),
+            Err(SimulationError::MaxStepsReached { max_steps: 20 })
+        );
+    }
+
+    #[test]
+    fn is_reversible() {
+        const NUM_STATES: i32 = 5;
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![
+                ((state + 1).rem_euclid(NUM_STATES), "forward", 0.5),
+                ((state - 1).rem_euclid(NUM_STATES), "backward", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert!(simulation.is_reversible());
+
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![((state + 1).rem_euclid(NUM_STATES), "forward", 1.0)]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert!(!simulation.is_reversible());
+
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![((state + 1).min(NUM_STATES), "forward", 1.0)]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert!(!simulation.is_reversible());
+    }
+
+    #[test]
+    fn communication_classes() {
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            match state {
+                0 => vec![(1, "forward", 0.5), (0, "stay", 0.5)],
+                1 => vec![(0, "backward", 0.5), (2, "forward", 0.5)],
+                2 => vec![(3, "forward", 1.0)],
+                _ => vec![(2, "backward", 1.0)],
+            }
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let communication_classes = simulation
+            .communication_classes()
+            .into_iter()
+            .map(|class| class.into_iter().sorted().collect_vec())
+            .sorted()
+            .collect_vec();
+        assert_eq!(communication_classes, vec![vec![0, 1], vec![2, 3]]);
+        let strongly_connected_components = simulation
+            .strongly_connected_components()
+            .into_iter()
+            .map(|component| component.into_iter().sorted().collect_vec())
+            .sorted()
+            .collect_vec();
+        assert_eq!(strongly_connected_components, communication_classes);
+        assert_eq!(simulation.classify_state(0), StateClass::Transient);
+        assert_eq!(simulation.classify_state(1), StateClass::Transient);
+        assert_eq!(simulation.classify_state(2), StateClass::PositiveRecurrent);
+        assert_eq!(simulation.classify_state(3), StateClass::PositiveRecurrent);
+    }
+
+    #[test]
+    fn new_normalizing() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "a", 2.0), (state - 1, "b", 6.0)]);
+        let mut simulation = Simulation::new_normalizing(0, state_transition_generator);
+        let distribution = simulation.next_step().unwrap();
+        assert_eq!(distribution, HashMap::from([(1, 0.25), (-1, 0.75)]));
+        let graph = simulation.state_transition_graph();
+        let mut probabilities = graph
+            .edge_weights()
+            .map(|(_, probability)| *probability)
+            .collect_vec();
+        probabilities.sort_by(f64::total_cmp);
+        assert_eq!(probabilities, vec![0.25, 0.75]);
+
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "a", 0.0), (state - 1, "b", 0.0)]);
+        let mut simulation = Simulation::new_normalizing(0, state_transition_generator);
+        assert!(matches!(
+            simulation.next_step(),
+            Err(SimulationError::ProbabilitiesNotNormalizable { state: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn absorption() {
+        const NUM_STATES: i32 = 5;
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            if state == 0 || state == NUM_STATES - 1 {
+                vec![(state, "absorbed", 1.0)]
+            } else {
+                vec![(state + 1, "win", 0.5), (state - 1, "lose", 0.5)]
+            }
+        });
+        let mut simulation = Simulation::new(2, state_transition_generator);
+        let absorption_probabilities = simulation.absorption_probabilities().unwrap();
+        let expected_steps = simulation.expected_steps_to_absorption().unwrap();
+        assert_eq!(absorption_probabilities.len(), 3);
+        assert_eq!(expected_steps.len(), 3);
+        let goal = (NUM_STATES - 1) as f64;
+        for state in 1..NUM_STATES - 1 {
+            let probabilities = &absorption_probabilities[&state];
+            assert!((probabilities[&(NUM_STATES - 1)] - state as f64 / goal).abs() < 1e-9);
+            assert!((probabilities[&0] - (1. - state as f64 / goal)).abs() < 1e-9);
+            assert!((expected_steps[&state] - state as f64 * (goal - state as f64)).abs() < 1e-9);
+        }
+
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![((state + 1).rem_euclid(NUM_STATES), "forward", 1.0)]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert_eq!(
+            simulation.absorption_probabilities(),
+            Err(SimulationError::NotAbsorbing)
+        );
+
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            match state {
+                0 => vec![(1, "left", 0.5), (2, "right", 0.5)],
+                1 => vec![(1, "stay", 1.0)],
+                _ => vec![(2, "forward", 0.5), (3, "backward", 0.5)],
+            }
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert_eq!(
+            simulation.expected_steps_to_absorption(),
+            Err(SimulationError::NotAbsorbing)
+        );
+    }
+
+    #[test]
+    fn entropy_rate() {
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![(state, "stay", 0.5), ((state + 1) % 2, "switch", 0.5)]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert!((simulation.entropy_rate() - 1.0).abs() < 1e-12);
+
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![((state + 1) % 2, "switch", 1.0)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert_eq!(simulation.entropy_rate(), 0.0);
+
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            match state {
+                2 => vec![(2, "stay", 1.0)],
+                _ => vec![(state + 1, "forward", 0.5), (state, "stay", 0.5)],
+            }
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert!(simulation.entropy_rate().is_nan());
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct CollidingState(i32);
+
+    impl Hash for CollidingState {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            0.hash(state);
+        }
+    }
+
+    #[test]
+    fn hash_collision() {
+        let state_transition_generator = Arc::new(|state: CollidingState| {
+            vec![
+                (CollidingState(state.0 + 1), "next", 0.5),
+                (CollidingState(state.0 - 1), "previous", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(CollidingState(0), state_transition_generator.clone());
+        let error = simulation.next_step().unwrap_err();
+        assert!(matches!(error, SimulationError::HashCollision(_)));
+        assert_eq!(simulation.time(), 0);
+        assert_eq!(simulation.known_states(), vec![CollidingState(0)]);
+
+        let error = Simulation::try_new_with_distribution(
+            HashMap::from([(CollidingState(0), 0.5), (CollidingState(1), 0.5)]),
+            state_transition_generator,
+        )
+        .unwrap_err();
+        assert!(matches!(error, SimulationError::HashCollision(_)));
+
+        let state_transition_generator =
+            Arc::new(|state: CollidingState| vec![(state, "stay", 1.0)]);
+        let mut simulation = Simulation::new(CollidingState(0), state_transition_generator);
+        assert!(simulation.next_step().is_ok());
+    }
+
+    #[test]
+    fn history() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator.clone());
+        simulation.next_step().unwrap();
+        assert!(simulation.history().is_none());
+
+        let mut simulation = Simulation::new(0, state_transition_generator).with_history_tracking();
+        simulation.run(2).unwrap();
+        let history = simulation.history().unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[&0].transitions, HashSet::from(["next", "previous"]));
+        assert_eq!(history[&0].flows.len(), 2);
+        assert!(history[&0].flows.contains(&TransitionFlow {
+            source: 0,
+            target: 1,
+            transition: "next",
+            flow: 0.5
+        }));
+        assert_eq!(history[&1].flows.len(), 4);
+        assert!(history[&1].flows.contains(&TransitionFlow {
+            source: -1,
+            target: -2,
+            transition: "previous",
+            flow: 0.25
+        }));
+        let total_flow = history[&1].flows.iter().map(|flow| flow.flow).sum::<f64>();
+        assert!((total_flow - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn transition_rate_matrix() {
+        const NUM_STATES: i32 = 4;
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![
+                ((state + 1).rem_euclid(NUM_STATES), "forward", 0.5),
+                ((state - 1).rem_euclid(NUM_STATES), "backward", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.next_step().unwrap();
+        let error = simulation.transition_rate_matrix().unwrap_err();
+        assert_eq!(
+            error.untraversed_states.into_iter().sorted().collect_vec(),
+            vec![1, 3]
+        );
+
+        let (complete_matrix, complete_ordering) =
+            simulation.transition_rate_matrix_complete(None).unwrap();
+        let (matrix, ordering) = simulation.transition_rate_matrix().unwrap();
+        assert_eq!(matrix, complete_matrix);
+        assert_eq!(ordering, complete_ordering);
+        assert_eq!(matrix.dim(), (NUM_STATES as usize, NUM_STATES as usize));
+        assert_eq!(simulation.time(), 1);
+    }
+
+    #[test]
+    fn from_transition_matrix() {
+        const NUM_STATES: i32 = 5;
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![
+                ((state + 1).rem_euclid(NUM_STATES), "forward", 0.3),
+                ((state - 1).rem_euclid(NUM_STATES), "backward", 0.2),
+                (state, "stay", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let (matrix, ordering) = simulation.transition_rate_matrix_complete(None).unwrap();
+
+        let mut imported_simulation =
+            Simulation::from_transition_matrix(matrix.clone(), ordering.clone()).unwrap();
+        let (imported_matrix, imported_ordering) =
+            imported_simulation.transition_rate_matrix().unwrap();
+        let position = |state| {
+            imported_ordering
+                .iter()
+                .position(|imported_state| *imported_state == state)
+                .unwrap()
+        };
+        for ((row, column), probability) in matrix.indexed_iter() {
+            assert_eq!(
+                imported_matrix[(position(ordering[row]), position(ordering[column]))],
+                *probability
+            );
+        }
+        assert_eq!(
+            imported_simulation.initial_distribution()[&ordering[0]],
+            1.0
+        );
+
+        imported_simulation.run(3).unwrap();
+        let mut simulation = Simulation::new(
+            ordering[0],
+            simulation.state_transition_generator.function(),
+        );
+        let distribution = simulation.run(3).unwrap();
+        for (state, probability) in imported_simulation.probability_distribution(3) {
+            assert!((distribution[&state] - probability).abs() < 1e-12);
+        }
+
+        let invalid_matrices = [
+            (Array2::from_elem((2, 3), 0.5), vec![0, 1]),
+            (Array2::eye(2), vec![0, 1, 2]),
+            (Array2::from_elem((2, 2), 0.4), vec![0, 1]),
+            (
+                Array2::from_shape_vec((2, 2), vec![1.5, -0.5, 0.0, 1.0]).unwrap(),
+                vec![0, 1],
+            ),
+            (Array2::eye(2), vec![0, 0]),
+            (Array2::eye(0), vec![]),
+        ];
+        for (matrix, states) in invalid_matrices {
+            assert!(matches!(
+                Simulation::from_transition_matrix(matrix, states),
+                Err(SimulationError::InvalidTransitionMatrix { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn mixing_time() {
+        let ring = |num_states: i32| {
+            Arc::new(move |state: i32| -> OutgoingTransitions<i32, &str> {
+                vec![
+                    ((state + 1).rem_euclid(num_states), "forward", 0.5),
+                    ((state - 1).rem_euclid(num_states), "backward", 0.5),
+                ]
+            })
+        };
+        // Starting anywhere on the symmetric 5-state ring, the total variation
+        // distances to the uniform distribution are 0.8, 0.6, 0.4 and 0.35
+        let mut simulation = Simulation::new(0, ring(5));
+        assert_eq!(simulation.mixing_time(0.81, 100).unwrap(), 0);
+        assert_eq!(simulation.mixing_time(0.61, 100).unwrap(), 1);
+        assert_eq!(simulation.mixing_time(0.41, 100).unwrap(), 2);
+        assert_eq!(simulation.mixing_time(0.39, 100).unwrap(), 3);
+        assert!(simulation.mixing_time(0.01, 100).unwrap() > 3);
+        assert_eq!(simulation.time(), 0);
+        let Err(MixingError::MaxStepsReached {
+            max_steps,
+            distance,
+            ..
+        }) = simulation.mixing_time(0.01, 2)
+        else {
+            panic!("The ring should not be mixed after 2 steps");
+        };
+        assert_eq!(max_steps, 2);
+        assert!((distance - 0.4).abs() < 1e-12);
+
+        let mut simulation = Simulation::new(0, ring(4));
+        assert_eq!(
+            simulation.mixing_time(0.1, 100),
+            Err(MixingError::Periodic { period: 2 })
+        );
+
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![((state + 1).min(2), "next", 1.0)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert_eq!(
+            simulation.mixing_time(0.1, 100),
+            Err(MixingError::NotIrreducible)
+        );
+    }
+
+    #[test]
+    fn mixing_times() {
+        // A lazy walk on a path where state 0 is hard to leave
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            match state {
+                0 => vec![(0, "stay", 0.9), (1, "right", 0.1)],
+                1 => vec![(0, "left", 0.1), (1, "stay", 0.4), (2, "right", 0.5)],
+                _ => vec![(1, "left", 0.5), (2, "stay", 0.5)],
+            }
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let mixing_times = simulation.mixing_times(0.05, 1000).unwrap();
+        assert_eq!(mixing_times.len(), 3);
+        assert!(mixing_times[&0] > mixing_times[&2]);
+        assert_eq!(
+            simulation.mixing_time(0.05, 1000).unwrap(),
+            *mixing_times.values().max().unwrap()
+        );
+    }
+
+    #[test]
+    fn renyi_entropy() {
+        let state_transition_generator = Arc::new(|state: i32| vec![(state, "stay", 1.0)]);
+        let mut simulation = Simulation::new(0, state_transition_generator.clone());
+        assert_eq!(simulation.min_entropy(0), 0.0);
+        assert_eq!(simulation.renyi_entropy(0, 2.0), 0.0);
+
+        let initial_distribution = HashMap::from([(0, 0.25), (1, 0.25), (2, 0.25), (3, 0.25)]);
+        simulation =
+            Simulation::new_with_distribution(initial_distribution, state_transition_generator);
+        assert!((simulation.renyi_entropy(0, 1.0) - simulation.entropy(0)).abs() < 1e-10);
+        for alpha in [0.0, 0.5, 2.0, f64::INFINITY] {
+            assert!((simulation.renyi_entropy(0, alpha) - 2.0).abs() < 1e-10);
+        }
+        assert_eq!(simulation.min_entropy(0), 2.0);
+
+        let initial_distribution = HashMap::from([(0, 0.5), (1, 0.25), (2, 0.25), (3, 0.0)]);
+        let simulation = Simulation::new_with_distribution(
+            initial_distribution,
+            Arc::new(|state: i32| vec![(state, "stay", 1.0)]),
+        );
+        assert!((simulation.renyi_entropy(0, 0.0) - 3_f64.log2()).abs() < 1e-10);
+        assert!((simulation.renyi_entropy(0, 2.0) - (8.0 / 3.0_f64).log2()).abs() < 1e-10);
+        assert_eq!(simulation.min_entropy(0), 1.0);
+    }
+
+    #[test]
+    fn spectral_gap() {
+        let ring = |num_states: i32, stay: Probability| {
+            Arc::new(move |state: i32| -> OutgoingTransitions<i32, &str> {
+                vec![
+                    (
+                        (state + 1).rem_euclid(num_states),
+                        "forward",
+                        (1.0 - stay) / 2.0,
+                    ),
+                    (
+                        (state - 1).rem_euclid(num_states),
+                        "backward",
+                        (1.0 - stay) / 2.0,
+                    ),
+                    (state, "stay", stay),
+                ]
+            })
+        };
+        // The eigenvalues of the symmetric ring are cos(2πk/5), the one with
+        // the second largest absolute value is cos(4π/5) = -cos(π/5)
+        let mut simulation = Simulation::new(0, ring(5, 0.0));
+        let expected_rate = (std::f64::consts::PI / 5.0).cos();
+        assert!((simulation.convergence_rate() - expected_rate).abs() < 1e-10);
+        assert!((simulation.spectral_gap() - (1.0 - expected_rate)).abs() < 1e-10);
+        assert!((simulation.spectral_gap() - 0.191).abs() < 1e-3);
+
+        let mut simulation = Simulation::new(0, ring(4, 0.0));
+        assert_eq!(simulation.spectral_gap(), 0.0);
+
+        let mut simulation = Simulation::new(0, ring(6, 0.5));
+        let expected_rate = (1.0 + (std::f64::consts::PI / 3.0).cos()) / 2.0;
+        assert!((simulation.convergence_rate() - expected_rate).abs() < 1e-10);
+
+        // The directed ring has complex eigenvalues (1 + e^(iπk/2)) / 2
+        let state_transition_generator = Arc::new(|state: i32| {
+            vec![
+                ((state + 1).rem_euclid(4), "forward", 0.5),
+                (state, "stay", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let expected_rate = std::f64::consts::FRAC_1_SQRT_2;
+        assert!((simulation.convergence_rate() - expected_rate).abs() < 1e-10);
+
+        let state_transition_generator = Arc::new(|state: i32| match state {
+            0 | 3 => vec![(state, "stay", 1.0)],
+            _ => vec![(state + 1, "forward", 0.5), (state - 1, "backward", 0.5)],
+        });
+        let mut simulation = Simulation::new(1, state_transition_generator);
+        assert_eq!(simulation.spectral_gap(), 0.0);
+
+        let mut simulation = Simulation::new(0, Arc::new(|state: i32| vec![(state, "stay", 1.0)]));
+        assert_eq!(simulation.convergence_rate(), 0.0);
+    }
+
+    #[test]
+    fn stationary_distribution_power_iteration() {
+        let state_transition_generator = Arc::new(|state: i32| match state {
+            0 => vec![(1, "up", 0.6), (0, "stay", 0.4)],
+            3 => vec![(2, "down", 0.3), (3, "stay", 0.7)],
+            _ => vec![
+                (state + 1, "up", 0.5),
+                (state - 1, "down", 0.2),
+                (state, "stay", 0.3),
+            ],
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let steady_state_distribution = simulation.steady_state_distribution().unwrap();
+        let stationary_distribution = simulation
+            .stationary_distribution_power_iteration(10_000, 1e-14)
+            .unwrap();
+        assert_eq!(stationary_distribution.len(), 4);
+        for (state, probability) in &steady_state_distribution {
+            assert!((stationary_distribution[state] - probability).abs() < 1e-10);
+        }
+
+        assert_eq!(
+            simulation.stationary_distribution_power_iteration(2, 1e-14),
+            None
+        );
+    }
+
+    #[test]
+    fn parallel_transitions() {
+        let state_transition_generator = Arc::new(|state: bool| {
+            vec![
+                (!state, "flip", 0.3),
+                (!state, "toggle", 0.2),
+                (state, "stay", 0.4),
+                (state, "wait", 0.1),
+            ]
+        });
+        let mut simulation = Simulation::new(false, state_transition_generator);
+        simulation.run(3).unwrap();
+        let graph = simulation.state_transition_graph();
+        assert_eq!(graph.edge_count(), 8);
+        let transitions = graph
+            .edge_references()
+            .filter(|edge| !graph[edge.source()] && graph[edge.target()])
+            .map(|edge| edge.weight().0)
+            .sorted()
+            .collect_vec();
+        assert_eq!(transitions, vec!["flip", "toggle"]);
+
+        let (matrix, ordering) = simulation.transition_rate_matrix().unwrap();
+        let index = |state| ordering.iter().position(|other| *other == state).unwrap();
+        assert!((matrix[(index(false), index(true))] - 0.5).abs() < 1e-12);
+        assert!((matrix[(index(true), index(true))] - 0.5).abs() < 1e-12);
+
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state, "stay", 0.5), (state, "wait", 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert!(simulation.is_absorbing(0));
+    }
+
+    #[test]
+    fn apply_intervention() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator).with_history_tracking();
+        simulation.next_step().unwrap();
+        let distribution = simulation
+            .apply_intervention(|state| state.abs() + 10, "shock")
+            .unwrap();
+        assert_eq!(distribution, HashMap::from([(11, 1.0)]));
+        assert_eq!(simulation.time(), 2);
+        assert!(simulation.known_states().contains(&11));
+        let step = &simulation.history().unwrap()[&1];
+        assert_eq!(step.transitions, HashSet::from(["shock"]));
+        assert_eq!(step.flows.len(), 2);
+        assert!(step
+            .flows
+            .iter()
+            .all(|flow| flow.target == 11 && flow.flow == 0.5));
+        assert_eq!(simulation.state_transition_graph().edge_count(), 2);
+
+        simulation.next_step().unwrap();
+        assert_eq!(
+            simulation.probability_distribution(3),
+            HashMap::from([(10, 0.5), (12, 0.5)])
+        );
+
+        assert_eq!(
+            simulation.set_distribution(HashMap::from([(0, 0.5), (1, 0.6)])),
+            Err(SimulationError::InitialProbabilitiesDoNotSumToOne { sum: 1.1 })
+        );
+        assert_eq!(simulation.time(), 3);
+        simulation
+            .set_distribution(HashMap::from([(0, 0.5), (100, 0.5)]))
+            .unwrap();
+        assert_eq!(simulation.time(), 4);
+        simulation.next_step().unwrap();
+        assert_eq!(simulation.state_probability(101, 5), 0.25);
+    }
+
+    #[test]
+    fn branch() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.run(3).unwrap();
+        let probability_distributions = simulation.probability_distributions();
+        assert_eq!(
+            simulation.branch(4).unwrap_err(),
+            SimulationError::NoProbabilityDistribution { time: 4 }
+        );
+
+        let mut branch = simulation.branch(1).unwrap();
+        let mut other_branch = simulation.branch(1).unwrap();
+        assert_eq!(branch.time(), 1);
+        branch.run(2).unwrap();
+        other_branch
+            .apply_intervention(|state| state.abs(), "reflect")
+            .unwrap();
+        other_branch.next_step().unwrap();
+        assert_eq!(branch.time(), 3);
+        assert_eq!(other_branch.time(), 3);
+        assert_eq!(
+            branch.probability_distribution(3),
+            simulation.probability_distribution(3)
+        );
+        assert_eq!(
+            other_branch.probability_distribution(3),
+            HashMap::from([(0, 0.5), (2, 0.5)])
+        );
+        assert_eq!(
+            simulation.probability_distributions(),
+            probability_distributions
+        );
+
+        assert_eq!(
+            simulation.truncate(4),
+            Err(SimulationError::NoProbabilityDistribution { time: 4 })
+        );
+        simulation.truncate(1).unwrap();
+        assert_eq!(simulation.time(), 1);
+        simulation.next_step().unwrap();
+        assert_eq!(
+            simulation.probability_distribution(2),
+            probability_distributions[&2]
+        );
+    }
+
+    #[test]
+    fn probability_flow() {
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![
+                ((state + 1).rem_euclid(5), "forward", 0.5),
+                ((state - 1).rem_euclid(5), "backward", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let probability_flow = simulation.probability_flow().unwrap();
+        assert_eq!(probability_flow.len(), 10);
+        assert!(probability_flow.values().all(|flow| flow.abs() < 1e-12));
+
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![((state + 1).rem_euclid(5), "forward", 1.0)]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let probability_flow = simulation.probability_flow().unwrap();
+        assert_eq!(probability_flow.len(), 5);
+        for state in 0..5 {
+            assert!((probability_flow[&(state, (state + 1) % 5)] - 0.2).abs() < 1e-12);
+        }
+        let (net_flux_matrix, ordering) = simulation.net_flux_matrix().unwrap();
+        let index = |state: i32| ordering.iter().position(|s| *s == state).unwrap();
+        assert!((net_flux_matrix[(index(1), index(0))] + 0.2).abs() < 1e-12);
+        assert_eq!(net_flux_matrix[(index(0), index(2))], 0.0);
+
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![(state.max(1), "absorb", 1.0)]
+        });
+        assert_eq!(
+            Simulation::new(0, state_transition_generator).probability_flow(),
+            None
+        );
+    }
+
+    #[test]
+    fn expected_hitting_time() {
+        const NUM_STATES: i32 = 6;
+        // Gambler's ruin with a reflecting upper boundary, the expected time to
+        // ruin starting with i is i (2 N - i)
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            match state {
+                0 => vec![(0, "ruined", 1.0)],
+                NUM_STATES => vec![(NUM_STATES - 1, "lose", 1.0)],
+                _ => vec![(state + 1, "win", 0.5), (state - 1, "lose", 0.5)],
+            }
+        });
+        let mut simulation = Simulation::new(3, state_transition_generator);
+        let hitting_times = simulation.expected_hitting_time(0);
+        assert_eq!(hitting_times.len(), NUM_STATES as usize + 1);
+        for (state, hitting_time) in hitting_times {
+            let expected = (state * (2 * NUM_STATES - state)) as f64;
+            assert!((hitting_time - expected).abs() < 1e-9);
+        }
+
+        // With an absorbing upper boundary the gambler may win instead
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            match state {
+                0 | NUM_STATES => vec![(state, "done", 1.0)],
+                _ => vec![(state + 1, "win", 0.5), (state - 1, "lose", 0.5)],
+            }
+        });
+        let mut simulation = Simulation::new(3, state_transition_generator);
+        let hitting_times = simulation.expected_hitting_time(0);
+        assert_eq!(hitting_times[&0], 0.0);
+        for state in 1..=NUM_STATES {
+            assert_eq!(hitting_times[&state], f64::INFINITY);
+        }
+        assert!(simulation
+            .expected_hitting_time(-1)
+            .values()
+            .all(|hitting_time| *hitting_time == f64::INFINITY));
+    }
+
+    #[test]
+    fn watch() {
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            match state {
+                0 => vec![(0, "stay", 0.5), (1, "leave", 0.5)],
+                _ => vec![(1, "stay", 1.0)],
+            }
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let watch = |calls: &Arc<std::sync::Mutex<Vec<(Time, Probability)>>>| -> WatchCallback {
+            let calls = calls.clone();
+            Arc::new(move |time, probability| calls.lock().unwrap().push((time, probability)))
+        };
+        simulation.watch(1, 0.8, watch(&calls));
+        simulation.watch(0, 0.2, watch(&calls));
+        simulation.run(2).unwrap();
+        assert!(calls.lock().unwrap().is_empty());
+        simulation.next_step().unwrap();
+        simulation.next_step().unwrap();
+        assert_eq!(*calls.lock().unwrap(), vec![(3, 0.875), (3, 0.125)]);
+
+        simulation.clear_watches();
+        simulation.watch(1, 0.5, watch(&calls));
+        simulation.n_step_probability(0, 1, 2);
+        simulation.full_traversal(true, None).unwrap();
+        simulation.rollback(0).unwrap();
+        simulation.next_step().unwrap();
+        simulation.next_step().unwrap();
+        assert_eq!(calls.lock().unwrap().len(), 3);
+        // Crossing the threshold downwards by setting the distribution
+        // doesn't trigger the watch, only steps of the markov chain do
+        simulation
+            .set_distribution(HashMap::from([(0, 1.0)]))
+            .unwrap();
+        simulation.next_step().unwrap();
+        assert_eq!(calls.lock().unwrap()[2..], [(1, 0.5), (4, 0.5)]);
+    }
+
+    #[test]
+    fn filter_states() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.run(4).unwrap();
+        let positive_distribution = simulation.filter_states(4, |state| *state > 0);
+        assert_eq!(positive_distribution, HashMap::from([(2, 0.8), (4, 0.2)]));
+        assert!((positive_distribution.values().sum::<Probability>() - 1.0).abs() < 1e-12);
+        assert_eq!(
+            simulation.filter_states(4, |_| true),
+            simulation.probability_distribution(4)
+        );
+        assert!(simulation
+            .filter_states(4, |state| state % 2 != 0)
+            .is_empty());
+
+        assert_eq!(
+            simulation
+                .states_above_threshold(4, 0.2)
+                .into_iter()
+                .sorted()
+                .collect_vec(),
+            vec![-2, 0, 2]
+        );
+        assert!(simulation.states_above_threshold(4, 0.5).is_empty());
+    }
+
+    #[test]
+    fn mutual_information() {
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![
+                ((state + 1).rem_euclid(4), "forward", 0.5),
+                ((state - 1).rem_euclid(4), "backward", 0.25),
+                (state, "stay", 0.25),
+            ]
+        });
+        let mut simulation = Simulation::new_with_distribution(
+            HashMap::from([(0, 0.5), (1, 0.25), (2, 0.25)]),
+            state_transition_generator,
+        );
+        simulation.run(5).unwrap();
+        assert!((simulation.mutual_information(0, 0) - simulation.entropy(0)).abs() < 1e-12);
+        assert!((simulation.mutual_information(3, 3) - simulation.entropy(3)).abs() < 1e-12);
+        let mut last_mutual_information = simulation.mutual_information(0, 0);
+        for time in 1..=5 {
+            let mutual_information = simulation.mutual_information(0, time);
+            assert!(mutual_information >= 0.0);
+            // The data processing inequality
+            assert!(mutual_information <= last_mutual_information + 1e-12);
+            assert_eq!(mutual_information, simulation.mutual_information(time, 0));
+            last_mutual_information = mutual_information;
+        }
+        // One step of the walk from state 0: I = H(X_1) - H(X_1 | X_0)
+        let entropy_1 = simulation.entropy(1);
+        let conditional_entropy = -(0.5 * 0.5f64.log2() + 2.0 * 0.25 * 0.25f64.log2());
+        assert!(
+            (simulation.mutual_information(0, 1) - (entropy_1 - conditional_entropy)).abs() < 1e-12
+        );
+
+        let state_transition_generator =
+            Arc::new(|_: i32| vec![(0, "reset", 0.5), (1, "reset", 0.5)]);
+        let mut simulation = Simulation::new_with_distribution(
+            HashMap::from([(0, 0.5), (1, 0.5)]),
+            state_transition_generator,
+        );
+        simulation.next_step().unwrap();
+        assert!(simulation.mutual_information(0, 1).abs() < 1e-12);
+    }
+
+    #[test]
+    fn time_reversed() {
+        // A biased walk on a ring with a shortcut, which is not reversible
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            let mut next_states = vec![
+                ((state + 1).rem_euclid(4), "forward", 0.6),
+                ((state - 1).rem_euclid(4), "backward", 0.2),
+            ];
+            if state == 0 {
+                next_states.push((2, "shortcut", 0.2));
+            } else {
+                next_states.push((state, "stay", 0.2));
+            }
+            next_states
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let mut reversed_simulation = simulation.time_reversed().unwrap();
+        assert_eq!(
+            reversed_simulation.initial_distribution(),
+            simulation.initial_distribution()
+        );
+        let matrix = |simulation: &mut Simulation<i32, &str>| {
+            let (matrix, ordering) = simulation.transition_rate_matrix_complete(None).unwrap();
+            matrix
+                .indexed_iter()
+                .map(|((source, target), probability)| {
+                    ((ordering[source], ordering[target]), *probability)
+                })
+                .collect::<HashMap<_, _>>()
+        };
+        let original_matrix = matrix(&mut simulation);
+        let reversed_matrix = matrix(&mut reversed_simulation);
+        let steady_state_distribution = simulation.steady_state_distribution().unwrap();
+        for ((source, target), probability) in &reversed_matrix {
+            let expected = steady_state_distribution[target] * original_matrix[&(*target, *source)]
+                / steady_state_distribution[source];
+            assert!((probability - expected).abs() < 1e-12);
+        }
+        assert!((reversed_matrix[&(1, 0)] - original_matrix[&(1, 0)]).abs() > 0.1);
+        assert!(reversed_simulation
+            .known_transitions()
+            .contains(&"shortcut"));
+
+        let twice_reversed_matrix = matrix(&mut reversed_simulation.time_reversed().unwrap());
+        for (index, probability) in original_matrix {
+            assert!((twice_reversed_matrix[&index] - probability).abs() < 1e-12);
+        }
+
+        // A reversible chain is its own time-reversal
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![
+                ((state + 1).rem_euclid(5), "forward", 0.5),
+                ((state - 1).rem_euclid(5), "backward", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let original_matrix = matrix(&mut simulation);
+        let reversed_matrix = matrix(&mut simulation.time_reversed().unwrap());
+        for (index, probability) in original_matrix {
+            assert!((reversed_matrix[&index] - probability).abs() < 1e-12);
+        }
+
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![((state + 1).min(2), "next", 1.0)]);
+        assert!(Simulation::new(0, state_transition_generator)
+            .time_reversed()
+            .is_none());
+    }
+
+    #[test]
+    fn product() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut walk = Simulation::new(0, state_transition_generator);
+        let state_transition_generator =
+            Arc::new(|state: bool| -> OutgoingTransitions<bool, &str> {
+                vec![(!state, "flip", 0.25), (state, "keep", 0.75)]
+            });
+        let mut coin = Simulation::new_with_distribution(
+            HashMap::from([(false, 0.5), (true, 0.5)]),
+            state_transition_generator,
+        );
+        let mut product = walk.clone().product(coin.clone());
+        assert_eq!(
+            product.initial_distribution(),
+            HashMap::from([((0, false), 0.5), ((0, true), 0.5)])
+        );
+        walk.run(3).unwrap();
+        coin.run(3).unwrap();
+        product.run(3).unwrap();
+        for time in 0..=3 {
+            assert!(
+                (product.entropy(time) - walk.entropy(time) - coin.entropy(time)).abs() < 1e-12
+            );
+        }
+        assert_eq!(
+            product.state_probability((1, true), 1),
+            walk.state_probability(1, 1) * coin.state_probability(true, 1)
+        );
+        assert!(product.known_transitions().contains(&("next", "flip")));
+    }
+
+    #[test]
+    fn sensitivity_analysis() {
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            match state {
+                0 => vec![(0, "stay", 0.5), (1, "next", 0.5)],
+                1 => vec![(2, "next", 0.75), (0, "back", 0.25)],
+                _ => vec![(0, "restart", 1.0)],
+            }
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let sensitivities = simulation.sensitivity_analysis().unwrap();
+        assert_eq!(sensitivities.len(), 5);
+        let steady_state_distribution = simulation.steady_state_distribution().unwrap();
+        let (matrix, ordering) = simulation.transition_rate_matrix_complete(None).unwrap();
+        for derivatives in sensitivities.values() {
+            assert_eq!(derivatives.len(), 3);
+            let sum = derivatives.iter().map(|(_, value)| value).sum::<f64>();
+            assert!(sum.abs() < 1e-10);
+        }
+
+        // Moving probability from one transition of a state to another keeps
+        // the matrix stochastic, so the change of the steady state is the
+        // difference of the two derivatives
+        let index = |state: &i32| ordering.iter().position(|other| other == state).unwrap();
+        let delta = 1e-6;
+        for (source, target, other_target) in [(0, 1, 0), (1, 0, 2)] {
+            let mut perturbed = matrix.clone();
+            perturbed[(index(&source), index(&target))] += delta;
+            perturbed[(index(&source), index(&other_target))] -= delta;
+            let mut coefficients = (perturbed - Array2::<f64>::eye(3)).reversed_axes();
+            coefficients.row_mut(2).fill(1.0);
+            let perturbed_distribution =
+                solve_linear_system(coefficients, Array1::from(vec![0.0, 0.0, 1.0])).unwrap();
+            let derivatives = &sensitivities[&(source, target)];
+            let other_derivatives = &sensitivities[&(source, other_target)];
+            for (state, derivative) in derivatives {
+                let other_derivative = other_derivatives
+                    .iter()
+                    .find(|(other_state, _)| other_state == state)
+                    .unwrap()
+                    .1;
+                let difference = (perturbed_distribution[index(state)]
+                    - steady_state_distribution[state])
+                    / delta;
+                assert!((difference - (derivative - other_derivative)).abs() < 1e-4);
+            }
+        }
+
+        let mut reducible_simulation = Simulation::new(
+            0,
+            Arc::new(|state: i32| vec![(state.max(1), "absorb", 1.0)]),
+        );
+        assert!(reducible_simulation.sensitivity_analysis().is_none());
+    }
+
+    #[test]
+    fn pruning() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.set_pruning_threshold(1e-4);
+        simulation.run(50).unwrap();
+        let distribution = simulation.probability_distribution(50);
+        // Without pruning the distribution would contain 51 states
+        assert!(distribution.len() < 30);
+        assert!((distribution.values().sum::<Probability>() - 1.0).abs() < 1e-10);
+        assert!(distribution
+            .values()
+            .all(|probability| *probability >= 1e-4));
+        let total_pruned_mass = (0..=50)
+            .map(|time| simulation.pruned_mass(time))
+            .sum::<f64>();
+        assert!(total_pruned_mass > 0.0);
+        assert!(total_pruned_mass < 1e-2);
+        assert_eq!(simulation.pruned_mass(0), 0.0);
+        assert!(simulation.known_states().len() > distribution.len());
+
+        // Queries besides the main timeline are exact
+        assert_eq!(simulation.n_step_probability(0, 20, 20), 0.5_f64.powi(20));
+
+        simulation.rollback(10).unwrap();
+        assert_eq!(simulation.pruned_mass(20), 0.0);
+        simulation.set_pruning_threshold(0.0);
+        simulation.run(2).unwrap();
+        assert_eq!(simulation.pruned_mass(12), 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "The pruning threshold NaN is not in [0, 1)")]
+    fn nan_pruning_threshold() {
+        let mut simulation = Simulation::new(0, Arc::new(|state: i32| vec![(state, "stay", 1.0)]));
+        simulation.set_pruning_threshold(f64::NAN);
+    }
+
+    #[test]
+    fn most_probable_states() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.run(4).unwrap();
+        // The distribution at time 4 is (1, 4, 6, 4, 1) / 16 on -4, -2, 0, 2, 4
+        let most_probable_states = simulation.most_probable_states(4, 3);
+        assert_eq!(most_probable_states.len(), 3);
+        assert_eq!(most_probable_states[0], (0, 0.375));
+        assert_eq!(most_probable_states[1].1, 0.25);
+        assert_eq!(most_probable_states[2].1, 0.25);
+        assert_ne!(most_probable_states[1].0, most_probable_states[2].0);
+        // Ties are broken deterministically
+        assert_eq!(simulation.most_probable_states(4, 3), most_probable_states);
+        assert_eq!(simulation.most_probable_states(4, 10).len(), 5);
+        assert!(simulation.most_probable_states(4, 0).is_empty());
+
+        let (states, remaining_mass) = simulation.probability_mass_above(4, 0.1);
+        assert_eq!(states[0], (0, 0.375));
+        assert_eq!(
+            states
+                .iter()
+                .map(|(state, _)| *state)
+                .sorted()
+                .collect_vec(),
+            vec![-2, 0, 2]
+        );
+        assert_eq!(remaining_mass, 0.125);
+        let (states, remaining_mass) = simulation.probability_mass_above(4, 0.5);
+        assert!(states.is_empty());
+        assert_eq!(remaining_mass, 1.0);
+    }
+
+    #[test]
+    fn merge() {
+        const NUM_STATES: i32 = 4;
+        let state_transition_generator: StateTransitionGenerator<i32, &str> =
+            Arc::new(|state: i32| {
+                vec![
+                    ((state + 1).rem_euclid(NUM_STATES), "forward", 0.5),
+                    (state, "stay", 0.5),
+                ]
+            });
+        let edges = |simulation: &Simulation<i32, &'static str>| {
+            let graph = simulation.state_transition_graph();
+            graph
+                .edge_references()
+                .map(|edge| {
+                    (
+                        graph[edge.source()],
+                        graph[edge.target()],
+                        edge.weight().0,
+                        edge.weight().1.to_bits(),
+                    )
+                })
+                .sorted()
+                .collect_vec()
+        };
+        let mut full_simulation = Simulation::new(0, state_transition_generator.clone());
+        full_simulation.full_traversal(false, None).unwrap();
+
+        // Each half generates the transitions of two states
+        let mut simulation = Simulation::new(0, state_transition_generator.clone());
+        simulation.run(2).unwrap();
+        let mut other_simulation = Simulation::new(2, state_transition_generator.clone());
+        other_simulation.run(2).unwrap();
+        assert_ne!(edges(&simulation), edges(&full_simulation));
+        simulation.merge(&other_simulation).unwrap();
+        assert_eq!(edges(&simulation), edges(&full_simulation));
+        assert_eq!(
+            simulation.known_states().into_iter().sorted().collect_vec(),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(simulation.known_transitions().len(), 2);
+        assert_eq!(simulation.cache_len(), 4);
+        assert_eq!(simulation.time(), 2);
+        assert_eq!(simulation.initial_distribution(), HashMap::from([(0, 1.0)]));
+        // Merging again doesn't add parallel edges
+        simulation.merge(&other_simulation).unwrap();
+        assert_eq!(edges(&simulation), edges(&full_simulation));
+
+        let independent_simulation = Simulation::new(
+            0,
+            Arc::new(|state: i32| vec![(state, "stay", 1.0)]) as StateTransitionGenerator<_, _>,
+        );
+        assert_eq!(
+            simulation.merge(&independent_simulation),
+            Err(MergeError::DifferentGenerators)
+        );
+    }
+
+    #[test]
+    fn hashed_accessors() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.run(3).unwrap();
+        for time in 0..=3 {
+            let hashed_distribution = simulation.hashed_distribution(time).unwrap();
+            let distribution = simulation.probability_distribution(time);
+            assert_eq!(hashed_distribution.len(), distribution.len());
+            for (state_hash, probability) in hashed_distribution {
+                let state = simulation.resolve_state(*state_hash).unwrap();
+                assert_eq!(simulation.state_hash(state), *state_hash);
+                assert_eq!(distribution[state], *probability);
+            }
+        }
+        assert!(simulation.hashed_distribution(4).is_none());
+        assert!(simulation
+            .resolve_state(simulation.state_hash(&100))
+            .is_none());
+
+        let hashed_graph = simulation.hashed_graph();
+        let graph = simulation.state_transition_graph();
+        assert_eq!(hashed_graph.node_count(), graph.node_count());
+        for edge in hashed_graph.edge_references() {
+            let (transition_hash, probability) = edge.weight();
+            let (transition, other_probability) = &graph[edge.id()];
+            assert_eq!(
+                simulation.resolve_transition(*transition_hash),
+                Some(transition)
+            );
+            assert_eq!(probability, other_probability);
+            assert_eq!(
+                simulation.resolve_state(hashed_graph[edge.source()]),
+                Some(&graph[edge.source()])
+            );
+        }
+    }
+
+    #[test]
+    fn bfs_traversal() {
+        // A 10x10 lattice with reflecting boundaries
+        let lattice = |num_calls: Arc<AtomicUsize>| {
+            Arc::new(move |(x, y): (i32, i32)| {
+                num_calls.fetch_add(1, Ordering::Relaxed);
+                [(1, 0), (-1, 0), (0, 1), (0, -1)]
+                    .into_iter()
+                    .map(|(dx, dy)| {
+                        let neighbor = ((x + dx).clamp(0, 9), (y + dy).clamp(0, 9));
+                        (neighbor, (dx, dy), 0.25)
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        let num_calls = Arc::new(AtomicUsize::new(0));
+        let mut simulation = Simulation::new((0, 0), lattice(num_calls.clone()));
+        simulation.bfs_traversal(true).unwrap();
+        assert_eq!(num_calls.load(Ordering::Relaxed), 100);
+        assert_eq!(simulation.known_states().len(), 100);
+        assert_eq!(simulation.time(), 0);
+
+        let num_full_traversal_calls = Arc::new(AtomicUsize::new(0));
+        let mut other_simulation =
+            Simulation::new((0, 0), lattice(num_full_traversal_calls.clone()));
+        // The generator is cached, but full_traversal still calculates a
+        // probability distribution over the reached states in each step
+        let num_steps = AtomicUsize::new(0);
+        other_simulation
+            .full_traversal_with_progress(true, None, |steps, _| {
+                num_steps.store(steps as usize + 1, Ordering::Relaxed);
+                true
+            })
+            .unwrap();
+        assert_eq!(num_full_traversal_calls.load(Ordering::Relaxed), 100);
+        assert_eq!(num_steps.load(Ordering::Relaxed), 19);
+        assert_eq!(
+            other_simulation.state_transition_graph().edge_count(),
+            simulation.state_transition_graph().edge_count()
+        );
+        assert_eq!(
+            other_simulation
+                .known_states()
+                .into_iter()
+                .collect::<HashSet<_>>(),
+            simulation
+                .known_states()
+                .into_iter()
+                .collect::<HashSet<_>>()
+        );
+
+        // Already traversed states are not visited again
+        simulation.bfs_traversal(false).unwrap();
+        assert_eq!(num_calls.load(Ordering::Relaxed), 100);
+        assert_eq!(simulation.time(), 0);
+
+        let mut simulation = Simulation::new((0, 0), lattice(Arc::new(AtomicUsize::new(0))));
+        simulation.bfs_traversal(false).unwrap();
+        assert_eq!(simulation.time(), 19);
+        assert!(simulation.probability_distribution(19)[&(9, 9)] > 0.0);
+    }
+
+    #[test]
+    fn bfs_traversal_error() {
+        let state_transition_generator = Arc::new(|state: i32| {
+            if state < 3 {
+                vec![(state + 1, "next", 1.0)]
+            } else {
+                vec![(state + 1, "next", 0.5)]
+            }
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert!(matches!(
+            simulation.bfs_traversal(true),
+            Err(SimulationError::ProbabilitiesDoNotSumToOne { state: 3, .. })
+        ));
+        assert_eq!(simulation.known_states().len(), 4);
+    }
+
+    #[test]
+    fn probability_tolerance() {
+        let state_transition_generator = Arc::new(|state: i32| {
+            vec![
+                (state + 1, "next", 0.5 + 1e-10),
+                (state - 1, "previous", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator.clone());
+        assert_eq!(
+            simulation.probability_tolerance(),
+            DEFAULT_PROBABILITY_TOLERANCE
+        );
+        simulation.next_step().unwrap();
+
+        simulation.set_probability_tolerance(1e-11);
+        assert!(matches!(
+            simulation.next_step(),
+            Err(SimulationError::ProbabilitiesDoNotSumToOne { .. })
+        ));
+
+        let mut simulation = SimulationBuilder::new(state_transition_generator.clone())
+            .initial_state(0)
+            .probability_tolerance(1e-11)
+            .build()
+            .unwrap();
+        assert_eq!(simulation.probability_tolerance(), 1e-11);
+        assert!(simulation.next_step().is_err());
+
+        let mut simulation = Simulation::new(0, state_transition_generator.clone());
+        simulation.set_probability_tolerance(1e-6);
+        simulation
+            .set_distribution(HashMap::from([(0, 0.5 + 1e-8), (1, 0.5)]))
+            .unwrap();
+        simulation.set_probability_tolerance(1e-11);
+        assert!(matches!(
+            simulation.set_distribution(HashMap::from([(0, 0.5 + 1e-10), (1, 0.5)])),
+            Err(SimulationError::InitialProbabilitiesDoNotSumToOne { .. })
+        ));
+
+        let circular_walk = Arc::new(|state: i32| {
+            vec![
+                ((state + 1).rem_euclid(4), "forward", 0.5),
+                ((state - 1).rem_euclid(4), "backward", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, circular_walk.clone());
+        simulation.set_probability_tolerance(1e-6);
+        let mut other_simulation = Simulation::new(0, circular_walk);
+        other_simulation.set_probability_tolerance(1e-7);
+        assert_eq!(
+            simulation.time_reversed().unwrap().probability_tolerance(),
+            1e-6
+        );
+        assert_eq!(
+            simulation
+                .lumped_chain(|state| state % 2)
+                .probability_tolerance(),
+            1e-6
+        );
+        assert_eq!(
+            simulation.product(other_simulation).probability_tolerance(),
+            1e-6 + 1e-7
+        );
+
+        let state_transition_generator = Arc::new(|state: i32| vec![(state, "stay", f64::NAN)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.set_probability_tolerance(f64::INFINITY);
+        assert!(simulation.next_step().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "The probability tolerance NaN is negative or NaN")]
+    fn nan_probability_tolerance() {
+        let mut simulation = Simulation::new(0, Arc::new(|state: i32| vec![(state, "stay", 1.0)]));
+        simulation.set_probability_tolerance(f64::NAN);
+    }
+
+    #[test]
+    #[should_panic(expected = "The probability tolerance -0.1 is negative or NaN")]
+    fn negative_probability_tolerance() {
+        SimulationBuilder::new(Arc::new(|state: i32| vec![(state, "stay", 1.0)]))
+            .initial_state(0)
+            .probability_tolerance(-0.1)
+            .build()
+            .unwrap();
+    }
+
+    #[test]
+    fn non_deterministic_generator() {
+        let forward_probability = Arc::new(AtomicUsize::new(50));
+        let state_transition_generator = {
+            let forward_probability = forward_probability.clone();
+            Arc::new(move |state: bool| {
+                let probability = forward_probability.load(Ordering::Relaxed) as f64 / 100.;
+                vec![
+                    (!state, "flip", probability),
+                    (state, "stay", 1.0 - probability),
+                ]
+            })
+        };
+        let mut simulation = Simulation::new(false, state_transition_generator.clone());
+        simulation.run(2).unwrap();
+
+        // Deterministic outputs can be recomputed
+        simulation.clear_cache();
+        simulation.next_step().unwrap();
+
+        forward_probability.store(25, Ordering::Relaxed);
+        simulation.clear_cache();
+        let error = simulation.next_step().unwrap_err();
+        let SimulationError::NonDeterministicGenerator(error) = error else {
+            panic!("Unexpected error {error:?}");
+        };
+        let mut previous = error.previous;
+        previous.sort_by_key(|(_, transition, _)| *transition);
+        assert_eq!(
+            previous,
+            vec![(!error.state, "flip", 0.5), (error.state, "stay", 0.5)]
+        );
+        assert_eq!(
+            error.current,
+            vec![(!error.state, "flip", 0.25), (error.state, "stay", 0.75)]
+        );
+        assert_eq!(simulation.time(), 3);
+
+        // Invalidating the changed outputs replaces the transitions
+        simulation.clear_cache_where(|_, _| true);
+        simulation.next_step().unwrap();
+        assert_eq!(simulation.state_transition_graph().edge_count(), 4);
+        for edge in simulation.state_transition_graph().edge_weights() {
+            assert_eq!(edge.1, if edge.0 == "flip" { 0.25 } else { 0.75 });
+        }
+
+        // Merging a simulation which saw different transitions is rejected
+        forward_probability.store(50, Ordering::Relaxed);
+        let mut other_simulation = Simulation::new(false, state_transition_generator);
+        other_simulation.next_step().unwrap();
+        assert!(matches!(
+            simulation.merge(&other_simulation),
+            Err(MergeError::NonDeterministicGenerator(error)) if !error.state
+        ));
+        assert_eq!(simulation.state_transition_graph().edge_count(), 4);
+    }
+
+    #[test]
+    fn trim_history() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation =
+            Simulation::new(0, state_transition_generator.clone()).with_history_tracking();
+        simulation.run(10).unwrap();
+        let distribution = simulation.probability_distribution(10);
+        let num_edges = simulation.state_transition_graph().edge_count();
+        let cache_len = simulation.cache_len();
+
+        simulation.trim_history(3);
+        assert_eq!(simulation.probability_distributions().len(), 3);
+        assert_eq!(simulation.time(), 10);
+        assert_eq!(simulation.probability_distribution(10), distribution);
+        assert!(simulation.try_probability_distribution(7).is_none());
+        assert_eq!(simulation.history().unwrap().len(), 2);
+        assert_eq!(simulation.state_transition_graph().edge_count(), num_edges);
+        assert_eq!(simulation.cache_len(), cache_len);
+        assert!(simulation.rollback(5).is_err());
+        simulation.rollback(9).unwrap();
+
+        simulation.trim_before(100);
+        assert_eq!(simulation.probability_distributions().len(), 1);
+        simulation.trim_history(0);
+        assert_eq!(simulation.probability_distributions().len(), 1);
+        simulation.next_step().unwrap();
+        assert_eq!(
+            simulation.probability_distribution(10),
+            Simulation::new(0, state_transition_generator)
+                .run(10)
+                .unwrap()
+        );
+
+        simulation.trim_before(0);
+        assert_eq!(simulation.probability_distributions().len(), 2);
+    }
+
+    #[test]
+    fn trimmed_timeline() {
+        const NUM_STATES: i32 = 4;
+        let state_transition_generator = Arc::new(|state: i32| {
+            vec![
+                ((state + 1).rem_euclid(NUM_STATES), "forward", 0.5),
+                ((state - 1).rem_euclid(NUM_STATES), "backward", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator.clone());
+        simulation.run(3).unwrap();
+        simulation.trim_before(2);
+        let distribution = simulation.probability_distribution(2);
+
+        let mut reversed = simulation.time_reversed().unwrap();
+        assert_eq!(reversed.initial_distribution(), distribution);
+        let lumped = simulation.lumped_chain(|state| state % 2);
+        assert_eq!(lumped.initial_distribution(), HashMap::from([(0, 1.0)]));
+        assert_eq!(
+            simulation
+                .sample_path(2, &mut rand::thread_rng())
+                .unwrap()
+                .len(),
+            2
+        );
+        let product = simulation
+            .clone()
+            .product(Simulation::new(0, state_transition_generator));
+        assert_eq!(
+            product.initial_distribution(),
+            distribution
+                .iter()
+                .map(|(state, probability)| ((*state, 0), *probability))
+                .collect()
+        );
+        assert_eq!(reversed.run(1).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn occupation_time() {
+        const NUM_STATES: i32 = 5;
+        let state_transition_generator = Arc::new(|state: i32| {
+            vec![
+                ((state + 1).rem_euclid(NUM_STATES), "forward", 0.5),
+                ((state - 1).rem_euclid(NUM_STATES), "backward", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.run(3).unwrap();
+        let cache_hit_rate = simulation.cache_hit_rate();
+
+        let occupation_time = simulation.occupation_time(2).unwrap();
+        assert_eq!(simulation.time(), 3);
+        assert_eq!(simulation.cache_hit_rate(), cache_hit_rate);
+        assert_eq!(occupation_time[&0], 1.5);
+        assert_eq!(occupation_time[&1], 0.5);
+
+        let occupation_time = simulation.occupation_time(20).unwrap();
+        assert_eq!(simulation.time(), 20);
+        assert_eq!(occupation_time.len(), NUM_STATES as usize);
+        assert!((occupation_time.values().sum::<f64>() - 21.0).abs() < 1e-9);
+
+        let discounted_occupation = simulation.discounted_occupation(2, 0.5).unwrap();
+        assert_eq!(discounted_occupation[&0], 1.125);
+        assert_eq!(discounted_occupation[&1], 0.25);
+        let discounted_occupation = simulation.discounted_occupation(20, 0.9).unwrap();
+        let expected = (1.0 - 0.9_f64.powi(21)) / (1.0 - 0.9);
+        assert!((discounted_occupation.values().sum::<f64>() - expected).abs() < 1e-9);
+
+        simulation.trim_before(5);
+        assert_eq!(
+            simulation.occupation_time(20),
+            Err(SimulationError::NoProbabilityDistribution { time: 0 })
+        );
+    }
+
+    #[test]
+    fn multi_step_matrix() {
+        const NUM_STATES: i32 = 5;
+        let state_transition_generator = Arc::new(|state: i32| {
+            vec![
+                ((state + 1).rem_euclid(NUM_STATES), "forward", 0.6),
+                ((state - 1).rem_euclid(NUM_STATES), "backward", 0.3),
+                (state, "stay", 0.1),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator.clone());
+        let mut other_simulation = Simulation::new(0, state_transition_generator);
+
+        let distribution = simulation.multi_step_matrix(8);
+        let other_distribution = other_simulation.run(8).unwrap();
+        assert_eq!(simulation.time(), 8);
+        assert_eq!(simulation.probability_distributions().len(), 2);
+        assert_eq!(distribution.len(), other_distribution.len());
+        for (state, probability) in &other_distribution {
+            assert!((distribution[state] - probability).abs() < 1e-12);
+        }
+
+        assert_eq!(simulation.multi_step_matrix(0), distribution);
+        assert_eq!(simulation.time(), 8);
+        simulation.multi_step_matrix(3);
+        other_simulation.run(3).unwrap();
+        for (state, probability) in other_simulation.probability_distribution(11) {
+            assert!((simulation.probability_distribution(11)[&state] - probability).abs() < 1e-12);
+        }
+
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            match state {
+                0 => vec![(0, "stay", 0.5), (1, "leave", 0.5)],
+                _ => vec![(1, "stay", 1.0)],
+            }
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let watch_calls = calls.clone();
+        simulation.watch(
+            1,
+            0.9,
+            Arc::new(move |time, probability| {
+                watch_calls.lock().unwrap().push((time, probability))
+            }),
+        );
+        simulation.multi_step_matrix(4);
+        assert_eq!(*calls.lock().unwrap(), vec![(4, 0.9375)]);
+    }
+
+    #[test]
+    fn with_cache_capacity() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator.clone());
+        let mut bounded_simulation =
+            Simulation::new(0, state_transition_generator).with_cache_capacity(2);
+        simulation.run(10).unwrap();
+        bounded_simulation.run(10).unwrap();
+        assert_eq!(
+            bounded_simulation.probability_distribution(10),
+            simulation.probability_distribution(10)
+        );
+    }
+
+    #[test]
+    fn n_step_probability() {
+        const NUM_STATES: i32 = 5;
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![
+                ((state + 1).rem_euclid(NUM_STATES), "forward", 0.5),
+                ((state - 1).rem_euclid(NUM_STATES), "backward", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.next_step().unwrap();
+        let probability_distributions = simulation.probability_distributions();
+
+        assert_eq!(simulation.n_step_probability(0, 0, 2), 0.5);
+        assert_eq!(simulation.n_step_probability(0, 2, 2), 0.25);
+        assert_eq!(simulation.n_step_probability(0, 1, 2), 0.0);
+        assert_eq!(simulation.n_step_probability(3, 3, 0), 1.0);
+        assert_eq!(simulation.n_step_probability(3, 4, 1), 0.5);
+
+        assert_eq!(simulation.time(), 1);
+        assert_eq!(
+            simulation.probability_distributions(),
+            probability_distributions
+        );
+        assert_eq!(simulation.known_states().len(), NUM_STATES as usize);
+
+        // The states of the query are expanded by later traversals
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            match state {
+                0 => vec![(0, "stay", 1.0)],
+                _ => vec![((state + 1).min(12), "next", 0.5), (state, "stay", 0.5)],
+            }
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert_eq!(simulation.n_step_probability(10, 11, 1), 0.5);
+        let (transition_rate_matrix, ordering) =
+            simulation.transition_rate_matrix_complete(None).unwrap();
+        assert_eq!(transition_rate_matrix.dim(), (4, 4));
+        assert_eq!(
+            ordering.into_iter().sorted().collect_vec(),
+            vec![0, 10, 11, 12]
+        );
+        assert_eq!(
+            simulation
+                .communication_classes()
+                .into_iter()
+                .sorted()
+                .collect_vec(),
+            vec![vec![0], vec![10], vec![11], vec![12]]
+        );
+        assert_eq!(simulation.time(), 0);
+    }
 
     #[test]
-    fn random_walk() {
-        let initial_state = 0;
+    fn rollback() {
         let state_transition_generator =
             Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator).with_history_tracking();
+        simulation.run(4).unwrap();
+        let known_states = simulation.known_states().len();
 
-        let mut simulation = Simulation::new(initial_state, state_transition_generator);
-        dbg!(&simulation);
-        assert_eq!(simulation.known_states().len(), 1);
-        assert_eq!(simulation.known_transitions().len(), 0);
-        assert_eq!(simulation.probability_distributions().len(), 1);
-        assert_eq!(simulation.state_transition_graph().node_count(), 1);
-        assert_eq!(simulation.state_transition_graph().edge_count(), 0);
-        assert_eq!(simulation.entropy(0), 0.0);
-
-        simulation.next_step();
-        dbg!(&simulation);
-        assert_eq!(simulation.known_states().len(), 3);
-        assert_eq!(simulation.known_transitions().len(), 2);
-        assert_eq!(simulation.probability_distributions().len(), 2);
-        assert_eq!(simulation.state_transition_graph().node_count(), 3);
-        assert_eq!(simulation.state_transition_graph().edge_count(), 2);
-        assert_eq!(simulation.entropy(1), 1.0);
-
-        let graph = simulation.state_transition_graph();
-        dbg!(&graph);
-        assert_eq!(graph.node_count(), 3);
-        assert_eq!(graph.edge_count(), 2);
-
-        assert_eq!(simulation.state_probability(0, 1), 0.);
-        assert_eq!(simulation.state_probability(1, 1), 0.5);
-        assert_eq!(simulation.state_probability(-1, 1), 0.5);
+        simulation.rollback(2).unwrap();
+        assert_eq!(simulation.time(), 2);
+        assert_eq!(simulation.try_probability_distribution(3), None);
+        assert_eq!(simulation.history().unwrap().len(), 2);
+        assert_eq!(simulation.known_states().len(), known_states);
+        assert_eq!(
+            simulation.rollback(3),
+            Err(SimulationError::NoProbabilityDistribution { time: 3 })
+        );
 
+        simulation.next_step().unwrap();
+        assert_eq!(simulation.time(), 3);
+        assert_eq!(simulation.probability_distribution(3).len(), 4);
+        simulation.rollback(0).unwrap();
+        assert_eq!(simulation.time(), 0);
         assert_eq!(simulation.initial_distribution(), HashMap::from([(0, 1.0)]));
+    }
 
-        assert_eq!(simulation.time(), 1);
+    #[test]
+    fn period() {
+        // Two separate cycles of length 2 and 3 that are reachable from 0
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            match state {
+                0 => vec![(1, "left", 0.5), (10, "right", 0.5)],
+                1 => vec![(2, "next", 1.0)],
+                2 => vec![(1, "next", 1.0)],
+                10 => vec![(11, "next", 1.0)],
+                11 => vec![(12, "next", 1.0)],
+                12 => vec![(10, "next", 0.5), (11, "back", 0.5)],
+                _ => unreachable!(),
+            }
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert!(!simulation.is_irreducible());
+        let classes = simulation
+            .communication_classes()
+            .into_iter()
+            .map(|class| class.into_iter().sorted().collect_vec())
+            .sorted()
+            .collect_vec();
+        assert_eq!(classes, vec![vec![0], vec![1, 2], vec![10, 11, 12]]);
+        assert_eq!(simulation.period(0), 0);
+        assert_eq!(simulation.period(1), 2);
+        assert_eq!(simulation.period(2), 2);
+        // Cycles of length 2 and 3 through 11
+        assert_eq!(simulation.period(11), 1);
+
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![((state + 1).rem_euclid(2), "next", 1.0)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert!(simulation.is_irreducible());
+        assert_eq!(simulation.period(0), 2);
+        assert_eq!(simulation.period(1), 2);
     }
 
     #[test]
-    fn random_walk_with_initial_distribution() {
-        let initial_distribution = HashMap::from([(0, 0.5), (1, 0.5)]);
+    fn fork() {
         let state_transition_generator =
             Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
-        let mut simulation =
-            Simulation::new_with_distribution(initial_distribution, state_transition_generator);
-        assert_eq!(simulation.known_states().len(), 2);
-        assert_eq!(simulation.known_transitions().len(), 0);
-        assert_eq!(simulation.probability_distributions().len(), 1);
-        assert_eq!(simulation.state_transition_graph().node_count(), 2);
-        assert_eq!(simulation.state_transition_graph().edge_count(), 0);
-        assert_eq!(simulation.entropy(0), 1.0);
-        dbg!(&simulation);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.run(3).unwrap();
+        let probability_distributions = simulation.probability_distributions();
 
-        simulation.next_step();
+        let mut fork = simulation.fork(1);
+        assert_eq!(fork.time(), 1);
+        assert_eq!(
+            fork.probability_distribution(1),
+            simulation.probability_distribution(1)
+        );
+        fork.run(4).unwrap();
+        fork.rollback(0).unwrap();
+        fork.next_step().unwrap();
 
-        assert_eq!(simulation.known_states().len(), 4);
-        assert_eq!(simulation.known_transitions().len(), 2);
-        assert_eq!(simulation.probability_distributions().len(), 2);
-        assert_eq!(simulation.state_transition_graph().node_count(), 4);
-        assert_eq!(simulation.state_transition_graph().edge_count(), 4);
-        assert_eq!(simulation.entropy(1), 2.0);
+        assert_eq!(fork.time(), 1);
+        assert_eq!(simulation.time(), 3);
         assert_eq!(
             simulation.probability_distributions(),
-            HashMap::from([
-                (0, HashMap::from([(0, 0.5), (1, 0.5)]),),
-                (
-                    1,
-                    HashMap::from([(-1, 0.25), (0, 0.25), (1, 0.25), (2, 0.25)]),
-                ),
-            ])
+            probability_distributions
         );
-
-        dbg!(&simulation);
+        assert_eq!(simulation.known_states().len(), 7);
+        assert_eq!(fork.known_states().len(), 11);
     }
 
     #[test]
-    fn full_traversal() {
-        let initial_state = 0;
-        const NUM_STATES: i32 = 5;
-        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
-            vec![
-                (
-                    {
-                        if state + 1 == NUM_STATES {
-                            0
-                        } else {
-                            state + 1
-                        }
-                    },
-                    "forward",
-                    0.5,
-                ),
-                (
-                    {
-                        if state - 1 == -1 {
-                            NUM_STATES - 1
-                        } else {
-                            state - 1
-                        }
-                    },
-                    "backward",
-                    0.5,
-                ),
-            ]
-        });
-        let mut simulation = Simulation::new(initial_state, state_transition_generator);
-        simulation.full_traversal(false);
-        dbg!(&simulation);
-        let graph = simulation.state_transition_graph();
-        let dot = petgraph::dot::Dot::with_config(&graph, &[]);
-        println!("{dot:#?}");
-        assert_eq!(simulation.known_states().len(), NUM_STATES as usize);
-        assert_eq!(simulation.known_transitions().len(), 2);
+    fn initial_distribution_validation() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let new_with_distribution = |distribution: Vec<(i32, f64)>| {
+            Simulation::try_new_with_distribution(
+                HashMap::from_iter(distribution),
+                state_transition_generator.clone(),
+            )
+        };
+        assert!(new_with_distribution(vec![(0, 0.5), (1, 0.5)]).is_ok());
         assert_eq!(
-            simulation.probability_distribution(simulation.time()).len(),
-            4
+            new_with_distribution(vec![]).unwrap_err(),
+            SimulationError::EmptyDistribution
         );
         assert_eq!(
-            simulation.state_transition_graph().node_count(),
-            NUM_STATES as usize
+            new_with_distribution(vec![(0, 1.5), (1, -0.5)]).unwrap_err(),
+            SimulationError::NegativeProbability {
+                state: 1,
+                probability: -0.5
+            }
         );
         assert_eq!(
-            simulation.state_transition_graph().edge_count(),
-            2 * NUM_STATES as usize
+            new_with_distribution(vec![(0, 0.5), (1, 0.4)]).unwrap_err(),
+            SimulationError::InitialProbabilitiesDoNotSumToOne { sum: 0.9 }
         );
 
-        let (transition_rate_matrix, ordering) = simulation.transition_rate_matrix();
-        dbg!(&transition_rate_matrix);
-        dbg!(&ordering);
-        assert_eq!(transition_rate_matrix.nrows(), NUM_STATES as usize);
-        assert_eq!(transition_rate_matrix.ncols(), NUM_STATES as usize);
+        let simulation = Simulation::new_with_weights(
+            HashMap::from([(0, 1.0), (1, 3.0)]),
+            state_transition_generator.clone(),
+        )
+        .unwrap();
         assert_eq!(
-            transition_rate_matrix.sum_axis(Axis(0)),
-            Array1::from_elem(NUM_STATES as usize, 1.0)
+            simulation.initial_distribution(),
+            HashMap::from([(0, 0.25), (1, 0.75)])
         );
         assert_eq!(
-            transition_rate_matrix.sum_axis(Axis(1)),
-            Array1::from_elem(NUM_STATES as usize, 1.0)
+            Simulation::new_with_weights(
+                HashMap::from([(0, 0.0)]),
+                state_transition_generator.clone()
+            )
+            .unwrap_err(),
+            SimulationError::WeightsNotNormalizable { sum: 0.0 }
         );
-        assert_eq!(transition_rate_matrix.get((0, 1)), Some(&0.5));
-        assert_eq!(transition_rate_matrix.get((1, 0)), Some(&0.5));
+        assert!(matches!(
+            Simulation::new_with_weights(
+                HashMap::from([(0, f64::NAN)]),
+                state_transition_generator
+            )
+            .unwrap_err(),
+            SimulationError::NegativeProbability { state: 0, .. }
+        ));
     }
 
     #[test]
-    fn uniform_distribution_is_steady() {
-        {
-            let initial_state = 0;
-            const NUM_STATES: i32 = 5;
-            let state_transition_generator =
-                Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
-                    vec![
-                        (
-                            {
-                                if state + 1 == NUM_STATES {
-                                    0
-                                } else {
-                                    state + 1
-                                }
-                            },
-                            "forward",
-                            0.5,
-                        ),
-                        (
-                            {
-                                if state - 1 == -1 {
-                                    NUM_STATES - 1
-                                } else {
-                                    state - 1
-                                }
-                            },
-                            "backward",
-                            0.5,
-                        ),
-                    ]
-                });
-            let mut simulation = Simulation::new(initial_state, state_transition_generator);
-            assert!(simulation.uniform_distribution_is_steady());
+    fn sample() {
+        const NUM_SAMPLES: usize = 100_000;
+        const STEPS: u64 = 10;
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            match state {
+                0 => vec![(0, "stay", 0.7), (1, "switch", 0.3)],
+                _ => vec![(0, "switch", 0.4), (1, "stay", 0.6)],
+            }
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.next_step().unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let path = simulation.sample(STEPS, &mut rng).unwrap();
+        assert_eq!(path.len(), STEPS as usize + 1);
+        assert_eq!(simulation.time(), 1);
+
+        let final_states = (0..NUM_SAMPLES)
+            .map(|_| *simulation.sample(STEPS, &mut rng).unwrap().last().unwrap())
+            .counts();
+        assert_eq!(simulation.time(), 1);
+        let analytical_distribution = simulation.run(STEPS).unwrap();
+        for state in [0, 1] {
+            let empirical_probability = final_states[&state] as f64 / NUM_SAMPLES as f64;
+            assert!((empirical_probability - analytical_distribution[&state]).abs() < 0.01);
         }
-        {
-            let initial_state = 0;
-            const NUM_STATES: i32 = 5;
-            let state_transition_generator =
-                Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
-                    vec![
-                        (
-                            {
-                                if state + 1 == NUM_STATES {
-                                    1
-                                } else {
-                                    state + 1
-                                }
-                            },
-                            "forward",
-                            0.5,
-                        ),
-                        (0, "stay", 0.5),
-                    ]
-                });
-            let mut simulation = Simulation::new(initial_state, state_transition_generator);
-            assert!(!simulation.uniform_distribution_is_steady());
+    }
+
+    #[test]
+    fn large_state_transition_graph() {
+        const NUM_STATES: i32 = 1_000;
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![
+                ((state + 1).rem_euclid(NUM_STATES), "forward", 0.5),
+                ((state - 1).rem_euclid(NUM_STATES), "backward", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.run(NUM_STATES as u64 / 2).unwrap();
+        let graph = simulation.state_transition_graph();
+        assert_eq!(graph.node_count(), NUM_STATES as usize);
+        assert_eq!(graph.edge_count(), 2 * NUM_STATES as usize - 2);
+        assert_eq!(simulation.node_indices.len(), graph.node_count());
+        for (state_hash, node) in &simulation.node_indices {
+            assert_eq!(simulation.state_transition_graph[*node], *state_hash);
+            assert_eq!(
+                simulation.hash_function.hash(&graph[*node]),
+                *state_hash,
+                "The public graph has to keep the node indices"
+            );
         }
     }
 
     #[test]
-    fn stationary_distribution() {
-        let initial_state = 0;
-        const NUM_STATES: i32 = 5;
+    fn expected_value() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.run(6).unwrap();
+        for time in 0..=6 {
+            assert_eq!(simulation.expected_value(time, |state| *state as f64), 0.0);
+            // The variance of a simple random walk grows linearly with time
+            let second_moment = simulation.expected_value(time, |state| (state * state) as f64);
+            assert!((second_moment - time as f64).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn bounded_full_traversal() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator.clone());
+        assert_eq!(
+            simulation.full_traversal(true, Some(3)),
+            Err(SimulationError::IterationLimitReached(
+                IterationLimitReached {
+                    iterations: 3,
+                    known_states: 7
+                }
+            ))
+        );
+        assert_eq!(simulation.time(), 0);
+        assert_eq!(simulation.known_states().len(), 7);
+
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let progress = std::sync::Mutex::new(Vec::new());
+        let result =
+            simulation.full_traversal_with_progress(false, None, |iterations, known_states| {
+                progress.lock().unwrap().push((iterations, known_states));
+                iterations < 2
+            });
+        assert_eq!(
+            result,
+            Err(SimulationError::TraversalCancelled {
+                iterations: 2,
+                known_states: 5
+            })
+        );
+        assert_eq!(progress.into_inner().unwrap(), vec![(0, 1), (1, 3), (2, 5)]);
+        assert_eq!(simulation.time(), 2);
+
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![((state + 1).rem_euclid(3), "next", 1.0)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert!(simulation.full_traversal(true, Some(10)).is_ok());
+        assert_eq!(simulation.known_states().len(), 3);
+    }
+
+    #[test]
+    fn condensation_graph() {
+        // A transient cycle {0, 1} leading into the two closed classes {2, 3}
+        // and {4}
         let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
-            let next = if state + 1 == NUM_STATES {
-                0
-            } else {
-                state + 1
-            };
-            if next == 0 {
-                vec![(0, "forward|stay", 1.0)]
-            } else {
-                vec![(next, "forward", 0.1), (0, "stay", 0.9)]
+            match state {
+                0 => vec![(1, "next", 1.0)],
+                1 => vec![(0, "back", 0.5), (2, "left", 0.25), (4, "right", 0.25)],
+                2 => vec![(3, "next", 1.0)],
+                3 => vec![(2, "next", 1.0)],
+                4 => vec![(4, "stay", 1.0)],
+                _ => unreachable!(),
             }
         });
-        let mut simulation = Simulation::new(initial_state, state_transition_generator);
-        simulation.full_traversal(true);
-        for _ in 0..100 {
-            simulation.next_step();
-        }
-        let stationary_distribution = simulation.stationary_distribution(100);
-        assert!(
-            simulation
-                .probability_distribution(simulation.time())
-                .iter()
-                .zip(&stationary_distribution)
-                .map(|(a, b)| a.1 - b.1)
-                .sum::<f64>()
-                .abs()
-                < 1e-6
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let condensation_graph = simulation.condensation_graph();
+        assert_eq!(condensation_graph.node_count(), 3);
+        assert_eq!(condensation_graph.edge_count(), 2);
+        let class_node = |class: Vec<i32>| {
+            condensation_graph
+                .node_indices()
+                .find(|node| {
+                    condensation_graph[*node].iter().sorted().collect_vec()
+                        == class.iter().collect_vec()
+                })
+                .unwrap()
+        };
+        let transient = class_node(vec![0, 1]);
+        let cycle = class_node(vec![2, 3]);
+        let absorbing = class_node(vec![4]);
+        assert!(condensation_graph.contains_edge(transient, cycle));
+        assert!(condensation_graph.contains_edge(transient, absorbing));
+        assert_eq!(simulation.communication_classes().len(), 3);
+    }
+
+    #[test]
+    fn fundamental_matrix() {
+        // Gambler's ruin with a fair coin and a total of 4
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            match state {
+                0 | 4 => vec![(state, "stay", 1.0)],
+                _ => vec![(state + 1, "win", 0.5), (state - 1, "lose", 0.5)],
+            }
+        });
+        let mut simulation = Simulation::new(2, state_transition_generator);
+        let (fundamental_matrix, ordering) = simulation.fundamental_matrix().unwrap();
+        assert_eq!(
+            ordering.iter().sorted().copied().collect_vec(),
+            vec![1, 2, 3]
         );
+        // N[i][j] = 2 * min(i, j) * (4 - max(i, j)) / 4
+        for (a, state_a) in ordering.iter().enumerate() {
+            for (b, state_b) in ordering.iter().enumerate() {
+                let expected =
+                    2.0 * *state_a.min(state_b) as f64 * (4 - *state_a.max(state_b)) as f64 / 4.0;
+                assert!((fundamental_matrix[(a, b)] - expected).abs() < 1e-12);
+            }
+        }
+
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![((state + 1).rem_euclid(3), "next", 1.0)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert!(simulation.fundamental_matrix().is_none());
+    }
+
+    #[test]
+    fn cache() {
+        const NUM_STATES: i32 = 4;
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![
+                ((state + 1).rem_euclid(NUM_STATES), "forward", 0.5),
+                ((state - 1).rem_euclid(NUM_STATES), "backward", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        assert_eq!(simulation.cache_len(), 0);
+        assert_eq!(simulation.cache_hit_rate(), 0.0);
+
+        // 0, then 1 and 3, then 0 and 2, then 1 and 3 again
+        simulation.run(4).unwrap();
+        assert_eq!(simulation.cache_len(), NUM_STATES as usize);
+        assert_eq!(simulation.cache_hit_rate(), 3.0 / 7.0);
+
+        simulation.clear_cache();
+        assert_eq!(simulation.cache_len(), 0);
+        simulation.next_step().unwrap();
+        assert_eq!(simulation.cache_len(), 2);
+        assert_eq!(simulation.cache_hit_rate(), 3.0 / 9.0);
     }
 }
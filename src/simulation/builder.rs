@@ -0,0 +1,427 @@
+use std::{
+    fmt::Debug,
+    hash::{BuildHasher, Hash},
+};
+
+use hashbrown::HashMap;
+use thiserror::Error;
+
+use super::*;
+
+/// The error returned by
+/// [SimulationBuilder::build](struct.SimulationBuilder.html#method.build).
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum BuilderError<S, T> {
+    /// A required field of the builder was not set.
+    #[error("The field {field} of the simulation builder is missing")]
+    MissingField { field: &'static str },
+    /// The simulation could not be created from the given fields.
+    #[error(transparent)]
+    Simulation(#[from] SimulationError<S, T>),
+}
+
+/// The initial distribution of a simulation built by a
+/// [SimulationBuilder](struct.SimulationBuilder.html).
+enum InitialDistribution<S> {
+    State(S),
+    Probabilities(StateProbabilityDistribution<S>),
+    Weights(HashMap<S, f64>),
+}
+
+/// A builder for a [Simulation](struct.Simulation.html).
+///
+/// Besides the state transition generator and the initial state or
+/// distribution, which are required, the builder configures the hasher used
+/// for states and transitions, the normalization of the probabilities of the
+/// state transition generator and their tolerance, the history tracking, the
+/// maximum number of known states and the capacity of the generator cache.
+///
+/// # Example
+/// ```rust
+/// use entromatica::prelude::*;
+/// use std::{collections::hash_map::DefaultHasher, hash::BuildHasherDefault, sync::Arc};
+///
+/// let state_transition_generator =
+///     Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+/// let mut simulation = SimulationBuilder::new(state_transition_generator)
+///     .initial_state(0)
+///     .hasher(BuildHasherDefault::<DefaultHasher>::default())
+///     .history_tracking(true)
+///     .build()
+///     .unwrap();
+/// simulation.next_step().unwrap();
+/// assert_eq!(simulation.entropy(1), 1.0);
+/// ```
+pub struct SimulationBuilder<S, T> {
+    state_transition_generator: Option<StateTransitionGenerator<S, T>>,
+    initial_distribution: Option<InitialDistribution<S>>,
+    hash_function: HashFunction,
+    normalize_probabilities: bool,
+    history_tracking: bool,
+    cache_capacity: Option<usize>,
+    probability_tolerance: Probability,
+    max_known_states: Option<usize>,
+}
+
+impl<S, T> Default for SimulationBuilder<S, T> {
+    fn default() -> Self {
+        Self {
+            state_transition_generator: None,
+            initial_distribution: None,
+            hash_function: HashFunction::default(),
+            normalize_probabilities: false,
+            history_tracking: false,
+            cache_capacity: None,
+            probability_tolerance: DEFAULT_PROBABILITY_TOLERANCE,
+            max_known_states: None,
+        }
+    }
+}
+
+impl<S, T> SimulationBuilder<S, T>
+where
+    S: Hash + Clone + Send + Sync + PartialEq + Eq + Debug,
+    T: Hash + Clone + Send + Sync + PartialEq + Eq + Debug,
+{
+    /// Create a new builder for a simulation with the given state transition
+    /// generator.
+    pub fn new(state_transition_generator: StateTransitionGenerator<S, T>) -> Self {
+        Self::default().state_transition_generator(state_transition_generator)
+    }
+
+    /// Set the state transition generator.
+    pub fn state_transition_generator(
+        self,
+        state_transition_generator: StateTransitionGenerator<S, T>,
+    ) -> Self {
+        Self {
+            state_transition_generator: Some(state_transition_generator),
+            ..self
+        }
+    }
+
+    /// Start the simulation in a single state with a probability of 1.0.
+    pub fn initial_state(self, initial_state: S) -> Self {
+        Self {
+            initial_distribution: Some(InitialDistribution::State(initial_state)),
+            ..self
+        }
+    }
+
+    /// Start the simulation with the given probability distribution.
+    ///
+    /// See
+    /// [Simulation::try_new_with_distribution](struct.Simulation.html#method.try_new_with_distribution)
+    /// for the requirements on the distribution.
+    pub fn initial_distribution(self, probabilities: StateProbabilityDistribution<S>) -> Self {
+        Self {
+            initial_distribution: Some(InitialDistribution::Probabilities(probabilities)),
+            ..self
+        }
+    }
+
+    /// Start the simulation with a distribution given by relative weights.
+    ///
+    /// See
+    /// [Simulation::new_with_weights](struct.Simulation.html#method.new_with_weights)
+    /// for the requirements on the weights.
+    pub fn initial_weights(self, weights: HashMap<S, f64>) -> Self {
+        Self {
+            initial_distribution: Some(InitialDistribution::Weights(weights)),
+            ..self
+        }
+    }
+
+    /// Hash states and transitions with hashers built by `build_hasher`.
+    ///
+    /// By default the `DefaultHasher` of the standard library is used, whose
+    /// output is not guaranteed to be stable across Rust releases. A hasher
+    /// with a fixed algorithm and seed makes the hashes, e.g. of
+    /// [serializable snapshots](struct.SerializableSimulation.html),
+    /// reproducible across machines.
+    pub fn hasher<B>(self, build_hasher: B) -> Self
+    where
+        B: BuildHasher + Send + Sync + 'static,
+    {
+        Self {
+            hash_function: HashFunction::new(build_hasher),
+            ..self
+        }
+    }
+
+    /// Hash states and transitions with `hash_function`, e.g. the one of an
+    /// existing simulation.
+    pub(crate) fn hash_function(self, hash_function: HashFunction) -> Self {
+        Self {
+            hash_function,
+            ..self
+        }
+    }
+
+    /// Normalize the probabilities of the state transition generator, see
+    /// [Simulation::new_normalizing](struct.Simulation.html#method.new_normalizing).
+    pub fn normalize_probabilities(self, normalize_probabilities: bool) -> Self {
+        Self {
+            normalize_probabilities,
+            ..self
+        }
+    }
+
+    /// Track the transitions that carried probability in each step, see
+    /// [Simulation::with_history_tracking](struct.Simulation.html#method.with_history_tracking).
+    pub fn history_tracking(self, history_tracking: bool) -> Self {
+        Self {
+            history_tracking,
+            ..self
+        }
+    }
+
+    /// Limit the cache of the state transition generator, see
+    /// [Simulation::with_cache_capacity](struct.Simulation.html#method.with_cache_capacity).
+    pub fn cache_capacity(self, max_entries: usize) -> Self {
+        Self {
+            cache_capacity: Some(max_entries),
+            ..self
+        }
+    }
+
+    /// Set the tolerance for the sum of the probabilities of the state
+    /// transition generator, see
+    /// [Simulation::set_probability_tolerance](struct.Simulation.html#method.set_probability_tolerance).
+    pub fn probability_tolerance(self, probability_tolerance: Probability) -> Self {
+        Self {
+            probability_tolerance,
+            ..self
+        }
+    }
+
+    /// Limit the number of known states, see
+    /// [Simulation::with_max_known_states](struct.Simulation.html#method.with_max_known_states).
+    pub fn max_known_states(self, max_known_states: usize) -> Self {
+        Self {
+            max_known_states: Some(max_known_states),
+            ..self
+        }
+    }
+
+    /// Build the simulation.
+    ///
+    /// Returns `BuilderError::MissingField` if no state transition generator
+    /// or no initial state or distribution was given and the errors of
+    /// [Simulation::try_new_with_distribution](struct.Simulation.html#method.try_new_with_distribution)
+    /// and [Simulation::new_with_weights](struct.Simulation.html#method.new_with_weights)
+    /// for an invalid initial distribution.
+    ///
+    /// # Panics
+    /// This method panics if the cache capacity is zero or if the probability
+    /// tolerance is negative or NaN.
+    pub fn build(self) -> Result<Simulation<S, T>, BuilderError<S, T>> {
+        let state_transition_generator =
+            self.state_transition_generator
+                .ok_or(BuilderError::MissingField {
+                    field: "state_transition_generator",
+                })?;
+        let probabilities = match self.initial_distribution {
+            None => {
+                return Err(BuilderError::MissingField {
+                    field: "initial_distribution",
+                })
+            }
+            Some(InitialDistribution::State(initial_state)) => {
+                HashMap::from([(initial_state, 1.0)])
+            }
+            Some(InitialDistribution::Probabilities(probabilities)) => probabilities,
+            Some(InitialDistribution::Weights(weights)) => normalize_weights(weights)?,
+        };
+        let mut simulation = Simulation::from_distribution(
+            probabilities,
+            state_transition_generator,
+            self.hash_function,
+        )?;
+        simulation.normalize_probabilities = self.normalize_probabilities;
+        simulation.set_probability_tolerance(self.probability_tolerance);
+        simulation.max_known_states = self.max_known_states;
+        if self.history_tracking {
+            simulation = simulation.with_history_tracking();
+        }
+        if let Some(max_entries) = self.cache_capacity {
+            simulation = simulation.with_cache_capacity(max_entries);
+        }
+        Ok(simulation)
+    }
+
+    /// Build the simulation, whose required fields are known to be set.
+    ///
+    /// This is used by the constructors of
+    /// [Simulation](struct.Simulation.html), which only fail for an invalid
+    /// initial distribution.
+    pub(crate) fn build_complete(self) -> Result<Simulation<S, T>, SimulationError<S, T>> {
+        self.build().map_err(|error| match error {
+            BuilderError::Simulation(error) => error,
+            BuilderError::MissingField { field } => {
+                unreachable!("The field {field} of the simulation builder is missing")
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::hash_map::DefaultHasher,
+        hash::{BuildHasherDefault, Hasher},
+        sync::Arc,
+    };
+
+    use super::*;
+
+    /// A hasher whose hashes don't depend on the Rust release.
+    struct FnvHasher(u64);
+
+    impl Hasher for FnvHasher {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            for byte in bytes {
+                self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+            }
+        }
+    }
+
+    struct FnvBuildHasher;
+
+    impl BuildHasher for FnvBuildHasher {
+        type Hasher = FnvHasher;
+
+        fn build_hasher(&self) -> FnvHasher {
+            FnvHasher(0xcbf29ce484222325)
+        }
+    }
+
+    #[test]
+    fn builder() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, 1, 0.5), (state - 1, -1, 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator.clone());
+        let mut built_simulation = SimulationBuilder::new(state_transition_generator.clone())
+            .initial_state(0)
+            .hasher(FnvBuildHasher)
+            .history_tracking(true)
+            .cache_capacity(4)
+            .build()
+            .unwrap();
+        simulation.run(5).unwrap();
+        built_simulation.run(5).unwrap();
+        assert_eq!(
+            built_simulation.probability_distribution(5),
+            simulation.probability_distribution(5)
+        );
+        assert_eq!(built_simulation.history().unwrap().len(), 5);
+        assert!(built_simulation.cache_len() <= 4);
+
+        for (state_hash, state) in &built_simulation.known_states {
+            assert_eq!(*state_hash, FnvBuildHasher.hash_one(state));
+        }
+
+        let default_simulation = SimulationBuilder::new(state_transition_generator.clone())
+            .initial_state(0)
+            .hasher(BuildHasherDefault::<DefaultHasher>::default())
+            .build()
+            .unwrap();
+        assert_eq!(
+            default_simulation.known_states,
+            Simulation::new(0, state_transition_generator.clone()).known_states
+        );
+
+        #[cfg(feature = "serde")]
+        {
+            let snapshot = built_simulation.to_serializable();
+            let mut restored_simulation = Simulation::from_serializable_with_hasher(
+                snapshot,
+                state_transition_generator.clone(),
+                FnvBuildHasher,
+            );
+            restored_simulation.next_step().unwrap();
+            built_simulation.next_step().unwrap();
+            assert_eq!(
+                restored_simulation.probability_distribution(6),
+                built_simulation.probability_distribution(6)
+            );
+        }
+    }
+
+    #[test]
+    fn builder_initial_distribution() {
+        let state_transition_generator = Arc::new(|state: bool| vec![(!state, "flip", 2.0)]);
+        let builder = || SimulationBuilder::new(state_transition_generator.clone());
+        assert_eq!(
+            builder().build().unwrap_err(),
+            BuilderError::MissingField {
+                field: "initial_distribution"
+            }
+        );
+        assert_eq!(
+            SimulationBuilder::<bool, &str>::default()
+                .initial_state(false)
+                .build()
+                .unwrap_err(),
+            BuilderError::MissingField {
+                field: "state_transition_generator"
+            }
+        );
+        assert!(SimulationBuilder::default()
+            .state_transition_generator(state_transition_generator.clone())
+            .initial_state(false)
+            .normalize_probabilities(true)
+            .build()
+            .is_ok());
+
+        let mut simulation = builder()
+            .initial_weights(HashMap::from([(false, 3.0), (true, 1.0)]))
+            .normalize_probabilities(true)
+            .build()
+            .unwrap();
+        assert_eq!(
+            simulation.initial_distribution(),
+            HashMap::from([(false, 0.75), (true, 0.25)])
+        );
+        assert_eq!(
+            simulation.next_step().unwrap(),
+            HashMap::from([(false, 0.25), (true, 0.75)])
+        );
+
+        assert_eq!(
+            builder()
+                .initial_distribution(HashMap::from([(false, 0.5)]))
+                .build()
+                .unwrap_err(),
+            BuilderError::Simulation(SimulationError::InitialProbabilitiesDoNotSumToOne {
+                sum: 0.5
+            })
+        );
+    }
+
+    #[test]
+    fn builder_max_known_states() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation = SimulationBuilder::new(state_transition_generator)
+            .initial_state(0)
+            .max_known_states(5)
+            .build()
+            .unwrap();
+        simulation.run(2).unwrap();
+        assert_eq!(simulation.known_states().len(), 5);
+        assert_eq!(
+            simulation.next_step().unwrap_err(),
+            SimulationError::MaxKnownStatesExceeded {
+                max_known_states: 5,
+                known_states: 7
+            }
+        );
+        assert_eq!(simulation.time(), 2);
+        assert_eq!(simulation.known_states().len(), 5);
+    }
+}
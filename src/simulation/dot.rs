@@ -0,0 +1,234 @@
+use std::{fmt::Debug, fmt::Write, hash::Hash};
+
+use petgraph::visit::EdgeRef;
+
+use super::*;
+
+/// The `penwidth` of an edge with probability 1.
+const MAX_PENWIDTH: f64 = 5.0;
+
+/// Escapes a label so that it can be used inside a quoted DOT string.
+fn escape_label(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl<S, T> Simulation<S, T>
+where
+    S: Hash + Clone + Send + Sync + PartialEq + Eq + Debug,
+    T: Hash + Clone + Send + Sync + PartialEq + Eq + Debug,
+{
+    /// Render the state transition graph as a graphviz digraph.
+    ///
+    /// Each node is labeled with the `Debug` representation of its state and
+    /// its probability at `time`, each edge with the `Debug` representation of
+    /// its transition and its probability. States with a positive probability
+    /// at `time` are filled with light blue.
+    pub fn to_dot(&self, time: Time) -> String {
+        self.to_dot_with_labels(
+            |state| format!("{state:?}"),
+            |transition, probability| format!("{transition:?}\n{probability}"),
+            Some(time),
+        )
+    }
+
+    /// Render the state transition graph as a graphviz digraph with custom
+    /// labels.
+    ///
+    /// The labels of the nodes and edges are generated by `state_label` and
+    /// `transition_label` respectively. If `probabilities_at` is given, the
+    /// probability of each state at that time is appended to its node label
+    /// and states with a positive probability are filled with light blue.
+    pub fn to_dot_with_labels(
+        &self,
+        state_label: impl Fn(&S) -> String,
+        transition_label: impl Fn(&T, Probability) -> String,
+        probabilities_at: Option<Time>,
+    ) -> String {
+        let graph = &self.state_transition_graph;
+        let distribution = probabilities_at.map(|time| self.probability_distributions.get(&time));
+        let mut dot = String::from("digraph {\n");
+        for node in graph.node_indices() {
+            let state_hash = graph[node];
+            let mut label = escape_label(&state_label(self.state(state_hash).unwrap()));
+            let mut attributes = String::new();
+            if let Some(distribution) = distribution {
+                let probability = distribution
+                    .and_then(|distribution| distribution.get(&state_hash))
+                    .copied()
+                    .unwrap_or(0.0);
+                write!(label, "\\nP = {probability}").unwrap();
+                if probability > 0.0 {
+                    attributes.push_str(", style=filled, fillcolor=\"lightblue\"");
+                }
+            }
+            writeln!(dot, "    {} [label=\"{label}\"{attributes}]", node.index()).unwrap();
+        }
+        for edge in graph.edge_references() {
+            let (transition_hash, probability) = edge.weight();
+            let transition = self.transition(*transition_hash).unwrap();
+            writeln!(
+                dot,
+                "    {} -> {} [label=\"{}\"]",
+                edge.source().index(),
+                edge.target().index(),
+                escape_label(&transition_label(transition, *probability))
+            )
+            .unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Render the state transition graph at several times as a graphviz
+    /// digraph.
+    ///
+    /// The graph contains one cluster subgraph labeled `t = <time>` per
+    /// element of `times`, each with a copy of the whole state transition
+    /// graph. The node labels contain the probability of their state at that
+    /// time and states with a positive probability are filled with light blue.
+    /// The `penwidth` of each edge is proportional to the probability of its
+    /// transition. Times without a probability distribution render all states
+    /// with probability 0.
+    pub fn to_dot_with_probabilities(&self, times: &[Time]) -> String {
+        let graph = &self.state_transition_graph;
+        let mut dot = String::from("digraph {\n");
+        for time in times {
+            let distribution = self.probability_distributions.get(time);
+            writeln!(dot, "    subgraph cluster_{time} {{").unwrap();
+            writeln!(dot, "        label=\"t = {time}\"").unwrap();
+            for node in graph.node_indices() {
+                let state_hash = graph[node];
+                let label = escape_label(&format!("{:?}", self.state(state_hash).unwrap()));
+                let probability = distribution
+                    .and_then(|distribution| distribution.get(&state_hash))
+                    .copied()
+                    .unwrap_or(0.0);
+                let attributes = if probability > 0.0 {
+                    ", style=filled, fillcolor=\"lightblue\""
+                } else {
+                    ""
+                };
+                writeln!(
+                    dot,
+                    "        t{time}_{} [label=\"{label}\\nP = {probability}\"{attributes}]",
+                    node.index()
+                )
+                .unwrap();
+            }
+            for edge in graph.edge_references() {
+                let (transition_hash, probability) = edge.weight();
+                let transition = self.transition(*transition_hash).unwrap();
+                writeln!(
+                    dot,
+                    "        t{time}_{} -> t{time}_{} [label=\"{}\", penwidth={}]",
+                    edge.source().index(),
+                    edge.target().index(),
+                    escape_label(&format!("{transition:?}\n{probability}")),
+                    MAX_PENWIDTH * probability
+                )
+                .unwrap();
+            }
+            dot.push_str("    }\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn to_dot_with_labels() {
+        const NUM_STATES: i32 = 4;
+        let state_transition_generator =
+            Arc::new(|state: i32| -> OutgoingTransitions<i32, String> {
+                vec![
+                    (
+                        (state + 1).rem_euclid(NUM_STATES),
+                        "\"forward\"".to_string(),
+                        0.5,
+                    ),
+                    (
+                        (state - 1).rem_euclid(NUM_STATES),
+                        "backward".to_string(),
+                        0.5,
+                    ),
+                ]
+            });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.full_traversal(true, None).unwrap();
+
+        let dot = simulation.to_dot_with_labels(
+            |state| format!("state {state}"),
+            |transition, probability| format!("{transition} ({probability:.2})"),
+            Some(0),
+        );
+        assert!(dot.starts_with("digraph {"));
+        assert_eq!(dot.matches("->").count(), 2 * NUM_STATES as usize);
+        assert_eq!(dot.matches("[label=\"state ").count(), NUM_STATES as usize);
+        assert!(dot.contains("\\\"forward\\\" (0.50)"));
+        assert!(dot.contains("backward (0.50)"));
+        assert!(dot.contains("state 0\\nP = 1"));
+        assert!(dot.contains("state 1\\nP = 0"));
+
+        let dot =
+            simulation.to_dot_with_labels(|state| state.to_string(), |_, _| String::new(), None);
+        assert!(!dot.contains("P = "));
+    }
+
+    #[test]
+    fn to_dot_with_probabilities() {
+        let state_transition_generator = Arc::new(|state: i32| {
+            vec![
+                ((state + 1).rem_euclid(3), "forward", 0.75),
+                ((state - 1).rem_euclid(3), "backward", 0.25),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.run(2).unwrap();
+        let graph = simulation.state_transition_graph();
+
+        let dot = simulation.to_dot_with_probabilities(&[0, 2]);
+        assert!(dot.starts_with("digraph {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert_eq!(dot.matches('{').count(), dot.matches('}').count());
+        assert_eq!(dot.matches("subgraph cluster_").count(), 2);
+        assert!(dot.contains("label=\"t = 0\""));
+        assert!(dot.contains("label=\"t = 2\""));
+        assert_eq!(dot.matches("\\nP = ").count(), 2 * graph.node_count());
+        assert_eq!(dot.matches("->").count(), 2 * graph.edge_count());
+        assert_eq!(
+            dot.matches("penwidth=3.75]").count(),
+            2 * graph.node_count()
+        );
+        assert_eq!(
+            dot.matches("penwidth=1.25]").count(),
+            2 * graph.node_count()
+        );
+        assert!(dot.contains("t0_0 [label=\"0\\nP = 1\", style=filled"));
+        assert!(dot.contains("t2_0 [label=\"0\\nP = 0.375\", style=filled"));
+
+        let dot = simulation.to_dot_with_probabilities(&[]);
+        assert_eq!(dot, "digraph {\n}\n");
+    }
+
+    #[test]
+    fn to_dot() {
+        let state_transition_generator = Arc::new(|state: bool| vec![(!state, "flip", 1.0)]);
+        let mut simulation = Simulation::new(false, state_transition_generator);
+        simulation.next_step().unwrap();
+
+        let dot = simulation.to_dot(1);
+        assert_eq!(dot.matches("->").count(), 1);
+        assert!(dot.contains("[label=\"false\\nP = 0\"]"));
+        assert!(dot.contains("[label=\"true\\nP = 1\", style=filled, fillcolor=\"lightblue\"]"));
+        assert_eq!(dot.matches("[label=\"\\\"flip\\\"\\n1\"]").count(), 1);
+    }
+}
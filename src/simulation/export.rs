@@ -0,0 +1,593 @@
+use std::{
+    fmt::Debug,
+    fmt::Write,
+    fs::File,
+    hash::Hash,
+    io::{self, BufWriter},
+    path::Path,
+};
+
+use petgraph::visit::EdgeRef;
+#[cfg(feature = "csv")]
+use thiserror::Error;
+
+use super::*;
+
+/// Escapes the special characters of XML in text and attribute values.
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Quotes a CSV field if it contains a delimiter, a quote or a line break, so
+/// that e.g. multiline `Debug` representations stay in a single field.
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl<S, T> Simulation<S, T>
+where
+    S: Hash + Clone + Send + Sync + PartialEq + Eq + Debug,
+    T: Hash + Clone + Send + Sync + PartialEq + Eq + Debug,
+{
+    /// Export the state transition graph as GraphML.
+    ///
+    /// Each node has a `label` with the `Debug` representation of its state
+    /// and each edge a `label` with the `Debug` representation of its
+    /// transition and a `probability`. If `time` is given, the nodes
+    /// additionally have the `probability` of their state at that time.
+    pub fn to_graphml(&self, time: Option<Time>) -> String {
+        self.graphml(
+            |state| format!("{state:?}"),
+            |transition| format!("{transition:?}"),
+            time,
+        )
+    }
+
+    /// Export the state transition graph as GraphML with custom labels.
+    ///
+    /// This is the same as [to_graphml](#method.to_graphml) for the current
+    /// time, but the labels of the nodes and edges are generated by
+    /// `state_label` and `transition_label` respectively.
+    pub fn export_graphml(
+        &self,
+        state_label: impl Fn(&S) -> String,
+        transition_label: impl Fn(&T) -> String,
+    ) -> String {
+        self.graphml(state_label, transition_label, Some(self.time()))
+    }
+
+    fn graphml(
+        &self,
+        state_label: impl Fn(&S) -> String,
+        transition_label: impl Fn(&T) -> String,
+        time: Option<Time>,
+    ) -> String {
+        let graph = &self.state_transition_graph;
+        let distribution = time.map(|time| self.probability_distributions.get(&time));
+        let mut graphml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        graphml.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+        graphml.push_str(
+            "  <key id=\"node_label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n",
+        );
+        if distribution.is_some() {
+            graphml.push_str(
+                "  <key id=\"node_probability\" for=\"node\" attr.name=\"probability\" attr.type=\"double\"/>\n",
+            );
+        }
+        graphml.push_str(
+            "  <key id=\"edge_label\" for=\"edge\" attr.name=\"label\" attr.type=\"string\"/>\n",
+        );
+        graphml.push_str(
+            "  <key id=\"edge_probability\" for=\"edge\" attr.name=\"probability\" attr.type=\"double\"/>\n",
+        );
+        graphml.push_str("  <graph id=\"G\" edgedefault=\"directed\">\n");
+        for node in graph.node_indices() {
+            let state_hash = graph[node];
+            let state = self.state(state_hash).unwrap();
+            writeln!(graphml, "    <node id=\"n{}\">", node.index()).unwrap();
+            writeln!(
+                graphml,
+                "      <data key=\"node_label\">{}</data>",
+                escape_xml(&state_label(state))
+            )
+            .unwrap();
+            if let Some(distribution) = distribution {
+                let probability = distribution
+                    .and_then(|distribution| distribution.get(&state_hash))
+                    .copied()
+                    .unwrap_or(0.0);
+                writeln!(
+                    graphml,
+                    "      <data key=\"node_probability\">{probability}</data>"
+                )
+                .unwrap();
+            }
+            graphml.push_str("    </node>\n");
+        }
+        for edge in graph.edge_references() {
+            let (transition_hash, probability) = edge.weight();
+            let transition = self.transition(*transition_hash).unwrap();
+            writeln!(
+                graphml,
+                "    <edge id=\"e{}\" source=\"n{}\" target=\"n{}\">",
+                edge.id().index(),
+                edge.source().index(),
+                edge.target().index()
+            )
+            .unwrap();
+            writeln!(
+                graphml,
+                "      <data key=\"edge_label\">{}</data>",
+                escape_xml(&transition_label(transition))
+            )
+            .unwrap();
+            writeln!(
+                graphml,
+                "      <data key=\"edge_probability\">{probability}</data>"
+            )
+            .unwrap();
+            graphml.push_str("    </edge>\n");
+        }
+        graphml.push_str("  </graph>\n</graphml>\n");
+        graphml
+    }
+
+    /// Export the state transition graph as JSON.
+    ///
+    /// The object contains the current `time`, the `nodes` and the `edges`.
+    /// Each node has the index of its state in the state transition graph as
+    /// `id`, the `Debug` representation of its state as `label` and the
+    /// `probability` of its state at the current time. Each edge has the ids
+    /// of its `source` and `target`, the `Debug` representation of its
+    /// transition as `label` and its `probability`. The nodes are sorted by
+    /// their id and the edges by their source, target and label, so that the
+    /// output only depends on the state transition graph.
+    #[cfg(feature = "json")]
+    pub fn export_json(&self) -> serde_json::Value {
+        let graph = &self.state_transition_graph;
+        let time = self.time();
+        let distribution = self.probability_distributions.get(&time);
+        let nodes = graph
+            .node_indices()
+            .map(|node| {
+                let state_hash = graph[node];
+                serde_json::json!({
+                    "id": node.index(),
+                    "label": format!("{:?}", self.state(state_hash).unwrap()),
+                    "probability": distribution
+                        .and_then(|distribution| distribution.get(&state_hash))
+                        .copied()
+                        .unwrap_or(0.0),
+                })
+            })
+            .collect_vec();
+        let edges = graph
+            .edge_references()
+            .map(|edge| {
+                let (transition_hash, probability) = edge.weight();
+                (
+                    edge.source().index(),
+                    edge.target().index(),
+                    format!("{:?}", self.transition(*transition_hash).unwrap()),
+                    *probability,
+                )
+            })
+            .sorted_by(|a, b| (a.0, a.1, &a.2).cmp(&(b.0, b.1, &b.2)))
+            .map(|(source, target, label, probability)| {
+                serde_json::json!({
+                    "source": source,
+                    "target": target,
+                    "label": label,
+                    "probability": probability,
+                })
+            })
+            .collect_vec();
+        serde_json::json!({
+            "time": time,
+            "nodes": nodes,
+            "edges": edges,
+        })
+    }
+
+    /// Export the probability distributions over time as CSV.
+    ///
+    /// The header row contains `time` followed by the `Debug` representations
+    /// of all known states in the order they were discovered. It is followed
+    /// by one row per recorded time step in ascending order with the
+    /// probability of each state at that time, which is 0 for states that are
+    /// not part of the distribution. Times without a recorded distribution,
+    /// e.g. after [trim_before](#method.trim_before), are skipped.
+    /// Fields containing commas, quotes or line breaks are quoted.
+    pub fn export_csv(&self, mut writer: impl io::Write) -> io::Result<()> {
+        let graph = &self.state_transition_graph;
+        let state_hashes = graph
+            .node_indices()
+            .map(|node| graph[node])
+            .collect::<Vec<_>>();
+        let header =
+            std::iter::once("time".to_string())
+                .chain(state_hashes.iter().map(|state_hash| {
+                    escape_csv(&format!("{:?}", self.state(*state_hash).unwrap()))
+                }))
+                .collect::<Vec<_>>();
+        writeln!(writer, "{}", header.join(","))?;
+        for (time, distribution) in self
+            .probability_distributions
+            .iter()
+            .sorted_by_key(|(time, _)| **time)
+        {
+            write!(writer, "{time}")?;
+            for state_hash in &state_hashes {
+                write!(
+                    writer,
+                    ",{}",
+                    distribution.get(state_hash).copied().unwrap_or(0.0)
+                )?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Export the probability distributions over time as CSV to the file at
+    /// `path`, see [export_csv](#method.export_csv).
+    ///
+    /// The file is created or truncated.
+    pub fn export_csv_to_file(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.export_csv(&mut writer)?;
+        io::Write::flush(&mut writer)
+    }
+}
+
+/// The errors that can occur when importing a transition matrix from CSV, see
+/// [from_csv_transition_matrix](struct.Simulation.html#method.from_csv_transition_matrix).
+#[cfg(feature = "csv")]
+#[derive(Debug, Error)]
+pub enum CsvError<S> {
+    /// The CSV could not be read.
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    /// A row has the wrong number of fields, a label that doesn't match the
+    /// header or a cell that is not a number.
+    #[error("Row {row} is malformed: {reason}")]
+    MalformedRow { row: usize, reason: String },
+    /// The number of rows doesn't match the number of columns.
+    #[error("The matrix is not square, it has {rows} rows and {columns} columns")]
+    NotSquare { rows: usize, columns: usize },
+    /// The probabilities of a row don't sum up to 1.0.
+    #[error("The row of {state:?} sums up to {sum}, not 1.0")]
+    RowDoesNotSumToOne { state: S, sum: Probability },
+    /// The transition matrix is invalid for another reason, see
+    /// [from_transition_matrix](struct.Simulation.html#method.from_transition_matrix).
+    #[error(transparent)]
+    Simulation(#[from] SimulationError<S, ()>),
+}
+
+#[cfg(feature = "csv")]
+impl<S> Simulation<S, ()>
+where
+    S: Hash + Clone + Send + Sync + PartialEq + Eq + Debug + 'static,
+{
+    /// Create a new `Simulation` from a transition matrix in CSV format.
+    ///
+    /// The first row contains the labels of the states, the first column the
+    /// labels of the state of each row in the same order and the other cells
+    /// the transition probabilities. The content of the top left cell is
+    /// ignored. The labels are turned into states by `header_parser`. The
+    /// simulation is built with
+    /// [from_transition_matrix](#method.from_transition_matrix), so it has the
+    /// same structure and starts in the first state.
+    ///
+    /// ```text
+    /// ,a,b
+    /// a,0.5,0.5
+    /// b,1.0,0.0
+    /// ```
+    pub fn from_csv_transition_matrix<R: io::Read>(
+        reader: R,
+        header_parser: impl Fn(&str) -> S,
+    ) -> Result<Self, CsvError<S>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let labels = reader
+            .headers()?
+            .iter()
+            .skip(1)
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        let columns = labels.len();
+        let mut probabilities = Vec::with_capacity(columns * columns);
+        let mut rows = 0;
+        for (index, record) in reader.records().enumerate() {
+            let record = record?;
+            // The header is row 1
+            let row = index + 2;
+            let malformed = |reason: String| Err(CsvError::MalformedRow { row, reason });
+            if record.len() != columns + 1 {
+                return malformed(format!(
+                    "it has {} fields instead of {}",
+                    record.len(),
+                    columns + 1
+                ));
+            }
+            if labels.get(rows).map(String::as_str) != Some(&record[0]) {
+                return malformed(format!(
+                    "the label {:?} doesn't match the column {:?}",
+                    &record[0],
+                    labels.get(rows)
+                ));
+            }
+            let mut sum = 0.0;
+            for cell in record.iter().skip(1) {
+                let Ok(probability) = cell.parse::<Probability>() else {
+                    return malformed(format!("{cell:?} is not a number"));
+                };
+                sum += probability;
+                probabilities.push(probability);
+            }
+            if (sum - 1.0).abs() > TRANSITION_MATRIX_TOLERANCE {
+                return Err(CsvError::RowDoesNotSumToOne {
+                    state: header_parser(&record[0]),
+                    sum,
+                });
+            }
+            rows += 1;
+        }
+        if rows != columns {
+            return Err(CsvError::NotSquare { rows, columns });
+        }
+        let matrix = Array2::from_shape_vec((rows, columns), probabilities).unwrap();
+        let states = labels.iter().map(|label| header_parser(label)).collect();
+        Ok(Self::from_transition_matrix(matrix, states)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn to_graphml() {
+        const NUM_STATES: i32 = 4;
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![
+                ((state + 1).rem_euclid(NUM_STATES), "<forward>", 0.5),
+                ((state - 1).rem_euclid(NUM_STATES), "\"backward\"", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.full_traversal(false, None).unwrap();
+
+        for time in [None, Some(1)] {
+            let graphml = simulation.to_graphml(time);
+            let document = roxmltree::Document::parse(&graphml).unwrap();
+            let count = |name: &str| {
+                document
+                    .descendants()
+                    .filter(|node| node.has_tag_name(name))
+                    .count()
+            };
+            assert_eq!(count("node"), NUM_STATES as usize);
+            assert_eq!(count("edge"), 2 * NUM_STATES as usize);
+            let probabilities = document
+                .descendants()
+                .filter(|node| node.attribute("key") == Some("node_probability"))
+                .map(|node| node.text().unwrap().parse::<f64>().unwrap())
+                .collect_vec();
+            match time {
+                None => assert!(probabilities.is_empty()),
+                Some(_) => assert_eq!(probabilities.iter().sum::<f64>(), 1.0),
+            }
+            assert!(document
+                .descendants()
+                .any(|node| node.text() == Some("\"<forward>\"")));
+        }
+    }
+
+    #[test]
+    fn export_graphml() {
+        let state_transition_generator =
+            Arc::new(|state: bool| vec![(!state, "flip", 0.25), (state, "stay", 0.75)]);
+        let mut simulation = Simulation::new(false, state_transition_generator);
+        simulation.next_step().unwrap();
+
+        let graphml = simulation.export_graphml(
+            |state| format!("<{state}>"),
+            |transition| transition.to_uppercase(),
+        );
+        let document = roxmltree::Document::parse(&graphml).unwrap();
+        let data = |key: &str| {
+            document
+                .descendants()
+                .filter(|node| node.attribute("key") == Some(key))
+                .map(|node| node.text().unwrap().to_string())
+                .sorted()
+                .collect_vec()
+        };
+        assert_eq!(data("node_label"), vec!["<false>", "<true>"]);
+        assert_eq!(data("node_probability"), vec!["0.25", "0.75"]);
+        assert_eq!(data("edge_label"), vec!["FLIP", "STAY"]);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn export_json() {
+        const NUM_STATES: i32 = 4;
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![
+                ((state + 1).rem_euclid(NUM_STATES), "forward", 0.5),
+                ((state - 1).rem_euclid(NUM_STATES), "\"backward\"", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.full_traversal(false, None).unwrap();
+
+        let json = simulation.export_json();
+        let json: serde_json::Value = serde_json::from_str(&json.to_string()).unwrap();
+        assert_eq!(json["time"], simulation.time());
+        let nodes = json["nodes"].as_array().unwrap();
+        let edges = json["edges"].as_array().unwrap();
+        assert_eq!(nodes.len(), NUM_STATES as usize);
+        assert_eq!(edges.len(), 2 * NUM_STATES as usize);
+        for (index, node) in nodes.iter().enumerate() {
+            assert_eq!(node["id"], index);
+        }
+        let total_probability = nodes
+            .iter()
+            .map(|node| node["probability"].as_f64().unwrap())
+            .sum::<f64>();
+        assert!((total_probability - 1.0).abs() < 1e-12);
+        let endpoints = edges
+            .iter()
+            .map(|edge| {
+                (
+                    edge["source"].as_u64().unwrap(),
+                    edge["target"].as_u64().unwrap(),
+                )
+            })
+            .collect_vec();
+        assert!(endpoints.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(edges
+            .iter()
+            .any(|edge| edge["label"] == "\"\\\"backward\\\"\""));
+
+        // A single state with a self-loop as a snapshot
+        let mut simulation = Simulation::new((), Arc::new(|state: ()| vec![(state, "stay", 1.0)]));
+        simulation.next_step().unwrap();
+        assert_eq!(
+            simulation.export_json().to_string(),
+            concat!(
+                r#"{"edges":[{"label":"\"stay\"","probability":1.0,"source":0,"target":0}],"#,
+                r#""nodes":[{"id":0,"label":"()","probability":1.0}],"time":1}"#
+            )
+        );
+    }
+
+    /// A state whose `Debug` representation spans multiple lines.
+    #[derive(Clone, PartialEq, Eq, Hash)]
+    struct Multiline(i32);
+
+    impl Debug for Multiline {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "Multiline\n\"{}\", {}", self.0, self.0)
+        }
+    }
+
+    #[test]
+    fn export_csv() {
+        let state_transition_generator = Arc::new(|state: Multiline| {
+            vec![
+                (Multiline((state.0 + 1).min(2)), "next", 0.5),
+                (state, "stay", 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(Multiline(0), state_transition_generator);
+        simulation.run(3).unwrap();
+        let mut csv = Vec::new();
+        simulation.export_csv(&mut csv).unwrap();
+
+        let mut reader = csv::Reader::from_reader(csv.as_slice());
+        let header = reader.headers().unwrap().clone();
+        assert_eq!(header.len(), 4);
+        assert_eq!(&header[0], "time");
+        assert_eq!(&header[1], "Multiline\n\"0\", 0");
+        let rows = reader.records().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(rows.len(), 4);
+        for (time, row) in rows.iter().enumerate() {
+            assert_eq!(row[0].parse::<Time>().unwrap(), time as Time);
+            let distribution = simulation.probability_distribution(time as Time);
+            for (index, state) in (0..3).map(Multiline).enumerate() {
+                let probability = row[index + 1].parse::<Probability>().unwrap();
+                assert_eq!(
+                    probability,
+                    distribution.get(&state).copied().unwrap_or(0.0)
+                );
+            }
+        }
+
+        let path = std::env::temp_dir().join(format!("entromatica-{}.csv", std::process::id()));
+        simulation.export_csv_to_file(&path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), csv);
+        std::fs::remove_file(path).unwrap();
+
+        simulation.trim_before(2);
+        let mut csv = Vec::new();
+        simulation.export_csv(&mut csv).unwrap();
+        let times = csv::Reader::from_reader(csv.as_slice())
+            .records()
+            .map(|row| row.unwrap()[0].parse::<Time>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(times, vec![2, 3]);
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn from_csv_transition_matrix() {
+        let csv = "state, a, b, c\na, 0.5, 0.5, 0\nb, 0, 0.25, 0.75\nc, 1, 0, 0\n";
+        let mut simulation =
+            Simulation::from_csv_transition_matrix(csv.as_bytes(), str::to_string).unwrap();
+        let matrix = ndarray::arr2(&[[0.5, 0.5, 0.0], [0.0, 0.25, 0.75], [1.0, 0.0, 0.0]]);
+        let states = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let mut expected_simulation = Simulation::from_transition_matrix(matrix, states).unwrap();
+        assert_eq!(
+            simulation.initial_distribution(),
+            HashMap::from([("a".to_string(), 1.0)])
+        );
+        assert_eq!(
+            simulation.state_transition_graph().edge_count(),
+            expected_simulation.state_transition_graph().edge_count()
+        );
+        simulation.run(5).unwrap();
+        expected_simulation.run(5).unwrap();
+        assert_eq!(
+            simulation.probability_distribution(5),
+            expected_simulation.probability_distribution(5)
+        );
+
+        let import =
+            |csv: &str| Simulation::from_csv_transition_matrix(csv.as_bytes(), str::to_string);
+        assert!(matches!(
+            import(",a,b\na,0.5\nb,0.5,0.5\n"),
+            Err(CsvError::MalformedRow { row: 2, .. })
+        ));
+        assert!(matches!(
+            import(",a,b\nb,0.5,0.5\na,0.5,0.5\n"),
+            Err(CsvError::MalformedRow { row: 2, .. })
+        ));
+        assert!(matches!(
+            import(",a,b\na,0.5,0.5\nb,x,0.5\n"),
+            Err(CsvError::MalformedRow { row: 3, .. })
+        ));
+        assert!(matches!(
+            import(",a,b\na,0.5,0.5\n"),
+            Err(CsvError::NotSquare {
+                rows: 1,
+                columns: 2
+            })
+        ));
+        assert!(matches!(
+            import(",a,b\na,0.5,0.5\nb,0.5,0.25\n"),
+            Err(CsvError::RowDoesNotSumToOne { state, sum: 0.75 }) if state == "b"
+        ));
+        assert!(matches!(
+            import(",a,b\na,1.5,-0.5\nb,0.5,0.5\n"),
+            Err(CsvError::Simulation(
+                SimulationError::InvalidTransitionMatrix { .. }
+            ))
+        ));
+    }
+}
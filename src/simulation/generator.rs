@@ -0,0 +1,247 @@
+//! Adapters building new state transition generators from existing ones.
+//!
+//! All adapters preserve the invariant that the probabilities of the outgoing
+//! transitions of a state sum up to 1.0, provided the wrapped generators do.
+//!
+//! ```rust
+//! use entromatica::prelude::*;
+//! use entromatica::simulation::generator::*;
+//! use std::sync::Arc;
+//!
+//! let walk = Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+//! let lazy_walk = with_self_loop(walk, 0.5, "stay");
+//! let mut simulation = Simulation::new(0, lazy_walk);
+//! assert_eq!(simulation.next_step().unwrap()[&0], 0.5);
+//! ```
+
+use std::sync::Arc;
+
+use super::*;
+
+/// Merge the outgoing transitions with the same state and transition by
+/// adding up their probabilities, as the state transition graph keeps only
+/// one edge per state and transition.
+fn merge_duplicates<S, T>(
+    outgoing_transitions: OutgoingTransitions<S, T>,
+) -> OutgoingTransitions<S, T>
+where
+    S: Eq,
+    T: Eq,
+{
+    let mut merged: OutgoingTransitions<S, T> = Vec::with_capacity(outgoing_transitions.len());
+    for (state, transition, probability) in outgoing_transitions {
+        match merged
+            .iter_mut()
+            .find(|(other_state, other_transition, _)| {
+                *other_state == state && *other_transition == transition
+            }) {
+            Some((_, _, other_probability)) => *other_probability += probability,
+            None => merged.push((state, transition, probability)),
+        }
+    }
+    merged
+}
+
+fn assert_probability(probability: Probability) {
+    assert!(
+        (0.0..=1.0).contains(&probability),
+        "The probability {probability} is not in [0, 1]"
+    );
+}
+
+/// Run a generator on a projected state type.
+///
+/// Each state of the new generator is converted to a state of `generator`
+/// with `f` and the next states `generator` returns are converted back with
+/// `g`. The transitions and probabilities are kept. If `g` maps several next
+/// states of a state onto the same state, their probabilities are added up.
+pub fn map_states<S1, S2, T>(
+    generator: StateTransitionGenerator<S1, T>,
+    f: impl Fn(S2) -> S1 + Send + Sync + 'static,
+    g: impl Fn(S1) -> S2 + Send + Sync + 'static,
+) -> StateTransitionGenerator<S2, T>
+where
+    S1: 'static,
+    S2: Eq + 'static,
+    T: Eq + 'static,
+{
+    Arc::new(move |state: S2| {
+        merge_duplicates(
+            generator(f(state))
+                .into_iter()
+                .map(|(new_state, transition, probability)| (g(new_state), transition, probability))
+                .collect(),
+        )
+    })
+}
+
+/// Mix a transition into the current state into a generator.
+///
+/// Each state stays in place with the probability `probability` via
+/// `transition` and the probabilities of the transitions of `generator` are
+/// scaled by `1 - probability`. With a probability of 1.0, `generator` is not
+/// called anymore and each state only has the self-loop.
+///
+/// # Panics
+/// This function panics if `probability` is not in `[0, 1]`.
+pub fn with_self_loop<S, T>(
+    generator: StateTransitionGenerator<S, T>,
+    probability: Probability,
+    transition: T,
+) -> StateTransitionGenerator<S, T>
+where
+    S: Clone + Eq + 'static,
+    T: Clone + Eq + Send + Sync + 'static,
+{
+    assert_probability(probability);
+    Arc::new(move |state: S| {
+        let mut outgoing_transitions = vec![(state.clone(), transition.clone(), probability)];
+        if probability < 1.0 {
+            outgoing_transitions.extend(generator(state).into_iter().map(
+                |(new_state, transition, other_probability)| {
+                    (
+                        new_state,
+                        transition,
+                        (1.0 - probability) * other_probability,
+                    )
+                },
+            ));
+        }
+        merge_duplicates(outgoing_transitions)
+    })
+}
+
+/// Mix two generators.
+///
+/// In each step the transitions of `generator_a` are chosen with the
+/// probability `weight_a` and those of `generator_b` with `1 - weight_a`. If
+/// a weight is 0, the corresponding generator is not called.
+///
+/// # Panics
+/// This function panics if `weight_a` is not in `[0, 1]`.
+pub fn compose<S, T>(
+    generator_a: StateTransitionGenerator<S, T>,
+    generator_b: StateTransitionGenerator<S, T>,
+    weight_a: Probability,
+) -> StateTransitionGenerator<S, T>
+where
+    S: Clone + Eq + 'static,
+    T: Eq + 'static,
+{
+    assert_probability(weight_a);
+    Arc::new(move |state: S| {
+        let mut outgoing_transitions = Vec::new();
+        for (generator, weight) in [(&generator_a, weight_a), (&generator_b, 1.0 - weight_a)] {
+            if weight > 0.0 {
+                outgoing_transitions.extend(generator(state.clone()).into_iter().map(
+                    |(new_state, transition, probability)| {
+                        (new_state, transition, weight * probability)
+                    },
+                ));
+            }
+        }
+        merge_duplicates(outgoing_transitions)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn walk() -> StateTransitionGenerator<i32, &'static str> {
+        Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)])
+    }
+
+    fn sum<S, T>(outgoing_transitions: &OutgoingTransitions<S, T>) -> Probability {
+        outgoing_transitions
+            .iter()
+            .map(|(_, _, probability)| probability)
+            .sum()
+    }
+
+    #[test]
+    fn map_states() {
+        // The walk on shifted states
+        let generator = super::map_states(walk(), |state: i32| state - 10, |state| state + 10);
+        assert_eq!(generator(3), vec![(4, "next", 0.5), (2, "previous", 0.5)]);
+
+        // Projecting onto the parity merges the next states
+        let generator =
+            super::map_states(walk(), |parity: bool| parity as i32, |state| state % 2 != 0);
+        let outgoing_transitions = generator(false);
+        assert_eq!(
+            outgoing_transitions,
+            vec![(true, "next", 0.5), (true, "previous", 0.5)]
+        );
+        let mut simulation = Simulation::new(false, generator);
+        simulation.run(3).unwrap();
+        assert_eq!(
+            simulation.probability_distribution(3),
+            HashMap::from([(true, 1.0)])
+        );
+    }
+
+    #[test]
+    fn with_self_loop() {
+        let generator = super::with_self_loop(walk(), 0.5, "stay");
+        let outgoing_transitions = generator(0);
+        assert_eq!(sum(&outgoing_transitions), 1.0);
+        assert_eq!(
+            outgoing_transitions,
+            vec![(0, "stay", 0.5), (1, "next", 0.25), (-1, "previous", 0.25)]
+        );
+
+        // A self-loop with the same transition is merged
+        let generator = super::with_self_loop(
+            Arc::new(|state: i32| vec![(state, "stay", 0.5), (state + 1, "next", 0.5)]),
+            0.5,
+            "stay",
+        );
+        assert_eq!(generator(0), vec![(0, "stay", 0.75), (1, "next", 0.25)]);
+
+        let generator = super::with_self_loop(walk(), 1.0, "stay");
+        assert_eq!(generator(0), vec![(0, "stay", 1.0)]);
+        let generator = super::with_self_loop(walk(), 0.0, "stay");
+        assert_eq!(sum(&generator(0)), 1.0);
+        let mut simulation = Simulation::new(0, generator);
+        simulation.run(2).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "The probability 1.5 is not in [0, 1]")]
+    fn with_self_loop_invalid_probability() {
+        super::with_self_loop(walk(), 1.5, "stay");
+    }
+
+    #[test]
+    fn compose() {
+        let reset = Arc::new(|_: i32| vec![(0, "reset", 1.0)]);
+        let generator = super::compose(walk(), reset.clone(), 0.75);
+        let outgoing_transitions = generator(2);
+        assert_eq!(sum(&outgoing_transitions), 1.0);
+        assert_eq!(
+            outgoing_transitions,
+            vec![
+                (3, "next", 0.375),
+                (1, "previous", 0.375),
+                (0, "reset", 0.25)
+            ]
+        );
+        assert_eq!(
+            super::compose(walk(), walk(), 0.25)(0),
+            vec![(1, "next", 0.5), (-1, "previous", 0.5)]
+        );
+
+        // A weight of 1.0 or 0.0 only uses one of the generators
+        let panicking = Arc::new(|_: i32| -> OutgoingTransitions<i32, &str> { panic!() });
+        assert_eq!(
+            super::compose(reset.clone(), panicking.clone(), 1.0)(2),
+            reset(2)
+        );
+        assert_eq!(super::compose(panicking, reset.clone(), 0.0)(2), reset(2));
+
+        let mut simulation = Simulation::new(0, generator);
+        simulation.run(5).unwrap();
+        assert!(simulation.probability_distribution(5)[&0] > 0.25);
+    }
+}
@@ -0,0 +1,298 @@
+use std::{fmt::Debug, hash::Hash};
+
+use hashbrown::HashMap;
+use petgraph::visit::EdgeRef;
+use thiserror::Error;
+
+use super::*;
+
+const LUMPABILITY_TOLERANCE: Probability = 1e-10;
+
+/// The error returned if a markov chain is not lumpable with respect to a
+/// projection.
+///
+/// The two states are projected onto the same macro-state, but their
+/// probabilities of transitioning into the states of `group` differ.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("{state:?} and {other_state:?} transition into {group:?} with the probabilities {probability} and {other_probability}")]
+pub struct LumpingError<S, U> {
+    pub state: S,
+    pub other_state: S,
+    /// The macro-state whose aggregate transition probabilities differ.
+    pub group: U,
+    pub probability: Probability,
+    pub other_probability: Probability,
+}
+
+impl<S, T> Simulation<S, T>
+where
+    S: Hash + Clone + Send + Sync + PartialEq + Eq + Debug,
+    T: Hash + Clone + Send + Sync + PartialEq + Eq + Debug + 'static,
+{
+    /// Get the probability distribution at the given time projected onto
+    /// macro-states.
+    ///
+    /// Each state is mapped to its macro-state by `f` and the probabilities of
+    /// the states of each macro-state are added up.
+    ///
+    /// # Panics
+    /// This method panics if there is no probability distribution for the
+    /// given time.
+    pub fn project<U>(&self, time: Time, f: impl Fn(&S) -> U) -> StateProbabilityDistribution<U>
+    where
+        U: Hash + Eq,
+    {
+        let mut distribution = HashMap::new();
+        for (state_hash, probability) in self
+            .probability_distributions
+            .get(&time)
+            .expect(NO_PROBABILITY_DISTRIBUTION_FOUND)
+        {
+            *distribution
+                .entry(f(self.state(*state_hash).unwrap()))
+                .or_insert(0.0) += probability;
+        }
+        distribution
+    }
+
+    /// Lump the states of the markov chain into macro-states.
+    ///
+    /// This is the same as [lump](#method.lump), but it panics if the markov
+    /// chain is not lumpable.
+    ///
+    /// # Panics
+    /// This method panics if the markov chain is not strongly lumpable with
+    /// respect to `f`, if the probabilities of the state transition generator
+    /// do not sum up to 1.0 or if two distinct macro-states have the same hash.
+    pub fn lumped_chain<U>(&mut self, f: impl Fn(&S) -> U) -> Simulation<U, T>
+    where
+        U: Hash + Clone + Send + Sync + PartialEq + Eq + Debug + 'static,
+    {
+        self.lump(f).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Lump the states of the markov chain into macro-states.
+    ///
+    /// Each state is mapped to its macro-state by `projection`. The markov
+    /// chain has to be strongly lumpable, i.e. all states of a macro-state
+    /// need the same aggregate probability of transitioning into each
+    /// macro-state. Otherwise a `LumpingError` with a witness pair of states is
+    /// returned.
+    ///
+    /// The returned simulation starts with the projection of the initial
+    /// distribution, or of the earliest recorded distribution if the timeline
+    /// was [trimmed](#method.trim_before), and has the same probability
+    /// tolerance. Its state transition generator looks the macro-states up
+    /// in a table. A macro-state has a single transition into each macro-state
+    /// its states transition into, which is labeled with one of the original
+    /// transitions.
+    ///
+    /// If the number of states is infinte this method will never return. It
+    /// will modify the cache of the markov chain, as it makes a cache-only
+    /// [full_traversal](#method.full_traversal).
+    ///
+    /// # Panics
+    /// This method panics if the probabilities of the state transition
+    /// generator do not sum up to 1.0 or if two distinct macro-states have the
+    /// same hash.
+    pub fn lump<U>(
+        &mut self,
+        projection: impl Fn(&S) -> U,
+    ) -> Result<Simulation<U, T>, LumpingError<S, U>>
+    where
+        U: Hash + Clone + Send + Sync + PartialEq + Eq + Debug + 'static,
+    {
+        self.full_traversal_unchecked();
+        let graph = &self.state_transition_graph;
+        let groups = graph
+            .node_indices()
+            .map(|node| (graph[node], projection(self.state(graph[node]).unwrap())))
+            .collect::<HashMap<StateHash, U>>();
+
+        // The first state of each macro-state and its aggregate transition
+        // probabilities into each macro-state
+        let mut representatives: HashMap<U, (NodeIndex, HashMap<U, Probability>)> = HashMap::new();
+        for node in graph.node_indices() {
+            let mut aggregate_probabilities = HashMap::new();
+            for edge in graph.edges(node) {
+                *aggregate_probabilities
+                    .entry(groups[&graph[edge.target()]].clone())
+                    .or_insert(0.0) += edge.weight().1;
+            }
+            let group = &groups[&graph[node]];
+            let Some((representative, representative_probabilities)) = representatives.get(group)
+            else {
+                representatives.insert(group.clone(), (node, aggregate_probabilities));
+                continue;
+            };
+            let witness = representative_probabilities
+                .keys()
+                .chain(aggregate_probabilities.keys())
+                .map(|target| {
+                    (
+                        target,
+                        representative_probabilities
+                            .get(target)
+                            .copied()
+                            .unwrap_or(0.0),
+                        aggregate_probabilities.get(target).copied().unwrap_or(0.0),
+                    )
+                })
+                .find(|(_, probability, other_probability)| {
+                    (probability - other_probability).abs() > LUMPABILITY_TOLERANCE
+                });
+            if let Some((target, probability, other_probability)) = witness {
+                return Err(LumpingError {
+                    state: self.state(graph[*representative]).unwrap().clone(),
+                    other_state: self.state(graph[node]).unwrap().clone(),
+                    group: target.clone(),
+                    probability,
+                    other_probability,
+                });
+            }
+        }
+
+        let transitions = representatives
+            .into_iter()
+            .map(|(group, (representative, _))| {
+                let mut next_groups: HashMap<U, (T, Probability)> = HashMap::new();
+                for edge in graph.edges(representative) {
+                    let (transition_hash, probability) = edge.weight();
+                    next_groups
+                        .entry(groups[&graph[edge.target()]].clone())
+                        .or_insert_with(|| {
+                            (self.transition(*transition_hash).unwrap().clone(), 0.0)
+                        })
+                        .1 += probability;
+                }
+                let next_states = next_groups
+                    .into_iter()
+                    .map(|(next_group, (transition, probability))| {
+                        (next_group, transition, probability)
+                    })
+                    .collect::<OutgoingTransitions<U, T>>();
+                (group, next_states)
+            })
+            .collect::<HashMap<U, OutgoingTransitions<U, T>>>();
+
+        let state_transition_generator: StateTransitionGenerator<U, T> =
+            Arc::new(move |group: U| transitions[&group].clone());
+        let initial_distribution = self.project(self.earliest_time(), |state| {
+            groups[&self.hash_function.hash(state)].clone()
+        });
+        Ok(SimulationBuilder::new(state_transition_generator)
+            .initial_distribution(initial_distribution)
+            .hash_function(self.hash_function.clone())
+            .probability_tolerance(self.probability_tolerance)
+            .build()
+            .unwrap_or_else(|error| panic!("{error}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn circular_walk(forward: Probability) -> StateTransitionGenerator<i32, &'static str> {
+        Arc::new(move |state: i32| {
+            vec![
+                ((state + 1).rem_euclid(5), "forward", forward),
+                ((state - 1).rem_euclid(5), "backward", 1.0 - forward),
+            ]
+        })
+    }
+
+    /// Maps each state of the circular walk onto its mirror image.
+    fn reflection(state: &i32) -> i32 {
+        *state.min(&(5 - state))
+    }
+
+    #[test]
+    fn lump() {
+        let mut simulation = Simulation::new(1, circular_walk(0.5));
+        let mut lumped_simulation = simulation.lump(reflection).unwrap();
+        assert_eq!(
+            lumped_simulation.initial_distribution(),
+            HashMap::from([(1, 1.0)])
+        );
+        simulation.run(10).unwrap();
+        lumped_simulation.run(10).unwrap();
+        for time in 0..=10 {
+            let mut projected_distribution = HashMap::new();
+            for (state, probability) in simulation.probability_distribution(time) {
+                *projected_distribution
+                    .entry(reflection(&state))
+                    .or_insert(0.0) += probability;
+            }
+            let lumped_distribution = lumped_simulation.probability_distribution(time);
+            assert_eq!(lumped_distribution.len(), projected_distribution.len());
+            for (group, probability) in projected_distribution {
+                assert!((lumped_distribution[&group] - probability).abs() < 1e-12);
+            }
+        }
+        let stationary_distribution = lumped_simulation.stationary_distribution().unwrap();
+        assert!((stationary_distribution[&0] - 0.2).abs() < 1e-10);
+        assert!((stationary_distribution[&1] - 0.4).abs() < 1e-10);
+
+        let mut lumped_simulation = simulation.lump(|_| ()).unwrap();
+        assert_eq!(lumped_simulation.known_states().len(), 1);
+        lumped_simulation.next_step().unwrap();
+        assert_eq!(
+            lumped_simulation.probability_distribution(1),
+            HashMap::from([((), 1.0)])
+        );
+    }
+
+    #[test]
+    fn lump_not_lumpable() {
+        let mut simulation = Simulation::new(0, circular_walk(0.7));
+        let error = simulation.lump(reflection).unwrap_err();
+        assert_eq!(reflection(&error.state), reflection(&error.other_state));
+        assert_ne!(error.state, error.other_state);
+        assert!((error.probability - error.other_probability).abs() > 0.1);
+    }
+
+    #[test]
+    fn project() {
+        // Two copies of a three state cycle, the state modulo 3 is lumpable
+        let state_transition_generator = Arc::new(|state: i32| -> OutgoingTransitions<i32, &str> {
+            vec![
+                ((state + 1).rem_euclid(6), "forward", 0.6),
+                ((state + 4).rem_euclid(6), "jump", 0.1),
+                (state, "stay", 0.3),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.run(2).unwrap();
+        let projection = |state: &i32| state % 3;
+        assert_eq!(
+            simulation.project(1, projection),
+            HashMap::from([(0, 0.3), (1, 0.7)])
+        );
+        let projected_distribution = simulation.project(2, projection);
+        assert!((projected_distribution[&0] - 0.09).abs() < 1e-12);
+        assert!((projected_distribution[&1] - 0.42).abs() < 1e-12);
+        assert!((projected_distribution[&2] - 0.49).abs() < 1e-12);
+
+        let mut lumped_simulation = simulation.lumped_chain(projection);
+        let (matrix, ordering) = lumped_simulation
+            .transition_rate_matrix_complete(None)
+            .unwrap();
+        assert_eq!(ordering.len(), 3);
+        let index = |state| ordering.iter().position(|other| *other == state).unwrap();
+        for state in 0..3 {
+            assert!((matrix[(index(state), index(state))] - 0.3).abs() < 1e-12);
+            assert!((matrix[(index(state), index((state + 1) % 3))] - 0.7).abs() < 1e-12);
+        }
+        lumped_simulation.run(2).unwrap();
+        for (state, probability) in lumped_simulation.probability_distribution(2) {
+            assert!((projected_distribution[&state] - probability).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "transition into")]
+    fn lumped_chain_not_lumpable() {
+        Simulation::new(0, circular_walk(0.7)).lumped_chain(reflection);
+    }
+}
@@ -0,0 +1,549 @@
+use std::{
+    fmt::Debug,
+    hash::{BuildHasher, Hash},
+};
+
+use hashbrown::HashMap;
+use petgraph::{graph::Graph, visit::EdgeRef};
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+/// The errors that can occur when converting a simulation to or from
+/// MessagePack.
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Error)]
+pub enum MsgpackError {
+    #[error(transparent)]
+    Encode(#[from] rmp_serde::encode::Error),
+    #[error(transparent)]
+    Decode(#[from] rmp_serde::decode::Error),
+}
+
+/// The bytes every checkpoint starts with.
+#[cfg(feature = "msgpack")]
+const CHECKPOINT_MAGIC: &[u8; 4] = b"EMCP";
+
+/// The version of the checkpoint format, which is increased whenever the
+/// layout of a checkpoint changes.
+#[cfg(feature = "msgpack")]
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+/// The errors that can occur when restoring a simulation from a
+/// [checkpoint](struct.Simulation.html#method.checkpoint).
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Error)]
+pub enum CheckpointError {
+    /// The data does not start with the header of a checkpoint.
+    #[error("The data is not a checkpoint")]
+    InvalidHeader,
+    /// The checkpoint was created with a different version of the format.
+    #[error("The checkpoint has version {found}, but version {expected} is supported")]
+    SchemaMismatch { expected: u32, found: u32 },
+    /// The checkpoint could not be decoded, e.g. because it is truncated or
+    /// the types of the states or transitions differ.
+    #[error(transparent)]
+    Decode(#[from] rmp_serde::decode::Error),
+    /// A known state of the checkpoint has a different hash with the given
+    /// hasher, because the checkpoint was created with a different hasher.
+    #[error("The state with hash {expected} has hash {found} with the given hasher")]
+    HashMismatch {
+        expected: StateHash,
+        found: StateHash,
+    },
+}
+
+/// The contents of a checkpoint, which in addition to the snapshot contain
+/// the settings of the simulation that can be serialized.
+#[cfg(feature = "msgpack")]
+#[derive(Serialize, Deserialize)]
+struct Checkpoint<S, T> {
+    simulation: SerializableSimulation<S, T>,
+    pruning_threshold: Probability,
+    pruned_masses: HashMap<Time, Probability>,
+    probability_tolerance: Probability,
+    max_known_states: Option<usize>,
+}
+
+/// A serializable snapshot of a [Simulation](struct.Simulation.html).
+///
+/// It contains the probability distributions, the known states and
+/// transitions and the state transition graph, but not the state transition
+/// generator, as functions can't be serialized. To get a `Simulation` back the
+/// original generator has to be provided to
+/// [from_serializable](struct.Simulation.html#method.from_serializable).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SerializableSimulation<S, T> {
+    probability_distributions: HashMap<Time, HashedStateProbabilityDistribution>,
+    known_states: KnownStates<S>,
+    known_transitions: KnownTransitions<T>,
+    nodes: Vec<StateHash>,
+    edges: Vec<(StateHash, StateHash, TransitionHash, Probability)>,
+    normalize_probabilities: bool,
+    history: Option<HashMap<Time, HashedTransitionFlows>>,
+}
+
+impl<S, T> Simulation<S, T>
+where
+    S: Hash + Clone + Send + Sync + PartialEq + Eq + Debug,
+    T: Hash + Clone + Send + Sync + PartialEq + Eq + Debug,
+{
+    /// Get a serializable snapshot of the simulation.
+    ///
+    /// The state transition generator and its cache are not part of the
+    /// snapshot.
+    pub fn to_serializable(&self) -> SerializableSimulation<S, T>
+    where
+        S: Serialize,
+        T: Serialize,
+    {
+        let graph = &self.state_transition_graph;
+        SerializableSimulation {
+            probability_distributions: self.probability_distributions.clone(),
+            known_states: self.known_states.clone(),
+            known_transitions: self.known_transitions.clone(),
+            nodes: graph.node_weights().copied().collect(),
+            edges: graph
+                .edge_references()
+                .map(|edge| {
+                    let (transition_hash, probability) = edge.weight();
+                    (
+                        graph[edge.source()],
+                        graph[edge.target()],
+                        *transition_hash,
+                        *probability,
+                    )
+                })
+                .collect(),
+            normalize_probabilities: self.normalize_probabilities,
+            history: self.history.clone(),
+        }
+    }
+
+    /// Create a `Simulation` from a serializable snapshot.
+    ///
+    /// The state transition generator must be the one the snapshot was
+    /// created with, otherwise the behavior of the simulation is undefined.
+    /// If the simulation used a custom hasher, use
+    /// [from_serializable_with_hasher](#method.from_serializable_with_hasher)
+    /// instead.
+    pub fn from_serializable(
+        serializable_simulation: SerializableSimulation<S, T>,
+        state_transition_generator: StateTransitionGenerator<S, T>,
+    ) -> Self
+    where
+        S: for<'de> Deserialize<'de>,
+        T: for<'de> Deserialize<'de>,
+    {
+        Self::from_serializable_with_hash_function(
+            serializable_simulation,
+            state_transition_generator,
+            HashFunction::default(),
+        )
+    }
+
+    /// Create a `Simulation` from a serializable snapshot of a simulation which
+    /// was built with a custom hasher.
+    ///
+    /// The hasher must be the one the snapshot was created with, see
+    /// [SimulationBuilder::hasher](struct.SimulationBuilder.html#method.hasher).
+    pub fn from_serializable_with_hasher<B>(
+        serializable_simulation: SerializableSimulation<S, T>,
+        state_transition_generator: StateTransitionGenerator<S, T>,
+        build_hasher: B,
+    ) -> Self
+    where
+        S: for<'de> Deserialize<'de>,
+        T: for<'de> Deserialize<'de>,
+        B: BuildHasher + Send + Sync + 'static,
+    {
+        Self::from_serializable_with_hash_function(
+            serializable_simulation,
+            state_transition_generator,
+            HashFunction::new(build_hasher),
+        )
+    }
+
+    /// Serialize a [snapshot](#method.to_serializable) of the simulation to
+    /// MessagePack, which is much more compact than JSON.
+    #[cfg(feature = "msgpack")]
+    pub fn to_msgpack(&self) -> Result<Vec<u8>, MsgpackError>
+    where
+        S: Serialize,
+        T: Serialize,
+    {
+        Ok(rmp_serde::to_vec(&self.to_serializable())?)
+    }
+
+    /// Create a `Simulation` from a MessagePack snapshot created by
+    /// [to_msgpack](#method.to_msgpack).
+    ///
+    /// The state transition generator must be the one the snapshot was
+    /// created with, see [from_serializable](#method.from_serializable).
+    #[cfg(feature = "msgpack")]
+    pub fn from_msgpack(
+        bytes: &[u8],
+        state_transition_generator: StateTransitionGenerator<S, T>,
+    ) -> Result<Self, MsgpackError>
+    where
+        S: for<'de> Deserialize<'de>,
+        T: for<'de> Deserialize<'de>,
+    {
+        Ok(Self::from_serializable(
+            rmp_serde::from_slice(bytes)?,
+            state_transition_generator,
+        ))
+    }
+
+    /// Save the full state of the simulation as an opaque byte blob.
+    ///
+    /// In addition to the [snapshot](#method.to_serializable), the checkpoint
+    /// contains the pruning threshold and the pruned masses, the probability
+    /// tolerance and the maximum number of known states. The state transition
+    /// generator, its cache, the watches and the constraints are not part of
+    /// it. The simulation can be restored with [restore](#method.restore).
+    ///
+    /// # Panics
+    /// This method panics if a state or transition can't be serialized.
+    #[cfg(feature = "msgpack")]
+    pub fn checkpoint(&self) -> Vec<u8>
+    where
+        S: Serialize,
+        T: Serialize,
+    {
+        let checkpoint = Checkpoint {
+            simulation: self.to_serializable(),
+            pruning_threshold: self.pruning_threshold,
+            pruned_masses: self.pruned_masses.clone(),
+            probability_tolerance: self.probability_tolerance,
+            max_known_states: self.max_known_states,
+        };
+        let mut data = CHECKPOINT_MAGIC.to_vec();
+        data.extend_from_slice(&CHECKPOINT_VERSION.to_le_bytes());
+        rmp_serde::encode::write(&mut data, &checkpoint).unwrap_or_else(|error| panic!("{error}"));
+        data
+    }
+
+    /// Restore a simulation from a [checkpoint](#method.checkpoint).
+    ///
+    /// The state transition generator must be the one the checkpoint was
+    /// created with, see [from_serializable](#method.from_serializable).
+    /// If the simulation used a custom hasher, use
+    /// [restore_with_hasher](#method.restore_with_hasher) instead.
+    ///
+    /// Returns `CheckpointError::InvalidHeader` if the data is not a
+    /// checkpoint, `CheckpointError::SchemaMismatch` if it was created with
+    /// a different version of the format and `CheckpointError::HashMismatch`
+    /// if it was created with a different hasher.
+    #[cfg(feature = "msgpack")]
+    pub fn restore(
+        data: &[u8],
+        state_transition_generator: StateTransitionGenerator<S, T>,
+    ) -> Result<Self, CheckpointError>
+    where
+        S: for<'de> Deserialize<'de>,
+        T: for<'de> Deserialize<'de>,
+    {
+        Self::restore_with_hash_function(data, state_transition_generator, HashFunction::default())
+    }
+
+    /// Restore a simulation which was built with a custom hasher from a
+    /// [checkpoint](#method.checkpoint).
+    ///
+    /// The hasher must be the one the checkpoint was created with, see
+    /// [SimulationBuilder::hasher](struct.SimulationBuilder.html#method.hasher).
+    /// Otherwise `CheckpointError::HashMismatch` is returned. The other errors
+    /// are the same as for [restore](#method.restore).
+    #[cfg(feature = "msgpack")]
+    pub fn restore_with_hasher<B>(
+        data: &[u8],
+        state_transition_generator: StateTransitionGenerator<S, T>,
+        build_hasher: B,
+    ) -> Result<Self, CheckpointError>
+    where
+        S: for<'de> Deserialize<'de>,
+        T: for<'de> Deserialize<'de>,
+        B: BuildHasher + Send + Sync + 'static,
+    {
+        Self::restore_with_hash_function(
+            data,
+            state_transition_generator,
+            HashFunction::new(build_hasher),
+        )
+    }
+
+    #[cfg(feature = "msgpack")]
+    fn restore_with_hash_function(
+        data: &[u8],
+        state_transition_generator: StateTransitionGenerator<S, T>,
+        hash_function: HashFunction,
+    ) -> Result<Self, CheckpointError>
+    where
+        S: for<'de> Deserialize<'de>,
+        T: for<'de> Deserialize<'de>,
+    {
+        let header_len = CHECKPOINT_MAGIC.len() + std::mem::size_of::<u32>();
+        if data.len() < header_len || !data.starts_with(CHECKPOINT_MAGIC) {
+            return Err(CheckpointError::InvalidHeader);
+        }
+        let version =
+            u32::from_le_bytes(data[CHECKPOINT_MAGIC.len()..header_len].try_into().unwrap());
+        if version != CHECKPOINT_VERSION {
+            return Err(CheckpointError::SchemaMismatch {
+                expected: CHECKPOINT_VERSION,
+                found: version,
+            });
+        }
+        let checkpoint: Checkpoint<S, T> = rmp_serde::from_slice(&data[header_len..])?;
+        for (state_hash, state) in &checkpoint.simulation.known_states {
+            let found = hash_function.hash(state);
+            if found != *state_hash {
+                return Err(CheckpointError::HashMismatch {
+                    expected: *state_hash,
+                    found,
+                });
+            }
+        }
+        Ok(Self {
+            pruning_threshold: checkpoint.pruning_threshold,
+            pruned_masses: checkpoint.pruned_masses,
+            probability_tolerance: checkpoint.probability_tolerance,
+            max_known_states: checkpoint.max_known_states,
+            ..Self::from_serializable_with_hash_function(
+                checkpoint.simulation,
+                state_transition_generator,
+                hash_function,
+            )
+        })
+    }
+
+    fn from_serializable_with_hash_function(
+        serializable_simulation: SerializableSimulation<S, T>,
+        state_transition_generator: StateTransitionGenerator<S, T>,
+        hash_function: HashFunction,
+    ) -> Self {
+        let mut state_transition_graph: StateTransitionGraph = Graph::new();
+        let node_indices = serializable_simulation
+            .nodes
+            .iter()
+            .map(|state_hash| (*state_hash, state_transition_graph.add_node(*state_hash)))
+            .collect::<HashMap<_, _>>();
+        for (source, target, transition_hash, probability) in serializable_simulation.edges {
+            state_transition_graph.add_edge(
+                node_indices[&source],
+                node_indices[&target],
+                (transition_hash, probability),
+            );
+        }
+        Self {
+            state_transition_graph,
+            node_indices,
+            probability_distributions: serializable_simulation.probability_distributions,
+            known_states: serializable_simulation.known_states,
+            known_transitions: serializable_simulation.known_transitions,
+            state_transition_generator: CachedFunction::new(state_transition_generator),
+            normalize_probabilities: serializable_simulation.normalize_probabilities,
+            history: serializable_simulation.history,
+            hash_function,
+            watches: Vec::new(),
+            constraints: ConstraintSet::new(),
+            pruning_threshold: 0.0,
+            pruned_masses: HashMap::new(),
+            probability_tolerance: DEFAULT_PROBABILITY_TOLERANCE,
+            max_known_states: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn random_walk_round_trip() {
+        let state_transition_generator = Arc::new(|state: i32| {
+            vec![
+                (state + 1, "next".to_string(), 0.5),
+                (state - 1, "previous".to_string(), 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator.clone());
+        simulation.run(4).unwrap();
+
+        let json = serde_json::to_string(&simulation.to_serializable()).unwrap();
+        let mut deserialized_simulation = Simulation::from_serializable(
+            serde_json::from_str(&json).unwrap(),
+            state_transition_generator,
+        );
+        assert_eq!(
+            deserialized_simulation.to_serializable(),
+            simulation.to_serializable()
+        );
+        assert_eq!(deserialized_simulation.entropy(4), simulation.entropy(4));
+        deserialized_simulation.next_step().unwrap();
+        assert_eq!(deserialized_simulation.probability_distribution(5).len(), 6);
+    }
+
+    #[test]
+    fn serialization_round_trip() {
+        const NUM_STATES: i32 = 5;
+        let state_transition_generator =
+            Arc::new(|state: i32| -> OutgoingTransitions<i32, String> {
+                vec![
+                    (
+                        (state + 1).rem_euclid(NUM_STATES),
+                        "forward".to_string(),
+                        0.5,
+                    ),
+                    (
+                        (state - 1).rem_euclid(NUM_STATES),
+                        "backward".to_string(),
+                        0.5,
+                    ),
+                ]
+            });
+        let mut simulation = Simulation::new(0, state_transition_generator.clone());
+        simulation.run(3).unwrap();
+
+        let json = serde_json::to_string(&simulation.to_serializable()).unwrap();
+        let serializable_simulation = serde_json::from_str(&json).unwrap();
+        let mut deserialized_simulation =
+            Simulation::from_serializable(serializable_simulation, state_transition_generator);
+
+        assert_eq!(
+            deserialized_simulation.probability_distributions(),
+            simulation.probability_distributions()
+        );
+        assert_eq!(deserialized_simulation.time(), simulation.time());
+        let graph = simulation.state_transition_graph();
+        let deserialized_graph = deserialized_simulation.state_transition_graph();
+        assert_eq!(deserialized_graph.node_count(), graph.node_count());
+        assert_eq!(deserialized_graph.edge_count(), graph.edge_count());
+
+        simulation.next_step().unwrap();
+        deserialized_simulation.next_step().unwrap();
+        assert_eq!(
+            deserialized_simulation.probability_distribution(4),
+            simulation.probability_distribution(4)
+        );
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack_round_trip() {
+        const NUM_STATES: i32 = 100;
+        let state_transition_generator = Arc::new(|state: i32| {
+            vec![
+                (
+                    (state + 1).rem_euclid(NUM_STATES),
+                    "forward".to_string(),
+                    0.5,
+                ),
+                (
+                    (state - 1).rem_euclid(NUM_STATES),
+                    "backward".to_string(),
+                    0.5,
+                ),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator.clone());
+        simulation.full_traversal(false, None).unwrap();
+        assert_eq!(simulation.known_states().len(), NUM_STATES as usize);
+
+        let bytes = simulation.to_msgpack().unwrap();
+        let json = serde_json::to_string(&simulation.to_serializable()).unwrap();
+        assert!(bytes.len() * 2 < json.len());
+
+        let deserialized_simulation =
+            Simulation::from_msgpack(&bytes, state_transition_generator).unwrap();
+        assert_eq!(
+            deserialized_simulation.to_serializable(),
+            simulation.to_serializable()
+        );
+        assert!(matches!(
+            Simulation::<i32, String>::from_msgpack(
+                &bytes[..bytes.len() / 2],
+                Arc::new(|_| vec![])
+            ),
+            Err(MsgpackError::Decode(_))
+        ));
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn checkpoint() {
+        let state_transition_generator = Arc::new(|state: i32| {
+            vec![
+                (state + 1, "next".to_string(), 0.5),
+                (state - 1, "previous".to_string(), 0.5),
+            ]
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator.clone());
+        simulation.set_pruning_threshold(1e-3);
+        simulation.set_probability_tolerance(1e-6);
+        simulation.run(10).unwrap();
+        let data = simulation.checkpoint();
+
+        let mut restored_simulation =
+            Simulation::restore(&data, state_transition_generator.clone()).unwrap();
+        assert_eq!(restored_simulation.time(), 10);
+        assert_eq!(restored_simulation.probability_tolerance(), 1e-6);
+        assert_eq!(
+            restored_simulation.pruned_mass(10),
+            simulation.pruned_mass(10)
+        );
+        restored_simulation.run(5).unwrap();
+        simulation.run(5).unwrap();
+        for time in 0..=15 {
+            assert_eq!(
+                restored_simulation.probability_distribution(time),
+                simulation.probability_distribution(time)
+            );
+            assert_eq!(
+                restored_simulation.pruned_mass(time),
+                simulation.pruned_mass(time)
+            );
+        }
+
+        assert!(matches!(
+            Simulation::restore(&data[..3], state_transition_generator.clone()),
+            Err(CheckpointError::InvalidHeader)
+        ));
+        assert!(matches!(
+            Simulation::restore(&data[..data.len() / 2], state_transition_generator.clone()),
+            Err(CheckpointError::Decode(_))
+        ));
+        let mut other_version = data.clone();
+        other_version[CHECKPOINT_MAGIC.len()..CHECKPOINT_MAGIC.len() + 4]
+            .copy_from_slice(&(CHECKPOINT_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            Simulation::restore(&other_version, state_transition_generator.clone()),
+            Err(CheckpointError::SchemaMismatch { expected, found })
+                if expected == CHECKPOINT_VERSION && found == CHECKPOINT_VERSION + 1
+        ));
+
+        let build_hasher = std::collections::hash_map::RandomState::new();
+        let mut simulation = SimulationBuilder::new(state_transition_generator.clone())
+            .initial_state(0)
+            .hasher(build_hasher.clone())
+            .build()
+            .unwrap();
+        simulation.run(3).unwrap();
+        let data = simulation.checkpoint();
+        assert!(matches!(
+            Simulation::restore(&data, state_transition_generator.clone()),
+            Err(CheckpointError::HashMismatch { .. })
+        ));
+        let mut restored_simulation =
+            Simulation::restore_with_hasher(&data, state_transition_generator, build_hasher)
+                .unwrap();
+        restored_simulation.run(2).unwrap();
+        simulation.run(2).unwrap();
+        assert_eq!(
+            restored_simulation.probability_distribution(5),
+            simulation.probability_distribution(5)
+        );
+    }
+}
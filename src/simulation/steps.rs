@@ -0,0 +1,122 @@
+use std::{fmt::Debug, hash::Hash, iter::FusedIterator};
+
+use super::*;
+
+/// An iterator updating a [Simulation](struct.Simulation.html) by one step
+/// per item.
+///
+/// It is created by [Simulation::steps](struct.Simulation.html#method.steps).
+/// Each call of `next` performs a [next_step](struct.Simulation.html#method.next_step)
+/// and yields its result. After the first error the iterator is exhausted.
+///
+/// # Example
+/// ```rust
+/// use entromatica::prelude::*;
+/// use std::sync::Arc;
+///
+/// let state_transition_generator = Arc::new(|state: bool| vec![(!state, "flip", 1.0)]);
+/// let mut simulation = Simulation::new(false, state_transition_generator);
+/// for distribution in simulation.steps().take(3) {
+///     assert_eq!(distribution.unwrap().len(), 1);
+/// }
+/// assert_eq!(simulation.time(), 3);
+/// ```
+pub struct Steps<'a, S, T> {
+    simulation: &'a mut Simulation<S, T>,
+    failed: bool,
+}
+
+impl<S, T> Simulation<S, T>
+where
+    S: Hash + Clone + Send + Sync + PartialEq + Eq + Debug,
+    T: Hash + Clone + Send + Sync + PartialEq + Eq + Debug,
+{
+    /// Get an iterator updating the markov chain by one step per item.
+    ///
+    /// The iterator is infinite unless [next_step](#method.next_step) returns
+    /// an error, which is yielded as the last item.
+    pub fn steps(&mut self) -> Steps<'_, S, T> {
+        Steps {
+            simulation: self,
+            failed: false,
+        }
+    }
+}
+
+impl<'a, S, T> Iterator for Steps<'a, S, T>
+where
+    S: Hash + Clone + Send + Sync + PartialEq + Eq + Debug,
+    T: Hash + Clone + Send + Sync + PartialEq + Eq + Debug,
+{
+    type Item = Result<StateProbabilityDistribution<S>, SimulationError<S, T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let result = self.simulation.next_step();
+        self.failed = result.is_err();
+        Some(result)
+    }
+}
+
+impl<'a, S, T> FusedIterator for Steps<'a, S, T>
+where
+    S: Hash + Clone + Send + Sync + PartialEq + Eq + Debug,
+    T: Hash + Clone + Send + Sync + PartialEq + Eq + Debug,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn steps() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator.clone());
+        let distributions = simulation
+            .steps()
+            .take(4)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(simulation.time(), 4);
+
+        let mut other_simulation = Simulation::new(0, state_transition_generator);
+        for (time, distribution) in distributions.into_iter().enumerate() {
+            assert_eq!(distribution, other_simulation.next_step().unwrap());
+            assert_eq!(
+                distribution,
+                simulation.probability_distribution(time as Time + 1)
+            );
+        }
+    }
+
+    #[test]
+    fn steps_error() {
+        let state_transition_generator = Arc::new(|state: i32| {
+            if state < 2 {
+                vec![(state + 1, "next", 1.0)]
+            } else {
+                vec![(state + 1, "next", 0.5)]
+            }
+        });
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        let mut steps = simulation.steps();
+        assert!(steps.next().unwrap().is_ok());
+        assert!(steps.next().unwrap().is_ok());
+        assert!(matches!(
+            steps.next(),
+            Some(Err(SimulationError::ProbabilitiesDoNotSumToOne {
+                state: 2,
+                ..
+            }))
+        ));
+        assert!(steps.next().is_none());
+        assert!(steps.next().is_none());
+        assert_eq!(simulation.time(), 2);
+    }
+}
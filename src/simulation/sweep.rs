@@ -0,0 +1,161 @@
+use std::panic::{self, AssertUnwindSafe};
+
+use rayon::prelude::*;
+use thiserror::Error;
+
+use super::*;
+
+/// The error returned for a parameter of a [sweep](fn.sweep.html) whose build
+/// or run panicked.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Sweep panicked: {message}")]
+pub struct SweepError {
+    /// The panic message, if it was a string.
+    pub message: String,
+}
+
+/// Build and run a simulation for each parameter in parallel.
+///
+/// For each element of `params` a simulation is created by `build` and passed
+/// to `run`, whose result is returned together with the parameter in the
+/// order of `params`. As every simulation is built separately, each one has
+/// its own state transition generator cache.
+///
+/// A panic in `build` or `run` only affects the result of its parameter,
+/// which is then a `SweepError` with the panic message.
+///
+/// # Example
+/// ```rust
+/// use entromatica::prelude::*;
+/// use std::sync::Arc;
+///
+/// let results = sweep(
+///     vec![0.25, 0.5],
+///     |p: &f64| {
+///         let p = *p;
+///         Simulation::new(false, Arc::new(move |state: bool| {
+///             vec![(!state, "flip", p), (state, "stay", 1.0 - p)]
+///         }))
+///     },
+///     |simulation| simulation.run(1).unwrap()[&true],
+/// );
+/// assert_eq!(results, vec![(0.25, Ok(0.25)), (0.5, Ok(0.5))]);
+/// ```
+pub fn sweep<P, S, T, R>(
+    params: Vec<P>,
+    build: impl Fn(&P) -> Simulation<S, T> + Sync,
+    run: impl Fn(&mut Simulation<S, T>) -> R + Sync,
+) -> Vec<(P, Result<R, SweepError>)>
+where
+    P: Send,
+    R: Send,
+{
+    params
+        .into_par_iter()
+        .map(|param| {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                let mut simulation = build(&param);
+                run(&mut simulation)
+            }))
+            .map_err(|payload| SweepError {
+                message: payload
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_default(),
+            });
+            (param, result)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::models::rules::*;
+
+    #[test]
+    fn sweep() {
+        // A walk on 0..=5 which returns to 0 with a varying weight
+        let build = |return_weight: &f64| {
+            let rules = vec![
+                Rule::new(
+                    "Walk".to_string(),
+                    Condition::Custom(Arc::new(|state: i32| state < 5)),
+                    0.5,
+                    Action::Custom(Arc::new(|state| state + 1)),
+                ),
+                Rule::new(
+                    "Return".to_string(),
+                    Condition::Custom(Arc::new(|state: i32| state > 0)),
+                    *return_weight,
+                    Action::Custom(Arc::new(|_| 0)),
+                ),
+            ];
+            Simulation::new(0, get_state_transition_generator(rules))
+        };
+        let return_weights = (2..=9).map(|i| i as f64 / 10.).collect::<Vec<_>>();
+        let results = super::sweep(return_weights.clone(), build, |simulation| {
+            simulation.run(20).unwrap();
+            simulation.entropy(20)
+        });
+        assert_eq!(
+            results
+                .iter()
+                .map(|(weight, _)| *weight)
+                .collect::<Vec<_>>(),
+            return_weights
+        );
+        // The more likely the walk returns, the more concentrated it is on
+        // the small states
+        let entropies = results
+            .into_iter()
+            .map(|(_, entropy)| entropy.unwrap())
+            .collect::<Vec<_>>();
+        assert!(entropies.windows(2).all(|pair| pair[0] > pair[1]));
+    }
+
+    #[test]
+    fn sweep_panic() {
+        let results = super::sweep(
+            vec![1, 2, 3, 4],
+            |start: &i32| {
+                if *start == 2 {
+                    panic!("Invalid start");
+                }
+                Simulation::new(
+                    *start,
+                    Arc::new(|state: i32| vec![(state + 1, "next", 1.0)]),
+                )
+            },
+            |simulation| {
+                let state = *simulation.run(1).unwrap().keys().next().unwrap();
+                if state == 4 {
+                    panic!("Invalid state {state}");
+                }
+                state
+            },
+        );
+        assert_eq!(
+            results,
+            vec![
+                (1, Ok(2)),
+                (
+                    2,
+                    Err(SweepError {
+                        message: "Invalid start".to_string()
+                    })
+                ),
+                (
+                    3,
+                    Err(SweepError {
+                        message: "Invalid state 4".to_string()
+                    })
+                ),
+                (4, Ok(5)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,409 @@
+use std::{fmt::Debug, hash::Hash};
+
+use itertools::Itertools;
+use rand::Rng;
+
+use super::*;
+
+/// Amounts of probability below this are treated as zero by the optimal
+/// transport solver.
+const TRANSPORT_TOLERANCE: Probability = 1e-12;
+
+impl<S, T> Simulation<S, T>
+where
+    S: Hash + Clone + Send + Sync + PartialEq + Eq + Debug,
+    T: Hash + Clone + Send + Sync + PartialEq + Eq + Debug,
+{
+    /// Get the Wasserstein distance between the probability distributions at
+    /// the given times.
+    ///
+    /// See [wasserstein_distance](fn.wasserstein_distance.html) for details.
+    ///
+    /// # Panics
+    /// This method panics if there is no probability distribution for one of
+    /// the given times.
+    pub fn wasserstein_distance(
+        &self,
+        time_a: Time,
+        time_b: Time,
+        cost: impl Fn(&S, &S) -> f64,
+    ) -> f64 {
+        wasserstein_distance(
+            &self.probability_distribution(time_a),
+            &self.probability_distribution(time_b),
+            cost,
+        )
+    }
+
+    /// Get the sliced Wasserstein distance between the probability
+    /// distributions at the given times.
+    ///
+    /// See [sliced_wasserstein_distance](fn.sliced_wasserstein_distance.html)
+    /// for details.
+    ///
+    /// # Panics
+    /// This method panics if there is no probability distribution for one of
+    /// the given times.
+    pub fn sliced_wasserstein_distance(
+        &self,
+        time_a: Time,
+        time_b: Time,
+        embedding: impl Fn(&S) -> Vec<f64>,
+        num_projections: usize,
+        rng: &mut impl Rng,
+    ) -> f64 {
+        sliced_wasserstein_distance(
+            &self.probability_distribution(time_a),
+            &self.probability_distribution(time_b),
+            embedding,
+            num_projections,
+            rng,
+        )
+    }
+}
+
+/// Get the Wasserstein distance, also known as earth mover's distance, of two
+/// probability distributions.
+///
+/// It is the minimal total cost of moving the probability mass of `mu` to
+/// `nu`, where moving a probability of `p` from `x` to `y` costs
+/// `p * cost(x, y)`. In contrast to the
+/// [total variation distance](fn.total_variation_distance.html) it takes the
+/// distances between the states into account. The cost should be
+/// non-negative and zero for equal states, so that the distance of identical
+/// distributions is zero.
+///
+/// The optimal transport problem is solved exactly as a minimum cost flow,
+/// which takes polynomial, but more than quadratic time in the size of the
+/// supports. For large supports see
+/// [sliced_wasserstein_distance](fn.sliced_wasserstein_distance.html).
+///
+/// # Example
+/// ```rust
+/// use entromatica::prelude::*;
+/// use hashbrown::HashMap;
+///
+/// let mu = HashMap::from([(0, 0.5), (1, 0.5)]);
+/// let nu = HashMap::from([(1, 0.5), (3, 0.5)]);
+/// // Moving the mass of 0 to 1 and of 1 to 3 is cheaper than moving 0 to 3
+/// let distance = wasserstein_distance(&mu, &nu, |x: &i32, y: &i32| (x - y).abs() as f64);
+/// assert_eq!(distance, 1.5);
+/// ```
+pub fn wasserstein_distance<S>(
+    mu: &StateProbabilityDistribution<S>,
+    nu: &StateProbabilityDistribution<S>,
+    cost: impl Fn(&S, &S) -> f64,
+) -> f64
+where
+    S: Hash + Eq,
+{
+    let (sources, mut supplies): (Vec<_>, Vec<_>) = mu
+        .iter()
+        .filter(|(_, probability)| **probability > TRANSPORT_TOLERANCE)
+        .map(|(state, probability)| (state, *probability))
+        .unzip();
+    let (targets, mut demands): (Vec<_>, Vec<_>) = nu
+        .iter()
+        .filter(|(_, probability)| **probability > TRANSPORT_TOLERANCE)
+        .map(|(state, probability)| (state, *probability))
+        .unzip();
+    let costs = sources
+        .iter()
+        .map(|source| {
+            targets
+                .iter()
+                .map(|target| cost(source, target))
+                .collect_vec()
+        })
+        .collect_vec();
+    let mut flows = vec![vec![0.0; targets.len()]; sources.len()];
+
+    // Successive shortest paths in the residual network from the sources with
+    // remaining supply to the targets with remaining demand. Forward edges
+    // between a source and a target are uncapacitated, backward edges undo
+    // a part of an existing flow.
+    loop {
+        let mut source_distances = supplies
+            .iter()
+            .map(|supply| {
+                if *supply > TRANSPORT_TOLERANCE {
+                    0.0
+                } else {
+                    f64::INFINITY
+                }
+            })
+            .collect_vec();
+        let mut source_predecessors: Vec<Option<usize>> = vec![None; sources.len()];
+        let mut target_distances = vec![f64::INFINITY; targets.len()];
+        let mut target_predecessors = vec![0; targets.len()];
+        // Bellman-Ford, as the backward edges have negative costs
+        for _ in 0..=sources.len() + targets.len() {
+            let mut changed = false;
+            for (source, source_distance) in source_distances.iter().enumerate() {
+                if source_distance.is_infinite() {
+                    continue;
+                }
+                for target in 0..targets.len() {
+                    let distance = source_distance + costs[source][target];
+                    if distance < target_distances[target] - TRANSPORT_TOLERANCE {
+                        target_distances[target] = distance;
+                        target_predecessors[target] = source;
+                        changed = true;
+                    }
+                }
+            }
+            for (target, target_distance) in target_distances.iter().enumerate() {
+                if target_distance.is_infinite() {
+                    continue;
+                }
+                for source in 0..sources.len() {
+                    if flows[source][target] <= TRANSPORT_TOLERANCE {
+                        continue;
+                    }
+                    let distance = target_distance - costs[source][target];
+                    if distance < source_distances[source] - TRANSPORT_TOLERANCE {
+                        source_distances[source] = distance;
+                        source_predecessors[source] = Some(target);
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        let Some(target) = (0..targets.len())
+            .filter(|target| demands[*target] > TRANSPORT_TOLERANCE)
+            .filter(|target| target_distances[*target].is_finite())
+            .min_by(|a, b| target_distances[*a].total_cmp(&target_distances[*b]))
+        else {
+            break;
+        };
+
+        // Collect the path back to a source with remaining supply
+        let mut path = Vec::new();
+        let mut current_target = target;
+        let mut amount = demands[target];
+        let source = loop {
+            let source = target_predecessors[current_target];
+            path.push((source, current_target));
+            assert!(
+                path.len() <= sources.len() * targets.len(),
+                "Negative cycle in the residual network"
+            );
+            match source_predecessors[source] {
+                None => break source,
+                Some(previous_target) => {
+                    amount = amount.min(flows[source][previous_target]);
+                    current_target = previous_target;
+                }
+            }
+        };
+        amount = amount.min(supplies[source]);
+
+        // Push the flow along the forward edges and undo it along the backward
+        // edges, which lead from the target of a pair to the source of the
+        // pair before it
+        for (source, target) in &path {
+            flows[*source][*target] += amount;
+        }
+        for window in path.windows(2) {
+            let (source, _) = window[0];
+            let (_, previous_target) = window[1];
+            flows[source][previous_target] -= amount;
+        }
+        supplies[source] -= amount;
+        demands[target] -= amount;
+    }
+
+    flows
+        .iter()
+        .enumerate()
+        .flat_map(|(source, flows)| {
+            flows
+                .iter()
+                .enumerate()
+                .map(move |(target, flow)| (source, target, *flow))
+        })
+        .filter(|(_, _, flow)| *flow > 0.0)
+        .map(|(source, target, flow)| flow * costs[source][target])
+        .sum()
+}
+
+/// Get the sliced Wasserstein distance of two probability distributions.
+///
+/// The states are mapped to points in `R^d` by `embedding`. The distance is
+/// the average over `num_projections` random directions of the Wasserstein
+/// distance of the distributions projected onto that direction, with the
+/// absolute difference as cost. One-dimensional Wasserstein distances only
+/// need sorting, so this approximation of the
+/// [Wasserstein distance](fn.wasserstein_distance.html) with the euclidean
+/// distance as cost scales to large supports.
+///
+/// # Panics
+/// This function panics if `num_projections` is zero or if `embedding`
+/// returns points of different dimensions.
+pub fn sliced_wasserstein_distance<S>(
+    mu: &StateProbabilityDistribution<S>,
+    nu: &StateProbabilityDistribution<S>,
+    embedding: impl Fn(&S) -> Vec<f64>,
+    num_projections: usize,
+    rng: &mut impl Rng,
+) -> f64
+where
+    S: Hash + Eq,
+{
+    assert!(
+        num_projections > 0,
+        "The number of projections must be positive"
+    );
+    let points = mu
+        .iter()
+        .map(|(state, probability)| (embedding(state), *probability))
+        .chain(
+            nu.iter()
+                .map(|(state, probability)| (embedding(state), -probability)),
+        )
+        .collect_vec();
+    let Some(dimension) = points.first().map(|(point, _)| point.len()) else {
+        return 0.0;
+    };
+    assert!(
+        points.iter().all(|(point, _)| point.len() == dimension),
+        "The embedding returned points of different dimensions"
+    );
+    (0..num_projections)
+        .map(|_| {
+            let direction = random_direction(dimension, rng);
+            let projections = points
+                .iter()
+                .map(|(point, mass)| {
+                    let projection = point
+                        .iter()
+                        .zip(&direction)
+                        .map(|(x, y)| x * y)
+                        .sum::<f64>();
+                    (projection, *mass)
+                })
+                .sorted_by(|a, b| a.0.total_cmp(&b.0))
+                .collect_vec();
+            // The integral of the absolute difference of the cumulative
+            // distribution functions
+            let mut cumulative_difference = 0.0;
+            projections
+                .windows(2)
+                .map(|window| {
+                    cumulative_difference += window[0].1;
+                    cumulative_difference.abs() * (window[1].0 - window[0].0)
+                })
+                .sum::<f64>()
+        })
+        .sum::<f64>()
+        / num_projections as f64
+}
+
+/// A direction drawn uniformly from the unit sphere in `R^dimension`.
+fn random_direction(dimension: usize, rng: &mut impl Rng) -> Vec<f64> {
+    loop {
+        let direction = (0..dimension)
+            .map(|_| rng.gen_range(-1.0..=1.0))
+            .collect_vec();
+        let norm = direction.iter().map(|x| x * x).sum::<f64>().sqrt();
+        if norm > 1e-3 && norm <= 1.0 {
+            return direction.into_iter().map(|x| x / norm).collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn distance(x: &i32, y: &i32) -> f64 {
+        (x - y).abs() as f64
+    }
+
+    #[test]
+    fn wasserstein_distance() {
+        let mu = HashMap::from([(0, 0.5), (1, 0.25), (2, 0.25)]);
+        assert_eq!(super::wasserstein_distance(&mu, &mu, distance), 0.0);
+
+        // Shifting a distribution moves all of its mass by the shift
+        let shifted = mu
+            .iter()
+            .map(|(state, probability)| (state + 3, *probability))
+            .collect();
+        assert!((super::wasserstein_distance(&mu, &shifted, distance) - 3.0).abs() < 1e-12);
+
+        // The greedy transport of 0 to 1 and 1 to 2 would have to be undone
+        let mu = HashMap::from([(0, 0.5), (2, 0.5)]);
+        let nu = HashMap::from([(1, 0.5), (4, 0.5)]);
+        assert!((super::wasserstein_distance(&mu, &nu, distance) - 1.5).abs() < 1e-12);
+        assert!((super::wasserstein_distance(&nu, &mu, distance) - 1.5).abs() < 1e-12);
+
+        // With a discrete metric it is the total variation distance
+        let discrete = |x: &i32, y: &i32| if x == y { 0.0 } else { 1.0 };
+        let mu = HashMap::from([(0, 0.2), (1, 0.3), (2, 0.5)]);
+        let nu = HashMap::from([(1, 0.6), (2, 0.1), (3, 0.3)]);
+        assert!(
+            (super::wasserstein_distance(&mu, &nu, discrete) - total_variation_distance(&mu, &nu))
+                .abs()
+                < 1e-12
+        );
+    }
+
+    #[test]
+    fn wasserstein_distance_simulation() {
+        let state_transition_generator =
+            Arc::new(|state: i32| vec![(state + 1, "next", 0.5), (state - 1, "previous", 0.5)]);
+        let mut simulation = Simulation::new(0, state_transition_generator);
+        simulation.run(4).unwrap();
+        assert_eq!(simulation.wasserstein_distance(4, 4, distance), 0.0);
+        // The expected distance from the start is the mean absolute value
+        assert!((simulation.wasserstein_distance(0, 2, distance) - 1.0).abs() < 1e-12);
+        assert!(simulation.wasserstein_distance(1, 3, distance) > 0.0);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let embedding = |state: &i32| vec![*state as f64];
+        assert_eq!(
+            simulation.sliced_wasserstein_distance(4, 4, embedding, 10, &mut rng),
+            0.0
+        );
+        // In one dimension every projection gives the exact distance
+        assert!(
+            (simulation.sliced_wasserstein_distance(0, 2, embedding, 10, &mut rng) - 1.0).abs()
+                < 1e-12
+        );
+    }
+
+    #[test]
+    fn sliced_wasserstein_distance() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let embedding = |state: &(i32, i32)| vec![state.0 as f64, state.1 as f64];
+        let mu = HashMap::from([((0, 0), 0.5), ((1, 0), 0.5)]);
+        assert_eq!(
+            super::sliced_wasserstein_distance(&mu, &mu, embedding, 50, &mut rng),
+            0.0
+        );
+
+        let nu = mu
+            .iter()
+            .map(|((x, y), probability)| ((x + 3, y + 4), *probability))
+            .collect();
+        let sliced_distance =
+            super::sliced_wasserstein_distance(&mu, &nu, embedding, 500, &mut rng);
+        let euclidean = |a: &(i32, i32), b: &(i32, i32)| {
+            (((a.0 - b.0).pow(2) + (a.1 - b.1).pow(2)) as f64).sqrt()
+        };
+        let distance = super::wasserstein_distance(&mu, &nu, euclidean);
+        assert!((distance - 5.0).abs() < 1e-12);
+        // The projection of a shift by 5 has the expected length 5 * 2 / π
+        assert!(sliced_distance > 0.0);
+        assert!(sliced_distance < distance);
+        assert!((sliced_distance - 10.0 / std::f64::consts::PI).abs() < 0.2);
+    }
+}